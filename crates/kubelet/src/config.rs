@@ -13,6 +13,7 @@
 
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[cfg(any(feature = "cli", feature = "docs"))]
 use std::iter::FromIterator;
@@ -21,11 +22,27 @@ use std::iter::FromIterator;
 use structopt::StructOpt;
 
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use serde::Deserialize;
 
+use crate::resources::quantity::{Quantity, QuantityType};
+
 const DEFAULT_PORT: u16 = 3000;
 const DEFAULT_MAX_PODS: u16 = 110;
+const DEFAULT_CONTAINER_LOG_MAX_SIZE: &str = "10Mi";
+const DEFAULT_CONTAINER_LOG_MAX_FILES: usize = 5;
+const DEFAULT_NODE_STATUS_UPDATE_FREQUENCY: &str = "10s";
+const DEFAULT_NODE_LEASE_DURATION_SECONDS: u64 = 40;
+const DEFAULT_NODE_LEASE_RENEW_INTERVAL: &str = "10s";
+const DEFAULT_SHUTDOWN_GRACE_PERIOD: &str = "30s";
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_CRITICAL_PODS: &str = "10s";
+const DEFAULT_EVICTION_HARD: &[(&str, &str)] = &[
+    ("memory.available", "100Mi"),
+    ("nodefs.available", "10%"),
+    ("nodefs.inodesFree", "5%"),
+    ("imagefs.available", "15%"),
+];
 const BOOTSTRAP_FILE: &str = "/etc/kubernetes/bootstrap-kubelet.conf";
 
 /// The configuration needed for a kubelet to run properly.
@@ -52,6 +69,31 @@ pub struct Config {
     pub node_labels: HashMap<String, String>,
     /// The maximum pods for this kubelet (reported to apiserver)
     pub max_pods: u16,
+    /// Resources (cpu, memory and ephemeral-storage) reserved for system
+    /// daemons, which are not allocatable to pods
+    pub system_reserved: HashMap<String, String>,
+    /// Resources (cpu, memory and ephemeral-storage) reserved for the kubelet
+    /// itself, which are not allocatable to pods
+    pub kube_reserved: HashMap<String, String>,
+    /// Thresholds of eviction signals (e.g. `memory.available`) that, once
+    /// crossed, put the node under pressure and cause pods to be evicted.
+    /// Thresholds are quantities or percentages of the capacity, e.g.
+    /// `{"memory.available": "100Mi", "nodefs.available": "10%"}`
+    pub eviction_hard: HashMap<String, String>,
+    /// How often the status of the node is posted to the API server
+    pub node_status_update_frequency: Duration,
+    /// How long the node lease is valid for after it was renewed. The node is
+    /// considered unhealthy if the lease is not renewed in time.
+    pub node_lease_duration: Duration,
+    /// How often the node lease is renewed
+    pub node_lease_renew_interval: Duration,
+    /// How long pods are given to shut down when the node shuts down, by
+    /// their priority. Pods are shut down in order of increasing priority.
+    pub shutdown_grace_period_by_pod_priority: Vec<ShutdownGracePeriodByPodPriority>,
+    /// The size in bytes a container log file may grow to before it is rotated
+    pub container_log_max_size: u64,
+    /// The maximum number of log files kept for each run of a container
+    pub container_log_max_files: usize,
     /// The location of the tls bootstrapping file
     pub bootstrap_file: PathBuf,
     /// Whether to allow modules to be loaded directly from local
@@ -66,7 +108,82 @@ pub struct Config {
     /// device plugins lives. This is also where device plugins
     /// should host their services.
     pub device_plugins_dir: PathBuf,
+    /// Tuning options for the WebAssembly runtime
+    pub wasm: WasmConfig,
+    /// The filter for log messages, using the syntax of the `RUST_LOG`
+    /// environment variable (e.g. `info,kubelet=debug`). If not set, `RUST_LOG`
+    /// is used.
+    pub log_level: Option<String>,
+}
+
+/// The time pods of a priority are given to shut down when the node shuts
+/// down.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub struct ShutdownGracePeriodByPodPriority {
+    /// The lowest priority of the pods this grace period applies to. Pods
+    /// belong to the group with the highest priority that is not above their
+    /// own, or to the lowest group if there is none.
+    pub priority: i32,
+    /// How long pods of this priority are given to shut down
+    #[serde(rename = "shutdownGracePeriodSeconds")]
+    pub shutdown_grace_period_seconds: u64,
 }
+
+/// Tuning options for the WebAssembly runtime used by providers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WasmConfig {
+    /// Whether running modules can be interrupted. Modules that cannot be
+    /// interrupted keep running until they exit on their own, even if their
+    /// pod is deleted.
+    pub interruptable: bool,
+    /// How much the compiler optimizes modules
+    pub opt_level: WasmOptLevel,
+    /// Whether to preallocate the memory for module instances in a pool
+    /// instead of allocating it for every instance. The pool has room for as
+    /// many instances as `max_pods`.
+    pub pooling_allocator: bool,
+    /// Whether to compile the functions of a module in parallel
+    pub parallel_compilation: bool,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        WasmConfig {
+            interruptable: true,
+            opt_level: WasmOptLevel::Speed,
+            pooling_allocator: false,
+            parallel_compilation: true,
+        }
+    }
+}
+
+/// The optimization level used when compiling WebAssembly modules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WasmOptLevel {
+    /// No optimizations, which minimizes compilation time
+    None,
+    /// Optimize for the speed of the compiled code
+    Speed,
+    /// Optimize for both speed and size of the compiled code
+    SpeedAndSize,
+}
+
+impl FromStr for WasmOptLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(WasmOptLevel::None),
+            "speed" => Ok(WasmOptLevel::Speed),
+            "speed_and_size" => Ok(WasmOptLevel::SpeedAndSize),
+            _ => Err(anyhow::anyhow!(
+                "unknown optimization level {:?}, expected one of none, speed or speed_and_size",
+                s
+            )),
+        }
+    }
+}
+
 /// The configuration for the Kubelet server.
 #[derive(Clone, Debug)]
 pub struct ServerConfig {
@@ -103,6 +220,28 @@ struct ConfigBuilder {
     pub node_labels: Option<HashMap<String, String>>,
     #[serde(default, rename = "maxPods", deserialize_with = "try_deserialize_u16")]
     pub max_pods: Option<anyhow::Result<u16>>,
+    #[serde(default, rename = "systemReserved")]
+    pub system_reserved: Option<HashMap<String, String>>,
+    #[serde(default, rename = "kubeReserved")]
+    pub kube_reserved: Option<HashMap<String, String>>,
+    #[serde(default, rename = "evictionHard")]
+    pub eviction_hard: Option<HashMap<String, String>>,
+    #[serde(default, rename = "nodeStatusUpdateFrequency")]
+    pub node_status_update_frequency: Option<String>,
+    #[serde(default, rename = "nodeLeaseDurationSeconds")]
+    pub node_lease_duration_seconds: Option<u64>,
+    #[serde(default, rename = "nodeLeaseRenewInterval")]
+    pub node_lease_renew_interval: Option<String>,
+    #[serde(default, rename = "shutdownGracePeriod")]
+    pub shutdown_grace_period: Option<String>,
+    #[serde(default, rename = "shutdownGracePeriodCriticalPods")]
+    pub shutdown_grace_period_critical_pods: Option<String>,
+    #[serde(default, rename = "shutdownGracePeriodByPodPriority")]
+    pub shutdown_grace_period_by_pod_priority: Option<Vec<ShutdownGracePeriodByPodPriority>>,
+    #[serde(default, rename = "containerLogMaxSize")]
+    pub container_log_max_size: Option<String>,
+    #[serde(default, rename = "containerLogMaxFiles")]
+    pub container_log_max_files: Option<usize>,
     #[serde(
         default,
         rename = "listenerAddress",
@@ -127,6 +266,16 @@ struct ConfigBuilder {
     pub plugins_dir: Option<PathBuf>,
     #[serde(default, rename = "devicePluginsDir")]
     pub device_plugins_dir: Option<PathBuf>,
+    #[serde(default, rename = "wasmInterruptable")]
+    pub wasm_interruptable: Option<bool>,
+    #[serde(default, rename = "wasmOptLevel")]
+    pub wasm_opt_level: Option<String>,
+    #[serde(default, rename = "wasmPoolingAllocator")]
+    pub wasm_pooling_allocator: Option<bool>,
+    #[serde(default, rename = "wasmParallelCompilation")]
+    pub wasm_parallel_compilation: Option<bool>,
+    #[serde(default, rename = "logLevel")]
+    pub log_level: Option<String>,
 }
 
 struct ConfigBuilderFallbacks {
@@ -160,11 +309,25 @@ impl Config {
             hostname,
             data_dir,
             max_pods: DEFAULT_MAX_PODS,
+            system_reserved: HashMap::new(),
+            kube_reserved: HashMap::new(),
+            eviction_hard: default_eviction_hard(),
+            node_status_update_frequency: parse_duration(DEFAULT_NODE_STATUS_UPDATE_FREQUENCY)?,
+            node_lease_duration: Duration::from_secs(DEFAULT_NODE_LEASE_DURATION_SECONDS),
+            node_lease_renew_interval: parse_duration(DEFAULT_NODE_LEASE_RENEW_INTERVAL)?,
+            shutdown_grace_period_by_pod_priority: shutdown_grace_periods(
+                parse_duration(DEFAULT_SHUTDOWN_GRACE_PERIOD)?,
+                parse_duration(DEFAULT_SHUTDOWN_GRACE_PERIOD_CRITICAL_PODS)?,
+            )?,
+            container_log_max_size: parse_log_size(DEFAULT_CONTAINER_LOG_MAX_SIZE)?,
+            container_log_max_files: DEFAULT_CONTAINER_LOG_MAX_FILES,
             bootstrap_file: PathBuf::from(BOOTSTRAP_FILE),
             allow_local_modules: false,
             insecure_registries: None,
             plugins_dir,
             device_plugins_dir,
+            wasm: WasmConfig::default(),
+            log_level: None,
             server_config: ServerConfig {
                 addr: match preferred_ip_family {
                     IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
@@ -178,6 +341,10 @@ impl Config {
     }
 
     fn new_from_builder(builder: ConfigBuilder) -> Self {
+        Config::try_new_from_builder(builder).unwrap()
+    }
+
+    fn try_new_from_builder(builder: ConfigBuilder) -> anyhow::Result<Self> {
         let fallbacks = ConfigBuilderFallbacks {
             hostname: || default_hostname().expect("unable to get default hostname"),
             data_dir: || default_data_dir().expect("unable to get default data directory"),
@@ -188,7 +355,7 @@ impl Config {
             node_ip: |hn, ip| default_node_ip(hn, ip).expect("unable to get default node IP"),
             bootstrap_file: || PathBuf::from(BOOTSTRAP_FILE),
         };
-        ConfigBuilder::build(builder, fallbacks).unwrap()
+        ConfigBuilder::build(builder, fallbacks)
     }
 
     /// Parses the specified config file and sets the proper defaults.
@@ -239,15 +406,48 @@ impl Config {
     #[cfg(any(feature = "cli", feature = "docs"))]
     #[cfg_attr(feature = "docs", doc(cfg(feature = "cli")))]
     fn new_from_file_and_flags_impl(version: &str, config_file_path: PathBuf) -> Self {
+        // if the config file is actually malformed then we should halt even if there are CLI values
+        Config::try_new_from_file_and_flags_impl(version, config_file_path).unwrap()
+    }
+
+    /// Like [`Config::new_from_file_and_flags`], but returns an error instead
+    /// of panicking if the configuration is invalid. This is useful to reload
+    /// the configuration of a running kubelet.
+    #[cfg(any(feature = "cli", feature = "docs"))]
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "cli")))]
+    pub fn try_new_from_file_and_flags(
+        version: &str,
+        config_file_path: Option<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        match config_file_path {
+            None => {
+                let default_path = default_config_file_path();
+                if default_path.exists() {
+                    Config::try_new_from_file_and_flags_impl(version, default_path)
+                } else {
+                    let app = Opts::clap().version(version);
+                    let opts = Opts::from_clap(&app.get_matches());
+                    Config::try_new_from_builder(ConfigBuilder::from_opts(opts))
+                }
+            }
+            Some(path) => Config::try_new_from_file_and_flags_impl(version, path),
+        }
+    }
+
+    #[cfg(any(feature = "cli", feature = "docs"))]
+    fn try_new_from_file_and_flags_impl(
+        version: &str,
+        config_file_path: PathBuf,
+    ) -> anyhow::Result<Self> {
         // TODO: reduce duplication
         let app = Opts::clap().version(version);
         let opts = Opts::from_clap(&app.get_matches());
         let cli_builder = ConfigBuilder::from_opts(opts);
 
-        let config_file_builder = ConfigBuilder::from_config_file(config_file_path);
+        let config_file_builder = ConfigBuilder::from_config_file(config_file_path)?;
 
-        let builder = config_file_builder.unwrap().with_override(cli_builder); // if the config file is actually malformed then we should halt even if there are CLI values
-        Config::new_from_builder(builder)
+        let builder = config_file_builder.with_override(cli_builder);
+        Config::try_new_from_builder(builder)
     }
 }
 
@@ -285,6 +485,17 @@ impl ConfigBuilder {
             hostname: opts.hostname,
             data_dir: opts.data_dir,
             max_pods: ok_result_of(opts.max_pods),
+            system_reserved: parse_resource_list(&opts.system_reserved),
+            kube_reserved: parse_resource_list(&opts.kube_reserved),
+            eviction_hard: parse_eviction_thresholds(&opts.eviction_hard),
+            node_status_update_frequency: opts.node_status_update_frequency,
+            node_lease_duration_seconds: opts.node_lease_duration_seconds,
+            node_lease_renew_interval: opts.node_lease_renew_interval,
+            shutdown_grace_period: opts.shutdown_grace_period,
+            shutdown_grace_period_critical_pods: opts.shutdown_grace_period_critical_pods,
+            shutdown_grace_period_by_pod_priority: None,
+            container_log_max_size: opts.container_log_max_size,
+            container_log_max_files: opts.container_log_max_files,
             allow_local_modules: opts.allow_local_modules,
             insecure_registries: opts.insecure_registries.map(parse_comma_separated),
             plugins_dir: opts.plugins_dir,
@@ -293,6 +504,11 @@ impl ConfigBuilder {
             server_port: ok_result_of(opts.port),
             server_tls_cert_file: opts.cert_file,
             server_tls_private_key_file: opts.private_key_file,
+            wasm_interruptable: opts.wasm_interruptable,
+            wasm_opt_level: opts.wasm_opt_level,
+            wasm_pooling_allocator: opts.wasm_pooling_allocator,
+            wasm_parallel_compilation: opts.wasm_parallel_compilation,
+            log_level: opts.log_level,
         }
     }
 
@@ -320,6 +536,29 @@ impl ConfigBuilder {
             hostname: other.hostname.or(self.hostname),
             data_dir: other.data_dir.or(self.data_dir),
             max_pods: other.max_pods.or(self.max_pods),
+            system_reserved: other.system_reserved.or(self.system_reserved),
+            kube_reserved: other.kube_reserved.or(self.kube_reserved),
+            eviction_hard: other.eviction_hard.or(self.eviction_hard),
+            node_status_update_frequency: other
+                .node_status_update_frequency
+                .or(self.node_status_update_frequency),
+            node_lease_duration_seconds: other
+                .node_lease_duration_seconds
+                .or(self.node_lease_duration_seconds),
+            node_lease_renew_interval: other
+                .node_lease_renew_interval
+                .or(self.node_lease_renew_interval),
+            shutdown_grace_period: other.shutdown_grace_period.or(self.shutdown_grace_period),
+            shutdown_grace_period_critical_pods: other
+                .shutdown_grace_period_critical_pods
+                .or(self.shutdown_grace_period_critical_pods),
+            shutdown_grace_period_by_pod_priority: other
+                .shutdown_grace_period_by_pod_priority
+                .or(self.shutdown_grace_period_by_pod_priority),
+            container_log_max_size: other.container_log_max_size.or(self.container_log_max_size),
+            container_log_max_files: other
+                .container_log_max_files
+                .or(self.container_log_max_files),
             server_addr: other.server_addr.or(self.server_addr),
             server_port: other.server_port.or(self.server_port),
             server_tls_cert_file: other.server_tls_cert_file.or(self.server_tls_cert_file),
@@ -331,6 +570,13 @@ impl ConfigBuilder {
             server_tls_private_key_file: other
                 .server_tls_private_key_file
                 .or(self.server_tls_private_key_file),
+            wasm_interruptable: other.wasm_interruptable.or(self.wasm_interruptable),
+            wasm_opt_level: other.wasm_opt_level.or(self.wasm_opt_level),
+            wasm_pooling_allocator: other.wasm_pooling_allocator.or(self.wasm_pooling_allocator),
+            wasm_parallel_compilation: other
+                .wasm_parallel_compilation
+                .or(self.wasm_parallel_compilation),
+            log_level: other.log_level.or(self.log_level),
         }
     }
 
@@ -371,6 +617,105 @@ impl ConfigBuilder {
             .max_pods
             .unwrap_or(Ok(DEFAULT_MAX_PODS))
             .map_err(|e| invalid_config_value_error(e, "maximum pods"))?;
+        let system_reserved = self.system_reserved.unwrap_or_default();
+        crate::node::Resources::parse_reserved(&system_reserved)
+            .map_err(|e| invalid_config_value_error(e, "system reserved resources"))?;
+        let kube_reserved = self.kube_reserved.unwrap_or_default();
+        crate::node::Resources::parse_reserved(&kube_reserved)
+            .map_err(|e| invalid_config_value_error(e, "kube reserved resources"))?;
+        let eviction_hard = self.eviction_hard.unwrap_or_else(default_eviction_hard);
+        crate::node::Thresholds::parse(&eviction_hard)
+            .map_err(|e| invalid_config_value_error(e, "hard eviction thresholds"))?;
+        let node_status_update_frequency = parse_duration(
+            self.node_status_update_frequency
+                .as_deref()
+                .unwrap_or(DEFAULT_NODE_STATUS_UPDATE_FREQUENCY),
+        )
+        .map_err(|e| invalid_config_value_error(e, "node status update frequency"))?;
+        let node_lease_duration = Duration::from_secs(
+            self.node_lease_duration_seconds
+                .unwrap_or(DEFAULT_NODE_LEASE_DURATION_SECONDS),
+        );
+        let node_lease_renew_interval = parse_duration(
+            self.node_lease_renew_interval
+                .as_deref()
+                .unwrap_or(DEFAULT_NODE_LEASE_RENEW_INTERVAL),
+        )
+        .map_err(|e| invalid_config_value_error(e, "node lease renew interval"))?;
+        if node_lease_renew_interval >= node_lease_duration {
+            return Err(invalid_config_value_error(
+                anyhow::anyhow!("the lease must be renewed more often than it expires"),
+                "node lease renew interval",
+            ));
+        }
+        let shutdown_grace_period_by_pod_priority = match self.shutdown_grace_period_by_pod_priority
+        {
+            Some(_)
+                if self.shutdown_grace_period.is_some()
+                    || self.shutdown_grace_period_critical_pods.is_some() =>
+            {
+                return Err(invalid_config_value_error(
+                    anyhow::anyhow!(
+                        "cannot be combined with shutdown grace periods for all or critical pods"
+                    ),
+                    "shutdown grace periods by pod priority",
+                ));
+            }
+            Some(groups) if groups.is_empty() => {
+                return Err(invalid_config_value_error(
+                    anyhow::anyhow!("at least one priority must be given"),
+                    "shutdown grace periods by pod priority",
+                ));
+            }
+            Some(mut groups) => {
+                groups.sort_by_key(|g| g.priority);
+                if groups.windows(2).any(|g| g[0].priority == g[1].priority) {
+                    return Err(invalid_config_value_error(
+                        anyhow::anyhow!("priorities must be unique"),
+                        "shutdown grace periods by pod priority",
+                    ));
+                }
+                groups
+            }
+            None => {
+                let total = parse_duration(
+                    self.shutdown_grace_period
+                        .as_deref()
+                        .unwrap_or(DEFAULT_SHUTDOWN_GRACE_PERIOD),
+                )
+                .map_err(|e| invalid_config_value_error(e, "shutdown grace period"))?;
+                let critical = parse_duration(
+                    self.shutdown_grace_period_critical_pods
+                        .as_deref()
+                        .unwrap_or(DEFAULT_SHUTDOWN_GRACE_PERIOD_CRITICAL_PODS),
+                )
+                .map_err(|e| invalid_config_value_error(e, "shutdown grace period"))?;
+                shutdown_grace_periods(total, critical)
+                    .map_err(|e| invalid_config_value_error(e, "shutdown grace period"))?
+            }
+        };
+        let container_log_max_size = parse_log_size(
+            self.container_log_max_size
+                .as_deref()
+                .unwrap_or(DEFAULT_CONTAINER_LOG_MAX_SIZE),
+        )
+        .map_err(|e| invalid_config_value_error(e, "container log max size"))?;
+        let container_log_max_files = self
+            .container_log_max_files
+            .unwrap_or(DEFAULT_CONTAINER_LOG_MAX_FILES);
+        if container_log_max_files == 0 {
+            return Err(invalid_config_value_error(
+                anyhow::anyhow!("at least one file must be kept"),
+                "container log max files",
+            ));
+        }
+        let wasm_defaults = WasmConfig::default();
+        let wasm_opt_level = match self.wasm_opt_level {
+            Some(level) => level
+                .parse()
+                .map_err(|e| invalid_config_value_error(e, "wasm optimization level"))?,
+            None => wasm_defaults.opt_level,
+        };
 
         Ok(Config {
             node_ip,
@@ -379,11 +724,33 @@ impl ConfigBuilder {
             hostname,
             data_dir,
             max_pods,
+            system_reserved,
+            kube_reserved,
+            eviction_hard,
+            node_status_update_frequency,
+            node_lease_duration,
+            node_lease_renew_interval,
+            shutdown_grace_period_by_pod_priority,
+            container_log_max_size,
+            container_log_max_files,
             bootstrap_file,
             allow_local_modules: self.allow_local_modules.unwrap_or(false),
             insecure_registries: self.insecure_registries,
             plugins_dir,
             device_plugins_dir,
+            wasm: WasmConfig {
+                interruptable: self
+                    .wasm_interruptable
+                    .unwrap_or(wasm_defaults.interruptable),
+                opt_level: wasm_opt_level,
+                pooling_allocator: self
+                    .wasm_pooling_allocator
+                    .unwrap_or(wasm_defaults.pooling_allocator),
+                parallel_compilation: self
+                    .wasm_parallel_compilation
+                    .unwrap_or(wasm_defaults.parallel_compilation),
+            },
+            log_level: self.log_level,
             server_config: ServerConfig {
                 cert_file: server_tls_cert_file,
                 private_key_file: server_tls_private_key_file,
@@ -447,6 +814,87 @@ pub struct Opts {
     )]
     max_pods: Option<u16>,
 
+    #[structopt(
+        long = "system-reserved",
+        env = "KRUSTLET_SYSTEM_RESERVED",
+        use_delimiter = true,
+        help = "Resources reserved for system daemons, which are subtracted from the allocatable resources of the node.
+        Resources must be name=quantity pairs separated by ',' (e.g. cpu=200m,memory=512Mi).
+        Supported resources are cpu, memory and ephemeral-storage"
+    )]
+    system_reserved: Vec<String>,
+
+    #[structopt(
+        long = "kube-reserved",
+        env = "KRUSTLET_KUBE_RESERVED",
+        use_delimiter = true,
+        help = "Resources reserved for krustlet itself, which are subtracted from the allocatable resources of the node.
+        Uses the same format as --system-reserved"
+    )]
+    kube_reserved: Vec<String>,
+
+    #[structopt(
+        long = "eviction-hard",
+        env = "KRUSTLET_EVICTION_HARD",
+        use_delimiter = true,
+        help = "Thresholds that put the node under pressure and cause pods to be evicted once crossed.
+        Thresholds must be signal<quantity pairs separated by ',' (e.g. memory.available<100Mi,nodefs.available<10%).
+        Supported signals are memory.available, nodefs.available, nodefs.inodesFree, imagefs.available,
+        imagefs.inodesFree and pid.available. Setting any threshold replaces all of the defaults, which are
+        memory.available<100Mi,nodefs.available<10%,nodefs.inodesFree<5%,imagefs.available<15%"
+    )]
+    eviction_hard: Vec<String>,
+
+    #[structopt(
+        long = "node-status-update-frequency",
+        env = "KRUSTLET_NODE_STATUS_UPDATE_FREQUENCY",
+        help = "How often the status of the node is posted to the API server (e.g. 10s or 1m). Defaults to 10s"
+    )]
+    node_status_update_frequency: Option<String>,
+
+    #[structopt(
+        long = "node-lease-duration-seconds",
+        env = "KRUSTLET_NODE_LEASE_DURATION_SECONDS",
+        help = "How long the node lease is valid for after it was renewed, in seconds. Defaults to 40"
+    )]
+    node_lease_duration_seconds: Option<u64>,
+
+    #[structopt(
+        long = "node-lease-renew-interval",
+        env = "KRUSTLET_NODE_LEASE_RENEW_INTERVAL",
+        help = "How often the node lease is renewed (e.g. 10s). Must be shorter than the lease duration. Defaults to 10s"
+    )]
+    node_lease_renew_interval: Option<String>,
+
+    #[structopt(
+        long = "shutdown-grace-period",
+        env = "KRUSTLET_SHUTDOWN_GRACE_PERIOD",
+        help = "How long the node waits for pods to shut down when it shuts down (e.g. 30s). Defaults to 30s"
+    )]
+    shutdown_grace_period: Option<String>,
+
+    #[structopt(
+        long = "shutdown-grace-period-critical-pods",
+        env = "KRUSTLET_SHUTDOWN_GRACE_PERIOD_CRITICAL_PODS",
+        help = "How much of the shutdown grace period is reserved for critical pods, which are shut down after all other pods.
+        Defaults to 10s"
+    )]
+    shutdown_grace_period_critical_pods: Option<String>,
+
+    #[structopt(
+        long = "container-log-max-size",
+        env = "KRUSTLET_CONTAINER_LOG_MAX_SIZE",
+        help = "The size a container log file may grow to before it is rotated (e.g. 10Mi). Defaults to 10Mi"
+    )]
+    container_log_max_size: Option<String>,
+
+    #[structopt(
+        long = "container-log-max-files",
+        env = "KRUSTLET_CONTAINER_LOG_MAX_FILES",
+        help = "The maximum number of log files kept for each run of a container. Defaults to 5"
+    )]
+    container_log_max_files: Option<usize>,
+
     #[structopt(
         long = "cert-file",
         env = "KRUSTLET_CERT_FILE",
@@ -541,6 +989,42 @@ pub struct Opts {
         help = "Registries that should be accessed over HTTP instead of HTTPS (comma separated)"
     )]
     insecure_registries: Option<String>,
+
+    #[structopt(
+        long = "wasm-interruptable",
+        env = "KRUSTLET_WASM_INTERRUPTABLE",
+        help = "Whether running modules can be interrupted, which is needed to stop them before they exit. Defaults to true"
+    )]
+    wasm_interruptable: Option<bool>,
+
+    #[structopt(
+        long = "wasm-opt-level",
+        env = "KRUSTLET_WASM_OPT_LEVEL",
+        help = "The optimization level used when compiling modules: none, speed or speed_and_size. Defaults to speed"
+    )]
+    wasm_opt_level: Option<String>,
+
+    #[structopt(
+        long = "wasm-pooling-allocator",
+        env = "KRUSTLET_WASM_POOLING_ALLOCATOR",
+        help = "Whether to preallocate the memory for module instances in a pool. Defaults to false"
+    )]
+    wasm_pooling_allocator: Option<bool>,
+
+    #[structopt(
+        long = "wasm-parallel-compilation",
+        env = "KRUSTLET_WASM_PARALLEL_COMPILATION",
+        help = "Whether to compile the functions of a module in parallel. Defaults to true"
+    )]
+    wasm_parallel_compilation: Option<bool>,
+
+    #[structopt(
+        long = "log-level",
+        env = "KRUSTLET_LOG_LEVEL",
+        help = "The filter for log messages, using the syntax of RUST_LOG (e.g. info,kubelet=debug).
+        Defaults to the value of RUST_LOG"
+    )]
+    log_level: Option<String>,
 }
 
 fn default_hostname() -> anyhow::Result<String> {
@@ -634,11 +1118,99 @@ fn split_one_label(in_string: &str) -> Option<(String, String)> {
     }
 }
 
+#[cfg(any(feature = "cli", feature = "docs"))]
+fn parse_resource_list(resources: &[String]) -> Option<HashMap<String, String>> {
+    let resources: Vec<(String, String)> = resources
+        .iter()
+        .filter_map(|i| split_one_label(i))
+        .collect();
+    if resources.is_empty() {
+        None
+    } else {
+        Some(HashMap::from_iter(resources))
+    }
+}
+
+#[cfg(any(feature = "cli", feature = "docs"))]
+fn parse_eviction_thresholds(thresholds: &[String]) -> Option<HashMap<String, String>> {
+    let thresholds: Vec<(String, String)> = thresholds
+        .iter()
+        .filter_map(|t| {
+            let (signal, threshold) = t.split_once('<')?;
+            Some((signal.trim().to_owned(), threshold.trim().to_owned()))
+        })
+        .collect();
+    if thresholds.is_empty() {
+        None
+    } else {
+        Some(HashMap::from_iter(thresholds))
+    }
+}
+
+/// Splits the shutdown grace period between regular pods and critical pods,
+/// which are shut down in the last `critical` part of the grace period.
+fn shutdown_grace_periods(
+    total: Duration,
+    critical: Duration,
+) -> anyhow::Result<Vec<ShutdownGracePeriodByPodPriority>> {
+    if critical > total {
+        anyhow::bail!("the grace period of critical pods must not exceed the total grace period");
+    }
+    Ok(vec![
+        ShutdownGracePeriodByPodPriority {
+            priority: 0,
+            shutdown_grace_period_seconds: (total - critical).as_secs(),
+        },
+        ShutdownGracePeriodByPodPriority {
+            priority: crate::pod::SYSTEM_CRITICAL_PRIORITY,
+            shutdown_grace_period_seconds: critical.as_secs(),
+        },
+    ])
+}
+
+fn default_eviction_hard() -> HashMap<String, String> {
+    DEFAULT_EVICTION_HARD
+        .iter()
+        .map(|(signal, threshold)| (signal.to_string(), threshold.to_string()))
+        .collect()
+}
+
 fn invalid_config_value_error(e: anyhow::Error, value_name: &str) -> anyhow::Error {
     let context = format!("invalid {} in configuration file: {}", value_name, e);
     e.context(context)
 }
 
+fn parse_log_size(size: &str) -> anyhow::Result<u64> {
+    let quantity = k8s_openapi::apimachinery::pkg::api::resource::Quantity(size.to_owned());
+    match Quantity::from_kube_quantity(QuantityType::Memory(&quantity))? {
+        Quantity::Memory(bytes) => Ok(u64::try_from(bytes)?),
+        _ => anyhow::bail!("{:?} is not a size", size),
+    }
+}
+
+/// Parses a duration made up of a positive number and one of the units `ms`,
+/// `s`, `m` or `h`, e.g. `10s`.
+fn parse_duration(duration: &str) -> anyhow::Result<Duration> {
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow::anyhow!("{:?} has no unit", duration))?;
+    let (amount, unit) = duration.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| anyhow::anyhow!("{:?} is not a duration", duration))?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(amount),
+        "s" => Duration::from_secs(amount),
+        "m" => Duration::from_secs(amount * 60),
+        "h" => Duration::from_secs(amount * 60 * 60),
+        _ => anyhow::bail!("unsupported unit {:?}, expected one of ms, s, m or h", unit),
+    };
+    if duration.is_zero() {
+        anyhow::bail!("duration must not be zero");
+    }
+    Ok(duration)
+}
+
 fn parse_comma_separated(source: String) -> Vec<String> {
     source.split(',').map(|s| s.trim().to_owned()).collect()
 }
@@ -673,6 +1245,31 @@ mod test {
             "hostname": "krusty-host",
             "dataDir": "/krusty/data/dir",
             "maxPods": 400,
+            "containerLogMaxSize": "1Mi",
+            "containerLogMaxFiles": 3,
+            "systemReserved": {
+                "cpu": "500m",
+                "memory": "1Gi"
+            },
+            "kubeReserved": {
+                "ephemeral-storage": "10Gi"
+            },
+            "evictionHard": {
+                "memory.available": "200Mi"
+            },
+            "nodeStatusUpdateFrequency": "1m",
+            "nodeLeaseDurationSeconds": 60,
+            "nodeLeaseRenewInterval": "15s",
+            "shutdownGracePeriodByPodPriority": [
+                {
+                    "priority": 100000,
+                    "shutdownGracePeriodSeconds": 20
+                },
+                {
+                    "priority": 0,
+                    "shutdownGracePeriodSeconds": 5
+                }
+            ],
             "nodeIP": "173.183.193.2",
             "nodeLabels": {
                 "label1": "val1",
@@ -687,7 +1284,12 @@ mod test {
                 "local",
                 "dev"
             ],
-            "pluginsDir": "/some/plugins"
+            "pluginsDir": "/some/plugins",
+            "wasmInterruptable": false,
+            "wasmOptLevel": "speed_and_size",
+            "wasmPoolingAllocator": true,
+            "wasmParallelCompilation": false,
+            "logLevel": "info,kubelet=debug"
         }"#,
         );
         let config = config_builder.unwrap().build(fallbacks()).unwrap();
@@ -710,6 +1312,38 @@ mod test {
         assert_eq!(config.data_dir.to_string_lossy(), "/krusty/data/dir");
         assert_eq!(format!("{}", config.node_ip), "173.183.193.2");
         assert_eq!(config.max_pods, 400);
+        assert_eq!(config.container_log_max_size, 1024 * 1024);
+        assert_eq!(config.container_log_max_files, 3);
+        assert_eq!(
+            config.system_reserved.get("memory"),
+            Some(&("1Gi".to_owned()))
+        );
+        assert_eq!(
+            config.kube_reserved.get("ephemeral-storage"),
+            Some(&("10Gi".to_owned()))
+        );
+        assert_eq!(config.eviction_hard.len(), 1);
+        assert_eq!(config.node_status_update_frequency, Duration::from_secs(60));
+        assert_eq!(config.node_lease_duration, Duration::from_secs(60));
+        assert_eq!(config.node_lease_renew_interval, Duration::from_secs(15));
+        assert_eq!(
+            config.shutdown_grace_period_by_pod_priority,
+            vec![
+                ShutdownGracePeriodByPodPriority {
+                    priority: 0,
+                    shutdown_grace_period_seconds: 5,
+                },
+                ShutdownGracePeriodByPodPriority {
+                    priority: 100000,
+                    shutdown_grace_period_seconds: 20,
+                },
+            ]
+        );
+        assert_eq!(
+            config.eviction_hard.get("memory.available"),
+            Some(&("200Mi".to_owned()))
+        );
+        assert_eq!(config.log_level.as_deref(), Some("info,kubelet=debug"));
         assert!(config.allow_local_modules);
         assert_eq!(config.node_labels.len(), 2);
         assert_eq!(config.node_labels.get("label1"), Some(&("val1".to_owned())));
@@ -717,6 +1351,15 @@ mod test {
         assert_eq!(&config.insecure_registries.clone().unwrap()[0], "local");
         assert_eq!(&config.insecure_registries.unwrap()[1], "dev");
         assert_eq!(&config.plugins_dir.to_string_lossy(), "/some/plugins");
+        assert_eq!(
+            config.wasm,
+            WasmConfig {
+                interruptable: false,
+                opt_level: WasmOptLevel::SpeedAndSize,
+                pooling_allocator: true,
+                parallel_compilation: false,
+            }
+        );
     }
 
     #[test]
@@ -762,6 +1405,23 @@ mod test {
         let config = config_builder.unwrap().build(fallbacks()).unwrap();
         assert_eq!(config.server_config.port, 3000);
         assert_eq!(config.max_pods, 110);
+        assert_eq!(config.container_log_max_size, 10 * 1024 * 1024);
+        assert_eq!(config.container_log_max_files, 5);
+        assert_eq!(
+            config.eviction_hard.get("nodefs.available"),
+            Some(&("10%".to_owned()))
+        );
+        assert_eq!(config.node_status_update_frequency, Duration::from_secs(10));
+        assert_eq!(config.node_lease_duration, Duration::from_secs(40));
+        assert_eq!(config.node_lease_renew_interval, Duration::from_secs(10));
+        assert_eq!(
+            config
+                .shutdown_grace_period_by_pod_priority
+                .iter()
+                .map(|g| g.shutdown_grace_period_seconds)
+                .collect::<Vec<_>>(),
+            vec![20, 10]
+        );
         assert_eq!(format!("{}", config.server_config.addr), "0.0.0.0");
         assert_eq!(
             config.server_config.cert_file.to_string_lossy(),
@@ -782,6 +1442,7 @@ mod test {
             &config.plugins_dir.to_string_lossy(),
             "/fallback/plugins/dir"
         );
+        assert_eq!(config.wasm, WasmConfig::default());
     }
 
     #[test]
@@ -971,6 +1632,44 @@ mod test {
         assert!(error.to_string().contains("server port"), "{:?}", error);
     }
 
+    #[test]
+    fn invalid_wasm_opt_level_is_reported() {
+        let config_builder = builder_from_json_string(
+            r#"{
+            "wasmOptLevel": "fastest"
+        }"#,
+        );
+        let error = config_builder
+            .unwrap()
+            .build(fallbacks())
+            .expect_err("Expected config error but was okay");
+        assert!(
+            error.to_string().contains("wasm optimization level"),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn invalid_reserved_resources_are_reported() {
+        let config_builder = builder_from_json_string(
+            r#"{
+            "kubeReserved": {
+                "gpu": "1"
+            }
+        }"#,
+        );
+        let error = config_builder
+            .unwrap()
+            .build(fallbacks())
+            .expect_err("Expected config error but was okay");
+        assert!(
+            error.to_string().contains("kube reserved resources"),
+            "{:?}",
+            error
+        );
+    }
+
     #[test]
     fn out_of_range_config_value_is_reported() {
         let config_builder = builder_from_json_string(
@@ -1039,4 +1738,53 @@ mod test {
             error.to_string()
         );
     }
+
+    #[test]
+    fn node_lease_must_be_renewed_before_it_expires() {
+        let config_builder = builder_from_json_string(
+            r#"{
+            "nodeLeaseDurationSeconds": 10,
+            "nodeLeaseRenewInterval": "10s"
+        }"#,
+        )
+        .unwrap();
+        let error = config_builder
+            .build(fallbacks())
+            .expect_err("Expected config error but was okay");
+        assert!(error.to_string().contains("node lease renew interval"));
+    }
+
+    #[test]
+    fn shutdown_grace_periods_cannot_be_combined() {
+        let config_builder = builder_from_json_string(
+            r#"{
+            "shutdownGracePeriod": "1m",
+            "shutdownGracePeriodByPodPriority": [
+                {
+                    "priority": 0,
+                    "shutdownGracePeriodSeconds": 5
+                }
+            ]
+        }"#,
+        )
+        .unwrap();
+        let error = config_builder
+            .build(fallbacks())
+            .expect_err("Expected config error but was okay");
+        assert!(error
+            .to_string()
+            .contains("shutdown grace periods by pod priority"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("s").is_err());
+    }
 }
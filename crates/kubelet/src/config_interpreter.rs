@@ -35,6 +35,16 @@ mod test {
             plugins_dir: std::path::PathBuf::from("/nope"),
             device_plugins_dir: std::path::PathBuf::from("/nope"),
             max_pods: 0,
+            system_reserved: Default::default(),
+            kube_reserved: Default::default(),
+            eviction_hard: Default::default(),
+            node_status_update_frequency: std::time::Duration::from_secs(10),
+            node_lease_duration: std::time::Duration::from_secs(40),
+            node_lease_renew_interval: std::time::Duration::from_secs(10),
+            shutdown_grace_period_by_pod_priority: Vec::new(),
+            log_level: None,
+            container_log_max_size: 0,
+            container_log_max_files: 1,
             node_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
             node_labels: std::collections::HashMap::new(),
             node_name: "nope".to_owned(),
@@ -44,6 +54,7 @@ mod test {
                 cert_file: std::path::PathBuf::from("/nope"),
                 private_key_file: std::path::PathBuf::from("/nope"),
             },
+            wasm: Default::default(),
         }
     }
 
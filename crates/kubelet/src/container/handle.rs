@@ -3,8 +3,10 @@ use std::io::SeekFrom;
 use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt};
 
 use crate::container::ContainerMap;
-use crate::handle::StopHandler;
+use crate::exec::{ExecFuture, Session};
+use crate::handle::{ExecHandler, StatsHandler, StopHandler};
 use crate::log::{stream, HandleFactory, Sender};
+use crate::stats::ContainerUsage;
 
 /// Represents a handle to a running "container" (whatever that might be). This
 /// can be used on its own, however, it is generally better to use it as a part
@@ -37,17 +39,39 @@ impl<H: StopHandler, F> Handle<H, F> {
         self.handle.stop().await
     }
 
+    /// Ask the running instance to shut down gracefully, returning whether it
+    /// was signalled. This uses the underlying [`StopHandler`] implementation
+    /// passed to the constructor
+    pub async fn shutdown(&mut self) -> anyhow::Result<bool>
+    where
+        H: Send,
+    {
+        self.handle.shutdown().await
+    }
+
     /// Streams output from the running process into the given sender.
     /// Optionally tails the output and/or continues to watch the file and stream changes.
-    pub(crate) async fn output<R>(&mut self, sender: Sender) -> anyhow::Result<()>
+    pub(crate) async fn output<R>(&self, sender: Sender) -> anyhow::Result<()>
     where
         R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
         F: HandleFactory<R>,
     {
-        let mut handle = self.handle_factory.new_handle();
-        handle.seek(SeekFrom::Start(0)).await?;
-        tokio::spawn(stream(handle, sender));
-        Ok(())
+        stream_output(&self.handle_factory, sender).await
+    }
+
+    /// Consumes the handle, keeping only the factory for reading the output of
+    /// the process. Used to retain the logs of terminated processes.
+    pub(crate) fn into_handle_factory(self) -> F {
+        self.handle_factory
+    }
+
+    /// Returns the resources used by the running process so far. This uses the
+    /// underlying [`StatsHandler`] implementation passed to the constructor
+    pub fn usage(&self) -> ContainerUsage
+    where
+        H: StatsHandler,
+    {
+        self.handle.usage()
     }
 
     /// Wait for the running process to complete. Generally speaking,
@@ -58,5 +82,25 @@ impl<H: StopHandler, F> Handle<H, F> {
     }
 }
 
+impl<H: ExecHandler, F> ExecHandler for Handle<H, F> {
+    /// Prepares a command to run in the context of the running process. This uses the
+    /// underlying [`ExecHandler`] implementation passed to the constructor
+    fn exec(&self, session: Session) -> anyhow::Result<ExecFuture> {
+        self.handle.exec(session)
+    }
+}
+
+/// Streams output read from handles of the given factory into the given sender.
+pub(crate) async fn stream_output<R, F>(handle_factory: &F, sender: Sender) -> anyhow::Result<()>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+    F: HandleFactory<R>,
+{
+    let mut handle = handle_factory.new_handle()?;
+    handle.seek(SeekFrom::Start(0)).await?;
+    tokio::spawn(stream(handle, sender));
+    Ok(())
+}
+
 /// A map from containers to container handles.
 pub type HandleMap<H, F> = ContainerMap<Handle<H, F>>;
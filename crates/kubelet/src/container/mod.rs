@@ -1,6 +1,8 @@
 //! `container` is a collection of utilities surrounding the Kubernetes container API.
 
+use crate::resources::quantity::{Quantity, QuantityType};
 use k8s_openapi::api::core::v1::Container as KubeContainer;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity as KubeQuantity;
 use oci_distribution::Reference;
 use std::convert::TryInto;
 use std::fmt::Display;
@@ -9,8 +11,12 @@ mod handle;
 pub mod state;
 mod status;
 
+pub(crate) use handle::stream_output;
 pub use handle::{Handle, HandleMap};
-pub use status::{make_initial_container_status, patch_container_status, Status};
+pub use status::{
+    make_initial_container_status, patch_container_ready, patch_container_restart_count,
+    patch_container_started, patch_container_status, Status,
+};
 
 /// Specifies how the store should check for module updates
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -100,6 +106,9 @@ pub type ContainerMap<V> = std::collections::HashMap<ContainerKey, V>;
 
 /// Provides methods for accessing `ContainerMap` elements by name.
 pub trait ContainerMapByName<V> {
+    /// Gets a reference to the value associated with the container with the
+    /// given name.
+    fn get_by_name(&self, name: String) -> Option<&V>;
     /// Gets a mutable reference to the value associated with the container
     /// with the given name.
     fn get_mut_by_name(&mut self, name: String) -> Option<&mut V>;
@@ -108,6 +117,12 @@ pub trait ContainerMapByName<V> {
 }
 
 impl<V> ContainerMapByName<V> for ContainerMap<V> {
+    fn get_by_name(&self, name: String) -> Option<&V> {
+        let app_key = ContainerKey::App(name.clone());
+        self.get(&app_key)
+            .or_else(|| self.get(&ContainerKey::Init(name)))
+    }
+
     fn get_mut_by_name(&mut self, name: String) -> Option<&mut V> {
         // TODO: borrow checker objected to any of the more natural forms
         let app_key = ContainerKey::App(name.clone());
@@ -180,6 +195,44 @@ impl Container {
         self.0.liveness_probe.as_ref()
     }
 
+    /// Get the memory limit of container in bytes, if one is set.
+    pub fn memory_limit(&self) -> anyhow::Result<Option<u128>> {
+        self.limit("memory").map(memory_bytes).transpose()
+    }
+
+    /// Get the CPU limit of container as a (possibly fractional) number of
+    /// cores, if one is set.
+    pub fn cpu_limit(&self) -> anyhow::Result<Option<f64>> {
+        self.limit("cpu").map(cpu_cores).transpose()
+    }
+
+    /// Get the memory request of container in bytes, if one is set.
+    pub fn memory_request(&self) -> anyhow::Result<Option<u128>> {
+        self.request("memory").map(memory_bytes).transpose()
+    }
+
+    /// Get the CPU request of container as a (possibly fractional) number of
+    /// cores, if one is set.
+    pub fn cpu_request(&self) -> anyhow::Result<Option<f64>> {
+        self.request("cpu").map(cpu_cores).transpose()
+    }
+
+    fn limit(&self, resource: &str) -> Option<&KubeQuantity> {
+        self.0
+            .resources
+            .as_ref()
+            .and_then(|r| r.limits.as_ref())
+            .and_then(|l| l.get(resource))
+    }
+
+    fn request(&self, resource: &str) -> Option<&KubeQuantity> {
+        self.0
+            .resources
+            .as_ref()
+            .and_then(|r| r.requests.as_ref())
+            .and_then(|l| l.get(resource))
+    }
+
     /// Get name of container.
     pub fn name(&self) -> &str {
         &self.0.name
@@ -250,3 +303,53 @@ impl Container {
         self.0.working_dir.as_ref()
     }
 }
+
+fn memory_bytes(q: &KubeQuantity) -> anyhow::Result<u128> {
+    match Quantity::from_kube_quantity(QuantityType::Memory(q))? {
+        Quantity::Memory(bytes) => Ok(bytes),
+        Quantity::Cpu(_) => anyhow::bail!("Memory quantity was parsed as a CPU quantity"),
+    }
+}
+
+fn cpu_cores(q: &KubeQuantity) -> anyhow::Result<f64> {
+    match Quantity::from_kube_quantity(QuantityType::Cpu(q))? {
+        Quantity::Cpu(cores) => Ok(cores),
+        Quantity::Memory(_) => anyhow::bail!("CPU quantity was parsed as a memory quantity"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::ResourceRequirements;
+
+    #[test]
+    fn test_resource_limits() {
+        let container = Container::new(&KubeContainer {
+            resources: Some(ResourceRequirements {
+                limits: Some(
+                    vec![
+                        ("cpu".to_owned(), KubeQuantity("250m".to_owned())),
+                        ("memory".to_owned(), KubeQuantity("64Mi".to_owned())),
+                    ]
+                    .into_iter()
+                    .collect(),
+                ),
+                requests: Some(
+                    vec![("cpu".to_owned(), KubeQuantity("100m".to_owned()))]
+                        .into_iter()
+                        .collect(),
+                ),
+            }),
+            ..Default::default()
+        });
+        assert_eq!(container.cpu_request().unwrap(), Some(0.1));
+        assert_eq!(container.memory_request().unwrap(), None);
+        assert_eq!(container.cpu_limit().unwrap(), Some(0.25));
+        assert_eq!(container.memory_limit().unwrap(), Some(64 * 1024 * 1024));
+
+        let container = Container::new(&KubeContainer::default());
+        assert_eq!(container.cpu_limit().unwrap(), None);
+        assert_eq!(container.memory_limit().unwrap(), None);
+    }
+}
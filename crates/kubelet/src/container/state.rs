@@ -86,6 +86,9 @@ pub async fn run_to_completion<S: ObjectState<Manifest = Container, Status = Sta
         ),
     );
 
+    // The terminated status reported by the state machine itself, if any, which
+    // carries more detail than the one made up from an error
+    let mut terminated: Option<Status> = None;
     loop {
         debug!(?state, "Pod container entering state");
 
@@ -95,7 +98,11 @@ pub async fn run_to_completion<S: ObjectState<Manifest = Container, Status = Sta
         match state.status(&mut container_state, &latest_container).await {
             Ok(status) => {
                 match patch_container_status(&api, &latest_pod, &container_name, &status).await {
-                    Ok(_) => (),
+                    Ok(_) => {
+                        if let Status::Terminated { .. } = status {
+                            terminated = Some(status);
+                        }
+                    }
                     Err(e) => {
                         warn!(
                             error = %e,
@@ -135,14 +142,17 @@ pub async fn run_to_completion<S: ObjectState<Manifest = Container, Status = Sta
                         error = %e,
                         "Pod container state machine exited with error"
                     );
-                    let status = Status::Terminated {
-                        timestamp: Utc::now(),
-                        message: format!("Container exited with error: {:?}.", e),
-                        failed: true,
-                    };
-                    patch_container_status(&api, &latest_pod, &container_name, &status)
-                        .await
-                        .unwrap();
+                    if let Some(status) = error_status(terminated.as_ref(), e) {
+                        if let Err(e) =
+                            patch_container_status(&api, &latest_pod, &container_name, &status)
+                                .await
+                        {
+                            warn!(
+                                error = %e,
+                                "Pod container status patch request returned error"
+                            );
+                        }
+                    }
 
                     break result;
                 }
@@ -150,3 +160,56 @@ pub async fn run_to_completion<S: ObjectState<Manifest = Container, Status = Sta
         };
     }
 }
+
+/// Returns the status to report for a container whose state machine exited
+/// with the given error, or `None` if the state machine already reported how
+/// the container terminated, which must not be overwritten.
+fn error_status(terminated: Option<&Status>, error: &anyhow::Error) -> Option<Status> {
+    match terminated {
+        Some(Status::Terminated { .. }) => None,
+        _ => Some(Status::Terminated {
+            timestamp: Utc::now(),
+            message: format!("Container exited with error: {:?}.", error),
+            failed: true,
+            reason: None,
+            exit_code: None,
+            signal: None,
+            started_at: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_status_keeps_reported_exit() {
+        let error = anyhow::anyhow!("module exited with code 2");
+        let reported = Status::Terminated {
+            timestamp: Utc::now(),
+            message: "module exited with code 2".to_owned(),
+            failed: true,
+            reason: Some("Error".to_owned()),
+            exit_code: Some(2),
+            signal: None,
+            started_at: Some(Utc::now()),
+        };
+        assert!(error_status(Some(&reported), &error).is_none());
+        let kube_status = reported.to_kubernetes("app");
+        let terminated = kube_status.state.unwrap().terminated.unwrap();
+        assert_eq!(terminated.exit_code, 2);
+        assert_eq!(terminated.reason.as_deref(), Some("Error"));
+
+        match error_status(Some(&Status::running()), &error) {
+            Some(Status::Terminated {
+                failed, exit_code, ..
+            }) => {
+                assert!(failed);
+                assert_eq!(exit_code, None);
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+        assert!(error_status(None, &error).is_some());
+    }
+}
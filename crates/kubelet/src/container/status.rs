@@ -1,5 +1,5 @@
 use crate::container::{Container, ContainerKey};
-use crate::pod::Pod;
+use crate::pod::{patch_readiness_conditions, Pod};
 use chrono::{DateTime, Utc};
 use k8s_openapi::api::core::v1::{
     ContainerState, ContainerStateRunning, ContainerStateTerminated, ContainerStateWaiting,
@@ -21,6 +21,8 @@ pub enum Status {
         timestamp: DateTime<Utc>,
         /// A human readable string describing the why it is in a waiting status
         message: String,
+        /// A brief CamelCase reason for waiting (e.g. `CreateContainerConfigError`)
+        reason: Option<String>,
     },
     /// The container is running
     Running {
@@ -35,6 +37,16 @@ pub enum Status {
         message: String,
         /// Should be set to true if the process exited with an error
         failed: bool,
+        /// A brief CamelCase reason for the termination (e.g. `OOMKilled`)
+        reason: Option<String>,
+        /// The exit code of the process. If not set, `1` is reported for
+        /// failed and `0` for succeeded containers
+        exit_code: Option<i32>,
+        /// The signal that is equivalent to how the process was stopped, if it
+        /// did not exit by itself (e.g. `9` if it was killed)
+        signal: Option<i32>,
+        /// The time the process was started, if it was started at all
+        started_at: Option<DateTime<Utc>>,
     },
 }
 
@@ -44,6 +56,16 @@ impl Status {
         Status::Waiting {
             timestamp: Utc::now(),
             message: message.to_string(),
+            reason: None,
+        }
+    }
+
+    /// Create `Status::Waiting` from a brief CamelCase reason and message.
+    pub fn waiting_with_reason(reason: &str, message: &str) -> Self {
+        Status::Waiting {
+            timestamp: Utc::now(),
+            message: message.to_string(),
+            reason: Some(reason.to_string()),
         }
     }
 
@@ -60,6 +82,10 @@ impl Status {
             timestamp: Utc::now(),
             message: message.to_string(),
             failed,
+            reason: None,
+            exit_code: None,
+            signal: None,
+            started_at: None,
         }
     }
 
@@ -67,10 +93,12 @@ impl Status {
     pub fn to_kubernetes(&self, container_name: &str) -> KubeContainerStatus {
         let mut state = ContainerState::default();
         match self {
-            Self::Waiting { message, .. } => {
+            Self::Waiting {
+                message, reason, ..
+            } => {
                 state.waiting.replace(ContainerStateWaiting {
                     message: Some(message.clone()),
-                    ..Default::default()
+                    reason: reason.clone(),
                 });
             }
             Self::Running { timestamp } => {
@@ -82,11 +110,18 @@ impl Status {
                 timestamp,
                 message,
                 failed,
+                reason,
+                exit_code,
+                signal,
+                started_at,
             } => {
                 state.terminated.replace(ContainerStateTerminated {
                     finished_at: Some(Time(*timestamp)),
                     message: Some(message.clone()),
-                    exit_code: *failed as i32,
+                    reason: reason.clone(),
+                    exit_code: exit_code.unwrap_or(*failed as i32),
+                    signal: *signal,
+                    started_at: started_at.map(Time),
                     ..Default::default()
                 });
             }
@@ -95,11 +130,11 @@ impl Status {
         KubeContainerStatus {
             state: Some(state),
             name: container_name.to_string(),
-            // Right now we don't have a way to probe, so just set to ready if
-            // in a running state
+            // Without a readiness probe, a container is ready as soon as it is
+            // running. Probe results are patched in separately.
             ready,
-            // This is always true if startupProbe is not defined. When we
-            // handle probes, this should be updated accordingly
+            // This is always true if startupProbe is not defined. Probe results
+            // are patched in separately.
             started: Some(true),
             // The rest of the items in status (see docs here:
             // https://kubernetes.io/docs/reference/generated/kubernetes-api/v1.17/#containerstatus-v1-core)
@@ -120,7 +155,15 @@ pub async fn patch_container_status(
 ) -> anyhow::Result<()> {
     match pod.find_container(key) {
         Some(container) => {
-            let kube_status = status.to_kubernetes(container.name());
+            let mut kube_status = status.to_kubernetes(container.name());
+            // Containers with probes are only ready (or started) once their
+            // probes succeed, which is reported separately by the prober
+            if container.readiness_probe().is_some() {
+                kube_status.ready = false;
+            }
+            if container.startup_probe().is_some() {
+                kube_status.started = Some(false);
+            }
 
             let patches = match pod.container_status_index(key) {
                 Some(idx) => {
@@ -141,7 +184,7 @@ pub async fn patch_container_status(
                         }),
                         json_patch::PatchOperation::Replace(json_patch::ReplaceOperation {
                             path: format!("{}/started", path_prefix),
-                            value: serde_json::json!(true),
+                            value: serde_json::json!(kube_status.started.unwrap_or(true)),
                         }),
                     ]
                 }
@@ -162,9 +205,10 @@ pub async fn patch_container_status(
             let patch = json_patch::Patch(patches);
             let params = kube::api::PatchParams::default();
             debug!(?patch, "Patching container status");
-            client
+            let updated = client
                 .patch_status(pod.name(), &params, &kube::api::Patch::<()>::Json(patch))
                 .await?;
+            patch_readiness_conditions(client, &updated).await;
             Ok(())
         }
         None => {
@@ -177,6 +221,99 @@ pub async fn patch_container_status(
     }
 }
 
+/// Patch the restart count of a single container's status. This is a no-op if
+/// no status has been reported for the container yet.
+pub async fn patch_container_restart_count(
+    client: &kube::Api<KubePod>,
+    pod: &Pod,
+    key: &ContainerKey,
+    restart_count: i32,
+) -> anyhow::Result<()> {
+    replace_container_status_fields(
+        client,
+        pod,
+        key,
+        vec![("restartCount", serde_json::json!(restart_count))],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Patch whether a single container is ready, as determined by its readiness
+/// probe, and update the readiness conditions of the pod accordingly. This is
+/// a no-op if no status has been reported for the container yet.
+pub async fn patch_container_ready(
+    client: &kube::Api<KubePod>,
+    pod: &Pod,
+    key: &ContainerKey,
+    ready: bool,
+) -> anyhow::Result<()> {
+    if let Some(updated) =
+        replace_container_status_fields(client, pod, key, vec![("ready", serde_json::json!(ready))])
+            .await?
+    {
+        patch_readiness_conditions(client, &updated).await;
+    }
+    Ok(())
+}
+
+/// Patch whether a single container has started, as determined by its startup
+/// probe. This is a no-op if no status has been reported for the container yet.
+pub async fn patch_container_started(
+    client: &kube::Api<KubePod>,
+    pod: &Pod,
+    key: &ContainerKey,
+    started: bool,
+) -> anyhow::Result<()> {
+    replace_container_status_fields(
+        client,
+        pod,
+        key,
+        vec![("started", serde_json::json!(started))],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Replaces the given fields of a single container's status, returning the
+/// updated pod, or `None` if no status has been reported for the container yet.
+#[instrument(level = "info", skip(client, pod, key, fields), fields(pod_name = %pod.name(), namespace = %pod.namespace(), container_name = %key))]
+async fn replace_container_status_fields(
+    client: &kube::Api<KubePod>,
+    pod: &Pod,
+    key: &ContainerKey,
+    fields: Vec<(&str, serde_json::Value)>,
+) -> anyhow::Result<Option<KubePod>> {
+    let idx = match pod.container_status_index(key) {
+        Some(idx) => idx,
+        None => {
+            warn!("No container status to update");
+            return Ok(None);
+        }
+    };
+    let path_prefix = if key.is_init() {
+        format!("/status/initContainerStatuses/{}", idx)
+    } else {
+        format!("/status/containerStatuses/{}", idx)
+    };
+    let patches = fields
+        .into_iter()
+        .map(|(field, value)| {
+            json_patch::PatchOperation::Replace(json_patch::ReplaceOperation {
+                path: format!("{}/{}", path_prefix, field),
+                value,
+            })
+        })
+        .collect();
+    let patch = json_patch::Patch(patches);
+    let params = kube::api::PatchParams::default();
+    debug!(?patch, "Patching container status fields");
+    let updated = client
+        .patch_status(pod.name(), &params, &kube::api::Patch::<()>::Json(patch))
+        .await?;
+    Ok(Some(updated))
+}
+
 /// Create inital container status for registering pod.
 pub fn make_initial_container_status(container: &Container) -> KubeContainerStatus {
     let state = ContainerState {
@@ -0,0 +1,422 @@
+//! `event` records Kubernetes events about the pods and the node managed by
+//! the kubelet, so that they show up in `kubectl describe`.
+//!
+//! Like the upstream event broadcaster, the [`Recorder`] does not create a
+//! new event every time the same thing happens. Repeated events only bump the
+//! count of the event recorded first, and once an object has seen many
+//! similar events with different messages in a short time, they are combined
+//! into a single event.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use kube::api::{Api, Patch, PatchParams, PostParams};
+use tracing::{debug, warn};
+
+use crate::container::ContainerKey;
+use crate::pod::Pod;
+
+/// The component reported as the source of events.
+const COMPONENT: &str = "kubelet";
+/// Events about cluster scoped objects, like the node, live in this namespace.
+const DEFAULT_NAMESPACE: &str = "default";
+/// How many events with the same reason but different messages an object can
+/// see before they are combined.
+const MAX_SIMILAR_EVENTS: usize = 10;
+/// The prefix of the message of combined events.
+const AGGREGATED_PREFIX: &str = "(combined from similar events): ";
+/// How long an event is remembered after it last happened.
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+/// The most events remembered at once.
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// The type of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    /// Something expected happened.
+    Normal,
+    /// Something went wrong.
+    Warning,
+}
+
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventType::Normal => write!(f, "Normal"),
+            EventType::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// Records events about pods and the node. Clones share the same record of
+/// past events, so a single recorder should be used for everything.
+#[derive(Clone)]
+pub struct Recorder {
+    client: kube::Client,
+    node_name: String,
+    cache: Arc<Mutex<EventCache>>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("node_name", &self.node_name)
+            .finish()
+    }
+}
+
+impl Recorder {
+    /// Create a recorder for events reported by the kubelet of the given node.
+    pub fn new(client: kube::Client, node_name: &str) -> Self {
+        Recorder {
+            client,
+            node_name: node_name.to_owned(),
+            cache: Arc::new(Mutex::new(EventCache::default())),
+        }
+    }
+
+    /// Records an event about the given pod, or about one of its containers
+    /// if a container key is given.
+    pub async fn pod_event(
+        &self,
+        pod: &Pod,
+        container: Option<&ContainerKey>,
+        event_type: EventType,
+        reason: &str,
+        message: &str,
+    ) {
+        let field_path = container.map(|key| {
+            let field = if key.is_init() {
+                "initContainers"
+            } else {
+                "containers"
+            };
+            format!("spec.{}{{{}}}", field, key.name())
+        });
+        let object = ObjectReference {
+            api_version: Some("v1".to_owned()),
+            kind: Some("Pod".to_owned()),
+            name: Some(pod.name().to_owned()),
+            namespace: Some(pod.namespace().to_owned()),
+            uid: Some(pod.pod_uid().to_owned()),
+            field_path,
+            ..Default::default()
+        };
+        self.record(object, event_type, reason, message).await
+    }
+
+    /// Records an event about the node.
+    pub async fn node_event(&self, event_type: EventType, reason: &str, message: &str) {
+        // Like the upstream kubelet, refer to the node by name only, so that
+        // events can be recorded before the node exists
+        let object = ObjectReference {
+            kind: Some("Node".to_owned()),
+            name: Some(self.node_name.clone()),
+            uid: Some(self.node_name.clone()),
+            ..Default::default()
+        };
+        self.record(object, event_type, reason, message).await
+    }
+
+    async fn record(
+        &self,
+        object: ObjectReference,
+        event_type: EventType,
+        reason: &str,
+        message: &str,
+    ) {
+        let namespace = object
+            .namespace
+            .clone()
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_owned());
+        let key = EventKey::new(&object, event_type, reason);
+        let observed = self
+            .cache
+            .lock()
+            .unwrap()
+            .observe(&key, message, Instant::now());
+        let api: Api<Event> = Api::namespaced(self.client.clone(), &namespace);
+
+        if let Some((name, count)) = &observed.existing {
+            let patch = serde_json::json!({
+                "count": count,
+                "lastTimestamp": Time(Utc::now()),
+                "message": observed.message,
+            });
+            match api
+                .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+                .await
+            {
+                Ok(_) => return,
+                // The event may have been removed by the API server in the
+                // meantime, so record a new one
+                Err(e) => debug!(error = %e, event_name = %name, "Unable to update event"),
+            }
+        }
+
+        let event = new_event(
+            object,
+            &namespace,
+            event_type,
+            reason,
+            &observed.message,
+            &self.node_name,
+            Utc::now(),
+        );
+        let name = event.metadata.name.clone().unwrap_or_default();
+        match api.create(&PostParams::default(), &event).await {
+            Ok(_) => self.cache.lock().unwrap().remember(
+                key,
+                observed.dedup_message,
+                name,
+                Instant::now(),
+            ),
+            Err(e) => warn!(error = %e, %reason, "Unable to record event"),
+        }
+    }
+}
+
+fn new_event(
+    object: ObjectReference,
+    namespace: &str,
+    event_type: EventType,
+    reason: &str,
+    message: &str,
+    node_name: &str,
+    now: DateTime<Utc>,
+) -> Event {
+    let object_name = object.name.clone().unwrap_or_default();
+    Event {
+        metadata: ObjectMeta {
+            // Like the upstream kubelet, name events after the object and
+            // the time they were created at
+            name: Some(format!("{}.{:x}", object_name, now.timestamp_nanos())),
+            namespace: Some(namespace.to_owned()),
+            ..Default::default()
+        },
+        involved_object: object,
+        reason: Some(reason.to_owned()),
+        message: Some(message.to_owned()),
+        type_: Some(event_type.to_string()),
+        count: Some(1),
+        first_timestamp: Some(Time(now)),
+        last_timestamp: Some(Time(now)),
+        source: Some(EventSource {
+            component: Some(COMPONENT.to_owned()),
+            host: Some(node_name.to_owned()),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Identifies events that are similar: events about the same object (or part
+/// of it) for the same reason.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EventKey {
+    kind: Option<String>,
+    namespace: Option<String>,
+    name: Option<String>,
+    uid: Option<String>,
+    field_path: Option<String>,
+    event_type: EventType,
+    reason: String,
+}
+
+impl EventKey {
+    fn new(object: &ObjectReference, event_type: EventType, reason: &str) -> Self {
+        EventKey {
+            kind: object.kind.clone(),
+            namespace: object.namespace.clone(),
+            name: object.name.clone(),
+            uid: object.uid.clone(),
+            field_path: object.field_path.clone(),
+            event_type,
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+/// The outcome of observing an event.
+#[derive(Debug, PartialEq, Eq)]
+struct Observed {
+    /// The message to record
+    message: String,
+    /// The message identifying the recorded event among similar ones. All
+    /// combined events share the same one.
+    dedup_message: String,
+    /// The name and new count of the event this event repeats, if any
+    existing: Option<(String, i32)>,
+}
+
+struct Similar {
+    messages: HashSet<String>,
+    last_seen: Instant,
+}
+
+struct Recorded {
+    name: String,
+    count: i32,
+    last_seen: Instant,
+}
+
+/// Remembers recent events to combine and deduplicate them.
+#[derive(Default)]
+struct EventCache {
+    similar: HashMap<EventKey, Similar>,
+    recorded: HashMap<(EventKey, String), Recorded>,
+}
+
+impl EventCache {
+    fn observe(&mut self, key: &EventKey, message: &str, now: Instant) -> Observed {
+        self.expire(now);
+
+        let similar = self.similar.entry(key.clone()).or_insert_with(|| Similar {
+            messages: HashSet::new(),
+            last_seen: now,
+        });
+        similar.messages.insert(message.to_owned());
+        similar.last_seen = now;
+        let (message, dedup_message) = if similar.messages.len() >= MAX_SIMILAR_EVENTS {
+            (
+                format!("{}{}", AGGREGATED_PREFIX, message),
+                AGGREGATED_PREFIX.to_owned(),
+            )
+        } else {
+            (message.to_owned(), message.to_owned())
+        };
+
+        let existing = self
+            .recorded
+            .get_mut(&(key.clone(), dedup_message.clone()))
+            .map(|recorded| {
+                recorded.count += 1;
+                recorded.last_seen = now;
+                (recorded.name.clone(), recorded.count)
+            });
+        Observed {
+            message,
+            dedup_message,
+            existing,
+        }
+    }
+
+    fn remember(&mut self, key: EventKey, dedup_message: String, name: String, now: Instant) {
+        self.recorded.insert(
+            (key, dedup_message),
+            Recorded {
+                name,
+                count: 1,
+                last_seen: now,
+            },
+        );
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let fresh = |last_seen: Instant| now.saturating_duration_since(last_seen) < CACHE_TTL;
+        self.similar.retain(|_, s| fresh(s.last_seen));
+        self.recorded.retain(|_, r| fresh(r.last_seen));
+        // Rather forget some events than grow without bounds
+        if self.similar.len() + self.recorded.len() > MAX_CACHE_ENTRIES {
+            self.similar.clear();
+            self.recorded.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(reason: &str) -> EventKey {
+        let object = ObjectReference {
+            kind: Some("Pod".to_owned()),
+            name: Some("web".to_owned()),
+            namespace: Some("apps".to_owned()),
+            uid: Some("1234".to_owned()),
+            field_path: Some("spec.containers{app}".to_owned()),
+            ..Default::default()
+        };
+        EventKey::new(&object, EventType::Warning, reason)
+    }
+
+    #[test]
+    fn test_new_event() {
+        let object = ObjectReference {
+            kind: Some("Pod".to_owned()),
+            name: Some("web".to_owned()),
+            ..Default::default()
+        };
+        let event = new_event(
+            object,
+            "apps",
+            EventType::Warning,
+            "FailedPostStartHook",
+            "hook failed",
+            "krustlet",
+            Utc::now(),
+        );
+        assert!(event.metadata.name.unwrap().starts_with("web."));
+        assert_eq!(event.metadata.namespace.as_deref(), Some("apps"));
+        assert_eq!(event.type_.as_deref(), Some("Warning"));
+        assert_eq!(event.count, Some(1));
+        assert_eq!(event.source.unwrap().host.as_deref(), Some("krustlet"));
+    }
+
+    #[test]
+    fn test_deduplicate_events() {
+        let mut cache = EventCache::default();
+        let now = Instant::now();
+        let observed = cache.observe(&key("BackOff"), "back-off", now);
+        assert_eq!(observed.existing, None);
+        cache.remember(
+            key("BackOff"),
+            observed.dedup_message,
+            "web.1".to_owned(),
+            now,
+        );
+
+        let observed = cache.observe(&key("BackOff"), "back-off", now);
+        assert_eq!(observed.existing, Some(("web.1".to_owned(), 2)));
+        // Different reasons are different events
+        assert_eq!(
+            cache.observe(&key("Failed"), "back-off", now).existing,
+            None
+        );
+        // Events are forgotten after a while
+        let later = now + CACHE_TTL;
+        assert_eq!(
+            cache.observe(&key("BackOff"), "back-off", later).existing,
+            None
+        );
+    }
+
+    #[test]
+    fn test_aggregate_similar_events() {
+        let mut cache = EventCache::default();
+        let now = Instant::now();
+        for i in 0..MAX_SIMILAR_EVENTS - 1 {
+            let observed = cache.observe(&key("FailedMount"), &format!("volume {}", i), now);
+            assert_eq!(observed.message, format!("volume {}", i));
+        }
+
+        let observed = cache.observe(&key("FailedMount"), "volume 9", now);
+        assert_eq!(observed.message, "(combined from similar events): volume 9");
+        assert_eq!(observed.existing, None);
+        cache.remember(
+            key("FailedMount"),
+            observed.dedup_message,
+            "web.2".to_owned(),
+            now,
+        );
+
+        let observed = cache.observe(&key("FailedMount"), "volume 10", now);
+        assert_eq!(
+            observed.message,
+            "(combined from similar events): volume 10"
+        );
+        assert_eq!(observed.existing, Some(("web.2".to_owned(), 2)));
+    }
+}
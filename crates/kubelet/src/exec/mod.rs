@@ -0,0 +1,256 @@
+//! `exec` contains the types used to run commands inside of running workloads.
+//!
+//! An exec request is represented by a [`Session`], which carries the
+//! [`Options`] sent by the client along with the channels used to stream data
+//! between the client and the command. Providers receive a session through
+//! [`crate::provider::Provider::exec`] and generally hand it off to an
+//! [`crate::handle::ExecHandler`].
+use std::future::Future;
+use std::io::{Read, Write};
+use std::pin::Pin;
+
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// A future that runs an exec command to completion and resolves to its exit code.
+pub type ExecFuture = Pin<Box<dyn Future<Output = anyhow::Result<i32>> + Send + 'static>>;
+
+/// Client options for running a command in a container.
+///
+/// For more details on what the parameters mean please refer to
+/// https://kubernetes.io/docs/reference/generated/kubectl/kubectl-commands#exec
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    /// The command (and arguments) to run.
+    pub command: Vec<String>,
+    /// Whether the client will send data on stdin.
+    pub stdin: bool,
+    /// Whether the client wants to receive stdout.
+    pub stdout: bool,
+    /// Whether the client wants to receive stderr.
+    pub stderr: bool,
+    /// Whether the client requested a TTY.
+    pub tty: bool,
+}
+
+impl Options {
+    /// Parses exec options from a raw query string.
+    ///
+    /// The `command` parameter is repeated once per argument, so this cannot be
+    /// deserialized into a struct with the usual query helpers.
+    pub fn from_query(query: &str) -> anyhow::Result<Self> {
+        let mut opts = Options::default();
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "command" => opts.command.push(value.into_owned()),
+                "input" | "stdin" => opts.stdin = parse_bool(&value)?,
+                "output" | "stdout" => opts.stdout = parse_bool(&value)?,
+                "error" | "stderr" => opts.stderr = parse_bool(&value)?,
+                "tty" => opts.tty = parse_bool(&value)?,
+                _ => debug!(%key, "Ignoring unknown exec parameter"),
+            }
+        }
+        if opts.command.is_empty() {
+            anyhow::bail!("No command specified for exec");
+        }
+        Ok(opts)
+    }
+}
+
+fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "1" | "true" | "True" => Ok(true),
+        "0" | "false" | "False" | "" => Ok(false),
+        _ => Err(anyhow::anyhow!("invalid boolean value {:?}", value)),
+    }
+}
+
+/// The stream a chunk of exec output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamType {
+    /// Standard output.
+    Stdout,
+    /// Standard error.
+    Stderr,
+}
+
+/// A chunk of output produced by an exec command.
+#[derive(Debug)]
+pub struct Output {
+    /// The stream the data was written to.
+    pub stream: StreamType,
+    /// The data that was written.
+    pub data: Vec<u8>,
+}
+
+/// The client side of an exec request.
+///
+/// The IO adapters returned by [`Session::into_io`] block the current thread and
+/// should only be used from a blocking context (e.g. inside of
+/// [`tokio::task::spawn_blocking`]).
+pub struct Session {
+    opts: Options,
+    stdin: Option<mpsc::Receiver<Vec<u8>>>,
+    output: mpsc::Sender<Output>,
+}
+
+impl Session {
+    /// Create a new `Session`. `stdin` should be `None` if the client did not
+    /// request an input stream.
+    pub fn new(
+        opts: Options,
+        stdin: Option<mpsc::Receiver<Vec<u8>>>,
+        output: mpsc::Sender<Output>,
+    ) -> Self {
+        Session {
+            opts,
+            stdin,
+            output,
+        }
+    }
+
+    /// The command (and arguments) requested by the client.
+    pub fn command(&self) -> &[String] {
+        &self.opts.command
+    }
+
+    /// The tty flag indicated by the request.
+    pub fn tty(&self) -> bool {
+        self.opts.tty
+    }
+
+    /// Splits the session into blocking stdin, stdout and stderr adapters.
+    /// Output written to a stream the client did not request is discarded.
+    pub fn into_io(self) -> (Stdin, OutputWriter, OutputWriter) {
+        let stdout = OutputWriter {
+            stream: StreamType::Stdout,
+            sender: if self.opts.stdout {
+                Some(self.output.clone())
+            } else {
+                None
+            },
+        };
+        let stderr = OutputWriter {
+            stream: StreamType::Stderr,
+            sender: if self.opts.stderr {
+                Some(self.output)
+            } else {
+                None
+            },
+        };
+        let stdin = Stdin {
+            receiver: self.stdin,
+            buffer: Vec::new(),
+        };
+        (stdin, stdout, stderr)
+    }
+}
+
+/// A blocking reader over the stdin stream of an exec session.
+pub struct Stdin {
+    receiver: Option<mpsc::Receiver<Vec<u8>>>,
+    buffer: Vec<u8>,
+}
+
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.buffer.is_empty() {
+            match self.receiver.as_mut().and_then(|r| r.blocking_recv()) {
+                Some(data) => self.buffer = data,
+                // The client closed stdin or never requested it
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buffer.len());
+        buf[..len].copy_from_slice(&self.buffer[..len]);
+        self.buffer.drain(..len);
+        Ok(len)
+    }
+}
+
+/// A blocking writer that streams data back to the client of an exec session.
+pub struct OutputWriter {
+    stream: StreamType,
+    sender: Option<mpsc::Sender<Output>>,
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(sender) = self.sender.as_ref() {
+            let output = Output {
+                stream: self.stream,
+                data: buf.to_vec(),
+            };
+            sender.blocking_send(output).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "exec client has disconnected",
+                )
+            })?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_options_from_query() {
+        let opts = Options::from_query(
+            "command=ls&command=-l&command=%2Ftmp&input=1&output=1&error=0&tty=false",
+        )
+        .unwrap();
+        assert_eq!(
+            opts,
+            Options {
+                command: vec!["ls".to_owned(), "-l".to_owned(), "/tmp".to_owned()],
+                stdin: true,
+                stdout: true,
+                stderr: false,
+                tty: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_options_require_command() {
+        assert!(Options::from_query("output=1").is_err());
+        assert!(Options::from_query("command=ls&tty=maybe").is_err());
+    }
+
+    #[test]
+    fn test_session_io() {
+        let (stdin_tx, stdin_rx) = mpsc::channel(4);
+        let (output_tx, mut output_rx) = mpsc::channel(4);
+        let opts = Options {
+            command: vec!["cat".to_owned()],
+            stdin: true,
+            stdout: true,
+            stderr: false,
+            tty: false,
+        };
+        let (mut stdin, mut stdout, mut stderr) =
+            Session::new(opts, Some(stdin_rx), output_tx).into_io();
+
+        stdin_tx.blocking_send(b"hello".to_vec()).unwrap();
+        drop(stdin_tx);
+        let mut input = String::new();
+        stdin.read_to_string(&mut input).unwrap();
+        assert_eq!(input, "hello");
+
+        stdout.write_all(b"out").unwrap();
+        stderr.write_all(b"err").unwrap();
+        drop((stdout, stderr));
+
+        let output = output_rx.blocking_recv().unwrap();
+        assert_eq!(output.stream, StreamType::Stdout);
+        assert_eq!(output.data, b"out");
+        assert!(output_rx.blocking_recv().is_none());
+    }
+}
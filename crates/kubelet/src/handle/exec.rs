@@ -0,0 +1,12 @@
+use crate::exec::{ExecFuture, Session};
+
+/// An [`ExecHandler`] is used to run additional commands against a running process.
+pub trait ExecHandler {
+    /// Prepares the command requested by the given session to run in the context of the
+    /// running process.
+    ///
+    /// The returned future runs the command to completion and resolves to its exit code. It
+    /// must not borrow from the handler so that callers can release any locks guarding the
+    /// handler before awaiting it.
+    fn exec(&self, session: Session) -> anyhow::Result<ExecFuture>;
+}
@@ -2,7 +2,11 @@
 //!
 //! A collection of handle types for use in providers. These are entirely
 //! optional, but abstract away much of the logic around managing logging,
-//! status updates, and stopping pods
+//! status updates, stopping pods and running commands in them
+mod exec;
+mod stats;
 mod stopper;
 
+pub use exec::ExecHandler;
+pub use stats::StatsHandler;
 pub use stopper::StopHandler;
@@ -0,0 +1,7 @@
+use crate::stats::ContainerUsage;
+
+/// A [`StatsHandler`] reports the resources used by a running process.
+pub trait StatsHandler {
+    /// Returns the resources the process has used so far.
+    fn usage(&self) -> ContainerUsage;
+}
@@ -7,6 +7,14 @@ pub trait StopHandler {
     /// underlying handle to complete. Instead they should call wait() to wait for anything running
     /// to stop.
     async fn stop(&mut self) -> anyhow::Result<()>;
+    /// Asks anything running under the implementor to shut down on its own,
+    /// returning whether the request could be delivered. Callers give it
+    /// some time to exit before calling stop().
+    ///
+    /// The default implementation does not support graceful shutdown.
+    async fn shutdown(&mut self) -> anyhow::Result<bool> {
+        Ok(false)
+    }
     /// Wait for the implementor to stop anything it considers in the running state.
     async fn wait(&mut self) -> anyhow::Result<()>;
 }
@@ -1,20 +1,25 @@
 ///! This library contains code for running a kubelet. Use this to create a new
 ///! Kubelet with a specific handler (called a `Provider`)
 use crate::config::Config;
+use crate::event::{EventType, Recorder};
 use crate::node;
 use crate::operator::PodOperator;
 use crate::plugin_watcher::PluginRegistry;
-use crate::provider::{DevicePluginSupport, PluginSupport, Provider};
+use crate::provider::{AdmissionSupport, DevicePluginSupport, PluginSupport, Provider};
 use crate::resources::device_plugin_manager::{serve_device_registry, DeviceManager};
+use crate::resources::PodAdmitter;
 use crate::webserver::start as start_webserver;
 
 use futures::future::{FutureExt, TryFutureExt};
 use kube::api::ListParams;
 use std::convert::TryFrom;
+use std::future::Future;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::signal::ctrl_c;
+use tokio::sync::watch;
 use tokio::task;
+use tokio::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
 use krator::{ControllerBuilder, Manager};
@@ -35,8 +40,12 @@ pub struct Kubelet<P> {
     provider: Arc<P>,
     kube_config: kube::Config,
     config: Box<Config>,
+    reload: Option<ConfigReload>,
 }
 
+/// Reloads the Kubelet configuration, e.g. by reading the config file again.
+type ConfigReload = Arc<dyn Fn() -> anyhow::Result<Config> + Send + Sync>;
+
 impl<P: Provider> Kubelet<P> {
     /// Create a new Kubelet with a provider, a kubernetes configuration,
     /// and a kubelet configuration
@@ -51,9 +60,23 @@ impl<P: Provider> Kubelet<P> {
             // The config object can get a little bit for some reason, so put it
             // on the heap
             config: Box::new(config),
+            reload: None,
         })
     }
 
+    /// Reload the configuration with the given function whenever the Kubelet receives SIGHUP.
+    ///
+    /// Only settings that can safely be changed at runtime are applied: the node labels, the
+    /// insecure registries and the log level. The function is expected to apply the log level
+    /// itself, as logging is set up by the binary. Other settings require a restart.
+    pub fn with_config_reload<F>(mut self, reload: F) -> Self
+    where
+        F: Fn() -> anyhow::Result<Config> + Send + Sync + 'static,
+    {
+        self.reload = Some(Arc::new(reload));
+        self
+    }
+
     /// Begin answering requests for the Kubelet.
     ///
     /// This will listen on the given address, and will also begin watching for Pod
@@ -62,7 +85,8 @@ impl<P: Provider> Kubelet<P> {
         let client = kube::Client::try_from(self.kube_config.clone())?;
 
         // Create the node. If it already exists, this will exit
-        node::create(&client, &self.config, self.provider.clone()).await;
+        let recorder = Recorder::new(client.clone(), &self.config.node_name);
+        node::create(&client, &self.config, self.provider.clone(), &recorder).await;
 
         // Flag to indicate graceful shutdown has started.
         let signal = Arc::new(AtomicBool::new(false));
@@ -89,14 +113,41 @@ impl<P: Provider> Kubelet<P> {
         .boxed();
 
         // Start the webserver
-        let webserver = start_webserver(self.provider.clone(), &self.config.server_config)
+        let webserver = start_webserver(self.provider.clone(), &self.config)
             .fuse()
             .boxed();
 
-        // Start updating the node lease and status periodically
-        let node_updater = start_node_updater(client.clone(), self.config.node_name.clone())
-            .fuse()
-            .boxed();
+        // Watch the resources of the node and evict pods when it runs low on them
+        let (eviction_manager, pressure) = node::EvictionManager::new(
+            client.clone(),
+            self.provider.clone(),
+            &self.config,
+            recorder.clone(),
+        );
+        let eviction_manager = eviction_manager.run().fuse().boxed();
+
+        // Start updating the node lease and status periodically. Pods wait to be admitted while
+        // the node cannot be updated.
+        let pod_admitter = self.provider.provider_state().read().await.pod_admitter();
+        let node_updater = start_node_updater(
+            client.clone(),
+            &self.config,
+            pressure,
+            pod_admitter,
+            recorder.clone(),
+        )
+        .fuse()
+        .boxed();
+
+        // Reload the configuration on SIGHUP
+        let config_reloader = start_config_reloader(
+            client.clone(),
+            &self.config,
+            self.provider.clone(),
+            self.reload.clone(),
+        )
+        .fuse()
+        .boxed();
 
         // If any of these tasks fail, we can initiate graceful shutdown.
         let services = Box::pin(async {
@@ -108,6 +159,12 @@ impl<P: Provider> Kubelet<P> {
                 res = node_updater => if let Err(e) = res {
                     error!(error = %e, "Node updater task completed with error");
                 },
+                res = eviction_manager => if let Err(e) = res {
+                    error!(error = %e, "Eviction manager task completed with error");
+                },
+                res = config_reloader => if let Err(e) = res {
+                    error!(error = %e, "Config reloader task completed with error");
+                },
                 res = plugin_registrar => if let Err(e) = res {
                     error!(error = %e, "Plugin registrar task completed with error");
                 },
@@ -137,19 +194,26 @@ impl<P: Provider> Kubelet<P> {
 
         // These must all be running for graceful shutdown. An error here exits ungracefully.
         let core = Box::pin(async {
+            let mut operator_task = operator_task;
             tokio::select! {
-                res = signal_handler => match res {
-                    Ok(()) => self.provider.shutdown(&self.config.node_name).await,
-                    Err(e) => {
-                        error!(error = %e, "Signal handler task joined with error");
-                        Err(e)
-                    }
+                res = signal_handler => if let Err(e) = res {
+                    error!(error = %e, "Signal handler task joined with error");
+                    return Err(e);
                 },
-                _ = operator_task => {
+                _ = &mut operator_task => {
                     warn!("Pod operator has completed");
-                    Ok(())
+                    return Ok(());
                 }
             }
+            let shutdown = async {
+                let shutdown_manager =
+                    node::ShutdownManager::new(client.clone(), &self.config, recorder.clone());
+                if let Err(e) = shutdown_manager.shutdown().await {
+                    error!(error = %e, "Unable to shut down node gracefully");
+                }
+                self.provider.shutdown(&self.config.node_name).await
+            };
+            shutdown_with_operator(shutdown, operator_task).await
         });
 
         // Services will not return an error, so this will wait for both to return, or core to
@@ -168,18 +232,104 @@ impl<P> Clone for Kubelet<P> {
             provider: self.provider.clone(),
             kube_config: self.kube_config.clone(),
             config: self.config.clone(),
+            reload: self.reload.clone(),
+        }
+    }
+}
+
+/// Runs the shutdown of the node while keeping the pod operator running, as it
+/// is the operator that stops the containers of deleted pods and drives them to
+/// their terminated state. The operator is stopped once the shutdown completes.
+async fn shutdown_with_operator(
+    shutdown: impl Future<Output = anyhow::Result<()>>,
+    operator: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    tokio::pin!(shutdown);
+    tokio::select! {
+        res = &mut shutdown => res,
+        _ = operator => {
+            warn!("Pod operator has completed during shutdown");
+            shutdown.await
         }
     }
 }
 
-/// Awaits SIGINT and sets graceful shutdown flag if detected.
+/// Awaits SIGINT or SIGTERM and sets graceful shutdown flag if detected.
 async fn start_signal_task(signal: Arc<AtomicBool>) -> anyhow::Result<()> {
-    ctrl_c().await?;
-    warn!("Caught keyboard interrupt.");
+    #[cfg(target_family = "unix")]
+    {
+        use tokio::signal::unix::{signal as unix_signal, SignalKind};
+
+        let mut terminate = unix_signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = ctrl_c() => {
+                res?;
+                warn!("Caught keyboard interrupt.");
+            }
+            _ = terminate.recv() => warn!("Caught SIGTERM."),
+        }
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        ctrl_c().await?;
+        warn!("Caught keyboard interrupt.");
+    }
     signal.store(true, Ordering::Relaxed);
     Ok(())
 }
 
+/// Awaits SIGHUP and applies the runtime-safe settings of the reloaded configuration. Never
+/// completes if no way to reload the configuration was given.
+async fn start_config_reloader<P: Provider>(
+    client: kube::Client,
+    config: &Config,
+    provider: Arc<P>,
+    reload: Option<ConfigReload>,
+) -> anyhow::Result<()> {
+    let reload = match reload {
+        Some(reload) => reload,
+        None => return futures::future::pending().await,
+    };
+    #[cfg(target_family = "unix")]
+    {
+        use tokio::signal::unix::{signal as unix_signal, SignalKind};
+
+        let mut hangup = unix_signal(SignalKind::hangup())?;
+        let mut current = config.clone();
+        while hangup.recv().await.is_some() {
+            info!("Caught SIGHUP, reloading configuration.");
+            let reloaded = match reload() {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    error!(error = %e, "Unable to reload configuration");
+                    continue;
+                }
+            };
+            let applied = Config {
+                node_labels: reloaded.node_labels,
+                insecure_registries: reloaded.insecure_registries,
+                log_level: reloaded.log_level,
+                ..current.clone()
+            };
+            if let Err(e) = node::update_labels(&client, P::ARCH, &current, &applied).await {
+                error!(error = %e, "Unable to update node labels");
+            }
+            if let Err(e) = provider.reconfigure(&applied).await {
+                error!(error = %e, "Provider was unable to apply reloaded configuration");
+            }
+            current = applied;
+            info!("Reloaded configuration.");
+        }
+        Ok(())
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = (client, config, provider, reload);
+        warn!("Reloading the configuration on SIGHUP is only supported on Unix");
+        futures::future::pending().await
+    }
+}
+
 async fn start_plugin_registry(registrar: Option<Arc<PluginRegistry>>) -> anyhow::Result<()> {
     match registrar {
         Some(r) => r.run().await,
@@ -215,15 +365,93 @@ async fn start_device_manager(device_manager: Option<Arc<DeviceManager>>) -> any
     }
 }
 
+/// The longest time to wait before retrying a failed node update.
+const MAX_NODE_UPDATE_BACKOFF: Duration = Duration::from_secs(60);
+
 /// Periodically renew node lease and status. Exits if signal is caught.
-async fn start_node_updater(client: kube::Client, node_name: String) -> anyhow::Result<()> {
-    let sleep_interval = std::time::Duration::from_secs(10);
+///
+/// Failed updates never end the task. While the node cannot be updated it is considered NotReady
+/// locally, which holds new pods back from being admitted by the pod admitter, if any. Updates
+/// are retried with an exponential backoff until the API server can be reached again.
+async fn start_node_updater(
+    client: kube::Client,
+    config: &Config,
+    pressure: watch::Receiver<node::Pressure>,
+    pod_admitter: Option<Arc<PodAdmitter>>,
+    recorder: Recorder,
+) -> anyhow::Result<()> {
+    let node_name = &config.node_name;
+    let mut next_lease_renewal = Instant::now();
+    let mut next_status_update = Instant::now();
+    let mut ready = true;
+    let mut failures = 0;
     loop {
-        node::update(&client, &node_name).await;
-        tokio::time::sleep(sleep_interval).await;
+        tokio::time::sleep_until(next_lease_renewal.min(next_status_update)).await;
+        let now = Instant::now();
+        let retry_at = now + node_update_backoff(failures + 1);
+        let mut result = Ok(());
+        if now >= next_lease_renewal {
+            result = node::renew_lease(&client, node_name, config.node_lease_duration).await;
+            next_lease_renewal = match result {
+                Ok(()) => now + config.node_lease_renew_interval,
+                // The status is not updated before the lease could be renewed
+                Err(_) => {
+                    next_status_update = next_status_update.max(retry_at);
+                    retry_at
+                }
+            };
+        }
+        if result.is_ok() && now >= next_status_update {
+            let current_pressure = *pressure.borrow();
+            result = node::update_status(&client, node_name, &current_pressure).await;
+            next_status_update = match result {
+                Ok(()) => now + config.node_status_update_frequency,
+                Err(_) => retry_at,
+            };
+        }
+
+        match result {
+            Ok(()) => {
+                if !ready {
+                    info!(failures, "Node updates succeeded again, node is Ready");
+                    recorder
+                        .node_event(
+                            EventType::Normal,
+                            "NodeReady",
+                            &format!("Node {} status is now: NodeReady", node_name),
+                        )
+                        .await;
+                    ready = true;
+                    if let Some(pod_admitter) = pod_admitter.as_ref() {
+                        pod_admitter.set_node_ready(true);
+                    }
+                }
+                failures = 0;
+            }
+            Err(e) => {
+                failures += 1;
+                if ready {
+                    warn!(error = %e, "Unable to update node, marking node NotReady");
+                    ready = false;
+                    if let Some(pod_admitter) = pod_admitter.as_ref() {
+                        pod_admitter.set_node_ready(false);
+                    }
+                } else {
+                    warn!(error = %e, failures, "Unable to update node");
+                }
+            }
+        }
     }
 }
 
+/// Returns how long to wait before retrying after the given number of consecutive failed node
+/// updates. The wait starts at one second and doubles up to `MAX_NODE_UPDATE_BACKOFF`.
+fn node_update_backoff(failures: u32) -> Duration {
+    Duration::from_secs(1)
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_NODE_UPDATE_BACKOFF)
+}
+
 /// Checks for shutdown signal and cleans up resources gracefully.
 async fn start_signal_handler(signal: Arc<AtomicBool>) -> anyhow::Result<()> {
     let duration = std::time::Duration::from_millis(100);
@@ -284,6 +512,8 @@ mod test {
         }
     }
 
+    impl AdmissionSupport for ProviderState {}
+
     struct PodState;
 
     #[async_trait::async_trait]
@@ -424,7 +654,9 @@ mod test {
                 ..Default::default()
             }),
         });
-        let env = MockProvider::env_vars(&container, &pod, &mock_client()).await;
+        let env = MockProvider::env_vars(&container, &pod, &mock_client(), None)
+            .await
+            .unwrap();
 
         assert_eq!(
             "value",
@@ -449,4 +681,38 @@ mod test {
         assert_eq!("10.21.77.2", env.get("POD_IP").expect("pod_ip").as_str());
         assert_eq!("10.21.77.1", env.get("HOST_IP").expect("host_ip").as_str());
     }
+
+    #[tokio::test]
+    async fn test_deleted_pod_terminates_during_shutdown() {
+        // Stands in for the pod operator, which moves deleted pods to their
+        // terminated state and otherwise keeps running
+        let (deleted_tx, mut deleted_rx) = tokio::sync::mpsc::channel::<String>(1);
+        let (phase_tx, mut phase_rx) = watch::channel("Running");
+        let operator = async move {
+            while let Some(_pod_name) = deleted_rx.recv().await {
+                phase_tx.send("Terminated").unwrap();
+            }
+            futures::future::pending::<()>().await
+        };
+        // Deletes a pod and waits for it to terminate, like the shutdown
+        // manager does
+        let shutdown = async move {
+            deleted_tx.send("foo".to_owned()).await?;
+            tokio::time::timeout(Duration::from_secs(5), phase_rx.changed()).await??;
+            assert_eq!(*phase_rx.borrow(), "Terminated");
+            Ok(())
+        };
+
+        shutdown_with_operator(shutdown, operator)
+            .await
+            .expect("pod should terminate during shutdown");
+    }
+
+    #[test]
+    fn test_node_update_backoff() {
+        assert_eq!(node_update_backoff(1), Duration::from_secs(1));
+        assert_eq!(node_update_backoff(4), Duration::from_secs(8));
+        assert_eq!(node_update_backoff(7), MAX_NODE_UPDATE_BACKOFF);
+        assert_eq!(node_update_backoff(u32::MAX), MAX_NODE_UPDATE_BACKOFF);
+    }
 }
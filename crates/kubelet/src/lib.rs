@@ -11,7 +11,7 @@
 //! use kubelet::resources::DeviceManager;
 //! use kubelet::plugin_watcher::PluginRegistry;
 //! use kubelet::pod::Pod;
-//! use kubelet::provider::{DevicePluginSupport, Provider, PluginSupport};
+//! use kubelet::provider::{AdmissionSupport, DevicePluginSupport, Provider, PluginSupport};
 //! use std::sync::Arc;
 //! use tokio::sync::RwLock;
 //! use kubelet::pod::state::prelude::*;
@@ -65,6 +65,8 @@
 //!     }
 //! }
 //!
+//! impl AdmissionSupport for ProviderState {}
+//!
 //! async {
 //!     // Instantiate your provider type
 //!     let provider = MyProvider;
@@ -113,15 +115,21 @@ pub(crate) mod mio_uds_windows;
 pub mod backoff;
 pub mod config;
 pub mod container;
+pub mod event;
+pub mod exec;
 pub mod handle;
+pub mod lifecycle;
 pub mod log;
+pub mod metrics;
 pub mod node;
 pub mod plugin_watcher;
 pub mod pod;
+pub mod probe;
 pub mod provider;
 pub mod resources;
 pub mod secret;
 pub mod state;
+pub mod stats;
 pub mod store;
 pub mod volume;
 
@@ -0,0 +1,194 @@
+//! `lifecycle` implements the postStart and preStop hooks of containers.
+//!
+//! Hooks are configured in the `lifecycle` section of a container and run
+//! either an HTTP GET request against the container or a command in its
+//! context, using the provider's [`ExecHandler`].
+use std::time::Duration;
+
+use k8s_openapi::api::core::v1::Handler as KubeHandler;
+
+use crate::container::Container;
+use crate::handle::ExecHandler;
+use crate::probe::Handler;
+
+/// The kinds of lifecycle hooks that can be configured for a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookType {
+    /// Run right after the container was started.
+    PostStart,
+    /// Run before the container is stopped.
+    PreStop,
+}
+
+impl std::fmt::Display for HookType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookType::PostStart => write!(f, "PostStart"),
+            HookType::PreStop => write!(f, "PreStop"),
+        }
+    }
+}
+
+/// A lifecycle hook configured for a container.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    hook_type: HookType,
+    handler: Handler,
+}
+
+impl Hook {
+    /// Creates a hook from its Kubernetes definition. Hooks without a host are
+    /// run against `host`, and named ports are resolved from the ports of the
+    /// given container.
+    pub fn new(
+        hook_type: HookType,
+        handler: &KubeHandler,
+        container: &Container,
+        host: &str,
+    ) -> anyhow::Result<Self> {
+        let handler = Handler::new(
+            handler.exec.as_ref(),
+            handler.http_get.as_ref(),
+            handler.tcp_socket.as_ref(),
+            container,
+            host,
+        )
+        .map_err(|e| anyhow::anyhow!("{} hook: {:#}", hook_type, e))?;
+        Ok(Hook { hook_type, handler })
+    }
+
+    /// Returns the hook of the given type configured for the container, if
+    /// any.
+    pub fn for_container(
+        hook_type: HookType,
+        container: &Container,
+        host: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let lifecycle = match container.lifecycle() {
+            Some(lifecycle) => lifecycle,
+            None => return Ok(None),
+        };
+        let handler = match hook_type {
+            HookType::PostStart => lifecycle.post_start.as_ref(),
+            HookType::PreStop => lifecycle.pre_stop.as_ref(),
+        };
+        handler
+            .map(|h| Hook::new(hook_type, h, container, host))
+            .transpose()
+    }
+
+    /// The type of the hook.
+    pub fn hook_type(&self) -> HookType {
+        self.hook_type
+    }
+
+    /// Runs the hook once, failing if it does not complete within `timeout`.
+    /// Exec hooks fail if no `exec` handler is given.
+    pub async fn run(
+        &self,
+        timeout: Duration,
+        exec: Option<&(dyn ExecHandler + Send + Sync)>,
+    ) -> anyhow::Result<()> {
+        match tokio::time::timeout(timeout, self.handler.run("kube-lifecycle", timeout, exec)).await
+        {
+            Ok(result) => {
+                result.map_err(|e| anyhow::anyhow!("{} hook failed: {:#}", self.hook_type, e))
+            }
+            Err(_) => Err(anyhow::anyhow!(
+                "{} hook timed out after {}s",
+                self.hook_type,
+                timeout.as_secs()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::{
+        Container as KubeContainer, ExecAction, HTTPGetAction, Lifecycle,
+    };
+    use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+
+    fn container(lifecycle: Lifecycle) -> Container {
+        Container::new(&KubeContainer {
+            name: "app".to_owned(),
+            lifecycle: Some(lifecycle),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_hook_for_container() {
+        let container = container(Lifecycle {
+            pre_stop: Some(KubeHandler {
+                http_get: Some(HTTPGetAction {
+                    path: Some("/shutdown".to_owned()),
+                    port: IntOrString::Int(8080),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let hook = Hook::for_container(HookType::PreStop, &container, "10.0.0.1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(hook.hook_type(), HookType::PreStop);
+        match hook.handler {
+            Handler::HttpGet { url, .. } => {
+                assert_eq!(url.as_str(), "http://10.0.0.1:8080/shutdown")
+            }
+            h => panic!("unexpected handler {:?}", h),
+        }
+        assert!(
+            Hook::for_container(HookType::PostStart, &container, "10.0.0.1")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_hook_without_handler() {
+        let container = container(Lifecycle {
+            post_start: Some(KubeHandler {
+                exec: Some(ExecAction {
+                    command: Some(vec!["warm_up".to_owned()]),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let hook = Hook::for_container(HookType::PostStart, &container, "127.0.0.1")
+            .unwrap()
+            .unwrap();
+        let err = hook.run(Duration::from_secs(1), None).await.unwrap_err();
+        assert!(err.to_string().starts_with("PostStart hook failed"));
+    }
+
+    #[tokio::test]
+    async fn test_hook_timeout() {
+        // Connections are queued by the listener but never answered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let container = container(Lifecycle {
+            post_start: Some(KubeHandler {
+                http_get: Some(HTTPGetAction {
+                    port: IntOrString::Int(port as i32),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let hook = Hook::for_container(HookType::PostStart, &container, "127.0.0.1")
+            .unwrap()
+            .unwrap();
+        let err = hook
+            .run(Duration::from_millis(100), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("PostStart hook"));
+    }
+}
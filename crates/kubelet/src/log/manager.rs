@@ -0,0 +1,569 @@
+//! Persistent container logs, written in the CRI logging format.
+//!
+//! The output of every run of a container is kept in its own file, at
+//! `<data_dir>/pods/<namespace>_<pod>_<uid>/<container>/<restart count>.log`,
+//! so that log shippers can pick it up like the logs of any other kubelet.
+//! Once a file grows beyond the configured size it is rotated, and only the
+//! configured number of files is kept. The logs of a pod are removed once the
+//! pod is deleted.
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use chrono::Utc;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tracing::{debug, error, warn};
+
+use super::{format_timestamp, HandleFactory, Stream};
+use crate::config::Config;
+use crate::pod::Pod;
+
+const PODS_DIR: &str = "pods";
+const LOG_EXTENSION: &str = "log";
+/// Lines longer than this are split into several partial records, like the
+/// default maximum log line size of containerd.
+const MAX_LINE_SIZE: usize = 16 * 1024;
+
+/// Creates the log files for containers and decides when they are rotated.
+#[derive(Clone, Debug)]
+pub struct LogManager {
+    root: PathBuf,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl LogManager {
+    /// Create a `LogManager` keeping logs under the given data directory.
+    /// Files are rotated once they grow beyond `max_size` bytes, and at most
+    /// `max_files` files, including the one being written, are kept for every
+    /// run of a container.
+    pub fn new(data_dir: &Path, max_size: u64, max_files: usize) -> Self {
+        LogManager {
+            root: data_dir.join(PODS_DIR),
+            max_size,
+            max_files: max_files.max(1),
+        }
+    }
+
+    /// Create a `LogManager` using the data directory and log settings of the
+    /// given kubelet configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            &config.data_dir,
+            config.container_log_max_size,
+            config.container_log_max_files,
+        )
+    }
+
+    /// Returns the directory holding the logs of all containers of the given
+    /// pod.
+    pub fn pod_dir(&self, pod: &Pod) -> PathBuf {
+        self.root.join(format!(
+            "{}_{}_{}",
+            pod.namespace(),
+            pod.name(),
+            pod.pod_uid()
+        ))
+    }
+
+    /// Returns the directory holding the logs of a container of the given pod.
+    pub fn container_dir(&self, pod: &Pod, container_name: &str) -> PathBuf {
+        self.pod_dir(pod).join(container_name)
+    }
+
+    /// Removes the logs of all containers of the given pod, e.g. once the pod
+    /// has been deleted.
+    pub async fn remove_pod(&self, pod: &Pod) -> anyhow::Result<()> {
+        let dir = self.pod_dir(pod);
+        tokio::task::spawn_blocking(move || match std::fs::remove_dir_all(&dir) {
+            Ok(()) => {
+                debug!(path = %dir.display(), "Removed pod logs");
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::anyhow!(
+                "unable to remove pod logs {}: {}",
+                dir.display(),
+                e
+            )),
+        })
+        .await?
+    }
+
+    /// Opens the log of the given run of a container of the given pod. Logs
+    /// of runs before the previous one are removed, as they can no longer be
+    /// requested.
+    pub async fn open(
+        &self,
+        pod: &Pod,
+        container_name: &str,
+        restart_count: u32,
+    ) -> anyhow::Result<ContainerLog> {
+        let dir = self.container_dir(pod, container_name);
+        let max_size = self.max_size;
+        let max_files = self.max_files;
+        tokio::task::spawn_blocking(move || -> anyhow::Result<ContainerLog> {
+            std::fs::create_dir_all(&dir)?;
+            remove_old_runs(&dir, restart_count.saturating_sub(1));
+            let path = dir.join(format!("{}.{}", restart_count, LOG_EXTENSION));
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let size = file.metadata()?.len();
+            Ok(ContainerLog {
+                file: Arc::new(Mutex::new(LogFile {
+                    path,
+                    file,
+                    size,
+                    max_size,
+                    max_files,
+                    generation: 0,
+                    rotated: VecDeque::new(),
+                })),
+            })
+        })
+        .await?
+    }
+}
+
+/// Removes the log files of all runs before `oldest_kept`.
+fn remove_old_runs(dir: &Path, oldest_kept: u32) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(error = %e, path = %dir.display(), "Unable to list container logs");
+            return;
+        }
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name();
+        let run = name
+            .to_str()
+            .and_then(|name| name.split('.').next())
+            .and_then(|run| run.parse::<u32>().ok());
+        if matches!(run, Some(run) if run < oldest_kept) {
+            debug!(path = %entry.path().display(), "Removing log of old container run");
+            if let Err(e) = std::fs::remove_file(entry.path()) {
+                warn!(error = %e, path = %entry.path().display(), "Unable to remove container log");
+            }
+        }
+    }
+}
+
+/// The log of a single run of a container. Clones write to the same file.
+#[derive(Clone)]
+pub struct ContainerLog {
+    file: Arc<Mutex<LogFile>>,
+}
+
+impl ContainerLog {
+    /// Returns a writer that records everything written to it as output of
+    /// the given stream.
+    pub fn writer(&self, stream: Stream) -> LogWriter {
+        LogWriter {
+            log: self.clone(),
+            stream,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Returns a factory for readers of the file currently written to.
+    pub fn handle_factory(&self) -> LogHandleFactory {
+        LogHandleFactory {
+            file: self.file.clone(),
+        }
+    }
+
+    /// The size in bytes of the file currently written to.
+    pub fn size(&self) -> u64 {
+        self.file.lock().unwrap().size
+    }
+
+    fn write_record(&self, record: &[u8]) -> std::io::Result<()> {
+        self.file.lock().unwrap().write_record(record)
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+    /// How often the file was rotated
+    generation: u64,
+    /// The generations and paths of the files that were rotated and may still
+    /// exist, oldest first
+    rotated: VecDeque<(u64, PathBuf)>,
+}
+
+impl LogFile {
+    /// Returns the generation and path of the file that was written to after
+    /// the file of the given generation.
+    fn next_file(&self, generation: u64) -> (u64, PathBuf) {
+        self.rotated
+            .iter()
+            .find(|(rotated, _)| *rotated > generation)
+            .cloned()
+            .unwrap_or_else(|| (self.generation, self.path.clone()))
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> std::io::Result<()> {
+        if self.size > 0 && self.size + record.len() as u64 > self.max_size {
+            if let Err(e) = self.rotate() {
+                // Rather keep writing to a file that is too big than lose output
+                error!(error = %e, path = %self.path.display(), "Unable to rotate container log");
+            }
+        }
+        self.file.write_all(record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Moves the current file aside, suffixed with the time of rotation, and
+    /// removes the oldest rotated files beyond the configured number.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(Utc::now().format(".%Y%m%d-%H%M%S%.9f").to_string());
+        std::fs::rename(&self.path, &rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.rotated.push_back((self.generation, rotated.into()));
+        while self.rotated.len() >= self.max_files {
+            self.rotated.pop_front();
+        }
+        self.generation += 1;
+
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let prefix = format!(
+            "{}.",
+            self.path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        let mut rotated: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        // The suffixes sort in the order the files were rotated in
+        rotated.sort();
+        let excess = (rotated.len() + 1).saturating_sub(self.max_files);
+        for path in rotated.into_iter().take(excess) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes output of a container to its log, one CRI log line per line of
+/// output. Lines longer than `MAX_LINE_SIZE` are split into partial (`P`)
+/// records followed by a full (`F`) one, as the CRI format expects. A trailing
+/// line without a newline is recorded once the writer is dropped.
+pub struct LogWriter {
+    log: ContainerLog,
+    stream: Stream,
+    partial: Vec<u8>,
+}
+
+impl LogWriter {
+    fn write_line(&self, line: &[u8], partial: bool) -> std::io::Result<()> {
+        let tag = if partial { "P" } else { "F" };
+        let mut record =
+            format!("{} {} {} ", format_timestamp(Utc::now()), self.stream, tag).into_bytes();
+        record.extend_from_slice(line);
+        record.push(b'\n');
+        // Write each record at once so that readers never see half of one
+        self.log.write_record(&record)
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        loop {
+            let end = self
+                .partial
+                .iter()
+                .take(MAX_LINE_SIZE)
+                .position(|b| *b == b'\n');
+            match end {
+                Some(end) => {
+                    let line: Vec<u8> = self.partial.drain(..=end).collect();
+                    self.write_line(&line[..end], false)?;
+                }
+                None if self.partial.len() >= MAX_LINE_SIZE => {
+                    let line: Vec<u8> = self.partial.drain(..MAX_LINE_SIZE).collect();
+                    self.write_line(&line, true)?;
+                }
+                None => break,
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.log.file.lock().unwrap().file.flush()
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            if let Err(e) = self.write_line(&line, false) {
+                error!(error = %e, "Unable to write container log");
+            }
+        }
+    }
+}
+
+/// Opens readers of the file a container log is currently written to.
+pub struct LogHandleFactory {
+    file: Arc<Mutex<LogFile>>,
+}
+
+impl HandleFactory<LogReader> for LogHandleFactory {
+    /// Creates a `LogReader` on demand for log reading. Fails if the file was
+    /// removed, e.g. because the pod was cleaned up.
+    fn new_handle(&self) -> anyhow::Result<LogReader> {
+        let (path, generation) = {
+            let log = self.file.lock().unwrap();
+            (log.path.clone(), log.generation)
+        };
+        let file = File::open(&path).map_err(|e| {
+            anyhow::anyhow!("unable to open container log {}: {}", path.display(), e)
+        })?;
+        Ok(LogReader {
+            log: self.file.clone(),
+            file: tokio::fs::File::from_std(file),
+            generation,
+            read_generation: None,
+        })
+    }
+}
+
+/// Reads a container log. Once the end of a file that has been rotated in
+/// the meantime is reached, reading continues with the file written after it,
+/// so that following a log does not stop at its first rotation.
+pub struct LogReader {
+    log: Arc<Mutex<LogFile>>,
+    file: tokio::fs::File,
+    /// The generation of the log file being read
+    generation: u64,
+    /// The generation of the log at the start of the read in progress, if any
+    read_generation: Option<u64>,
+}
+
+impl AsyncRead for LogReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            // The generation has to be taken before reading, so that no
+            // output written before a rotation is skipped when reaching the
+            // end of the file
+            let generation = match self.read_generation {
+                Some(generation) => generation,
+                None => {
+                    let generation = self.log.lock().unwrap().generation;
+                    self.read_generation = Some(generation);
+                    generation
+                }
+            };
+            let filled = buf.filled().len();
+            let result = futures::ready!(Pin::new(&mut self.file).poll_read(cx, buf));
+            self.read_generation = None;
+            result?;
+            if buf.filled().len() > filled || buf.remaining() == 0 || generation == self.generation
+            {
+                return Poll::Ready(Ok(()));
+            }
+            let (next, path) = self.log.lock().unwrap().next_file(self.generation);
+            match File::open(path) {
+                Ok(file) => self.file = tokio::fs::File::from_std(file),
+                // Rotated files beyond the configured number are removed, in
+                // which case reading continues with the one after it
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && next < generation => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            self.generation = next;
+        }
+    }
+}
+
+impl AsyncSeek for LogReader {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.file).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.file).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::log::Record;
+
+    fn pod() -> Pod {
+        let pod: k8s_openapi::api::core::v1::Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": "web",
+                "namespace": "default",
+                "uid": "1234"
+            }
+        }))
+        .unwrap();
+        Pod::from(pod)
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn test_write_log() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let manager = LogManager::new(data_dir.path(), 1024, 2);
+        let log = manager.open(&pod(), "app", 0).await.unwrap();
+        {
+            let mut stdout = log.writer(Stream::Stdout);
+            let mut stderr = log.writer(Stream::Stderr);
+            stdout.write_all(b"first\nsec").unwrap();
+            stderr.write_all(b"oops\n").unwrap();
+            stdout.write_all(b"ond\nunterminated").unwrap();
+        }
+
+        let path = data_dir.path().join("pods/default_web_1234/app/0.log");
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(log.size(), content.len() as u64);
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with(" stderr F oops"));
+        let records: Vec<Record> = lines
+            .into_iter()
+            .map(|l| Record::parse(l.to_owned()))
+            .collect();
+        let messages: Vec<&str> = records.iter().map(|r| r.message.as_str()).collect();
+        assert_eq!(messages, vec!["first", "oops", "second", "unterminated"]);
+        assert!(records.iter().all(|r| r.timestamp.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_rotate_log() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let manager = LogManager::new(data_dir.path(), 100, 3);
+        let log = manager.open(&pod(), "app", 0).await.unwrap();
+        let mut writer = log.writer(Stream::Stdout);
+        for _ in 0..10 {
+            // Each record is about 80 bytes, so every one goes to a new file
+            writer.write_all(&b"x".repeat(40)).unwrap();
+            writer.write_all(b"\n").unwrap();
+        }
+
+        let dir = manager.container_dir(&pod(), "app");
+        let files = files(&dir);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0], "0.log");
+        assert!(files[1].starts_with("0.log."));
+        assert!(files[2].starts_with("0.log."));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("0.log")).unwrap().len() as u64,
+            log.size()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_across_rotation() {
+        use tokio::io::AsyncReadExt;
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let manager = LogManager::new(data_dir.path(), 100, 3);
+        let log = manager.open(&pod(), "app", 0).await.unwrap();
+        let mut writer = log.writer(Stream::Stdout);
+        writer.write_all(b"first\n").unwrap();
+        let mut reader = log.handle_factory().new_handle().unwrap();
+
+        // Records of more than 50 bytes rotate the file, so the reader has to
+        // continue with two files written after it was opened
+        writer.write_all(&b"x".repeat(40)).unwrap();
+        writer.write_all(b"\nsecond\n").unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await.unwrap();
+        let messages: Vec<String> = content
+            .lines()
+            .map(|l| Record::parse(l.to_owned()).message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["first".to_owned(), "x".repeat(40), "second".to_owned()]
+        );
+
+        std::fs::remove_dir_all(manager.container_dir(&pod(), "app")).unwrap();
+        assert!(log.handle_factory().new_handle().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_split_long_lines() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let manager = LogManager::new(data_dir.path(), 1024 * 1024, 2);
+        let log = manager.open(&pod(), "app", 0).await.unwrap();
+        {
+            let mut writer = log.writer(Stream::Stdout);
+            writer.write_all(&b"x".repeat(MAX_LINE_SIZE + 10)).unwrap();
+            writer.write_all(b"\nshort\n").unwrap();
+        }
+
+        let path = manager.container_dir(&pod(), "app").join("0.log");
+        let content = std::fs::read_to_string(path).unwrap();
+        let records: Vec<Record> = content
+            .lines()
+            .map(|l| Record::parse(l.to_owned()))
+            .collect();
+        let parts: Vec<(usize, bool)> = records
+            .iter()
+            .map(|r| (r.message.len(), r.partial))
+            .collect();
+        assert_eq!(parts, vec![(MAX_LINE_SIZE, true), (10, false), (5, false)]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_pod() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let manager = LogManager::new(data_dir.path(), 1024, 2);
+        let log = manager.open(&pod(), "app", 0).await.unwrap();
+        log.writer(Stream::Stdout).write_all(b"hello\n").unwrap();
+        assert!(manager.pod_dir(&pod()).exists());
+
+        manager.remove_pod(&pod()).await.unwrap();
+        assert!(!manager.pod_dir(&pod()).exists());
+        // Removing the logs of a pod without any is not an error
+        manager.remove_pod(&pod()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove_old_runs() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let manager = LogManager::new(data_dir.path(), 1024, 2);
+        for restart_count in 0..3 {
+            let log = manager.open(&pod(), "app", restart_count).await.unwrap();
+            log.writer(Stream::Stdout).write_all(b"hello\n").unwrap();
+        }
+        assert_eq!(
+            files(&manager.container_dir(&pod(), "app")),
+            vec!["1.log", "2.log"]
+        );
+    }
+}
@@ -1,11 +1,15 @@
 //! `log` contains convenient wrappers around fetching logs from the Kubernetes API.
 use anyhow::bail;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use serde::Deserialize;
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncRead};
 use tracing::{debug, error};
 
+mod manager;
+
+pub use manager::{ContainerLog, LogHandleFactory, LogManager, LogReader, LogWriter};
+
 /// Possible errors sending log data.
 #[derive(Debug)]
 pub enum SendError {
@@ -39,7 +43,7 @@ impl std::error::Error for SendError {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 /// Client options for fetching logs.
 /// For more details on what the parameters mean please refer to
 /// https://kubernetes.io/docs/reference/generated/kubectl/kubectl-commands#logs
@@ -50,7 +54,7 @@ pub struct Options {
     /// determines whether the stream should stay open after tailing until the channel has closed.
     #[serde(default)]
     pub follow: bool,
-    /// determines whether the stream should stay open after tailing until the channel has closed.
+    /// determines whether to return the logs of the previous, terminated instance of the container.
     #[serde(default)]
     pub previous: bool,
     /// determines whether the returned log messages should include a timestamp or just the message
@@ -59,24 +63,57 @@ pub struct Options {
     /// specifies how far back logs should be retrieved in seconds
     #[serde(rename = "sinceSeconds")]
     pub since: Option<u64>,
-    /// specifies a point in time up to which logs should be retrieved
+    /// specifies a point in time from which logs should be retrieved
     #[serde(rename = "sinceTime")]
     pub since_time: Option<DateTime<Utc>>,
     /// specifies a size limit of how many logs should be returned in bytes
     #[serde(rename = "limitBytes")]
     pub limit_bytes: Option<u64>,
+    /// specifies the output stream to return logs of, or both if absent
+    #[serde(default, deserialize_with = "deserialize_stream")]
+    pub stream: Option<Stream>,
+}
+
+/// Deserializes the stream option, where `All` selects both streams.
+fn deserialize_stream<'de, D>(d: D) -> Result<Option<Stream>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let stream = String::deserialize(d)?;
+    if stream.eq_ignore_ascii_case("all") {
+        return Ok(None);
+    }
+    stream.parse().map(Some).map_err(serde::de::Error::custom)
 }
 
 /// Sender for streaming logs to client.
 pub struct Sender {
     sender: hyper::body::Sender,
     opts: Options,
+    /// Records written before this point in time are skipped
+    cutoff: Option<DateTime<Utc>>,
+    /// The number of bytes that may still be sent, if limited
+    remaining_bytes: Option<u64>,
+    /// Whether the last record sent was partial, so that the next one
+    /// continues its line
+    in_line: bool,
 }
 
 impl Sender {
     /// Create new `Sender` from `hyper::body::Sender`.
     pub fn new(sender: hyper::body::Sender, opts: Options) -> Self {
-        Sender { sender, opts }
+        let cutoff = opts.since_time.or_else(|| {
+            opts.since
+                .map(|since| Utc::now() - chrono::Duration::seconds(since as i64))
+        });
+        let remaining_bytes = opts.limit_bytes;
+        Sender {
+            sender,
+            opts,
+            cutoff,
+            remaining_bytes,
+            in_line: false,
+        }
     }
 
     /// The tail flag indicated by the request if present.
@@ -114,9 +151,29 @@ impl Sender {
         self.opts.limit_bytes
     }
 
-    /// Async send some data to a client.
+    /// The output stream indicated by the request, or `None` if both streams
+    /// were requested.
+    pub fn stream(&self) -> Option<Stream> {
+        self.opts.stream
+    }
+
+    /// Whether as many bytes as the request allows have been sent already.
+    pub fn limit_reached(&self) -> bool {
+        self.remaining_bytes == Some(0)
+    }
+
+    /// Async send some data to a client. Data beyond the limit_bytes
+    /// indicated by the request is dropped.
     pub async fn send(&mut self, data: String) -> Result<(), SendError> {
-        let b: hyper::body::Bytes = data.into();
+        let mut b: hyper::body::Bytes = data.into();
+        if let Some(remaining) = self.remaining_bytes.as_mut() {
+            let len = (b.len() as u64).min(*remaining);
+            b.truncate(len as usize);
+            *remaining -= len;
+        }
+        if b.is_empty() {
+            return Ok(());
+        }
         self.sender.send_data(b).await.map_err(|e| {
             if e.is_closed() {
                 debug!("channel closed");
@@ -127,35 +184,167 @@ impl Sender {
             }
         })
     }
+
+    /// Whether the record was requested, according to the since, since_time
+    /// and stream options. Records without a timestamp or stream are always
+    /// sent.
+    fn wants(&self, record: &Record) -> bool {
+        let in_time = match (self.cutoff, record.timestamp) {
+            (Some(cutoff), Some(timestamp)) => timestamp >= cutoff,
+            _ => true,
+        };
+        let in_stream = match (self.stream(), record.stream) {
+            (Some(wanted), Some(stream)) => wanted == stream,
+            _ => true,
+        };
+        in_time && in_stream
+    }
+
+    /// Send a record, with its timestamp if requested. Partial records are
+    /// sent without a trailing newline, so that the line is joined with the
+    /// records that follow it.
+    async fn send_record(&mut self, record: Record) -> Result<(), SendError> {
+        let newline = if record.partial { "" } else { "\n" };
+        let line = match record.timestamp {
+            Some(timestamp) if self.timestamps() && !self.in_line => format!(
+                "{} {}{}",
+                format_timestamp(timestamp),
+                record.message,
+                newline
+            ),
+            _ => format!("{}{}", record.message, newline),
+        };
+        self.in_line = record.partial;
+        self.send(line).await
+    }
 }
 
-/// Stream last `n` lines.
-async fn tail<R: AsyncRead + std::marker::Unpin>(
+/// The output stream of a container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr,
+}
+
+impl std::str::FromStr for Stream {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The CRI log format uses lower case names, the Kubernetes API
+        // capitalized ones
+        if s.eq_ignore_ascii_case("stdout") {
+            Ok(Stream::Stdout)
+        } else if s.eq_ignore_ascii_case("stderr") {
+            Ok(Stream::Stderr)
+        } else {
+            Err(anyhow::anyhow!(
+                "unknown stream {:?}, expected one of stdout or stderr",
+                s
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// A line of container output, along with when it was written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// When the line was written, if known
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The stream the line was written to, if known
+    pub stream: Option<Stream>,
+    /// The line, without its trailing newline
+    pub message: String,
+    /// Whether the line continues in the next record, because it was split
+    /// for being too long
+    pub partial: bool,
+}
+
+impl Record {
+    /// Parses a line in the CRI log format, `<timestamp> <stream> <tag>
+    /// <message>`, as written by a [`LogWriter`]. Lines in any other format
+    /// are returned as they are.
+    pub fn parse(line: String) -> Self {
+        let mut parts = line.splitn(4, ' ');
+        if let (Some(timestamp), Some(stream), Some(tag), Some(message)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            if let (Ok(timestamp), Ok(stream)) =
+                (DateTime::parse_from_rfc3339(timestamp), stream.parse())
+            {
+                return Record {
+                    timestamp: Some(timestamp.with_timezone(&Utc)),
+                    stream: Some(stream),
+                    message: message.to_owned(),
+                    partial: tag == "P",
+                };
+            }
+        }
+        Record {
+            timestamp: None,
+            stream: None,
+            message: line,
+            partial: false,
+        }
+    }
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+/// Read the next record from the log.
+async fn next_record<R: AsyncRead + std::marker::Unpin>(
     lines: &mut tokio::io::Lines<tokio::io::BufReader<R>>,
     sender: &mut Sender,
-    n: usize,
-) -> Result<(), SendError> {
-    let mut line_buf = std::collections::VecDeque::with_capacity(n);
-
-    while let Some(line) = match lines.next_line().await {
-        Ok(line) => line,
+) -> Result<Option<Record>, SendError> {
+    match lines.next_line().await {
+        Ok(line) => Ok(line.map(Record::parse)),
         Err(e) => {
             error!(error = %e, "Error reading from log");
             sender
                 .send(format!("Error reading from log: {:?}", e))
                 .await?;
-            return Err(e.into());
+            Err(e.into())
         }
-    } {
-        if line_buf.len() == n {
-            line_buf.pop_front();
+    }
+}
+
+/// Stream last `n` records.
+async fn tail<R: AsyncRead + std::marker::Unpin>(
+    lines: &mut tokio::io::Lines<tokio::io::BufReader<R>>,
+    sender: &mut Sender,
+    n: usize,
+) -> Result<(), SendError> {
+    let mut record_buf = std::collections::VecDeque::with_capacity(n);
+
+    while let Some(record) = next_record(lines, sender).await? {
+        if !sender.wants(&record) {
+            continue;
+        }
+        if record_buf.len() == n {
+            record_buf.pop_front();
+        }
+        if n > 0 {
+            record_buf.push_back(record);
         }
-        line_buf.push_back(line);
     }
 
-    for mut line in line_buf {
-        line.push('\n');
-        sender.send(line).await?;
+    for record in record_buf {
+        if sender.limit_reached() {
+            break;
+        }
+        sender.send_record(record).await?;
     }
     Ok(())
 }
@@ -165,23 +354,22 @@ async fn stream_to_end<R: AsyncRead + std::marker::Unpin>(
     lines: &mut tokio::io::Lines<tokio::io::BufReader<R>>,
     sender: &mut Sender,
 ) -> Result<(), SendError> {
-    while let Some(mut line) = match lines.next_line().await {
-        Ok(line) => line,
-        Err(e) => {
-            error!(error = %e, "Error reading from log");
-            sender
-                .send(format!("Error reading from log: {:?}", e))
-                .await?;
-            return Err(e.into());
+    while let Some(record) = next_record(lines, sender).await? {
+        if sender.limit_reached() {
+            break;
+        }
+        if sender.wants(&record) {
+            sender.send_record(record).await?;
         }
-    } {
-        line.push('\n');
-        sender.send(line).await?;
     }
     Ok(())
 }
 
 /// Future that streams logs from provided `AsyncRead` to provided `Sender`.
+///
+/// The log is expected to consist of records written by a [`LogWriter`],
+/// which are filtered and formatted according to the
+/// options of the sender.
 pub async fn stream<R: AsyncRead + std::marker::Unpin>(
     handle: R,
     mut sender: Sender,
@@ -204,7 +392,7 @@ pub async fn stream<R: AsyncRead + std::marker::Unpin>(
     }
 
     if sender.follow() {
-        loop {
+        while !sender.limit_reached() {
             match stream_to_end(&mut lines, &mut sender).await {
                 Ok(_) => (),
                 Err(SendError::ChannelClosed) => return Ok(()),
@@ -218,11 +406,151 @@ pub async fn stream<R: AsyncRead + std::marker::Unpin>(
     Ok(())
 }
 
-// TODO: Both providers make a handle containing a tempfile. If this is a common pattern,
-// it might make sense to provide that implementation here. This would add `tempfile` as a
-// dependency of `kubelet`.
 /// Trait to describe necessary behavior for creating multiple log readers.
 pub trait HandleFactory<R>: Sync + Send {
-    /// Create new log reader.
-    fn new_handle(&self) -> R;
+    /// Create new log reader. Fails if the log can no longer be read.
+    fn new_handle(&self) -> anyhow::Result<R>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use hyper::Body;
+
+    async fn read(log: &[u8], opts: Options) -> String {
+        let (sender, body) = Body::channel();
+        let (result, bytes) = tokio::join!(
+            stream(log, Sender::new(sender, opts)),
+            hyper::body::to_bytes(body)
+        );
+        result.unwrap();
+        let bytes = bytes.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let record = Record::parse("2021-01-01T00:00:01.5Z stdout F hello world".to_owned());
+        assert_eq!(
+            record.timestamp,
+            Some(Utc.ymd(2021, 1, 1).and_hms_milli(0, 0, 1, 500))
+        );
+        assert_eq!(record.stream, Some(Stream::Stdout));
+        assert_eq!(record.message, "hello world");
+
+        let record = Record::parse("no timestamp here".to_owned());
+        assert_eq!(record.timestamp, None);
+        assert_eq!(record.stream, None);
+        assert_eq!(record.message, "no timestamp here");
+    }
+
+    #[test]
+    fn test_deserialize_options() {
+        let opts: Options = serde_json::from_str(r#"{"stream": "Stderr"}"#).unwrap();
+        assert_eq!(opts.stream, Some(Stream::Stderr));
+        let opts: Options = serde_json::from_str(r#"{"stream": "All"}"#).unwrap();
+        assert_eq!(opts.stream, None);
+        let opts: Options = serde_json::from_str("{}").unwrap();
+        assert_eq!(opts.stream, None);
+        assert!(serde_json::from_str::<Options>(r#"{"stream": "stdin"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_join_partial_records() {
+        let log = b"2021-01-01T00:00:00Z stdout P one \n\
+            2021-01-01T00:00:01Z stdout F line\n\
+            2021-01-01T00:00:02Z stdout F two\n";
+
+        assert_eq!(read(log, Options::default()).await, "one line\ntwo\n");
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    timestamps: true,
+                    ..Default::default()
+                }
+            )
+            .await,
+            "2021-01-01T00:00:00.000000000Z one line\n2021-01-01T00:00:02.000000000Z two\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_options() {
+        let log = b"2021-01-01T00:00:00Z stdout F one\n\
+            2021-01-01T00:01:00Z stdout F two\n\
+            2021-01-01T00:02:00Z stderr F three\n";
+
+        assert_eq!(read(log, Options::default()).await, "one\ntwo\nthree\n");
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    tail: Some(1),
+                    timestamps: true,
+                    ..Default::default()
+                }
+            )
+            .await,
+            "2021-01-01T00:02:00.000000000Z three\n"
+        );
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    since_time: Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 30)),
+                    ..Default::default()
+                }
+            )
+            .await,
+            "two\nthree\n"
+        );
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    since: Some(60),
+                    ..Default::default()
+                }
+            )
+            .await,
+            ""
+        );
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    limit_bytes: Some(6),
+                    follow: true,
+                    ..Default::default()
+                }
+            )
+            .await,
+            "one\ntw"
+        );
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    stream: Some(Stream::Stderr),
+                    ..Default::default()
+                }
+            )
+            .await,
+            "three\n"
+        );
+        assert_eq!(
+            read(
+                log,
+                Options {
+                    tail: Some(1),
+                    stream: Some(Stream::Stdout),
+                    ..Default::default()
+                }
+            )
+            .await,
+            "two\n"
+        );
+    }
 }
@@ -0,0 +1,171 @@
+//! Prometheus metrics describing what the kubelet is doing, served on `/metrics`.
+//!
+//! All metrics are registered in a registry of their own, prefixed with
+//! `krustlet_`. Providers can record their own measurements in the public
+//! metrics, e.g. [`MODULE_COMPILE_DURATION`].
+use std::future::Future;
+use std::time::Instant;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Registry =
+        Registry::new_custom(Some("krustlet".to_owned()), None).unwrap();
+
+    /// Transitions between the states of pod state machines, labelled with the
+    /// state left and the state entered. Completed state machines enter `Complete`.
+    pub static ref POD_STATE_TRANSITIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "pod_state_transitions_total",
+            "Number of transitions between pod states"
+        ),
+        &["from", "to"]
+    ));
+
+    /// How long pulling images from a registry took, labelled with whether
+    /// the pull succeeded.
+    pub static ref IMAGE_PULL_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "image_pull_duration_seconds",
+            "Time taken to pull images from registries"
+        )
+        .buckets(exponential_buckets(0.1, 2.0, 12).unwrap()),
+        &["result"]
+    ));
+
+    /// The number of bytes of image layers pulled from registries.
+    pub static ref IMAGE_PULL_BYTES: IntCounter = register(IntCounter::new(
+        "image_pull_bytes_total",
+        "Number of bytes of image layers pulled from registries"
+    ));
+
+    /// Lookups of images in the local image store, labelled with whether the
+    /// image was already present (`hit`) or had to be pulled (`miss`).
+    pub static ref IMAGE_CACHE_LOOKUPS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "image_cache_lookups_total",
+            "Number of lookups of images in the local image store"
+        ),
+        &["result"]
+    ));
+
+    /// How long compiling wasm modules took.
+    pub static ref MODULE_COMPILE_DURATION: Histogram = register(Histogram::with_opts(
+        HistogramOpts::new(
+            "module_compile_duration_seconds",
+            "Time taken to compile wasm modules"
+        )
+        .buckets(exponential_buckets(0.01, 2.0, 12).unwrap())
+    ));
+
+    /// How long updating the node lease took, labelled with whether the
+    /// update succeeded.
+    pub static ref NODE_LEASE_UPDATE_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "node_lease_update_duration_seconds",
+            "Time taken to update the node lease"
+        ),
+        &["result"]
+    ));
+
+    /// Device allocations requested from device plugins, labelled with the
+    /// resource and whether the allocation succeeded.
+    pub static ref DEVICE_PLUGIN_ALLOCATIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "device_plugin_allocations_total",
+            "Number of device allocations requested from device plugins"
+        ),
+        &["resource", "result"]
+    ));
+
+    /// How long calls to CSI plugins took, labelled with the method called and
+    /// whether the call succeeded.
+    pub static ref CSI_OPERATION_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "csi_operation_duration_seconds",
+            "Time taken by calls to CSI plugins"
+        ),
+        &["method", "result"]
+    ));
+}
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.expect("metric definitions are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metrics are only registered once");
+    metric
+}
+
+/// Returns the label describing the outcome of an operation.
+pub(crate) fn result_label<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(_) => "error",
+    }
+}
+
+/// Awaits the future and records how long it took in `histogram`, under the
+/// given labels followed by the result label.
+pub(crate) async fn timed<T, E>(
+    histogram: &HistogramVec,
+    labels: &[&str],
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = future.await;
+    let mut labels = labels.to_vec();
+    labels.push(result_label(&result));
+    histogram
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+/// Encodes all metrics in the Prometheus text format.
+pub(crate) fn encode() -> anyhow::Result<(String, Vec<u8>)> {
+    // Metrics are only registered once first used, make sure that all of them
+    // show up even if nothing was recorded yet
+    lazy_static::initialize(&POD_STATE_TRANSITIONS);
+    lazy_static::initialize(&IMAGE_PULL_DURATION);
+    lazy_static::initialize(&IMAGE_PULL_BYTES);
+    lazy_static::initialize(&IMAGE_CACHE_LOOKUPS);
+    lazy_static::initialize(&MODULE_COMPILE_DURATION);
+    lazy_static::initialize(&NODE_LEASE_UPDATE_DURATION);
+    lazy_static::initialize(&DEVICE_PLUGIN_ALLOCATIONS);
+    lazy_static::initialize(&CSI_OPERATION_DURATION);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok((encoder.format_type().to_owned(), buffer))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_encode() {
+        POD_STATE_TRANSITIONS
+            .with_label_values(&["Registered", "ImagePull"])
+            .inc();
+        let result: Result<(), ()> = timed(&CSI_OPERATION_DURATION, &["NodeStageVolume"], async {
+            Err(())
+        })
+        .await;
+        assert!(result.is_err());
+
+        let (content_type, body) = encode().unwrap();
+        assert!(content_type.starts_with("text/plain"));
+        let body = String::from_utf8(body).unwrap();
+        assert!(body
+            .contains(r#"krustlet_pod_state_transitions_total{from="Registered",to="ImagePull"}"#));
+        assert!(body.contains(
+            r#"krustlet_csi_operation_duration_seconds_count{method="NodeStageVolume",result="error"} 1"#
+        ));
+    }
+}
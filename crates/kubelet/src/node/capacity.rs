@@ -0,0 +1,213 @@
+//! Detection of the resources a node can offer to pods.
+use std::collections::HashMap;
+use std::path::Path;
+
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity as KubeQuantity;
+use tracing::warn;
+
+use crate::config::Config;
+use crate::resources::quantity::{Quantity, QuantityType};
+use crate::stats::system;
+
+/// The resources that are reserved for the system or kubelet, or that are
+/// available on a node. Any resource that could not be determined is `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Resources {
+    /// The number of (possibly fractional) CPU cores
+    pub cpu: Option<f64>,
+    /// The number of bytes of memory
+    pub memory: Option<u128>,
+    /// The number of bytes of disk space
+    pub ephemeral_storage: Option<u128>,
+}
+
+impl Resources {
+    /// Detects the capacity of the machine the kubelet is running on.
+    /// Ephemeral storage is the free space of the filesystem `data_dir` lives on.
+    pub(crate) fn detect(data_dir: &Path) -> Self {
+        let cpu = match std::thread::available_parallelism() {
+            Ok(n) => Some(n.get() as f64),
+            Err(e) => {
+                warn!(error = %e, "Unable to detect the number of CPUs");
+                None
+            }
+        };
+        let memory = match total_memory() {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!(error = %e, "Unable to detect the total amount of memory");
+                None
+            }
+        };
+        let ephemeral_storage = match free_disk_space(data_dir) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!(error = %e, path = %data_dir.display(), "Unable to detect free disk space");
+                None
+            }
+        };
+        Resources {
+            cpu,
+            memory,
+            ephemeral_storage,
+        }
+    }
+
+    /// Parses reserved resources as given in the kubelet configuration, e.g.
+    /// `{"cpu": "100m", "memory": "256Mi"}`.
+    pub(crate) fn parse_reserved(reserved: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut resources = Resources::default();
+        for (name, value) in reserved {
+            let quantity = KubeQuantity(value.clone());
+            match name.as_str() {
+                "cpu" => {
+                    if let Quantity::Cpu(cores) =
+                        Quantity::from_kube_quantity(QuantityType::Cpu(&quantity))?
+                    {
+                        resources.cpu = Some(cores);
+                    }
+                }
+                "memory" | "ephemeral-storage" => {
+                    if let Quantity::Memory(bytes) =
+                        Quantity::from_kube_quantity(QuantityType::Memory(&quantity))?
+                    {
+                        if name == "memory" {
+                            resources.memory = Some(bytes);
+                        } else {
+                            resources.ephemeral_storage = Some(bytes);
+                        }
+                    }
+                }
+                _ => anyhow::bail!(
+                    "unsupported reserved resource {:?}, expected one of cpu, memory or ephemeral-storage",
+                    name
+                ),
+            }
+        }
+        Ok(resources)
+    }
+
+    /// Parses the system and kube reserved resources of the configuration.
+    /// Invalid reservations are ignored with a warning.
+    pub(crate) fn reserved(config: &Config) -> Vec<Self> {
+        let mut reserved = Vec::new();
+        for (name, r) in [
+            ("system", &config.system_reserved),
+            ("kube", &config.kube_reserved),
+        ] {
+            match Resources::parse_reserved(r) {
+                Ok(resources) => reserved.push(resources),
+                Err(e) => warn!(error = %e, "Ignoring invalid {} reserved resources", name),
+            }
+        }
+        reserved
+    }
+
+    /// Returns the resources left after setting aside all of `reserved`.
+    /// Resources never drop below zero.
+    pub(crate) fn allocatable(&self, reserved: &[Resources]) -> Self {
+        let mut allocatable = self.clone();
+        for r in reserved {
+            allocatable.cpu = allocatable
+                .cpu
+                .map(|cpu| (cpu - r.cpu.unwrap_or_default()).max(0.0));
+            allocatable.memory = allocatable
+                .memory
+                .map(|memory| memory.saturating_sub(r.memory.unwrap_or_default()));
+            allocatable.ephemeral_storage = allocatable
+                .ephemeral_storage
+                .map(|storage| storage.saturating_sub(r.ephemeral_storage.unwrap_or_default()));
+        }
+        allocatable
+    }
+
+    /// Returns the resources as Kubernetes quantity strings, keyed by resource name.
+    pub(crate) fn to_quantities(&self) -> Vec<(&'static str, String)> {
+        let mut quantities = Vec::new();
+        if let Some(cpu) = self.cpu {
+            quantities.push(("cpu", format_cpu(cpu)));
+        }
+        if let Some(storage) = self.ephemeral_storage {
+            quantities.push(("ephemeral-storage", format!("{}Ki", storage / 1024)));
+        }
+        if let Some(memory) = self.memory {
+            quantities.push(("memory", format!("{}Ki", memory / 1024)));
+        }
+        quantities
+    }
+}
+
+fn format_cpu(cores: f64) -> String {
+    let millis = (cores * 1000.0).round() as u64;
+    if millis % 1000 == 0 {
+        (millis / 1000).to_string()
+    } else {
+        format!("{}m", millis)
+    }
+}
+
+fn total_memory() -> anyhow::Result<u128> {
+    system::meminfo()?
+        .get("MemTotal")
+        .map(|bytes| *bytes as u128)
+        .ok_or_else(|| anyhow::anyhow!("no MemTotal entry in /proc/meminfo"))
+}
+
+fn free_disk_space(path: &Path) -> anyhow::Result<u128> {
+    Ok(system::filesystem_usage(path)?.available as u128)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocatable() {
+        let capacity = Resources {
+            cpu: Some(4.0),
+            memory: Some(4 * 1024 * 1024 * 1024),
+            ephemeral_storage: Some(1024 * 1024),
+        };
+        let mut reserved = HashMap::new();
+        reserved.insert("cpu".to_owned(), "250m".to_owned());
+        reserved.insert("memory".to_owned(), "1Gi".to_owned());
+        reserved.insert("ephemeral-storage".to_owned(), "2Mi".to_owned());
+        let system = Resources::parse_reserved(&reserved).unwrap();
+        let kube = Resources {
+            cpu: Some(0.5),
+            ..Default::default()
+        };
+
+        let allocatable = capacity.allocatable(&[system, kube]);
+        assert_eq!(
+            allocatable.to_quantities(),
+            vec![
+                ("cpu", "3250m".to_owned()),
+                ("ephemeral-storage", "0Ki".to_owned()),
+                ("memory", "3145728Ki".to_owned()),
+            ]
+        );
+        assert_eq!(capacity.to_quantities()[0], ("cpu", "4".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_reserved_rejects_unknown_resources() {
+        let mut reserved = HashMap::new();
+        reserved.insert("pid".to_owned(), "100".to_owned());
+        assert!(Resources::parse_reserved(&reserved).is_err());
+        reserved.clear();
+        reserved.insert("memory".to_owned(), "100m".to_owned());
+        assert!(Resources::parse_reserved(&reserved).is_err());
+    }
+
+    #[test]
+    fn test_detect() {
+        let dir = tempfile::tempdir().unwrap();
+        let resources = Resources::detect(&dir.path().join("not-created-yet"));
+        assert!(resources.cpu.unwrap() >= 1.0);
+        if cfg!(target_os = "linux") {
+            assert!(resources.memory.is_some());
+            assert!(resources.ephemeral_storage.is_some());
+        }
+    }
+}
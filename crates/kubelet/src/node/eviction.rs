@@ -0,0 +1,328 @@
+//! The eviction manager observes the resources of the node, reports the
+//! resulting pressure and evicts pods while hard eviction thresholds are
+//! crossed. Pods are also evicted when their emptyDir volumes grow beyond
+//! their size limit.
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use kube::api::{Api, DeleteParams, ListParams};
+use tokio::sync::watch;
+use tracing::{debug, error, info, instrument, warn};
+
+use super::pressure::{observe, Pressure, Signal, Thresholds};
+use crate::config::Config;
+use crate::event::{EventType, Recorder};
+use crate::pod::Pod;
+use crate::provider::Provider;
+use crate::stats::VolumeStats;
+use crate::volume::emptydir;
+
+/// How often the resources of the node are observed.
+const MONITORING_INTERVAL: Duration = Duration::from_secs(10);
+/// Marks mirror pods, which represent static pods and cannot be evicted
+/// through the API.
+const MIRROR_POD_ANNOTATION: &str = "kubernetes.io/config.mirror";
+
+/// Evicts pods while the node is low on resources.
+pub(crate) struct EvictionManager<P> {
+    client: kube::Client,
+    provider: Arc<P>,
+    node_name: String,
+    data_dir: PathBuf,
+    thresholds: Thresholds,
+    recorder: Recorder,
+    pressure: watch::Sender<Pressure>,
+}
+
+impl<P: Provider> EvictionManager<P> {
+    /// Creates an eviction manager for the hard eviction thresholds of the
+    /// configuration. The returned receiver is updated whenever the pressure
+    /// on the node changes. The volume usage of pods is taken from the stats
+    /// of the provider.
+    pub(crate) fn new(
+        client: kube::Client,
+        provider: Arc<P>,
+        config: &Config,
+        recorder: Recorder,
+    ) -> (Self, watch::Receiver<Pressure>) {
+        let thresholds = Thresholds::parse(&config.eviction_hard).unwrap_or_else(|e| {
+            warn!(error = %e, "Ignoring invalid hard eviction thresholds");
+            Thresholds::default()
+        });
+        let (sender, receiver) = watch::channel(Pressure::default());
+        let manager = EvictionManager {
+            client,
+            provider,
+            node_name: config.node_name.clone(),
+            data_dir: config.data_dir.clone(),
+            thresholds,
+            recorder,
+            pressure: sender,
+        };
+        (manager, receiver)
+    }
+
+    /// Observes the node periodically until the task is dropped.
+    pub(crate) async fn run(self) -> anyhow::Result<()> {
+        loop {
+            self.synchronize().await;
+            tokio::time::sleep(MONITORING_INTERVAL).await;
+        }
+    }
+
+    /// Observes the node once and evicts a single pod if any threshold is
+    /// crossed. Evicting one pod at a time gives the node a chance to recover
+    /// before more pods are evicted. Otherwise, pods that exceed the size
+    /// limits of their volumes are evicted.
+    async fn synchronize(&self) {
+        let data_dir = self.data_dir.clone();
+        let observations = match tokio::task::spawn_blocking(move || observe(&data_dir)).await {
+            Ok(observations) => observations,
+            Err(e) => {
+                error!(error = %e, "Unable to observe node resources");
+                return;
+            }
+        };
+        let signals = self.thresholds.crossed(&observations);
+        let pressure = Pressure::from_signals(&signals);
+        let previous = *self.pressure.borrow();
+        if pressure != previous {
+            info!(?pressure, "Node pressure changed");
+            for reason in pressure.transitions(&previous) {
+                self.recorder
+                    .node_event(
+                        EventType::Normal,
+                        reason,
+                        &format!("Node {} status is now: {}", self.node_name, reason),
+                    )
+                    .await;
+            }
+            // Sending only fails once the node updater is gone
+            let _ = self.pressure.send(pressure);
+        }
+        if let Some(signal) = signals.first() {
+            if let Err(e) = self.evict_pod(*signal).await {
+                error!(error = %e, signal = signal.name(), "Unable to evict pod");
+            }
+        } else if let Err(e) = self.enforce_volume_limits().await {
+            error!(error = %e, "Unable to enforce volume size limits");
+        }
+    }
+
+    /// Evicts the pod that is first in line for eviction, without waiting for
+    /// a grace period.
+    #[instrument(level = "info", skip(self), fields(signal = signal.name()))]
+    async fn evict_pod(&self, signal: Signal) -> anyhow::Result<()> {
+        let api: Api<KubePod> = Api::all(self.client.clone());
+        let params = ListParams::default().fields(&format!("spec.nodeName={}", self.node_name));
+        let pods = api.list(&params).await?.items.into_iter().map(Pod::from);
+        let pod = match eviction_order(pods.collect()).into_iter().next() {
+            Some(pod) => pod,
+            None => {
+                warn!("Node is under pressure, but no pods can be evicted");
+                return Ok(());
+            }
+        };
+
+        let message = format!("The node was low on resource: {}.", signal.resource());
+        self.evict(&pod, &message).await
+    }
+
+    /// Evicts the pods that use more space in an emptyDir volume than the
+    /// size limit of the volume allows.
+    async fn enforce_volume_limits(&self) -> anyhow::Result<()> {
+        let stats = match self.provider.stats().await {
+            Ok(stats) => stats,
+            Err(e) => {
+                debug!(error = %e, "Provider does not report volume usage");
+                return Ok(());
+            }
+        };
+        if stats.is_empty() {
+            return Ok(());
+        }
+        let api: Api<KubePod> = Api::all(self.client.clone());
+        let params = ListParams::default().fields(&format!("spec.nodeName={}", self.node_name));
+        for pod in api.list(&params).await?.items.into_iter().map(Pod::from) {
+            let volumes = stats
+                .iter()
+                .find(|s| s.pod_ref.name == pod.name() && s.pod_ref.namespace == pod.namespace())
+                .and_then(|s| s.volume.as_deref())
+                .unwrap_or_default();
+            if let Some(message) = exceeded_volume_limit(&pod, volumes) {
+                if pod.deletion_timestamp().is_none() {
+                    self.evict(&pod, &message).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evicts a pod without waiting for a grace period.
+    async fn evict(&self, pod: &Pod, message: &str) -> anyhow::Result<()> {
+        warn!(pod_name = pod.name(), namespace = pod.namespace(), %message, "Evicting pod");
+        self.recorder
+            .pod_event(pod, None, EventType::Warning, "Evicted", message)
+            .await;
+        let api: Api<KubePod> = Api::namespaced(self.client.clone(), pod.namespace());
+        let params = DeleteParams {
+            grace_period_seconds: Some(0),
+            ..Default::default()
+        };
+        api.delete(pod.name(), &params).await?;
+        Ok(())
+    }
+}
+
+/// Returns the eviction message if any emptyDir volume of the pod uses more
+/// than its size limit.
+fn exceeded_volume_limit(pod: &Pod, volumes: &[VolumeStats]) -> Option<String> {
+    pod.volumes()?.iter().find_map(|volume| {
+        let limit = volume.empty_dir.as_ref()?.size_limit.as_ref()?;
+        let limit_bytes = match emptydir::size_limit(limit) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!(error = %e, volume_name = %volume.name, "Ignoring invalid emptyDir size limit");
+                return None;
+            }
+        };
+        let used_bytes = volumes
+            .iter()
+            .find(|v| v.name == volume.name)?
+            .fs
+            .used_bytes?;
+        if used_bytes > limit_bytes {
+            Some(format!(
+                "Usage of EmptyDir volume \"{}\" exceeds the limit \"{}\".",
+                volume.name, limit.0
+            ))
+        } else {
+            None
+        }
+    })
+}
+
+/// Orders the pods that can be evicted by when they should be evicted: pods
+/// with the lowest priority go first and, among pods of the same priority,
+/// the most recently created ones. Critical, mirror, terminating and finished
+/// pods are left out.
+fn eviction_order(pods: Vec<Pod>) -> Vec<Pod> {
+    let mut pods: Vec<Pod> = pods
+        .into_iter()
+        .filter(|pod| {
+            let phase = pod
+                .as_kube_pod()
+                .status
+                .as_ref()
+                .and_then(|s| s.phase.as_deref());
+            let finished = matches!(phase, Some("Succeeded") | Some("Failed"));
+            !finished
+                && pod.deletion_timestamp().is_none()
+                && pod.get_annotation(MIRROR_POD_ANNOTATION).is_none()
+                && !pod.is_critical()
+        })
+        .collect();
+    pods.sort_by(|a, b| {
+        a.priority()
+            .cmp(&b.priority())
+            .then_with(|| b.creation_timestamp().cmp(&a.creation_timestamp()))
+    });
+    pods
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pod::SYSTEM_CRITICAL_PRIORITY;
+
+    fn pod(name: &str, priority: i32, created: &str, phase: &str) -> Pod {
+        pod_with_annotations(name, priority, created, phase, serde_json::json!({}))
+    }
+
+    fn pod_with_annotations(
+        name: &str,
+        priority: i32,
+        created: &str,
+        phase: &str,
+        annotations: serde_json::Value,
+    ) -> Pod {
+        serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": name,
+                "creationTimestamp": created,
+                "annotations": annotations,
+            },
+            "spec": {
+                "priority": priority,
+                "containers": [],
+            },
+            "status": {
+                "phase": phase,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let pods = vec![
+            pod("high", 1000, "2021-01-01T00:00:00Z", "Running"),
+            pod("old", 0, "2021-01-01T00:00:00Z", "Running"),
+            pod("new", 0, "2021-01-02T00:00:00Z", "Running"),
+            pod("done", -10, "2021-01-01T00:00:00Z", "Succeeded"),
+            pod(
+                "system",
+                SYSTEM_CRITICAL_PRIORITY,
+                "2021-01-01T00:00:00Z",
+                "Running",
+            ),
+            pod_with_annotations(
+                "mirror",
+                0,
+                "2021-01-01T00:00:00Z",
+                "Running",
+                serde_json::json!({ MIRROR_POD_ANNOTATION: "mirror" }),
+            ),
+        ];
+        let order: Vec<String> = eviction_order(pods)
+            .iter()
+            .map(|p| p.name().to_owned())
+            .collect();
+        assert_eq!(order, vec!["new", "old", "high"]);
+    }
+
+    #[test]
+    fn test_exceeded_volume_limit() {
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": "scratch",
+            },
+            "spec": {
+                "containers": [],
+                "volumes": [
+                    { "name": "unlimited", "emptyDir": {} },
+                    { "name": "limited", "emptyDir": { "sizeLimit": "1Ki" } },
+                ],
+            },
+        }))
+        .unwrap();
+        let volume = |name: &str, used_bytes: u64| VolumeStats {
+            name: name.to_owned(),
+            fs: crate::stats::FsStats {
+                used_bytes: Some(used_bytes),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            exceeded_volume_limit(&pod, &[volume("unlimited", 4096), volume("limited", 1024)]),
+            None
+        );
+        assert_eq!(
+            exceeded_volume_limit(&pod, &[volume("limited", 1025)]).as_deref(),
+            Some("Usage of EmptyDir volume \"limited\" exceeds the limit \"1Ki\".")
+        );
+    }
+}
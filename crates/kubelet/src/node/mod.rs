@@ -1,23 +1,32 @@
 //! `node` contains wrappers around the Kubernetes node API, containing ways to create and update
 //! nodes operating within the cluster.
 use crate::config::Config;
-use crate::container::Status as ContainerStatus;
-use crate::pod::{Phase, Pod};
+use crate::event::{EventType, Recorder};
+use crate::metrics;
 use crate::provider::Provider;
 use chrono::prelude::*;
-use futures::{StreamExt, TryStreamExt};
 use k8s_openapi::api::coordination::v1::Lease;
-use k8s_openapi::api::core::v1::ContainerStatus as KubeContainerStatus;
 use k8s_openapi::api::core::v1::Node as KubeNode;
-use k8s_openapi::api::core::v1::Pod as KubePod;
+use k8s_openapi::api::core::v1::NodeCondition;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
-use kube::api::{Api, ListParams, ObjectMeta, PatchParams, PostParams};
+use kube::api::{Api, ObjectMeta, PatchParams, PostParams};
 use kube::error::ErrorResponse;
 use kube::Error;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use tracing::{debug, error, info, instrument, trace, warn};
 
+mod capacity;
+mod eviction;
+mod pressure;
+mod shutdown;
+
+pub(crate) use capacity::Resources;
+pub(crate) use eviction::EvictionManager;
+pub use pressure::Pressure;
+pub(crate) use pressure::Thresholds;
+pub(crate) use shutdown::ShutdownManager;
+
 const KUBELET_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 macro_rules! retry {
@@ -65,14 +74,28 @@ macro_rules! retry {
 /// A node comes with a lease, and we maintain the lease to tell Kubernetes that the
 /// node remains alive and functional. Note that this will not work in
 /// versions of Kubernetes prior to 1.14.
-#[instrument(level = "info", skip(client, config, provider), fields(node_name = %config.node_name))]
-pub async fn create<P: Provider>(client: &kube::Client, config: &Config, provider: Arc<P>) {
+#[instrument(level = "info", skip(client, config, provider, recorder), fields(node_name = %config.node_name))]
+pub async fn create<P: Provider>(
+    client: &kube::Client,
+    config: &Config,
+    provider: Arc<P>,
+    recorder: &Recorder,
+) {
+    recorder
+        .node_event(EventType::Normal, "Starting", "Starting kubelet.")
+        .await;
     let node_client: Api<KubeNode> = Api::all(client.clone());
 
     match retry!(node_client.get(&config.node_name).await, times: 4, break_on: &Error::Api(ErrorResponse { code: 404, .. }))
     {
-        Ok(_) => {
+        Ok(node) => {
             debug!("Node already exists, skipping node creation");
+            if let Err(e) = update_resources(&node_client, config).await {
+                error!(error = %e, "Failed to update node capacity and allocatable resources");
+            }
+            if let Err(e) = uncordon_after_shutdown(&node_client, node).await {
+                error!(error = %e, "Failed to uncordon node after shutdown");
+            }
             return;
         }
         Err(Error::Api(ErrorResponse { code: 404, .. })) => (),
@@ -97,30 +120,25 @@ pub async fn create<P: Provider>(client: &kube::Client, config: &Config, provide
 
     node_labels_definition(P::ARCH, config, &mut builder);
 
-    // TODO Do we want to detect this?
-    builder.add_capacity("cpu", "4");
-    builder.add_capacity("ephemeral-storage", "61255492Ki");
-    builder.add_capacity("hugepages-1Gi", "0");
-    builder.add_capacity("hugepages-2Mi", "0");
-    builder.add_capacity("memory", "4032800Ki");
-    builder.add_capacity("pods", &config.max_pods.to_string());
-
-    builder.add_allocatable("cpu", "4");
-    builder.add_allocatable("ephemeral-storage", "61255492Ki");
-    builder.add_allocatable("hugepages-1Gi", "0");
-    builder.add_allocatable("hugepages-2Mi", "0");
-    builder.add_allocatable("memory", "4032800Ki");
-    builder.add_allocatable("pods", &config.max_pods.to_string());
+    let (capacity, allocatable) = node_resources(config);
+    for (name, quantity) in capacity {
+        builder.add_capacity(&name, &quantity);
+    }
+    for (name, quantity) in allocatable {
+        builder.add_allocatable(&name, &quantity);
+    }
 
     let ts = Utc::now();
     builder.add_condition("Ready", "True", &ts, "KubeletReady", "kubelet is ready");
-    builder.add_condition(
-        "OutOfDisk",
-        "False",
-        &ts,
-        "KubeletHasSufficientDisk",
-        "kubelet has sufficient disk space available",
-    );
+    for condition in Pressure::default().conditions() {
+        builder.add_condition(
+            condition.type_,
+            condition.status,
+            &ts,
+            condition.reason,
+            condition.message,
+        );
+    }
 
     builder.add_address("InternalIP", &format!("{}", config.node_ip));
     builder.add_address("Hostname", &config.hostname);
@@ -137,7 +155,14 @@ pub async fn create<P: Provider>(client: &kube::Client, config: &Config, provide
     match retry!(node_client.create(&PostParams::default(), &node).await, times: 4) {
         Ok(node) => {
             let node_uid = node.metadata.uid.unwrap();
-            if let Err(e) = create_lease(&node_uid, &config.node_name, client).await {
+            if let Err(e) = create_lease(
+                &node_uid,
+                &config.node_name,
+                config.node_lease_duration,
+                client,
+            )
+            .await
+            {
                 error!(error = %e, "Failed to create lease");
                 return;
             }
@@ -147,6 +172,13 @@ pub async fn create<P: Provider>(client: &kube::Client, config: &Config, provide
                 error = %e,
                 "Exhausted retries creating node after failed create. Not retrying"
             );
+            recorder
+                .node_event(
+                    EventType::Warning,
+                    "FailedNodeRegistration",
+                    &format!("Unable to register node: {}", e),
+                )
+                .await;
             return;
         }
     };
@@ -154,6 +186,183 @@ pub async fn create<P: Provider>(client: &kube::Client, config: &Config, provide
     info!("Successfully created node");
 }
 
+/// Detects the capacity of the node and returns it along with the allocatable resources, as
+/// quantities by resource name.
+fn node_resources(config: &Config) -> (BTreeMap<String, String>, BTreeMap<String, String>) {
+    let capacity = Resources::detect(&config.data_dir);
+    let allocatable = capacity.allocatable(&Resources::reserved(config));
+    (
+        resource_quantities(&capacity, config.max_pods),
+        resource_quantities(&allocatable, config.max_pods),
+    )
+}
+
+/// Returns the quantities of the given resources, along with the pods the node can run and the
+/// huge pages it does not offer.
+fn resource_quantities(resources: &Resources, max_pods: u16) -> BTreeMap<String, String> {
+    let mut quantities: BTreeMap<String, String> = resources
+        .to_quantities()
+        .into_iter()
+        .map(|(name, quantity)| (name.to_owned(), quantity))
+        .collect();
+    quantities.insert("hugepages-1Gi".to_owned(), "0".to_owned());
+    quantities.insert("hugepages-2Mi".to_owned(), "0".to_owned());
+    quantities.insert("pods".to_owned(), max_pods.to_string());
+    quantities
+}
+
+/// Updates the capacity and allocatable resources of an existing node, which change whenever the
+/// machine or the reserved resources do. Resources added by others, e.g. device plugins, are
+/// kept.
+async fn update_resources(node_client: &Api<KubeNode>, config: &Config) -> anyhow::Result<()> {
+    let (capacity, allocatable) = node_resources(config);
+    let patch = serde_json::json!({
+        "status": {
+            "capacity": capacity,
+            "allocatable": allocatable,
+        },
+    });
+    node_client
+        .patch_status(
+            &config.node_name,
+            &PatchParams::default(),
+            &kube::api::Patch::Strategic(patch),
+        )
+        .await?;
+    debug!("Updated node capacity and allocatable resources");
+    Ok(())
+}
+
+/// Marks the node schedulable again if it was cordoned by a previous graceful shutdown.
+async fn uncordon_after_shutdown(
+    node_client: &Api<KubeNode>,
+    node: KubeNode,
+) -> anyhow::Result<()> {
+    let cordoned = node
+        .metadata
+        .annotations
+        .as_ref()
+        .map(|a| a.contains_key(shutdown::CORDONED_FOR_SHUTDOWN_ANNOTATION))
+        .unwrap_or(false);
+    if !cordoned {
+        return Ok(());
+    }
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                shutdown::CORDONED_FOR_SHUTDOWN_ANNOTATION: null,
+            },
+        },
+        "spec": {
+            "unschedulable": false,
+        },
+    });
+    node_client
+        .patch(
+            node.metadata.name.as_deref().unwrap_or_default(),
+            &PatchParams::default(),
+            &kube::api::Patch::Merge(patch),
+        )
+        .await?;
+    info!("Uncordoned node after graceful shutdown");
+    Ok(())
+}
+
+/// Applies the labels of a reloaded configuration to the node. Labels that were configured
+/// before, but no longer are, are removed from the node.
+#[instrument(level = "info", skip(client, old, new), fields(node_name = %new.node_name))]
+pub(crate) async fn update_labels(
+    client: &kube::Client,
+    arch: &str,
+    old: &Config,
+    new: &Config,
+) -> anyhow::Result<()> {
+    let mut old_builder = Node::builder();
+    node_labels_definition(arch, old, &mut old_builder);
+    let mut new_builder = Node::builder();
+    node_labels_definition(arch, new, &mut new_builder);
+    let labels = labels_patch(&old_builder.labels, &new_builder.labels);
+    if labels.is_empty() {
+        debug!("Node labels are unchanged");
+        return Ok(());
+    }
+
+    let node_client: Api<KubeNode> = Api::all(client.clone());
+    let patch = serde_json::json!({
+        "metadata": {
+            "labels": labels,
+        },
+    });
+    node_client
+        .patch(
+            &new.node_name,
+            &PatchParams::default(),
+            &kube::api::Patch::Merge(patch),
+        )
+        .await?;
+    info!(num_labels = labels.len(), "Updated node labels");
+    Ok(())
+}
+
+/// Returns the merge patch for the labels that changed between `old` and `new`. Removed labels
+/// are set to `null`, which deletes them.
+fn labels_patch(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let removed = old
+        .keys()
+        .filter(|key| !new.contains_key(*key))
+        .map(|key| (key.clone(), serde_json::Value::Null));
+    let changed = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(value))
+        .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone())));
+    removed.chain(changed).collect()
+}
+
+/// Cordons node and evicts all pods.
+#[deprecated(
+    since = "1.0.0-alpha.1",
+    note = "the kubelet terminates pods itself when it shuts down"
+)]
+pub async fn drain(client: &kube::Client, node_name: &str) -> anyhow::Result<()> {
+    #[allow(deprecated)]
+    evict_pods(client, node_name).await
+}
+
+/// Fetches list of pods on this node and deletes them.
+#[deprecated(
+    since = "1.0.0-alpha.1",
+    note = "the kubelet terminates pods itself when it shuts down"
+)]
+#[instrument(level = "info", skip(client))]
+pub async fn evict_pods(client: &kube::Client, node_name: &str) -> anyhow::Result<()> {
+    ShutdownManager::for_eviction(client.clone(), node_name)
+        .evict_pods()
+        .await
+}
+
+/// Update the timestamps on the Node object.
+///
+/// This is how we report liveness to the upstream.
+/// If we are unable to update the node after several retries we panic, as we could be in an
+/// inconsistent state
+#[deprecated(
+    since = "1.0.0-alpha.1",
+    note = "use `renew_lease` and `update_status` instead"
+)]
+#[instrument(level = "info", skip(client))]
+pub async fn update(client: &kube::Client, node_name: &str) {
+    // The lease duration nodes were updated with before it became configurable
+    renew_lease(client, node_name, std::time::Duration::from_secs(300))
+        .await
+        .expect("Could not update lease");
+    update_status(client, node_name, &Pressure::default())
+        .await
+        .expect("Could not update node status");
+}
+
 /// Fetch the uid of a node by name.
 #[instrument(level = "info", skip(client))]
 pub async fn uid(client: &kube::Client, node_name: &str) -> anyhow::Result<String> {
@@ -175,148 +384,66 @@ pub async fn uid(client: &kube::Client, node_name: &str) -> anyhow::Result<Strin
     }
 }
 
-/// Cordons node and evicts all pods.
-pub async fn drain(client: &kube::Client, node_name: &str) -> anyhow::Result<()> {
-    evict_pods(client, node_name).await?;
-    Ok(())
-}
-
-/// Fetches list of pods on this node and deletes them.
+/// Renew the node lease for another `lease_duration`.
+///
+/// This is how we report liveness to the upstream. An error is returned if the lease could not
+/// be renewed after several retries.
 #[instrument(level = "info", skip(client))]
-pub async fn evict_pods(client: &kube::Client, node_name: &str) -> anyhow::Result<()> {
-    let pod_client: Api<KubePod> = Api::all(client.clone());
-    let node_selector = format!("spec.nodeName={}", node_name);
-    let params = ListParams {
-        field_selector: Some(node_selector),
-        ..Default::default()
-    };
-    let kube::api::ObjectList { items: pods, .. } = pod_client.list(&params).await?;
-
-    let lp = ListParams::default().fields(&format!("spec.nodeName={}", node_name));
-
-    // The delete call may return a "pending" response, we must watch for the actual delete event.
-    let mut stream = pod_client.watch(&lp, "0").await?.boxed();
-
-    info!(num_pods = pods.len(), "Evicting pods");
-
-    for pod in pods {
-        let pod = Pod::from(pod);
-        if pod.is_daemonset() {
-            info!(pod_name = pod.name(), "Skipping eviction of DaemonSet pod");
-            continue;
-        } else if pod.is_static() {
-            let api: Api<KubePod> = Api::namespaced(client.clone(), pod.namespace());
-            let patch = serde_json::json!(
-                {
-                    "metadata": {
-                        "resourceVersion": "",
-                    },
-                    "status": {
-                        "phase": Phase::Succeeded,
-                        "reason": "Pod terminated on node shutdown.",
-                        "containerStatuses": pod.all_containers().iter().map(|container| {
-                            ContainerStatus::Terminated {
-                                timestamp: Utc::now(),
-                                message: "Evicted on node shutdown".to_string(),
-                                failed: false
-                            }.to_kubernetes(container.name())
-                        }).collect::<Vec<KubeContainerStatus>>()
-                    }
-                }
-            );
-            api.patch_status(
-                pod.name(),
-                &PatchParams::default(),
-                &kube::api::Patch::Strategic(patch),
-            )
-            .await?;
-
-            info!("Marked static pod as terminated");
-            continue;
-        } else {
-            match evict_pod(client, pod.name(), pod.namespace(), &mut stream).await {
-                Ok(_) => (),
-                Err(e) => {
-                    // Absorb the error and attempt to delete other pods with best effort.
-                    error!(error = %e, "Error evicting pod")
-                }
-            }
-        }
-    }
-    Ok(())
-}
-
-type PodStream = std::pin::Pin<
-    Box<
-        dyn futures::Stream<Item = Result<kube::api::WatchEvent<KubePod>, kube::error::Error>>
-            + Send,
-    >,
->;
-
-#[instrument(level = "info", skip(client, stream))]
-async fn evict_pod(
+pub async fn renew_lease(
     client: &kube::Client,
-    name: &str,
-    namespace: &str,
-    stream: &mut PodStream,
+    node_name: &str,
+    lease_duration: std::time::Duration,
 ) -> anyhow::Result<()> {
-    let ns_client: Api<KubePod> = Api::namespaced(client.clone(), namespace);
-    info!("Evicting pod");
-    let params = Default::default();
-    let response = ns_client.delete(name, &params).await?;
-
-    if response.is_left() {
-        // TODO Timeout?
-        info!("Waiting for pod eviction");
-        while let Some(event) = stream.try_next().await? {
-            if let kube::api::WatchEvent::Deleted(s) = event {
-                let pod = Pod::from(s);
-                if name == pod.name() && namespace == pod.namespace() {
-                    info!("Pod evicted");
-                    break;
-                }
-            }
-        }
-    } else {
-        info!("Pod evicted");
-    }
+    debug!("Renewing node lease");
+    let uid = uid(client, node_name).await?;
+    trace!("Fetched current node object to renew lease");
+    retry!(update_lease(&uid, node_name, lease_duration, client).await, times: 4)
+        .map_err(|e| anyhow::anyhow!("Unable to update lease: {}", e))?;
     Ok(())
 }
 
-/// Update the timestamps on the Node object.
+/// Update the timestamps, conditions and pressure taints on the Node object.
 ///
-/// This is how we report liveness to the upstream.
-/// If we are unable to update the node after several retries we panic, as we could be in an
-/// inconsistent state
+/// An error is returned if the node could not be updated after several retries.
 #[instrument(level = "info", skip(client))]
-pub async fn update(client: &kube::Client, node_name: &str) {
-    debug!("Updating node");
-    if let Ok(uid) = uid(client, node_name).await {
-        trace!("Fetched current node object to update");
-        retry!(update_lease(&uid, node_name, client).await, times: 4)
-            .expect("Could not update lease");
-        retry!(update_status(node_name, client).await, times: 4)
-            .expect("Could not update node status");
-    }
+pub async fn update_status(
+    client: &kube::Client,
+    node_name: &str,
+    pressure: &Pressure,
+) -> anyhow::Result<()> {
+    debug!("Updating node status");
+    retry!(patch_node_status(node_name, client, pressure).await, times: 4)
 }
 
-async fn update_status(node_name: &str, client: &kube::Client) -> anyhow::Result<()> {
-    // TODO: Update the lastTransitionTime properly
+async fn patch_node_status(
+    node_name: &str,
+    client: &kube::Client,
+    pressure: &Pressure,
+) -> anyhow::Result<()> {
+    let node_client: Api<KubeNode> = Api::all(client.clone());
+    let node = node_client
+        .get(node_name)
+        .await
+        .map_err(|e| anyhow::anyhow!("Unable to get node: {}", e))?;
+    let existing = node
+        .status
+        .as_ref()
+        .and_then(|s| s.conditions.as_deref())
+        .unwrap_or_default();
+
+    let mut conditions = vec![pressure::Condition {
+        type_: "Ready",
+        status: "True",
+        reason: "KubeletReady",
+        message: "kubelet is posting ready status",
+    }];
+    conditions.extend(pressure.conditions());
     let status_patch = serde_json::json!({
         "status": {
-            "conditions": [
-                {
-                    "lastHeartbeatTime": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
-                    "message": "kubelet is posting ready status",
-                    "reason": "KubeletReady",
-                    "status": "True",
-                    "type": "Ready"
-                }
-            ],
+            "conditions": node_conditions(&conditions, existing, Utc::now()),
         }
     });
-    let node_client: Api<KubeNode> = Api::all(client.clone());
-    let _node = node_client
+    node_client
         .patch_status(
             node_name,
             &PatchParams::default(),
@@ -324,9 +451,65 @@ async fn update_status(node_name: &str, client: &kube::Client) -> anyhow::Result
         )
         .await
         .map_err(|e| anyhow::anyhow!("Unable to patch node status: {}", e))?;
+
+    let taints = node
+        .spec
+        .as_ref()
+        .and_then(|s| s.taints.as_deref())
+        .unwrap_or_default();
+    let updated_taints = pressure.apply_taints(taints);
+    if updated_taints != taints {
+        info!(?pressure, "Updating node pressure taints");
+        // Taints are replaced as a whole, so make sure no one else changed
+        // them in the meantime
+        let taints_patch = serde_json::json!({
+            "metadata": {
+                "resourceVersion": node.metadata.resource_version,
+            },
+            "spec": {
+                "taints": updated_taints,
+            }
+        });
+        node_client
+            .patch(
+                node_name,
+                &PatchParams::default(),
+                &kube::api::Patch::Merge(taints_patch),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("Unable to patch node taints: {}", e))?;
+    }
     Ok(())
 }
 
+/// Turns the conditions reported by the kubelet into node conditions. The
+/// lastTransitionTime of a condition is only moved to `now` if its status
+/// differs from the existing condition of the same type.
+fn node_conditions(
+    conditions: &[pressure::Condition],
+    existing: &[NodeCondition],
+    now: DateTime<Utc>,
+) -> Vec<NodeCondition> {
+    conditions
+        .iter()
+        .map(|condition| {
+            let last_transition_time = existing
+                .iter()
+                .find(|c| c.type_ == condition.type_ && c.status == condition.status)
+                .and_then(|c| c.last_transition_time.clone())
+                .unwrap_or(Time(now));
+            NodeCondition {
+                type_: condition.type_.to_owned(),
+                status: condition.status.to_owned(),
+                last_heartbeat_time: Some(Time(now)),
+                last_transition_time: Some(last_transition_time),
+                reason: Some(condition.reason.to_owned()),
+                message: Some(condition.message.to_owned()),
+            }
+        })
+        .collect()
+}
+
 /// Create a node lease
 ///
 /// These creates a new node lease and claims the node for a set
@@ -336,11 +519,16 @@ async fn update_status(node_name: &str, client: &kube::Client) -> anyhow::Result
 /// As far as I can tell, leases ALWAYS go in the 'kube-node-lease'
 /// namespace, no exceptions.
 #[instrument(level = "info", err, skip(client))]
-async fn create_lease(node_uid: &str, node_name: &str, client: &kube::Client) -> Result<(), Error> {
+async fn create_lease(
+    node_uid: &str,
+    node_name: &str,
+    lease_duration: std::time::Duration,
+    client: &kube::Client,
+) -> Result<(), Error> {
     debug!("Creating lease for node");
     let leases: Api<Lease> = Api::namespaced(client.clone(), "kube-node-lease");
 
-    let lease = lease_definition(node_uid, node_name);
+    let lease = lease_definition(node_uid, node_name, lease_duration);
     let lease = serde_json::from_value(lease)
         .expect("failed to deserialize lease from lease definition JSON");
 
@@ -375,20 +563,24 @@ async fn create_lease(node_uid: &str, node_name: &str, client: &kube::Client) ->
 async fn update_lease(
     node_uid: &str,
     node_name: &str,
+    lease_duration: std::time::Duration,
     client: &kube::Client,
 ) -> Result<Lease, Error> {
     debug!("Updating lease for node");
     let leases: Api<Lease> = Api::namespaced(client.clone(), "kube-node-lease");
 
-    let lease = lease_definition(node_uid, node_name);
+    let lease = lease_definition(node_uid, node_name, lease_duration);
 
-    let resp = leases
-        .patch(
+    let resp = metrics::timed(
+        &metrics::NODE_LEASE_UPDATE_DURATION,
+        &[],
+        leases.patch(
             node_name,
             &PatchParams::default(),
             &kube::api::Patch::Strategic(lease),
-        )
-        .await;
+        ),
+    )
+    .await;
     match &resp {
         Ok(_) => debug!("Lease updated"),
         Err(_) => error!("Failed to update lease"),
@@ -401,7 +593,11 @@ async fn update_lease(
 /// The lease tells Kubernetes that we want to claim the node for a while
 /// longer. And then tells Kubernetes how long it should wait before
 /// expecting a new lease.
-fn lease_definition(node_uid: &str, node_name: &str) -> serde_json::Value {
+fn lease_definition(
+    node_uid: &str,
+    node_name: &str,
+    lease_duration: std::time::Duration,
+) -> serde_json::Value {
     serde_json::json!(
         {
             "apiVersion": "coordination.k8s.io/v1",
@@ -417,7 +613,7 @@ fn lease_definition(node_uid: &str, node_name: &str) -> serde_json::Value {
                     }
                 ]
             },
-            "spec": lease_spec_definition(node_name)
+            "spec": lease_spec_definition(node_name, lease_duration)
         }
     )
 }
@@ -425,7 +621,10 @@ fn lease_definition(node_uid: &str, node_name: &str) -> serde_json::Value {
 /// Defines a new coordiation lease for Kubernetes
 ///
 /// We set the lease times, the lease duration, and the node name.
-fn lease_spec_definition(node_name: &str) -> serde_json::Value {
+fn lease_spec_definition(
+    node_name: &str,
+    lease_duration: std::time::Duration,
+) -> serde_json::Value {
     // Workaround for https://github.com/deislabs/krustlet/issues/5
     // In the future, use LeaseSpec rather than a JSON value
     let now = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
@@ -435,7 +634,7 @@ fn lease_spec_definition(node_name: &str) -> serde_json::Value {
             "holderIdentity": node_name,
             "acquireTime": now,
             "renewTime": now,
-            "leaseDurationSeconds": 300
+            "leaseDurationSeconds": lease_duration.as_secs()
         }
     )
 }
@@ -770,6 +969,17 @@ mod test {
             device_plugins_dir: PathBuf::new(),
             node_labels,
             max_pods: 110,
+            system_reserved: HashMap::new(),
+            kube_reserved: HashMap::new(),
+            eviction_hard: HashMap::new(),
+            node_status_update_frequency: std::time::Duration::from_secs(10),
+            node_lease_duration: std::time::Duration::from_secs(40),
+            node_lease_renew_interval: std::time::Duration::from_secs(10),
+            shutdown_grace_period_by_pod_priority: Vec::new(),
+            log_level: None,
+            container_log_max_size: 0,
+            container_log_max_files: 1,
+            wasm: Default::default(),
         };
 
         let mut builder = Node::builder();
@@ -784,4 +994,75 @@ mod test {
         assert!(!result.get("beta.kubernetes.io/os").unwrap().eq("managed"));
         assert!(result.get("beta.kubernetes.io/os").unwrap().eq("linux"));
     }
+
+    #[test]
+    fn test_resource_quantities() {
+        let resources = Resources {
+            cpu: Some(1.5),
+            memory: Some(2 * 1024 * 1024),
+            ephemeral_storage: None,
+        };
+        let quantities = resource_quantities(&resources, 110);
+        let expected: BTreeMap<String, String> = [
+            ("cpu", "1500m"),
+            ("memory", "2048Ki"),
+            ("hugepages-1Gi", "0"),
+            ("hugepages-2Mi", "0"),
+            ("pods", "110"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(quantities, expected);
+    }
+
+    #[test]
+    fn test_labels_patch() {
+        let labels = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let old = labels(&[("kept", "a"), ("changed", "b"), ("removed", "c")]);
+        let new = labels(&[("kept", "a"), ("changed", "d"), ("added", "e")]);
+
+        let patch = serde_json::Value::Object(labels_patch(&old, &new));
+        assert_eq!(
+            patch,
+            serde_json::json!({
+                "changed": "d",
+                "removed": null,
+                "added": "e",
+            })
+        );
+        assert!(labels_patch(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_node_conditions() {
+        let then = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let now = Utc.ymd(2021, 1, 2).and_hms(0, 0, 0);
+        let existing = Pressure::default()
+            .conditions()
+            .iter()
+            .map(|c| NodeCondition {
+                type_: c.type_.to_owned(),
+                status: c.status.to_owned(),
+                last_transition_time: Some(Time(then)),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let pressure = Pressure {
+            memory: true,
+            ..Default::default()
+        };
+        let conditions = node_conditions(&pressure.conditions(), &existing, now);
+        assert_eq!(conditions[0].type_, "MemoryPressure");
+        assert_eq!(conditions[0].status, "True");
+        assert_eq!(conditions[0].last_transition_time, Some(Time(now)));
+        assert_eq!(conditions[1].type_, "DiskPressure");
+        assert_eq!(conditions[1].last_transition_time, Some(Time(then)));
+        assert_eq!(conditions[1].last_heartbeat_time, Some(Time(now)));
+    }
 }
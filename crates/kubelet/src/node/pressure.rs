@@ -0,0 +1,504 @@
+//! Monitoring of the resources of a node, which are reported as the
+//! `MemoryPressure`, `DiskPressure` and `PIDPressure` conditions once the
+//! hard eviction thresholds of the kubelet are crossed.
+use std::collections::HashMap;
+use std::path::Path;
+
+use k8s_openapi::api::core::v1::Taint;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity as KubeQuantity;
+use tracing::warn;
+
+use crate::resources::quantity::{Quantity, QuantityType};
+use crate::stats::system;
+
+/// The directory in `data_dir` providers store pulled modules in.
+const MODULE_STORE_DIR: &str = ".oci";
+/// The directory in `data_dir` container logs are written to.
+const LOG_DIR: &str = "pods";
+
+/// A signal of the node's resources that eviction thresholds are defined for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum Signal {
+    MemoryAvailable,
+    NodeFsAvailable,
+    NodeFsInodesFree,
+    ImageFsAvailable,
+    ImageFsInodesFree,
+    PidAvailable,
+}
+
+impl Signal {
+    const ALL: [Signal; 6] = [
+        Signal::MemoryAvailable,
+        Signal::NodeFsAvailable,
+        Signal::NodeFsInodesFree,
+        Signal::ImageFsAvailable,
+        Signal::ImageFsInodesFree,
+        Signal::PidAvailable,
+    ];
+
+    /// The name of the signal as used in the kubelet configuration.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Signal::MemoryAvailable => "memory.available",
+            Signal::NodeFsAvailable => "nodefs.available",
+            Signal::NodeFsInodesFree => "nodefs.inodesFree",
+            Signal::ImageFsAvailable => "imagefs.available",
+            Signal::ImageFsInodesFree => "imagefs.inodesFree",
+            Signal::PidAvailable => "pid.available",
+        }
+    }
+
+    /// The resource the node runs low on when the signal's threshold is crossed.
+    pub(crate) fn resource(self) -> &'static str {
+        match self {
+            Signal::MemoryAvailable => "memory",
+            Signal::NodeFsAvailable | Signal::ImageFsAvailable => "ephemeral-storage",
+            Signal::NodeFsInodesFree | Signal::ImageFsInodesFree => "inodes",
+            Signal::PidAvailable => "pids",
+        }
+    }
+}
+
+impl std::str::FromStr for Signal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Signal::ALL
+            .iter()
+            .copied()
+            .find(|signal| signal.name() == s)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unsupported eviction signal {:?}, expected one of {}",
+                    s,
+                    Signal::ALL
+                        .iter()
+                        .map(|signal| signal.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+/// The amount of a resource that is available, out of its total capacity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Observation {
+    pub available: u128,
+    pub capacity: u128,
+}
+
+/// The minimum amount of a resource that must stay available.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Threshold {
+    /// An absolute amount, e.g. `100Mi`
+    Quantity(u128),
+    /// A percentage of the capacity, e.g. `10%`
+    Percentage(f64),
+}
+
+impl Threshold {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        if let Some(percentage) = value.strip_suffix('%') {
+            let percentage: f64 = percentage.trim().parse()?;
+            if !(0.0..=100.0).contains(&percentage) {
+                anyhow::bail!("percentage {:?} must be between 0% and 100%", value);
+            }
+            return Ok(Threshold::Percentage(percentage));
+        }
+        let quantity = KubeQuantity(value.to_owned());
+        match Quantity::from_kube_quantity(QuantityType::Memory(&quantity))? {
+            Quantity::Memory(amount) => Ok(Threshold::Quantity(amount)),
+            _ => anyhow::bail!("{:?} is not a quantity", value),
+        }
+    }
+
+    fn is_crossed(&self, observation: &Observation) -> bool {
+        match self {
+            Threshold::Quantity(amount) => observation.available < *amount,
+            Threshold::Percentage(percentage) => {
+                (observation.available as f64) < observation.capacity as f64 * percentage / 100.0
+            }
+        }
+    }
+}
+
+/// The hard eviction thresholds of the kubelet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Thresholds(Vec<(Signal, Threshold)>);
+
+impl Thresholds {
+    /// Parses thresholds as given in the kubelet configuration, e.g.
+    /// `{"memory.available": "100Mi", "nodefs.available": "10%"}`.
+    pub(crate) fn parse(thresholds: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut parsed = thresholds
+            .iter()
+            .map(|(signal, value)| {
+                let signal: Signal = signal.parse()?;
+                let threshold = Threshold::parse(value)
+                    .map_err(|e| anyhow::anyhow!("threshold of {}: {:#}", signal.name(), e))?;
+                Ok((signal, threshold))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        parsed.sort_by_key(|(signal, _)| *signal);
+        Ok(Thresholds(parsed))
+    }
+
+    /// Returns the signals whose threshold is crossed by at least one of the
+    /// observations.
+    pub(crate) fn crossed(&self, observations: &[(Signal, Observation)]) -> Vec<Signal> {
+        self.0
+            .iter()
+            .filter(|(signal, threshold)| {
+                observations
+                    .iter()
+                    .any(|(s, observation)| s == signal && threshold.is_crossed(observation))
+            })
+            .map(|(signal, _)| *signal)
+            .collect()
+    }
+}
+
+/// Observes the resources of the node. The filesystems of `data_dir` and of
+/// the log directory are reported as `nodefs`, the filesystem of the module
+/// store as `imagefs`. Resources that cannot be observed are left out with a
+/// warning. This blocks while reading from `/proc` and the filesystems.
+pub(crate) fn observe(data_dir: &Path) -> Vec<(Signal, Observation)> {
+    let mut observations = Vec::new();
+    match system::meminfo() {
+        Ok(meminfo) => {
+            if let (Some(available), Some(total)) =
+                (meminfo.get("MemAvailable"), meminfo.get("MemTotal"))
+            {
+                observations.push((
+                    Signal::MemoryAvailable,
+                    Observation {
+                        available: *available as u128,
+                        capacity: *total as u128,
+                    },
+                ));
+            }
+        }
+        Err(e) => warn!(error = %e, "Unable to observe available memory"),
+    }
+
+    let filesystems = [
+        (
+            data_dir.to_path_buf(),
+            Signal::NodeFsAvailable,
+            Signal::NodeFsInodesFree,
+        ),
+        (
+            data_dir.join(LOG_DIR),
+            Signal::NodeFsAvailable,
+            Signal::NodeFsInodesFree,
+        ),
+        (
+            data_dir.join(MODULE_STORE_DIR),
+            Signal::ImageFsAvailable,
+            Signal::ImageFsInodesFree,
+        ),
+    ];
+    for (path, available, inodes_free) in filesystems {
+        match system::filesystem_usage(&path) {
+            Ok(usage) => {
+                observations.push((
+                    available,
+                    Observation {
+                        available: usage.available as u128,
+                        capacity: usage.capacity as u128,
+                    },
+                ));
+                observations.push((
+                    inodes_free,
+                    Observation {
+                        available: usage.inodes_free as u128,
+                        capacity: usage.inodes as u128,
+                    },
+                ));
+            }
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "Unable to observe filesystem usage")
+            }
+        }
+    }
+
+    match system::pid_usage() {
+        Ok(usage) => observations.push((
+            Signal::PidAvailable,
+            Observation {
+                available: usage.max.saturating_sub(usage.running) as u128,
+                capacity: usage.max as u128,
+            },
+        )),
+        Err(e) => warn!(error = %e, "Unable to observe available process IDs"),
+    }
+    observations
+}
+
+/// A condition of the node as reported by the kubelet.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Condition {
+    pub type_: &'static str,
+    pub status: &'static str,
+    pub reason: &'static str,
+    pub message: &'static str,
+}
+
+/// How one of the states of a pressure condition is reported.
+struct Report {
+    reason: &'static str,
+    message: &'static str,
+    event: &'static str,
+}
+
+/// A condition reporting pressure on a resource, with the taint that keeps
+/// new pods off the node while it is under pressure.
+struct PressureCondition {
+    type_: &'static str,
+    taint: &'static str,
+    pressure: Report,
+    no_pressure: Report,
+}
+
+impl PressureCondition {
+    fn report(&self, under_pressure: bool) -> &Report {
+        if under_pressure {
+            &self.pressure
+        } else {
+            &self.no_pressure
+        }
+    }
+}
+
+const MEMORY_PRESSURE: PressureCondition = PressureCondition {
+    type_: "MemoryPressure",
+    taint: "node.kubernetes.io/memory-pressure",
+    pressure: Report {
+        reason: "KubeletHasInsufficientMemory",
+        message: "kubelet has insufficient memory available",
+        event: "NodeHasInsufficientMemory",
+    },
+    no_pressure: Report {
+        reason: "KubeletHasSufficientMemory",
+        message: "kubelet has sufficient memory available",
+        event: "NodeHasSufficientMemory",
+    },
+};
+
+const DISK_PRESSURE: PressureCondition = PressureCondition {
+    type_: "DiskPressure",
+    taint: "node.kubernetes.io/disk-pressure",
+    pressure: Report {
+        reason: "KubeletHasDiskPressure",
+        message: "kubelet has disk pressure",
+        event: "NodeHasDiskPressure",
+    },
+    no_pressure: Report {
+        reason: "KubeletHasNoDiskPressure",
+        message: "kubelet has no disk pressure",
+        event: "NodeHasNoDiskPressure",
+    },
+};
+
+const PID_PRESSURE: PressureCondition = PressureCondition {
+    type_: "PIDPressure",
+    taint: "node.kubernetes.io/pid-pressure",
+    pressure: Report {
+        reason: "KubeletHasInsufficientPID",
+        message: "kubelet has insufficient PID available",
+        event: "NodeHasInsufficientPID",
+    },
+    no_pressure: Report {
+        reason: "KubeletHasSufficientPID",
+        message: "kubelet has sufficient PID available",
+        event: "NodeHasSufficientPID",
+    },
+};
+
+/// The resources a node is under pressure on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pressure {
+    /// Whether the node is low on memory
+    pub memory: bool,
+    /// Whether the node is low on disk space or inodes
+    pub disk: bool,
+    /// Whether the node is low on process IDs
+    pub pid: bool,
+}
+
+impl Pressure {
+    /// Returns the pressure caused by the given crossed signals.
+    pub(crate) fn from_signals(signals: &[Signal]) -> Self {
+        let mut pressure = Pressure::default();
+        for signal in signals {
+            match signal {
+                Signal::MemoryAvailable => pressure.memory = true,
+                Signal::NodeFsAvailable
+                | Signal::NodeFsInodesFree
+                | Signal::ImageFsAvailable
+                | Signal::ImageFsInodesFree => pressure.disk = true,
+                Signal::PidAvailable => pressure.pid = true,
+            }
+        }
+        pressure
+    }
+
+    fn states(&self) -> [(&'static PressureCondition, bool); 3] {
+        [
+            (&MEMORY_PRESSURE, self.memory),
+            (&DISK_PRESSURE, self.disk),
+            (&PID_PRESSURE, self.pid),
+        ]
+    }
+
+    /// The pressure conditions of the node.
+    pub(crate) fn conditions(&self) -> Vec<Condition> {
+        self.states()
+            .iter()
+            .map(|(condition, under_pressure)| {
+                let report = condition.report(*under_pressure);
+                Condition {
+                    type_: condition.type_,
+                    status: if *under_pressure { "True" } else { "False" },
+                    reason: report.reason,
+                    message: report.message,
+                }
+            })
+            .collect()
+    }
+
+    /// The reasons of the node events to record for the conditions that
+    /// changed since `previous`.
+    pub(crate) fn transitions(&self, previous: &Pressure) -> Vec<&'static str> {
+        self.states()
+            .iter()
+            .zip(previous.states().iter())
+            .filter(|((_, now), (_, before))| now != before)
+            .map(|((condition, now), _)| condition.report(*now).event)
+            .collect()
+    }
+
+    /// Returns the given taints of the node with the pressure taints updated
+    /// to match the pressure. All other taints are kept as they are.
+    pub(crate) fn apply_taints(&self, taints: &[Taint]) -> Vec<Taint> {
+        let mut taints: Vec<Taint> = taints
+            .iter()
+            .filter(|taint| {
+                self.states().iter().all(|(condition, under_pressure)| {
+                    *under_pressure || taint.key != condition.taint
+                })
+            })
+            .cloned()
+            .collect();
+        for (condition, under_pressure) in self.states() {
+            if under_pressure && !taints.iter().any(|t| t.key == condition.taint) {
+                taints.push(Taint {
+                    key: condition.taint.to_owned(),
+                    effect: "NoSchedule".to_owned(),
+                    ..Default::default()
+                });
+            }
+        }
+        taints
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn observation(available: u128, capacity: u128) -> Observation {
+        Observation {
+            available,
+            capacity,
+        }
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let thresholds = Thresholds::parse(
+            &vec![
+                ("memory.available".to_owned(), "100Mi".to_owned()),
+                ("nodefs.available".to_owned(), "10%".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+        )
+        .unwrap();
+        let observations = vec![
+            (
+                Signal::MemoryAvailable,
+                observation(200 * 1024 * 1024, 1 << 40),
+            ),
+            (Signal::NodeFsAvailable, observation(50, 100)),
+            (Signal::NodeFsAvailable, observation(5, 100)),
+            (Signal::PidAvailable, observation(0, 100)),
+        ];
+        assert_eq!(
+            thresholds.crossed(&observations),
+            vec![Signal::NodeFsAvailable]
+        );
+
+        let invalid = |signal: &str, value: &str| {
+            Thresholds::parse(
+                &vec![(signal.to_owned(), value.to_owned())]
+                    .into_iter()
+                    .collect(),
+            )
+            .is_err()
+        };
+        assert!(invalid("memory.free", "100Mi"));
+        assert!(invalid("nodefs.available", "110%"));
+        assert!(invalid("memory.available", "lots"));
+    }
+
+    #[test]
+    fn test_pressure() {
+        let pressure = Pressure::from_signals(&[Signal::ImageFsInodesFree]);
+        assert_eq!(
+            pressure,
+            Pressure {
+                disk: true,
+                ..Default::default()
+            }
+        );
+        let conditions = pressure.conditions();
+        assert_eq!(conditions[0].status, "False");
+        assert_eq!(conditions[1].type_, "DiskPressure");
+        assert_eq!(conditions[1].status, "True");
+        assert_eq!(conditions[1].reason, "KubeletHasDiskPressure");
+        assert_eq!(
+            pressure.transitions(&Pressure::default()),
+            vec!["NodeHasDiskPressure"]
+        );
+        assert!(pressure.transitions(&pressure).is_empty());
+    }
+
+    #[test]
+    fn test_apply_taints() {
+        let taint = |key: &str| Taint {
+            key: key.to_owned(),
+            effect: "NoSchedule".to_owned(),
+            ..Default::default()
+        };
+        let existing = vec![
+            taint("node.kubernetes.io/memory-pressure"),
+            taint("kubernetes.io/arch"),
+        ];
+        let pressure = Pressure {
+            disk: true,
+            ..Default::default()
+        };
+        let taints = pressure.apply_taints(&existing);
+        assert_eq!(
+            taints,
+            vec![
+                taint("kubernetes.io/arch"),
+                taint("node.kubernetes.io/disk-pressure")
+            ]
+        );
+        assert_eq!(pressure.apply_taints(&taints), taints);
+    }
+}
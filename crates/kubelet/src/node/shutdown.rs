@@ -0,0 +1,392 @@
+//! Graceful shutdown of the node. The node is cordoned and its pods are
+//! terminated in order of increasing priority, each group of pods within the
+//! shutdown grace period of its priority.
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::ContainerStatus as KubeContainerStatus;
+use k8s_openapi::api::core::v1::Node as KubeNode;
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use kube::api::{Api, DeleteParams, ListParams, Patch, PatchParams, WatchEvent};
+use tracing::{error, info, instrument, warn};
+
+use super::node_conditions;
+use super::pressure::Condition;
+use crate::config::{Config, ShutdownGracePeriodByPodPriority};
+use crate::container::Status as ContainerStatus;
+use crate::event::{EventType, Recorder};
+use crate::pod::{Phase, Pod, PodKey};
+
+/// Marks a node that was cordoned for shutdown, so that it is uncordoned
+/// again when the kubelet starts.
+pub(crate) const CORDONED_FOR_SHUTDOWN_ANNOTATION: &str = "krustlet.dev/cordoned-for-shutdown";
+/// Extra time given to the API server to report the deletion of pods after
+/// their grace period has passed.
+const DELETION_SLACK: Duration = Duration::from_secs(5);
+/// The longest pods evicted outside of a graceful shutdown are given to shut
+/// down. Up to this, pods get their own termination grace period.
+const EVICTION_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+const SHUTTING_DOWN: Condition = Condition {
+    type_: "Ready",
+    status: "False",
+    reason: "KubeletShuttingDown",
+    message: "kubelet is shutting down, pods are being terminated",
+};
+
+const SHUT_DOWN: Condition = Condition {
+    type_: "Ready",
+    status: "False",
+    reason: "KubeletShutdown",
+    message: "kubelet performed an orderly shutdown",
+};
+
+/// Shuts down the node gracefully.
+pub(crate) struct ShutdownManager {
+    client: kube::Client,
+    node_name: String,
+    grace_periods: Vec<ShutdownGracePeriodByPodPriority>,
+    recorder: Recorder,
+}
+
+impl ShutdownManager {
+    /// Creates a shutdown manager using the shutdown grace periods of the
+    /// configuration.
+    pub(crate) fn new(client: kube::Client, config: &Config, recorder: Recorder) -> Self {
+        let mut grace_periods = config.shutdown_grace_period_by_pod_priority.clone();
+        grace_periods.sort_by_key(|g| g.priority);
+        ShutdownManager {
+            client,
+            node_name: config.node_name.clone(),
+            grace_periods,
+            recorder,
+        }
+    }
+
+    /// Creates a shutdown manager that evicts pods without a configured
+    /// shutdown grace period, giving each pod its own termination grace period.
+    pub(crate) fn for_eviction(client: kube::Client, node_name: &str) -> Self {
+        ShutdownManager {
+            recorder: Recorder::new(client.clone(), node_name),
+            client,
+            node_name: node_name.to_owned(),
+            grace_periods: vec![ShutdownGracePeriodByPodPriority {
+                priority: i32::MIN,
+                shutdown_grace_period_seconds: EVICTION_GRACE_PERIOD.as_secs(),
+            }],
+        }
+    }
+
+    /// Cordons the node and terminates its pods. Returns once all pods have
+    /// terminated or the grace periods of all priorities have passed.
+    #[instrument(level = "info", skip(self), fields(node_name = %self.node_name))]
+    pub(crate) async fn shutdown(&self) -> anyhow::Result<()> {
+        info!("Shutting down node");
+        self.recorder
+            .node_event(
+                EventType::Normal,
+                "NodeShutdown",
+                "Shutting down node, terminating pods",
+            )
+            .await;
+        if let Err(e) = self.set_ready_condition(SHUTTING_DOWN).await {
+            warn!(error = %e, "Unable to report node shutdown");
+        }
+        self.cordon().await?;
+        self.evict_pods().await?;
+
+        self.set_ready_condition(SHUT_DOWN).await?;
+        info!("Node shut down");
+        Ok(())
+    }
+
+    /// Terminates the pods of the node in order of increasing priority.
+    /// Returns once all pods have terminated or the grace periods of all
+    /// priorities have passed.
+    pub(crate) async fn evict_pods(&self) -> anyhow::Result<()> {
+        let api: Api<KubePod> = Api::all(self.client.clone());
+        let params = ListParams::default().fields(&format!("spec.nodeName={}", self.node_name));
+        let pods = api.list(&params).await?.items.into_iter().map(Pod::from);
+        let groups = group_by_priority(&self.grace_periods, pods.collect());
+        // Bound the shutdown as a whole as well, in case the API server hangs
+        let total: Duration = groups
+            .iter()
+            .map(|(grace_period, _)| *grace_period + DELETION_SLACK)
+            .sum();
+        let terminate_all = async {
+            for (grace_period, pods) in groups {
+                info!(
+                    num_pods = pods.len(),
+                    grace_period_seconds = grace_period.as_secs(),
+                    "Terminating pods"
+                );
+                if let Err(e) = self.terminate_pods(grace_period, pods).await {
+                    error!(error = %e, "Error terminating pods");
+                }
+            }
+        };
+        if tokio::time::timeout(total, terminate_all).await.is_err() {
+            warn!("Pods did not terminate within the shutdown grace period");
+        }
+        Ok(())
+    }
+
+    /// Marks the node unschedulable. Nodes that already were unschedulable are
+    /// left alone, so that they are not uncordoned on the next start.
+    async fn cordon(&self) -> anyhow::Result<()> {
+        let api: Api<KubeNode> = Api::all(self.client.clone());
+        let node = api.get(&self.node_name).await?;
+        if node.spec.and_then(|s| s.unschedulable).unwrap_or(false) {
+            info!("Node is already cordoned");
+            return Ok(());
+        }
+        let patch = serde_json::json!({
+            "metadata": {
+                "annotations": {
+                    CORDONED_FOR_SHUTDOWN_ANNOTATION: "true",
+                },
+            },
+            "spec": {
+                "unschedulable": true,
+            },
+        });
+        api.patch(
+            &self.node_name,
+            &PatchParams::default(),
+            &Patch::Merge(patch),
+        )
+        .await?;
+        info!("Cordoned node");
+        Ok(())
+    }
+
+    async fn set_ready_condition(&self, condition: Condition) -> anyhow::Result<()> {
+        let api: Api<KubeNode> = Api::all(self.client.clone());
+        let node = api.get(&self.node_name).await?;
+        let existing = node
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_deref())
+            .unwrap_or_default();
+        let patch = serde_json::json!({
+            "status": {
+                "conditions": node_conditions(&[condition], existing, Utc::now()),
+            }
+        });
+        api.patch_status(
+            &self.node_name,
+            &PatchParams::default(),
+            &Patch::Strategic(patch),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes the given pods, giving each of them at most `grace_period` to
+    /// shut down, and waits until they are gone or the grace period has
+    /// passed. DaemonSet pods are left running and static pods are only marked
+    /// as terminated.
+    async fn terminate_pods(&self, grace_period: Duration, pods: Vec<Pod>) -> anyhow::Result<()> {
+        let api: Api<KubePod> = Api::all(self.client.clone());
+        let params = ListParams::default().fields(&format!("spec.nodeName={}", self.node_name));
+        // Deletion may only be pending once the delete call returns, so watch
+        // for the actual delete events
+        let mut stream = api.watch(&params, "0").await?.boxed();
+
+        let mut pending = HashSet::new();
+        for pod in pods {
+            if pod.is_daemonset() {
+                info!(
+                    pod_name = pod.name(),
+                    "Skipping termination of DaemonSet pod"
+                );
+            } else if pod.is_static() {
+                if let Err(e) = self.mark_terminated(&pod).await {
+                    error!(error = %e, pod_name = pod.name(), "Unable to mark static pod as terminated");
+                }
+            } else {
+                self.recorder
+                    .pod_event(
+                        &pod,
+                        None,
+                        EventType::Normal,
+                        "Evicted",
+                        "Evicting pod for node shutdown",
+                    )
+                    .await;
+                let grace_period = pod.termination_grace_period().min(grace_period);
+                match self.delete_pod(&pod, grace_period).await {
+                    Ok(true) => {
+                        pending.insert(PodKey::from(&pod));
+                    }
+                    Ok(false) => info!(pod_name = pod.name(), "Pod evicted"),
+                    // Absorb the error and attempt to delete other pods with best effort.
+                    Err(e) => error!(error = %e, pod_name = pod.name(), "Error evicting pod"),
+                }
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        info!(num_pods = pending.len(), "Waiting for pod eviction");
+        let wait = async {
+            while let Some(event) = stream.try_next().await? {
+                if let WatchEvent::Deleted(pod) = event {
+                    let key = PodKey::from(&pod);
+                    if pending.remove(&key) {
+                        info!(pod_name = %key.name(), "Pod evicted");
+                    }
+                    if pending.is_empty() {
+                        break;
+                    }
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+        match tokio::time::timeout(grace_period + DELETION_SLACK, wait).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    num_pods = pending.len(),
+                    "Pods were not evicted within their shutdown grace period"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Deletes a pod and returns whether the deletion is still pending.
+    async fn delete_pod(&self, pod: &Pod, grace_period: Duration) -> anyhow::Result<bool> {
+        let api: Api<KubePod> = Api::namespaced(self.client.clone(), pod.namespace());
+        let params = DeleteParams {
+            grace_period_seconds: Some(grace_period.as_secs() as u32),
+            ..Default::default()
+        };
+        let response = api.delete(pod.name(), &params).await?;
+        Ok(response.is_left())
+    }
+
+    async fn mark_terminated(&self, pod: &Pod) -> anyhow::Result<()> {
+        let api: Api<KubePod> = Api::namespaced(self.client.clone(), pod.namespace());
+        let patch = serde_json::json!(
+            {
+                "metadata": {
+                    "resourceVersion": "",
+                },
+                "status": {
+                    "phase": Phase::Succeeded,
+                    "reason": "Pod terminated on node shutdown.",
+                    "containerStatuses": pod.all_containers().iter().map(|container| {
+                        ContainerStatus::Terminated {
+                            timestamp: Utc::now(),
+                            message: "Evicted on node shutdown".to_string(),
+                            failed: false,
+                            reason: None,
+                            exit_code: None,
+                            signal: None,
+                            started_at: None,
+                        }.to_kubernetes(container.name())
+                    }).collect::<Vec<KubeContainerStatus>>()
+                }
+            }
+        );
+        api.patch_status(
+            pod.name(),
+            &PatchParams::default(),
+            &Patch::Strategic(patch),
+        )
+        .await?;
+        info!(pod_name = pod.name(), "Marked static pod as terminated");
+        Ok(())
+    }
+}
+
+/// Groups pods by the shutdown grace period of their priority, in order of
+/// increasing priority. Groups without pods are left out.
+fn group_by_priority(
+    grace_periods: &[ShutdownGracePeriodByPodPriority],
+    pods: Vec<Pod>,
+) -> Vec<(Duration, Vec<Pod>)> {
+    let mut groups: Vec<(Duration, Vec<Pod>)> = grace_periods
+        .iter()
+        .map(|g| {
+            (
+                Duration::from_secs(g.shutdown_grace_period_seconds),
+                Vec::new(),
+            )
+        })
+        .collect();
+    if groups.is_empty() {
+        groups.push((Duration::from_secs(0), Vec::new()));
+    }
+    for pod in pods {
+        let index = grace_periods
+            .iter()
+            .rposition(|g| g.priority <= pod.priority())
+            .unwrap_or(0);
+        groups[index].1.push(pod);
+    }
+    groups.retain(|(_, pods)| !pods.is_empty());
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pod(name: &str, priority: i32) -> Pod {
+        serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": name,
+            },
+            "spec": {
+                "priority": priority,
+                "containers": [],
+            },
+        }))
+        .unwrap()
+    }
+
+    fn grace_period(priority: i32, seconds: u64) -> ShutdownGracePeriodByPodPriority {
+        ShutdownGracePeriodByPodPriority {
+            priority,
+            shutdown_grace_period_seconds: seconds,
+        }
+    }
+
+    #[test]
+    fn test_group_by_priority() {
+        let grace_periods = vec![
+            grace_period(0, 20),
+            grace_period(1000, 15),
+            grace_period(crate::pod::SYSTEM_CRITICAL_PRIORITY, 10),
+        ];
+        let pods = vec![
+            pod("critical", crate::pod::SYSTEM_CRITICAL_PRIORITY + 1000),
+            pod("low", -5),
+            pod("default", 0),
+            pod("high", 1500),
+        ];
+        let groups: Vec<(u64, Vec<String>)> = group_by_priority(&grace_periods, pods)
+            .into_iter()
+            .map(|(grace_period, pods)| {
+                (
+                    grace_period.as_secs(),
+                    pods.iter().map(|p| p.name().to_owned()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (20, vec!["low".to_owned(), "default".to_owned()]),
+                (15, vec!["high".to_owned()]),
+                (10, vec!["critical".to_owned()]),
+            ]
+        );
+        assert_eq!(group_by_priority(&[], vec![pod("any", 0)]).len(), 1);
+    }
+}
@@ -1,11 +1,14 @@
+use crate::metrics::POD_STATE_TRANSITIONS;
 use crate::pod::initialize_pod_container_statuses;
-use crate::pod::Pod;
-use crate::provider::Provider;
+use crate::pod::state::Stub;
+use crate::pod::{Pod, Status as PodStatus};
+use crate::provider::{AdmissionSupport, Provider};
 use k8s_openapi::api::core::v1::Pod as KubePod;
 use krator::ObjectState;
 use krator::SharedState;
-use krator::{Manifest, Operator};
+use krator::{Manifest, Operator, State, Transition};
 use kube::Api;
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 pub(crate) struct PodOperator<P: Provider> {
@@ -24,8 +27,8 @@ impl<P: Provider> Operator for PodOperator<P> {
     type Manifest = crate::pod::Pod;
     type Status = crate::pod::Status;
     type ObjectState = P::PodState;
-    type InitialState = P::InitialState;
-    type DeletedState = P::TerminatedState;
+    type InitialState = Observed<P::PodState, P::InitialState>;
+    type DeletedState = Observed<P::PodState, P::TerminatedState>;
 
     async fn initialize_object_state(&self, manifest: &Pod) -> anyhow::Result<P::PodState> {
         self.provider.initialize_pod_state(manifest).await
@@ -48,3 +51,215 @@ impl<P: Provider> Operator for PodOperator<P> {
         Ok(())
     }
 }
+
+/// Wraps the states of a pod state machine, starting at `I`, to count the
+/// transitions between them in [`POD_STATE_TRANSITIONS`].
+///
+/// Once the state machine completes, the pod is in a terminal phase, so the
+/// resources admitted for it are released.
+pub(crate) struct Observed<S, I> {
+    state: Box<dyn State<S>>,
+    initial: PhantomData<fn() -> I>,
+}
+
+impl<S, I> Observed<S, I>
+where
+    S: ObjectState<Manifest = Pod, Status = PodStatus>,
+{
+    fn wrap(state: Box<dyn State<S>>) -> Self {
+        Observed {
+            state,
+            initial: PhantomData,
+        }
+    }
+}
+
+impl<S, I> Default for Observed<S, I>
+where
+    S: ObjectState<Manifest = Pod, Status = PodStatus>,
+    I: State<S> + Default,
+{
+    fn default() -> Self {
+        Self::wrap(Box::<I>::default())
+    }
+}
+
+impl<S, I> std::fmt::Debug for Observed<S, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.state.fmt(f)
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, I> State<S> for Observed<S, I>
+where
+    S: ObjectState<Manifest = Pod, Status = PodStatus>,
+    S::SharedState: AdmissionSupport,
+    I: 'static,
+{
+    async fn next(
+        mut self: Box<Self>,
+        shared: SharedState<S::SharedState>,
+        object_state: &mut S,
+        manifest: Manifest<Pod>,
+    ) -> Transition<S> {
+        let state = std::mem::replace(&mut self.state, Box::new(Stub));
+        let from = state_name(&state);
+        match state
+            .next(shared.clone(), object_state, manifest.clone())
+            .await
+        {
+            Transition::Next(next) => {
+                let next: Box<dyn State<S>> = next.into();
+                POD_STATE_TRANSITIONS
+                    .with_label_values(&[&from, &state_name(&next)])
+                    .inc();
+                Transition::next_unchecked(self, Self::wrap(next))
+            }
+            Transition::Complete(result) => {
+                POD_STATE_TRANSITIONS
+                    .with_label_values(&[&from, "Complete"])
+                    .inc();
+                if let Some(pod_admitter) = shared.read().await.pod_admitter() {
+                    pod_admitter.release(&manifest.latest());
+                }
+                Transition::Complete(result)
+            }
+        }
+    }
+
+    async fn status(&self, object_state: &mut S, manifest: &Pod) -> anyhow::Result<PodStatus> {
+        self.state.status(object_state, manifest).await
+    }
+}
+
+/// Returns the name of the type of a state, e.g. `Registered`, from its debug
+/// representation. The generic states print their name as a quoted string
+/// (e.g. `"Error: message"`), so leading quotes are skipped.
+fn state_name(state: &dyn std::fmt::Debug) -> String {
+    format!("{:?}", state)
+        .trim_start_matches(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::node::Resources;
+    use crate::provider::{DevicePluginSupport, EventSupport, PluginSupport, VolumeSupport};
+    use crate::resources::PodAdmitter;
+    use crate::state::common::error::Error;
+    use crate::state::common::registered::Registered;
+    use crate::state::common::{
+        BackoffSequence, GenericPodState, GenericProvider, GenericProviderState, ThresholdTrigger,
+    };
+    use k8s_openapi::api::core::v1::PodSpec;
+    use kube::api::ObjectMeta;
+    use std::collections::HashMap;
+    use tokio::sync::RwLock;
+
+    struct MockProvider;
+
+    struct ProviderState(Option<Arc<PodAdmitter>>);
+
+    #[async_trait::async_trait]
+    impl GenericProviderState for ProviderState {
+        fn client(&self) -> kube::Client {
+            unimplemented!()
+        }
+        fn store(&self) -> Arc<dyn crate::store::Store + Sync + Send> {
+            unimplemented!()
+        }
+        fn stop(&self, _pod: &Pod) -> futures::future::BoxFuture<'static, anyhow::Result<()>> {
+            unimplemented!()
+        }
+    }
+
+    impl VolumeSupport for ProviderState {}
+    impl PluginSupport for ProviderState {}
+    impl DevicePluginSupport for ProviderState {}
+    impl AdmissionSupport for ProviderState {
+        fn pod_admitter(&self) -> Option<Arc<PodAdmitter>> {
+            self.0.clone()
+        }
+    }
+    impl EventSupport for ProviderState {}
+
+    struct PodState;
+
+    #[async_trait::async_trait]
+    impl ObjectState for PodState {
+        type Manifest = Pod;
+        type Status = PodStatus;
+        type SharedState = ProviderState;
+        async fn async_drop(self, _provider_state: &mut ProviderState) {}
+    }
+
+    #[async_trait::async_trait]
+    impl GenericPodState for PodState {
+        async fn set_env_vars(&mut self, _env_vars: HashMap<String, HashMap<String, String>>) {}
+        async fn set_modules(&mut self, _modules: HashMap<String, Vec<u8>>) {}
+        async fn set_volumes(&mut self, _volumes: HashMap<String, crate::volume::VolumeRef>) {}
+        async fn backoff(&mut self, _sequence: BackoffSequence) {}
+        async fn reset_backoff(&mut self, _sequence: BackoffSequence) {}
+        async fn record_error(&mut self) -> ThresholdTrigger {
+            ThresholdTrigger::Untriggered
+        }
+    }
+
+    impl GenericProvider for MockProvider {
+        type ProviderState = ProviderState;
+        type PodState = PodState;
+        type RunState = Registered<Self>;
+
+        fn validate_pod_runnable(_pod: &Pod) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn validate_container_runnable(
+            _container: &crate::container::Container,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_state_name() {
+        let registered: Box<dyn State<PodState>> = Box::<Registered<MockProvider>>::default();
+        assert_eq!(state_name(&registered), "Registered");
+        let error: Box<dyn State<PodState>> =
+            Box::new(Error::<MockProvider>::new("image not found".to_string()));
+        assert_eq!(state_name(&error), "Error");
+        assert_eq!(state_name(&Stub), "Stub");
+    }
+
+    fn pod(uid: &str) -> Pod {
+        Pod::from(KubePod {
+            metadata: ObjectMeta {
+                name: Some(uid.to_owned()),
+                uid: Some(uid.to_owned()),
+                ..Default::default()
+            },
+            spec: Some(PodSpec::default()),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_release_on_completion() {
+        let pod_admitter = Arc::new(PodAdmitter::with_allocatable(Resources::default(), 1));
+        pod_admitter.admit(&pod("first")).unwrap();
+        assert!(pod_admitter.admit(&pod("second")).is_err());
+
+        let shared = Arc::new(RwLock::new(ProviderState(Some(pod_admitter.clone()))));
+        let (_tx, manifest) = Manifest::new(pod("first"), krator::Store::new());
+        let state = Box::<Observed<PodState, Stub>>::default();
+        match state.next(shared, &mut PodState, manifest).await {
+            Transition::Complete(result) => result.unwrap(),
+            Transition::Next(_) => panic!("Expected the state machine to complete"),
+        }
+        pod_admitter.admit(&pod("second")).unwrap();
+    }
+}
@@ -1,20 +1,31 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use futures::future::join_all;
 use tokio::io::{AsyncRead, AsyncSeek};
 use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, error, info, warn};
 
 use crate::container::{
-    ContainerKey, ContainerMapByName, Handle as ContainerHandle, HandleMap as ContainerHandleMap,
+    stream_output, ContainerKey, ContainerMap, ContainerMapByName, Handle as ContainerHandle,
+    HandleMap as ContainerHandleMap,
 };
-use crate::handle::StopHandler;
+use crate::exec::Session;
+use crate::handle::{ExecHandler, StatsHandler, StopHandler};
+use crate::lifecycle::{Hook, HookType};
 use crate::log::{HandleFactory, Sender};
 use crate::pod::Pod;
 use crate::provider::ProviderError;
+use crate::stats::{ContainerStats, PodStats, VolumeStats};
 
 /// Handle is the top level handle into managing a pod. It manages updating
 /// statuses for the containers in the pod and can be used to stop the pod and
 /// access logs
 pub struct Handle<H, F> {
     container_handles: RwLock<ContainerHandleMap<H, F>>,
+    /// Log handle factories of the previous instance of restarted containers
+    previous_handle_factories: RwLock<ContainerMap<F>>,
     pod: Pod,
 }
 
@@ -33,23 +44,95 @@ impl<H: StopHandler, F> Handle<H, F> {
     pub fn new(container_handles: ContainerHandleMap<H, F>, pod: Pod) -> Self {
         Self {
             container_handles: RwLock::new(container_handles),
+            previous_handle_factories: RwLock::new(ContainerMap::new()),
             pod,
         }
     }
 
-    /// Insert container `Handle` by `ContainerKey`.
+    /// Insert container `Handle` by `ContainerKey`. If the container is being
+    /// restarted, the output of the instance it replaces is retained.
     pub async fn insert_container_handle(&self, key: ContainerKey, value: ContainerHandle<H, F>) {
         let mut map = self.container_handles.write().await;
-        map.insert(key, value);
+        if let Some(previous) = map.insert(key.clone(), value) {
+            self.previous_handle_factories
+                .write()
+                .await
+                .insert(key, previous.into_handle_factory());
+        }
     }
 
     /// Streams output from the specified container into the given sender.
     /// Optionally tails the output and/or continues to watch the file and stream changes.
+    /// If the sender asks for the previous instance of the container, the
+    /// output of the instance that was last restarted is streamed instead.
     pub async fn output<R>(&self, container_name: &str, sender: Sender) -> anyhow::Result<()>
     where
         R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
         F: HandleFactory<R>,
     {
+        if sender.previous() {
+            let previous = self.previous_handle_factories.read().await;
+            let handle_factory =
+                previous
+                    .get_by_name(container_name.to_owned())
+                    .ok_or_else(|| ProviderError::PreviousContainerNotFound {
+                        pod_name: self.pod.name().to_owned(),
+                        container_name: container_name.to_owned(),
+                    })?;
+            return stream_output(handle_factory, sender).await;
+        }
+
+        let handles = self.container_handles.read().await;
+        let handle = handles
+            .get_by_name(container_name.to_owned())
+            .ok_or_else(|| ProviderError::ContainerNotFound {
+                pod_name: self.pod.name().to_owned(),
+                container_name: container_name.to_owned(),
+            })?;
+        handle.output(sender).await
+    }
+
+    /// Runs the command requested by the given session in the specified container, returning
+    /// the exit code of the command once it completes.
+    pub async fn exec(&self, container_name: &str, session: Session) -> anyhow::Result<i32>
+    where
+        H: ExecHandler,
+    {
+        let exec = {
+            let handles = self.container_handles.read().await;
+            let handle = handles
+                .get_by_name(container_name.to_owned())
+                .ok_or_else(|| ProviderError::ContainerNotFound {
+                    pod_name: self.pod.name().to_owned(),
+                    container_name: container_name.to_owned(),
+                })?;
+            handle.exec(session)?
+        };
+        exec.await
+    }
+
+    /// Returns the pod this handle manages.
+    pub fn pod(&self) -> &Pod {
+        &self.pod
+    }
+
+    /// Collects the resource usage of all containers of the pod. Volume usage is
+    /// left to the caller, as only the provider knows where volumes live.
+    pub async fn stats(&self, volumes: Vec<VolumeStats>) -> PodStats
+    where
+        H: StatsHandler,
+    {
+        let handles = self.container_handles.read().await;
+        let containers = handles
+            .iter()
+            .map(|(key, handle)| ContainerStats::new(&key.name(), &handle.usage()))
+            .collect();
+        PodStats::new(&self.pod, containers, volumes)
+    }
+
+    /// Signal a single container of the pod to stop. Use the container's status to
+    /// determine when it has exited.
+    pub async fn stop_container(&self, container_name: &str) -> anyhow::Result<()> {
         let mut handles = self.container_handles.write().await;
         let handle = handles
             .get_mut_by_name(container_name.to_owned())
@@ -57,7 +140,8 @@ impl<H: StopHandler, F> Handle<H, F> {
                 pod_name: self.pod.name().to_owned(),
                 container_name: container_name.to_owned(),
             })?;
-        handle.output(sender).await
+        info!(container_name, "Stopping container");
+        handle.stop().await
     }
 
     /// Signal the pod and all its running containers to stop and wait for them
@@ -81,6 +165,80 @@ impl<H: StopHandler, F> Handle<H, F> {
         Ok(())
     }
 
+    /// Gracefully terminate the pod. The preStop hooks of its containers are
+    /// run and the containers are asked to shut down. Containers that are
+    /// still running once the grace period has passed, as well as containers
+    /// that cannot be shut down gracefully, are stopped. Hooks without a host
+    /// are run against `host`.
+    pub async fn terminate(&self, grace_period: Duration, host: &str) -> anyhow::Result<()>
+    where
+        H: ExecHandler + Send + Sync,
+        F: Send + Sync,
+    {
+        let deadline = Instant::now() + grace_period;
+
+        // Run the hooks under a read lock so that logs and exec remain available
+        let hooked = {
+            let handles = self.container_handles.read().await;
+            let hooks: Vec<_> = handles
+                .iter()
+                .filter_map(|(key, handle)| {
+                    let container = self.pod.find_container(key)?;
+                    match Hook::for_container(HookType::PreStop, &container, host) {
+                        Ok(hook) => hook.map(|hook| (key.clone(), hook, handle)),
+                        Err(e) => {
+                            warn!(container_name = %key, error = %e, "Invalid preStop hook");
+                            None
+                        }
+                    }
+                })
+                .collect();
+            let runs = hooks.iter().map(|(key, hook, handle)| async move {
+                info!(container_name = %key, "Running preStop hook");
+                if let Err(e) = hook.run(grace_period, Some(*handle)).await {
+                    warn!(container_name = %key, error = %e, "PreStop hook failed");
+                }
+            });
+            if timeout_at(deadline, join_all(runs)).await.is_err() {
+                warn!("PreStop hooks did not complete within the grace period");
+            }
+            hooks
+                .into_iter()
+                .map(|(key, _, _)| key)
+                .collect::<HashSet<_>>()
+        };
+
+        let mut handles = self.container_handles.write().await;
+        let terminations = handles.iter_mut().map(|(key, handle)| {
+            let hooked = hooked.contains(key);
+            async move {
+                let signalled = match handle.shutdown().await {
+                    Ok(signalled) => signalled,
+                    Err(e) => {
+                        warn!(container_name = %key, error = %e, "Unable to shut down container");
+                        false
+                    }
+                };
+                // Nothing will make a container exit on its own unless it was
+                // told to, so there is no point in waiting for it
+                if signalled || hooked {
+                    info!(container_name = %key, ?grace_period, "Waiting for container to exit");
+                    if timeout_at(deadline, handle.wait()).await.is_ok() {
+                        debug!(container_name = %key, "Container exited within the grace period");
+                        return;
+                    }
+                    info!(container_name = %key, "Grace period expired");
+                }
+                info!(container_name = %key, "Stopping container");
+                if let Err(e) = handle.stop().await {
+                    error!(container_name = %key, error = %e, "Error while trying to stop container");
+                }
+            }
+        });
+        join_all(terminations).await;
+        Ok(())
+    }
+
     /// Wait for all containers in the pod to complete
     pub async fn wait(&mut self) -> anyhow::Result<()> {
         let mut handles = self.container_handles.write().await;
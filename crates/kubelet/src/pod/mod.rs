@@ -4,7 +4,7 @@ pub mod state;
 mod status;
 
 pub use handle::Handle;
-pub(crate) use status::initialize_pod_container_statuses;
+pub(crate) use status::{initialize_pod_container_statuses, patch_readiness_conditions};
 pub use status::{
     make_registered_status, make_status, make_status_with_containers, patch_status, Phase, Status,
 };
@@ -19,6 +19,11 @@ use kube::api::{Resource, ResourceExt};
 use serde::Deserialize;
 use serde::Serialize;
 
+const DEFAULT_TERMINATION_GRACE_PERIOD_SECONDS: i64 = 30;
+/// Pods of the system-cluster-critical and system-node-critical priority
+/// classes have at least this priority.
+pub(crate) const SYSTEM_CRITICAL_PRIORITY: i32 = 2_000_000_000;
+
 /// A Kubernetes Pod
 ///
 /// This is a new type around the k8s_openapi Pod definition
@@ -67,6 +72,53 @@ impl Pod {
         spec.volumes.as_ref()
     }
 
+    /// Get the pod's restart policy
+    ///
+    /// Returns [`RestartPolicy::Always`] if no policy was explicitly set
+    pub fn restart_policy(&self) -> RestartPolicy {
+        let policy = self
+            .kube_pod
+            .spec
+            .as_ref()
+            .and_then(|s| s.restart_policy.as_deref());
+        match policy {
+            Some("OnFailure") => RestartPolicy::OnFailure,
+            Some("Never") => RestartPolicy::Never,
+            _ => RestartPolicy::Always,
+        }
+    }
+
+    /// Get the name of the node the pod is scheduled to
+    pub fn node_name(&self) -> Option<&str> {
+        let spec = self.kube_pod.spec.as_ref()?;
+        spec.node_name.as_deref()
+    }
+
+    /// Get the priority of the pod, resolved from its priority class by the
+    /// API server. Pods without a priority have priority 0.
+    pub fn priority(&self) -> i32 {
+        self.kube_pod
+            .spec
+            .as_ref()
+            .and_then(|s| s.priority)
+            .unwrap_or_default()
+    }
+
+    /// Indicate if this pod is critical to the cluster or node, based on its
+    /// priority.
+    pub fn is_critical(&self) -> bool {
+        self.priority() >= SYSTEM_CRITICAL_PRIORITY
+    }
+
+    /// Get the time the pod was created
+    pub fn creation_timestamp(&self) -> Option<&DateTime<Utc>> {
+        self.kube_pod
+            .meta()
+            .creation_timestamp
+            .as_ref()
+            .map(|t| &t.0)
+    }
+
     /// Get the pod's host ip
     pub fn host_ip(&self) -> Option<&str> {
         let status = self.kube_pod.status.as_ref()?;
@@ -149,6 +201,24 @@ impl Pod {
             .map(|t| &t.0)
     }
 
+    /// Get the time containers are given to shut down when the pod is deleted.
+    /// The grace period of the deletion takes precedence over the
+    /// terminationGracePeriodSeconds of the pod spec, which defaults to 30s.
+    pub fn termination_grace_period(&self) -> std::time::Duration {
+        let seconds = self
+            .kube_pod
+            .meta()
+            .deletion_grace_period_seconds
+            .or_else(|| {
+                self.kube_pod
+                    .spec
+                    .as_ref()
+                    .and_then(|s| s.termination_grace_period_seconds)
+            })
+            .unwrap_or(DEFAULT_TERMINATION_GRACE_PERIOD_SECONDS);
+        std::time::Duration::from_secs(seconds.max(0) as u64)
+    }
+
     /// Find container by `ContainerKey` and return it.
     pub fn find_container(&self, key: &ContainerKey) -> Option<Container> {
         let containers: Vec<Container> = if key.is_init() {
@@ -182,6 +252,24 @@ impl Pod {
         }
     }
 
+    /// Gets the restart count last reported in the container's status, or 0 if
+    /// no status has been reported for the container yet.
+    pub fn container_restart_count(&self, key: &ContainerKey) -> i32 {
+        let status = match self.kube_pod.status.as_ref() {
+            Some(status) => status,
+            None => return 0,
+        };
+        let statuses = if key.is_init() {
+            status.init_container_statuses.as_ref()
+        } else {
+            status.container_statuses.as_ref()
+        };
+        statuses
+            .and_then(|s| s.iter().find(|status| status.name == key.name()))
+            .map(|status| status.restart_count)
+            .unwrap_or(0)
+    }
+
     /// Get a pod's containers
     pub fn containers(&self) -> Vec<Container> {
         self.kube_pod
@@ -263,6 +351,29 @@ impl std::convert::From<Pod> for KubePod {
     }
 }
 
+/// Specifies whether the containers of a pod should be restarted after they exit
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RestartPolicy {
+    /// Always restart containers, regardless of how they exited
+    Always,
+    /// Restart containers only if they exited with an error
+    OnFailure,
+    /// Never restart containers
+    Never,
+}
+
+impl RestartPolicy {
+    /// Whether a container that exited with or without error (as indicated by
+    /// `failed`) should be restarted under this policy.
+    pub fn should_restart(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Never => false,
+        }
+    }
+}
+
 /// PodKey is a unique human readable key for storing a handle to a pod in a hash.
 #[derive(Hash, Ord, Eq, PartialOrd, PartialEq, Debug, Clone, Default)]
 pub struct PodKey {
@@ -332,3 +443,56 @@ lazy_static::lazy_static! {
     static ref EMPTY_VEC: Vec<KubeContainer> = Vec::new();
     static ref EMPTY_VOLUMES: Vec<KubeVolume> = Vec::new();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn pod(value: serde_json::Value) -> Pod {
+        let pod: KubePod = serde_json::from_value(value).unwrap();
+        Pod::from(pod)
+    }
+
+    #[test]
+    fn test_termination_grace_period() {
+        let default = pod(serde_json::json!({ "metadata": { "name": "web" } }));
+        assert_eq!(default.termination_grace_period(), Duration::from_secs(30));
+
+        let spec = pod(serde_json::json!({
+            "metadata": { "name": "web" },
+            "spec": { "containers": [], "terminationGracePeriodSeconds": 10 }
+        }));
+        assert_eq!(spec.termination_grace_period(), Duration::from_secs(10));
+
+        let deleted = pod(serde_json::json!({
+            "metadata": { "name": "web", "deletionGracePeriodSeconds": 0 },
+            "spec": { "containers": [], "terminationGracePeriodSeconds": 10 }
+        }));
+        assert_eq!(deleted.termination_grace_period(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_container_restart_count() {
+        let pod = pod(serde_json::json!({
+            "metadata": { "name": "web" },
+            "status": {
+                "containerStatuses": [
+                    { "name": "app", "image": "app", "imageID": "", "ready": true, "restartCount": 4 }
+                ]
+            }
+        }));
+        assert_eq!(
+            pod.container_restart_count(&ContainerKey::App("app".into())),
+            4
+        );
+        assert_eq!(
+            pod.container_restart_count(&ContainerKey::App("other".into())),
+            0
+        );
+        assert_eq!(
+            pod.container_restart_count(&ContainerKey::Init("app".into())),
+            0
+        );
+    }
+}
@@ -2,9 +2,11 @@
 
 use super::Pod;
 use crate::container::make_initial_container_status;
+use chrono::{DateTime, Utc};
 use k8s_openapi::api::core::v1::ContainerStatus as KubeContainerStatus;
 use k8s_openapi::api::core::v1::Pod as KubePod;
 use k8s_openapi::api::core::v1::PodCondition as KubePodCondition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
 use krator::{Manifest, ObjectStatus};
 use kube::api::PatchParams;
 use kube::Api;
@@ -30,6 +32,81 @@ pub async fn patch_status(api: &Api<KubePod>, name: &str, status: Status) {
     }
 }
 
+/// Update the `ContainersReady` and `Ready` conditions of a pod to reflect the
+/// readiness of its containers, if they changed.
+pub(crate) async fn patch_readiness_conditions(api: &Api<KubePod>, pod: &KubePod) {
+    if let (Some(name), Some(conditions)) = (
+        pod.metadata.name.as_deref(),
+        readiness_conditions(pod, Utc::now()),
+    ) {
+        debug!(
+            pod_name = name,
+            ?conditions,
+            "Updating pod readiness conditions"
+        );
+        patch_status(
+            api,
+            name,
+            StatusBuilder::new().conditions(conditions).build(),
+        )
+        .await;
+    }
+}
+
+/// Computes the readiness conditions of a pod from its container statuses, or
+/// `None` if they match the conditions already set on the pod.
+fn readiness_conditions(pod: &KubePod, now: DateTime<Utc>) -> Option<Vec<KubePodCondition>> {
+    let status = pod.status.clone().unwrap_or_default();
+    let container_statuses = status.container_statuses.unwrap_or_default();
+    let unready: Vec<&str> = pod
+        .spec
+        .iter()
+        .flat_map(|spec| spec.containers.iter())
+        .map(|c| c.name.as_str())
+        .filter(|name| {
+            !container_statuses
+                .iter()
+                .any(|s| s.name == *name && s.ready)
+        })
+        .collect();
+    let ready = if unready.is_empty() { "True" } else { "False" };
+
+    let existing = status.conditions.unwrap_or_default();
+    let unchanged = ["ContainersReady", "Ready"].iter().all(|type_| {
+        existing
+            .iter()
+            .any(|c| c.type_ == *type_ && c.status == ready)
+    });
+    if unchanged {
+        return None;
+    }
+
+    let (reason, message) = if unready.is_empty() {
+        (None, None)
+    } else {
+        (
+            Some("ContainersNotReady".to_string()),
+            Some(format!(
+                "containers with unready status: [{}]",
+                unready.join(" ")
+            )),
+        )
+    };
+    Some(
+        ["ContainersReady", "Ready"]
+            .iter()
+            .map(|type_| KubePodCondition {
+                type_: type_.to_string(),
+                status: ready.to_string(),
+                last_transition_time: Some(Time(now)),
+                reason: reason.clone(),
+                message: message.clone(),
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
 const MAX_STATUS_INIT_RETRIES: usize = 5;
 
 /// Initializes Pod container status array and wait for Pod reflection to update.
@@ -304,3 +381,59 @@ impl ObjectStatus for Status {
             .build()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::{
+        Container as KubeContainer, ContainerStatus as KubeContainerStatus, PodSpec, PodStatus,
+    };
+
+    fn pod(ready: &[bool], conditions: Vec<KubePodCondition>) -> KubePod {
+        KubePod {
+            spec: Some(PodSpec {
+                containers: (0..ready.len())
+                    .map(|i| KubeContainer {
+                        name: format!("c{}", i),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+            status: Some(PodStatus {
+                container_statuses: Some(
+                    ready
+                        .iter()
+                        .enumerate()
+                        .map(|(i, ready)| KubeContainerStatus {
+                            name: format!("c{}", i),
+                            ready: *ready,
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                conditions: Some(conditions),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_readiness_conditions() {
+        let now = Utc::now();
+        let conditions = readiness_conditions(&pod(&[true, false], vec![]), now).unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert!(conditions.iter().all(|c| c.status == "False"));
+        assert_eq!(
+            conditions[1].message.as_deref(),
+            Some("containers with unready status: [c1]")
+        );
+
+        let conditions = readiness_conditions(&pod(&[true, true], conditions), now).unwrap();
+        assert!(conditions.iter().all(|c| c.status == "True"));
+
+        // Nothing to patch if the readiness did not change
+        assert!(readiness_conditions(&pod(&[true, true], conditions), now).is_none());
+    }
+}
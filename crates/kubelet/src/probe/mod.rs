@@ -0,0 +1,537 @@
+//! `probe` implements container liveness, readiness and startup probes.
+//!
+//! A [`Prober`] runs the probes configured for a container in the background
+//! and reports [`ProbeEvent`]s describing changes in the container's health.
+//! Providers are responsible for acting on those events, generally by patching
+//! the container status with [`crate::container::patch_container_ready`] and
+//! [`crate::container::patch_container_started`] and by restarting containers
+//! that became unhealthy.
+use std::sync::Arc;
+use std::time::Duration;
+
+use k8s_openapi::api::core::v1::{ExecAction, HTTPGetAction, Probe as KubeProbe, TCPSocketAction};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, instrument, warn};
+
+use crate::container::Container;
+use crate::exec::{Options as ExecOptions, Session};
+use crate::handle::ExecHandler;
+
+// TODO: ~magic~ number
+const EVENT_BUFFER_SIZE: usize = 8;
+
+/// The kinds of probes that can be configured for a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeType {
+    /// Determines whether the container should be restarted.
+    Liveness,
+    /// Determines whether the container is ready to serve requests.
+    Readiness,
+    /// Determines whether the container has finished starting up. Other probes
+    /// are not run until this probe succeeds.
+    Startup,
+}
+
+impl std::fmt::Display for ProbeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeType::Liveness => write!(f, "Liveness"),
+            ProbeType::Readiness => write!(f, "Readiness"),
+            ProbeType::Startup => write!(f, "Startup"),
+        }
+    }
+}
+
+/// The outcome of running a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// The probe succeeded.
+    Success,
+    /// The probe failed, with a human readable reason.
+    Failure(String),
+}
+
+/// A change in the health of a container, as reported by a [`Prober`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeEvent {
+    /// The startup probe succeeded.
+    Started,
+    /// The result of the readiness probe changed.
+    Ready(bool),
+    /// The liveness or startup probe failed too many times in a row. The
+    /// container should be restarted.
+    Unhealthy(ProbeType, String),
+}
+
+/// An action run against a container, shared by probes and lifecycle hooks.
+#[derive(Debug, Clone)]
+pub(crate) enum Handler {
+    HttpGet {
+        url: url::Url,
+        headers: Vec<(String, String)>,
+    },
+    TcpSocket {
+        host: String,
+        port: u16,
+    },
+    Exec {
+        command: Vec<String>,
+    },
+}
+
+/// A probe configured for a container, with Kubernetes defaults applied.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    probe_type: ProbeType,
+    handler: Handler,
+    initial_delay: Duration,
+    period: Duration,
+    timeout: Duration,
+    success_threshold: u32,
+    failure_threshold: u32,
+}
+
+impl Probe {
+    /// Creates a probe from its Kubernetes definition. Probes without a host
+    /// are run against `host`, and named ports are resolved from the ports of
+    /// the given container.
+    pub fn new(
+        probe_type: ProbeType,
+        probe: &KubeProbe,
+        container: &Container,
+        host: &str,
+    ) -> anyhow::Result<Self> {
+        let handler = Handler::new(
+            probe.exec.as_ref(),
+            probe.http_get.as_ref(),
+            probe.tcp_socket.as_ref(),
+            container,
+            host,
+        )
+        .map_err(|e| anyhow::anyhow!("{} probe: {:#}", probe_type, e))?;
+
+        Ok(Probe {
+            probe_type,
+            handler,
+            initial_delay: seconds(probe.initial_delay_seconds, 0),
+            period: seconds(probe.period_seconds, 10),
+            timeout: seconds(probe.timeout_seconds, 1),
+            success_threshold: threshold(probe.success_threshold),
+            failure_threshold: threshold(probe.failure_threshold.or(Some(3))),
+        })
+    }
+
+    /// The type of the probe.
+    pub fn probe_type(&self) -> ProbeType {
+        self.probe_type
+    }
+
+    /// Runs the probe once. Exec probes fail if no `exec` handler is given.
+    pub async fn run(&self, exec: Option<&(dyn ExecHandler + Send + Sync)>) -> ProbeResult {
+        match tokio::time::timeout(
+            self.timeout,
+            self.handler.run("kube-probe", self.timeout, exec),
+        )
+        .await
+        {
+            Ok(Ok(())) => ProbeResult::Success,
+            Ok(Err(e)) => ProbeResult::Failure(format!("{:#}", e)),
+            Err(_) => {
+                ProbeResult::Failure(format!("probe timed out after {}s", self.timeout.as_secs()))
+            }
+        }
+    }
+}
+
+impl Handler {
+    /// Creates a handler from the actions of a probe or lifecycle hook, of
+    /// which only one may be set. Actions without a host are run against
+    /// `host`, and named ports are resolved from the ports of the container.
+    pub(crate) fn new(
+        exec: Option<&ExecAction>,
+        http_get: Option<&HTTPGetAction>,
+        tcp_socket: Option<&TCPSocketAction>,
+        container: &Container,
+        host: &str,
+    ) -> anyhow::Result<Self> {
+        if let Some(action) = http_get {
+            let scheme = action.scheme.as_deref().unwrap_or("HTTP").to_lowercase();
+            let host = action.host.as_deref().unwrap_or(host);
+            let port = resolve_port(&action.port, container)?;
+            let path = action.path.as_deref().unwrap_or("/");
+            let url = url::Url::parse(&format!("{}://{}:{}", scheme, host, port))?.join(path)?;
+            let headers = action
+                .http_headers
+                .iter()
+                .flatten()
+                .map(|h| (h.name.clone(), h.value.clone()))
+                .collect();
+            Ok(Handler::HttpGet { url, headers })
+        } else if let Some(action) = tcp_socket {
+            Ok(Handler::TcpSocket {
+                host: action.host.as_deref().unwrap_or(host).to_owned(),
+                port: resolve_port(&action.port, container)?,
+            })
+        } else if let Some(action) = exec {
+            let command = action.command.clone().unwrap_or_default();
+            if command.is_empty() {
+                anyhow::bail!("exec action has no command");
+            }
+            Ok(Handler::Exec { command })
+        } else {
+            anyhow::bail!("no supported handler is set");
+        }
+    }
+
+    /// Runs the action once. HTTP requests are sent with the given user agent
+    /// and fail if they take longer than `timeout`, and exec actions fail if no
+    /// `exec` handler is given.
+    pub(crate) async fn run(
+        &self,
+        user_agent: &str,
+        timeout: Duration,
+        exec: Option<&(dyn ExecHandler + Send + Sync)>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Handler::HttpGet { url, headers } => {
+                let client = http_client(timeout)?;
+                let mut request = client.get(url.clone()).header("User-Agent", user_agent);
+                for (name, value) in headers {
+                    request = request.header(name.as_str(), value.as_str());
+                }
+                let status = request.send().await?.status();
+                if status.is_success() || status.is_redirection() {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!(
+                        "HTTP request failed with statuscode: {}",
+                        status
+                    ))
+                }
+            }
+            Handler::TcpSocket { host, port } => {
+                tokio::net::TcpStream::connect((host.as_str(), *port)).await?;
+                Ok(())
+            }
+            Handler::Exec { command } => {
+                let exec = exec.ok_or_else(|| anyhow::anyhow!("exec actions are not supported"))?;
+                let opts = ExecOptions {
+                    command: command.clone(),
+                    ..Default::default()
+                };
+                // Output is discarded, so nothing will ever be sent on this channel
+                let (output_tx, _output_rx) = mpsc::channel(1);
+                match exec.exec(Session::new(opts, None, output_tx))?.await? {
+                    0 => Ok(()),
+                    code => Err(anyhow::anyhow!(
+                        "command {:?} exited with code {}",
+                        command,
+                        code
+                    )),
+                }
+            }
+        }
+    }
+}
+
+fn seconds(value: Option<i32>, default: u64) -> Duration {
+    Duration::from_secs(value.map(|v| v.max(0) as u64).unwrap_or(default))
+}
+
+fn threshold(value: Option<i32>) -> u32 {
+    value.map(|v| v.max(1) as u32).unwrap_or(1)
+}
+
+fn resolve_port(port: &IntOrString, container: &Container) -> anyhow::Result<u16> {
+    let port = match port {
+        IntOrString::Int(p) => *p,
+        IntOrString::String(name) => container
+            .ports()
+            .iter()
+            .flat_map(|ports| ports.iter())
+            .find(|p| p.name.as_deref() == Some(name.as_str()))
+            .map(|p| p.container_port)
+            .ok_or_else(|| {
+                anyhow::anyhow!("container {} has no port named {}", container.name(), name)
+            })?,
+    };
+    u16::try_from(port).map_err(|_| anyhow::anyhow!("invalid port {}", port))
+}
+
+fn http_client(timeout: Duration) -> anyhow::Result<reqwest::Client> {
+    let builder = reqwest::Client::builder().timeout(timeout);
+    // Like the upstream kubelet, HTTPS probes do not verify certificates
+    #[cfg(any(feature = "kube-native-tls", feature = "rustls-tls"))]
+    let builder = builder.danger_accept_invalid_certs(true);
+    Ok(builder.build()?)
+}
+
+/// Runs a probe periodically and tracks its consecutive results.
+struct Worker {
+    probe: Probe,
+    first_run: bool,
+}
+
+impl Worker {
+    fn new(probe: Probe) -> Self {
+        Worker {
+            probe,
+            first_run: true,
+        }
+    }
+
+    /// Runs the probe until it has either succeeded `success_threshold` or
+    /// failed `failure_threshold` times in a row.
+    async fn next_result(&mut self, exec: Option<&(dyn ExecHandler + Send + Sync)>) -> ProbeResult {
+        let mut successes = 0;
+        let mut failures = 0;
+        loop {
+            let delay = if self.first_run {
+                self.probe.initial_delay
+            } else {
+                self.probe.period
+            };
+            self.first_run = false;
+            tokio::time::sleep(delay).await;
+
+            match self.probe.run(exec).await {
+                ProbeResult::Success => {
+                    failures = 0;
+                    successes += 1;
+                    if successes >= self.probe.success_threshold {
+                        return ProbeResult::Success;
+                    }
+                }
+                ProbeResult::Failure(reason) => {
+                    debug!(probe_type = %self.probe.probe_type, %reason, "Probe failed");
+                    successes = 0;
+                    failures += 1;
+                    if failures >= self.probe.failure_threshold {
+                        return ProbeResult::Failure(reason);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs the probes of a single container in the background. The probes are
+/// stopped when the `Prober` is dropped.
+pub struct Prober {
+    events: mpsc::Receiver<ProbeEvent>,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for Prober {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Prober").finish()
+    }
+}
+
+impl Prober {
+    /// Starts running the probes configured for the given container. Probes
+    /// without a host are run against `host`, and exec probes are run using
+    /// the given `exec` handler.
+    pub fn start(
+        container: &Container,
+        host: &str,
+        exec: Option<Arc<dyn ExecHandler + Send + Sync>>,
+    ) -> anyhow::Result<Self> {
+        let probe = |probe_type, probe: Option<&KubeProbe>| {
+            probe
+                .map(|p| Probe::new(probe_type, p, container, host))
+                .transpose()
+        };
+        let startup = probe(ProbeType::Startup, container.startup_probe())?;
+        let liveness = probe(ProbeType::Liveness, container.liveness_probe())?;
+        let readiness = probe(ProbeType::Readiness, container.readiness_probe())?;
+
+        let (tx, events) = mpsc::channel(EVENT_BUFFER_SIZE);
+        let task = tokio::spawn(run_probes(
+            container.name().to_owned(),
+            startup,
+            liveness,
+            readiness,
+            exec,
+            tx,
+        ));
+        Ok(Prober { events, task })
+    }
+
+    /// Waits for the next probe event. Returns `None` once no more events will
+    /// be reported.
+    pub async fn next_event(&mut self) -> Option<ProbeEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for Prober {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[instrument(level = "info", skip(startup, liveness, readiness, exec, tx))]
+async fn run_probes(
+    container_name: String,
+    startup: Option<Probe>,
+    liveness: Option<Probe>,
+    readiness: Option<Probe>,
+    exec: Option<Arc<dyn ExecHandler + Send + Sync>>,
+    tx: mpsc::Sender<ProbeEvent>,
+) {
+    let exec = exec.as_deref();
+
+    if let Some(probe) = startup {
+        let event = match Worker::new(probe).next_result(exec).await {
+            ProbeResult::Success => ProbeEvent::Started,
+            ProbeResult::Failure(reason) => ProbeEvent::Unhealthy(ProbeType::Startup, reason),
+        };
+        info!(?event, "Startup probe completed");
+        let unhealthy = matches!(event, ProbeEvent::Unhealthy(..));
+        if tx.send(event).await.is_err() || unhealthy {
+            return;
+        }
+    }
+
+    let liveness = async {
+        if let Some(probe) = liveness {
+            let mut worker = Worker::new(probe);
+            loop {
+                if let ProbeResult::Failure(reason) = worker.next_result(exec).await {
+                    warn!(%reason, "Liveness probe failed");
+                    tx.send(ProbeEvent::Unhealthy(ProbeType::Liveness, reason))
+                        .await
+                        .ok();
+                    return;
+                }
+            }
+        }
+    };
+
+    let readiness = async {
+        if let Some(probe) = readiness {
+            let mut worker = Worker::new(probe);
+            let mut ready = false;
+            loop {
+                let result = worker.next_result(exec).await;
+                let now_ready = result == ProbeResult::Success;
+                if now_ready != ready {
+                    info!(ready = now_ready, ?result, "Readiness changed");
+                    ready = now_ready;
+                    if tx.send(ProbeEvent::Ready(ready)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    };
+
+    tokio::join!(liveness, readiness);
+    // Keep the channel open so that consumers do not see a hang up while the
+    // container is still running
+    tx.closed().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::{
+        Container as KubeContainer, ContainerPort, ExecAction, HTTPGetAction, TCPSocketAction,
+    };
+
+    fn container() -> Container {
+        Container::new(&KubeContainer {
+            name: "app".to_owned(),
+            ports: Some(vec![ContainerPort {
+                name: Some("http".to_owned()),
+                container_port: 8080,
+                ..Default::default()
+            }]),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_http_probe_defaults() {
+        let kube_probe = KubeProbe {
+            http_get: Some(HTTPGetAction {
+                path: Some("/healthz".to_owned()),
+                port: IntOrString::String("http".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let probe = Probe::new(ProbeType::Liveness, &kube_probe, &container(), "10.0.0.1").unwrap();
+        match probe.handler {
+            Handler::HttpGet { url, .. } => {
+                assert_eq!(url.as_str(), "http://10.0.0.1:8080/healthz")
+            }
+            h => panic!("unexpected handler {:?}", h),
+        }
+        assert_eq!(probe.initial_delay, Duration::from_secs(0));
+        assert_eq!(probe.period, Duration::from_secs(10));
+        assert_eq!(probe.timeout, Duration::from_secs(1));
+        assert_eq!(probe.success_threshold, 1);
+        assert_eq!(probe.failure_threshold, 3);
+    }
+
+    #[test]
+    fn test_probe_validation() {
+        let unknown_port = KubeProbe {
+            tcp_socket: Some(TCPSocketAction {
+                port: IntOrString::String("grpc".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(Probe::new(ProbeType::Readiness, &unknown_port, &container(), "h").is_err());
+
+        let empty_exec = KubeProbe {
+            exec: Some(ExecAction { command: None }),
+            ..Default::default()
+        };
+        assert!(Probe::new(ProbeType::Readiness, &empty_exec, &container(), "h").is_err());
+        assert!(Probe::new(
+            ProbeType::Readiness,
+            &KubeProbe::default(),
+            &container(),
+            "h"
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let kube_probe = KubeProbe {
+            tcp_socket: Some(TCPSocketAction {
+                port: IntOrString::Int(port as i32),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let probe =
+            Probe::new(ProbeType::Readiness, &kube_probe, &container(), "127.0.0.1").unwrap();
+        assert_eq!(probe.run(None).await, ProbeResult::Success);
+
+        drop(listener);
+        assert!(matches!(probe.run(None).await, ProbeResult::Failure(_)));
+    }
+
+    #[tokio::test]
+    async fn test_exec_probe_without_handler() {
+        let kube_probe = KubeProbe {
+            exec: Some(ExecAction {
+                command: Some(vec!["healthy".to_owned()]),
+            }),
+            ..Default::default()
+        };
+        let probe =
+            Probe::new(ProbeType::Liveness, &kube_probe, &container(), "127.0.0.1").unwrap();
+        assert!(matches!(probe.run(None).await, ProbeResult::Failure(_)));
+    }
+}
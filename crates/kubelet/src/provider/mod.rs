@@ -1,20 +1,28 @@
 //! Traits and types needed to create backend providers for a Kubelet
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use async_trait::async_trait;
-use k8s_openapi::api::core::v1::{ConfigMap, EnvVarSource, Secret};
+use k8s_openapi::api::core::v1::{
+    ConfigMap, EnvFromSource, EnvVarSource, ResourceFieldSelector, Secret,
+};
 use kube::api::Api;
+use serde::de::DeserializeOwned;
 use std::sync::Arc;
 use thiserror::Error;
-use tracing::{debug, error, info};
+use tracing::{debug, info, warn};
 
+use crate::config::Config;
 use crate::container::Container;
+use crate::event::{EventType, Recorder};
+use crate::exec::Session as ExecSession;
 use crate::log::Sender;
 use crate::node::Builder;
 use crate::plugin_watcher::PluginRegistry;
 use crate::pod::Pod;
 use crate::pod::Status as PodStatus;
-use crate::resources::DeviceManager;
+use crate::resources::{DeviceManager, PodAdmitter};
+use crate::stats::PodStats;
+use crate::volume::downward::{data_from_resource_ref, is_unset_limit, node_allocatable};
 use krator::{ObjectState, State};
 
 /// A back-end for a Kubelet.
@@ -36,7 +44,7 @@ use krator::{ObjectState, State};
 /// use kubelet::resources::DeviceManager;
 /// use kubelet::plugin_watcher::PluginRegistry;
 /// use kubelet::pod::{Pod, Status};
-/// use kubelet::provider::{DevicePluginSupport, Provider, PluginSupport};
+/// use kubelet::provider::{AdmissionSupport, DevicePluginSupport, Provider, PluginSupport};
 /// use kubelet::pod::state::Stub;
 /// use kubelet::pod::state::prelude::*;
 /// use std::sync::Arc;
@@ -86,11 +94,18 @@ use krator::{ObjectState, State};
 ///         None
 ///     }
 /// }
+///
+/// impl AdmissionSupport for ProviderState {}
 /// ```
 #[async_trait]
 pub trait Provider: Sized + Send + Sync + 'static {
     /// The state of the provider itself.
-    type ProviderState: 'static + Send + Sync + PluginSupport + DevicePluginSupport;
+    type ProviderState: 'static
+        + Send
+        + Sync
+        + PluginSupport
+        + DevicePluginSupport
+        + AdmissionSupport;
 
     /// The state that is passed between Pod state handlers.
     type PodState: ObjectState<
@@ -139,6 +154,15 @@ pub trait Provider: Sized + Send + Sync + 'static {
         Ok(())
     }
 
+    /// Hook to allow the provider to react to the Kubelet configuration being
+    /// reloaded, e.g. on SIGHUP.
+    ///
+    /// Only settings that can safely be changed at runtime are reloaded, such
+    /// as the insecure registries used by the module store.
+    async fn reconfigure(&self, _config: &Config) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// Given a Pod, get back the logs for the associated workload.
     async fn logs(
         &self,
@@ -148,11 +172,29 @@ pub trait Provider: Sized + Send + Sync + 'static {
         sender: Sender,
     ) -> anyhow::Result<()>;
 
-    /// Execute a given command on a workload and then return the result.
+    /// Execute the command requested by the given session in a workload's container,
+    /// streaming its input and output through the session, and then return its exit code.
     ///
     /// The default implementation of this returns a message that this feature is
     /// not available. Override this only when there is an implementation.
-    async fn exec(&self, _pod: Pod, _command: String) -> anyhow::Result<Vec<String>> {
+    async fn exec(
+        &self,
+        _namespace: String,
+        _pod: String,
+        _container: String,
+        _session: ExecSession,
+    ) -> anyhow::Result<i32> {
+        Err(NotImplementedError.into())
+    }
+
+    /// Get the resource usage of all pods running on the node, as served on
+    /// the summary API (`/stats/summary`). Node level statistics are collected
+    /// by the kubelet.
+    ///
+    /// The default implementation of this returns a message that this feature is
+    /// not available, in which case no pods are reported. Override this only when
+    /// there is an implementation.
+    async fn stats(&self) -> anyhow::Result<Vec<PodStats>> {
         Err(NotImplementedError.into())
     }
 
@@ -167,30 +209,9 @@ pub trait Provider: Sized + Send + Sync + 'static {
         container: &Container,
         pod: &Pod,
         client: &kube::Client,
-    ) -> HashMap<String, String> {
-        let mut env = HashMap::new();
-        let vars = match container.env() {
-            Some(e) => e,
-            None => return env,
-        };
-
-        for env_var in vars.clone().into_iter() {
-            let key = env_var.name;
-            let value = match env_var.value {
-                Some(v) => v,
-                None => {
-                    on_missing_env_value(
-                        env_var.value_from,
-                        client,
-                        pod.namespace(),
-                        &field_map(pod),
-                    )
-                    .await
-                }
-            };
-            env.insert(key, value);
-        }
-        env
+        recorder: Option<&Recorder>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        env_vars(container, pod, client, recorder).await
     }
 }
 
@@ -218,108 +239,287 @@ pub trait DevicePluginSupport {
     }
 }
 
+/// A trait for specifying whether pods are admitted against the resources of
+/// the node. Defaults to `None`
+pub trait AdmissionSupport {
+    /// Fetch the admitter that tracks the resources requested by pods
+    fn pod_admitter(&self) -> Option<Arc<PodAdmitter>> {
+        None
+    }
+}
+
+/// A trait for specifying whether events are recorded about pods. Defaults to
+/// `None`
+pub trait EventSupport {
+    /// Fetch the recorder used to record events about pods
+    fn event_recorder(&self) -> Option<Recorder> {
+        None
+    }
+}
+
 /// Resolve the environment variables for a container.
 ///
 /// This generally should not be overwritten unless you need to handle
 /// environment variable resolution in a special way, such as allowing
 /// custom Downward API fields.
 ///
+/// Variables from `envFrom` sources are resolved first, in order, so that
+/// explicitly set `env` variables take precedence over them. Keys of `envFrom`
+/// sources that are not valid variable names are skipped, which is recorded
+/// as a warning event if a recorder is given.
+///
+/// Referenced ConfigMaps, Secrets and keys that do not exist are skipped if
+/// the reference is optional. Otherwise an error is returned, in which case the
+/// container should not be started until the reference can be resolved, as
+/// with `CreateContainerConfigError` in upstream Kubernetes.
+///
 /// It is safe to call from within your own providers.
 pub async fn env_vars(
     container: &Container,
     pod: &Pod,
     client: &kube::Client,
-) -> HashMap<String, String> {
+    recorder: Option<&Recorder>,
+) -> anyhow::Result<HashMap<String, String>> {
     let mut env = HashMap::new();
+    for env_from in container.env_from().into_iter().flatten() {
+        env.extend(env_from_source(env_from, pod, client, recorder).await?);
+    }
     let vars = match container.env() {
         Some(e) => e,
-        None => return env,
+        None => return Ok(env),
     };
 
     for env_var in vars.clone().into_iter() {
         let key = env_var.name;
         let value = match env_var.value {
-            Some(v) => v,
+            Some(v) => Some(v),
             None => {
-                on_missing_env_value(env_var.value_from, client, pod.namespace(), &field_map(pod))
-                    .await
+                on_missing_env_value(env_var.value_from, client, pod, container, &field_map(pod))
+                    .await?
             }
         };
-        env.insert(key, value);
+        if let Some(value) = value {
+            env.insert(key, value);
+        }
+    }
+    Ok(env)
+}
+
+/// Resolves the variables of a single `envFrom` source.
+async fn env_from_source(
+    env_from: &EnvFromSource,
+    pod: &Pod,
+    client: &kube::Client,
+    recorder: Option<&Recorder>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let ns = pod.namespace();
+    let (kind, name, data) = if let Some(source) = env_from.config_map_ref.as_ref() {
+        let name = source.name.as_deref().unwrap_or_default();
+        let optional = source.optional.unwrap_or(false);
+        let data = get_referenced::<ConfigMap>(client, ns, "configmap", name, optional)
+            .await?
+            .map(|cfgmap| cfgmap.data.unwrap_or_default());
+        ("configMap", name, data)
+    } else if let Some(source) = env_from.secret_ref.as_ref() {
+        let name = source.name.as_deref().unwrap_or_default();
+        let optional = source.optional.unwrap_or(false);
+        let data = get_referenced::<Secret>(client, ns, "secret", name, optional)
+            .await?
+            .map(|secret| {
+                secret
+                    .data
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(k, v)| (k, String::from_utf8(v.0).unwrap_or_default()))
+                    .collect()
+            });
+        ("secret", name, data)
+    } else {
+        return Ok(HashMap::new());
+    };
+    let data = match data {
+        Some(data) => data,
+        None => return Ok(HashMap::new()),
+    };
+
+    let prefix = env_from.prefix.as_deref().unwrap_or_default();
+    let (env, invalid_keys) = prefixed_env_vars(prefix, data);
+    if !invalid_keys.is_empty() {
+        let message = format!(
+            "Keys [{}] from the EnvFrom {} {}/{} were skipped since they are considered invalid environment variable names.",
+            invalid_keys.join(", "),
+            kind,
+            ns,
+            name
+        );
+        warn!(%message, "Skipping invalid environment variables");
+        if let Some(recorder) = recorder {
+            recorder
+                .pod_event(
+                    pod,
+                    None,
+                    EventType::Warning,
+                    "InvalidEnvironmentVariableNames",
+                    &message,
+                )
+                .await;
+        }
+    }
+    Ok(env)
+}
+
+/// Fetches a ConfigMap or Secret referenced by an environment variable.
+/// Returns `None` if it does not exist, but the reference is optional.
+async fn get_referenced<K>(
+    client: &kube::Client,
+    ns: &str,
+    kind: &str,
+    name: &str,
+    optional: bool,
+) -> anyhow::Result<Option<K>>
+where
+    K: kube::Resource<DynamicType = ()> + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    match Api::<K>::namespaced(client.clone(), ns).get(name).await {
+        Ok(object) => Ok(Some(object)),
+        Err(kube::Error::Api(e)) if e.code == 404 => {
+            if optional {
+                debug!(kind, name, "Optional reference does not exist");
+                Ok(None)
+            } else {
+                Err(anyhow::anyhow!("{} \"{}\" not found", kind, name))
+            }
+        }
+        Err(e) => {
+            Err(anyhow::Error::new(e).context(format!("unable to fetch {} \"{}\"", kind, name)))
+        }
+    }
+}
+
+/// Prefixes the keys of an `envFrom` source. Returns the resulting variables,
+/// along with the keys that were skipped because they are not valid variable
+/// names.
+fn prefixed_env_vars(
+    prefix: &str,
+    data: BTreeMap<String, String>,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut env = HashMap::new();
+    let mut invalid_keys = Vec::new();
+    for (key, value) in data {
+        let key = format!("{}{}", prefix, key);
+        if is_env_var_name(&key) {
+            env.insert(key, value);
+        } else {
+            invalid_keys.push(key);
+        }
+    }
+    (env, invalid_keys)
+}
+
+/// Checks whether a name is a valid environment variable name, which consists
+/// of letters, digits, '_', '-' and '.' and must not start with a digit.
+fn is_env_var_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(first) if !first.is_ascii_digit() => name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'),
+        _ => false,
     }
-    env
 }
 
 /// Called when an env var does not have a value associated with.
 ///
-/// This follows the env_var_source to get the value
+/// This follows the env_var_source to get the value. Returns `None` if the
+/// variable should not be set because an optional reference is missing.
 #[doc(hidden)]
 async fn on_missing_env_value(
     env_var_source: Option<EnvVarSource>,
     client: &kube::Client,
-    ns: &str,
+    pod: &Pod,
+    container: &Container,
     fields: &HashMap<String, String>,
-) -> String {
+) -> anyhow::Result<Option<String>> {
     let env_src = match env_var_source {
         Some(env_src) => env_src,
-        None => return String::new(),
+        None => return Ok(Some(String::new())),
     };
+    let ns = pod.namespace();
 
     // ConfigMaps
     if let Some(cfkey) = env_src.config_map_key_ref.as_ref() {
         let name = cfkey.name.as_deref().unwrap_or_default();
-        match Api::<ConfigMap>::namespaced(client.clone(), ns)
-            .get(name)
-            .await
-        {
-            Ok(cfgmap) => {
-                // I am not totally clear on what the outcome should
-                // be of a cfgmap key miss. So for now just return an
-                // empty default.
-                return cfgmap
-                    .data
-                    .unwrap_or_default()
-                    .get(&cfkey.key)
-                    .cloned()
-                    .unwrap_or_default();
-            }
-            Err(e) => {
-                error!(error = %e, name, "Error fetching config map");
-                return "".to_string();
-            }
-        }
+        let optional = cfkey.optional.unwrap_or(false);
+        let data =
+            match get_referenced::<ConfigMap>(client, ns, "configmap", name, optional).await? {
+                Some(cfgmap) => cfgmap.data.unwrap_or_default(),
+                None => return Ok(None),
+            };
+        return match data.get(&cfkey.key) {
+            Some(value) => Ok(Some(value.clone())),
+            None if optional => Ok(None),
+            None => Err(anyhow::anyhow!(
+                "couldn't find key {} in ConfigMap {}/{}",
+                cfkey.key,
+                ns,
+                name
+            )),
+        };
     }
     // Secrets
     if let Some(seckey) = env_src.secret_key_ref.as_ref() {
         let name = seckey.name.as_deref().unwrap_or_default();
-        match Api::<Secret>::namespaced(client.clone(), ns)
-            .get(name)
-            .await
-        {
-            Ok(secret) => {
-                // I am not totally clear on what the outcome should
-                // be of a secret key miss. So for now just return an
-                // empty default.
-                return secret
-                    .data
-                    .unwrap_or_default()
-                    .remove(&seckey.key)
-                    .map(|s| String::from_utf8(s.0).unwrap_or_default())
-                    .unwrap_or_default();
-            }
-            Err(e) => {
-                error!(error = %e, name, "Error fetching secret");
-                return String::new();
-            }
-        }
+        let optional = seckey.optional.unwrap_or(false);
+        let mut data = match get_referenced::<Secret>(client, ns, "secret", name, optional).await? {
+            Some(secret) => secret.data.unwrap_or_default(),
+            None => return Ok(None),
+        };
+        return match data.remove(&seckey.key) {
+            Some(value) => Ok(Some(String::from_utf8(value.0).unwrap_or_default())),
+            None if optional => Ok(None),
+            None => Err(anyhow::anyhow!(
+                "couldn't find key {} in Secret {}/{}",
+                seckey.key,
+                ns,
+                name
+            )),
+        };
     }
     // Downward API (Field Refs)
     if let Some(cfkey) = env_src.field_ref.as_ref() {
-        return fields.get(&cfkey.field_path).cloned().unwrap_or_default();
+        return Ok(Some(
+            fields.get(&cfkey.field_path).cloned().unwrap_or_default(),
+        ));
+    }
+    // Resource Fields
+    if let Some(resource_ref) = env_src.resource_field_ref {
+        return resource_field_value(resource_ref, client, pod, container)
+            .await
+            .map(Some);
     }
-    // Reource Fields (Not implementable just yet... need more of a model.)
 
-    String::new()
+    Ok(Some(String::new()))
+}
+
+/// Resolves a resource field of the container the same way the Downward API
+/// volume does. Limits that are not set default to the allocatable resources
+/// of the node.
+async fn resource_field_value(
+    mut resource_ref: ResourceFieldSelector,
+    client: &kube::Client,
+    pod: &Pod,
+    container: &Container,
+) -> anyhow::Result<String> {
+    // Environment variables always refer to the resources of their own
+    // container
+    resource_ref.container_name = Some(container.name().to_owned());
+    let containers = std::slice::from_ref(container);
+    let node_allocatable = if is_unset_limit(&resource_ref, containers) {
+        node_allocatable(client, pod).await?
+    } else {
+        BTreeMap::new()
+    };
+    let data = data_from_resource_ref(&resource_ref, containers, &node_allocatable)?;
+    Ok(String::from_utf8(data)?)
 }
 
 /// Build the map of allowable field_ref values.
@@ -369,9 +569,60 @@ pub enum ProviderError {
         /// The container's name
         container_name: String,
     },
+    /// The container has not terminated before, so there is no previous instance
+    #[error(
+        "previous terminated container {} in pod {} not found",
+        container_name,
+        pod_name
+    )]
+    PreviousContainerNotFound {
+        /// The container's pod's name
+        pod_name: String,
+        /// The container's name
+        container_name: String,
+    },
 }
 
 /// A specific operation is not implemented
 #[derive(Error, Debug)]
 #[error("Operation not supported")]
 pub struct NotImplementedError;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prefixed_env_vars() {
+        let data: BTreeMap<String, String> = vec![
+            ("HOST", "localhost"),
+            ("port", "8080"),
+            ("1INVALID", "skipped"),
+            ("in valid", "skipped"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+
+        let (env, invalid_keys) = prefixed_env_vars("DB_", data.clone());
+        assert_eq!(env.len(), 3);
+        assert_eq!(env.get("DB_HOST").map(String::as_str), Some("localhost"));
+        assert_eq!(env.get("DB_port").map(String::as_str), Some("8080"));
+        assert_eq!(env.get("DB_1INVALID").map(String::as_str), Some("skipped"));
+        assert_eq!(invalid_keys, vec!["DB_in valid"]);
+
+        let (env, invalid_keys) = prefixed_env_vars("", data);
+        assert_eq!(env.len(), 2);
+        assert_eq!(invalid_keys, vec!["1INVALID", "in valid"]);
+    }
+
+    #[test]
+    fn test_is_env_var_name() {
+        for name in &["PATH", "_private", "my-var", "dotted.name", "-dash"] {
+            assert!(is_env_var_name(name), "{} should be valid", name);
+        }
+        for name in &["", "1st", "with space", "eq=", "ünïcode"] {
+            assert!(!is_env_var_name(name), "{} should be invalid", name);
+        }
+    }
+}
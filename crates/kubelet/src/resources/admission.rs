@@ -0,0 +1,287 @@
+//! Admission of pods against the resources the node has left.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use tracing::debug;
+
+use crate::config::Config;
+use crate::node::Resources;
+use crate::pod::Pod;
+
+/// The resources requested by a pod, with CPU in millicores and memory in bytes.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PodRequests {
+    cpu: u64,
+    memory: u128,
+}
+
+impl PodRequests {
+    fn of(pod: &Pod) -> anyhow::Result<Self> {
+        let mut requests = PodRequests::default();
+        for container in pod.containers() {
+            requests.cpu += millicores(container.cpu_request()?.unwrap_or_default());
+            requests.memory += container.memory_request()?.unwrap_or_default();
+        }
+        // Init containers run one at a time before any of the app containers
+        // are started, so only the largest of them needs to fit
+        for container in pod.init_containers() {
+            requests.cpu = requests
+                .cpu
+                .max(millicores(container.cpu_request()?.unwrap_or_default()));
+            requests.memory = requests
+                .memory
+                .max(container.memory_request()?.unwrap_or_default());
+        }
+        Ok(requests)
+    }
+}
+
+fn millicores(cores: f64) -> u64 {
+    (cores * 1000.0).round() as u64
+}
+
+/// The reason a pod was not admitted to the node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+    /// A brief CamelCase reason, e.g. `OutOfcpu`
+    pub reason: String,
+    /// A human readable description of why the pod was rejected
+    pub message: String,
+}
+
+impl Rejection {
+    fn insufficient(resource: &str, requested: u128, used: u128, capacity: u128) -> Self {
+        Rejection {
+            reason: format!("OutOf{}", resource),
+            message: format!(
+                "Node didn't have enough resource: {}, requested: {}, used: {}, capacity: {}",
+                resource, requested, used, capacity
+            ),
+        }
+    }
+}
+
+/// Tracks the resources requested by the pods admitted to the node, and
+/// rejects pods that would commit more than the node can allocate.
+///
+/// Pods count against the node from the moment they are admitted until they
+/// are released, which happens once their state machine completes, whether
+/// they ran to completion, failed or were deleted.
+pub struct PodAdmitter {
+    allocatable: Resources,
+    max_pods: usize,
+    // Keyed by pod uid
+    admitted: Mutex<HashMap<String, PodRequests>>,
+    node_ready: AtomicBool,
+}
+
+impl PodAdmitter {
+    /// Creates an admitter for the allocatable resources of the node
+    /// described by the configuration.
+    pub fn new(config: &Config) -> Self {
+        let allocatable =
+            Resources::detect(&config.data_dir).allocatable(&Resources::reserved(config));
+        Self::with_allocatable(allocatable, config.max_pods as usize)
+    }
+
+    pub(crate) fn with_allocatable(allocatable: Resources, max_pods: usize) -> Self {
+        PodAdmitter {
+            allocatable,
+            max_pods,
+            admitted: Mutex::new(HashMap::new()),
+            node_ready: AtomicBool::new(true),
+        }
+    }
+
+    /// The memory the node can allocate to pods in bytes, if it is known.
+    pub fn allocatable_memory(&self) -> Option<u128> {
+        self.allocatable.memory
+    }
+
+    /// Admits the pod if the node has enough resources left to run it. A pod
+    /// that was already admitted is checked again with its current requests.
+    pub fn admit(&self, pod: &Pod) -> Result<(), Rejection> {
+        let requests = PodRequests::of(pod).map_err(|e| Rejection {
+            reason: "UnexpectedAdmissionError".to_owned(),
+            message: format!("Invalid resource requests: {:#}", e),
+        })?;
+
+        let mut admitted = self.admitted.lock().unwrap();
+        let others: Vec<&PodRequests> = admitted
+            .iter()
+            .filter(|(uid, _)| uid.as_str() != pod.pod_uid())
+            .map(|(_, r)| r)
+            .collect();
+
+        if others.len() >= self.max_pods {
+            return Err(Rejection::insufficient(
+                "pods",
+                1,
+                others.len() as u128,
+                self.max_pods as u128,
+            ));
+        }
+        if let Some(cpu) = self.allocatable.cpu {
+            let capacity = millicores(cpu);
+            let used: u64 = others.iter().map(|r| r.cpu).sum();
+            if requests.cpu > 0 && used + requests.cpu > capacity {
+                return Err(Rejection::insufficient(
+                    "cpu",
+                    requests.cpu as u128,
+                    used as u128,
+                    capacity as u128,
+                ));
+            }
+        }
+        if let Some(capacity) = self.allocatable.memory {
+            let used: u128 = others.iter().map(|r| r.memory).sum();
+            if requests.memory > 0 && used + requests.memory > capacity {
+                return Err(Rejection::insufficient(
+                    "memory",
+                    requests.memory,
+                    used,
+                    capacity,
+                ));
+            }
+        }
+
+        debug!(pod = %pod.name(), ?requests, "Admitted pod");
+        admitted.insert(pod.pod_uid().to_owned(), requests);
+        Ok(())
+    }
+
+    /// Sets whether the node is ready, i.e. whether its status can be
+    /// updated.
+    pub fn set_node_ready(&self, ready: bool) {
+        self.node_ready.store(ready, Ordering::Relaxed);
+    }
+
+    /// Whether the node is ready. Pods are held back rather than admitted
+    /// while it is not, as the condition is usually transient.
+    pub fn node_ready(&self) -> bool {
+        self.node_ready.load(Ordering::Relaxed)
+    }
+
+    /// Releases the resources committed to the pod, if it was admitted.
+    pub fn release(&self, pod: &Pod) {
+        self.admitted.lock().unwrap().remove(pod.pod_uid());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::{
+        Container as KubeContainer, Pod as KubePod, PodSpec, ResourceRequirements,
+    };
+    use k8s_openapi::apimachinery::pkg::api::resource::Quantity as KubeQuantity;
+    use kube::api::ObjectMeta;
+
+    fn container(cpu: &str, memory: &str) -> KubeContainer {
+        KubeContainer {
+            resources: Some(ResourceRequirements {
+                requests: Some(
+                    vec![
+                        ("cpu".to_owned(), KubeQuantity(cpu.to_owned())),
+                        ("memory".to_owned(), KubeQuantity(memory.to_owned())),
+                    ]
+                    .into_iter()
+                    .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn pod(uid: &str, containers: Vec<KubeContainer>, init: Vec<KubeContainer>) -> Pod {
+        Pod::from(KubePod {
+            metadata: ObjectMeta {
+                name: Some(uid.to_owned()),
+                uid: Some(uid.to_owned()),
+                ..Default::default()
+            },
+            spec: Some(PodSpec {
+                containers,
+                init_containers: Some(init),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn admitter(max_pods: usize) -> PodAdmitter {
+        PodAdmitter::with_allocatable(
+            Resources {
+                cpu: Some(2.0),
+                memory: Some(1024 * 1024 * 1024),
+                ephemeral_storage: None,
+            },
+            max_pods,
+        )
+    }
+
+    #[test]
+    fn test_pod_requests() {
+        let requests = PodRequests::of(&pod(
+            "a",
+            vec![container("250m", "64Mi"), container("250m", "64Mi")],
+            vec![container("1", "16Mi")],
+        ))
+        .unwrap();
+        assert_eq!(
+            requests,
+            PodRequests {
+                cpu: 1000,
+                memory: 128 * 1024 * 1024
+            }
+        );
+    }
+
+    #[test]
+    fn test_admission() {
+        let admitter = admitter(10);
+        let first = pod("first", vec![container("1500m", "512Mi")], vec![]);
+        admitter.admit(&first).unwrap();
+        // Admitting the same pod again does not count its requests twice
+        admitter.admit(&first).unwrap();
+
+        let rejection = admitter
+            .admit(&pod("second", vec![container("1", "64Mi")], vec![]))
+            .unwrap_err();
+        assert_eq!(rejection.reason, "OutOfcpu");
+        assert_eq!(
+            rejection.message,
+            "Node didn't have enough resource: cpu, requested: 1000, used: 1500, capacity: 2000"
+        );
+
+        let rejection = admitter
+            .admit(&pod("third", vec![container("100m", "1Gi")], vec![]))
+            .unwrap_err();
+        assert_eq!(rejection.reason, "OutOfmemory");
+
+        admitter.release(&first);
+        admitter
+            .admit(&pod("second", vec![container("1", "64Mi")], vec![]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_max_pods() {
+        let admitter = admitter(1);
+        admitter.admit(&pod("first", vec![], vec![])).unwrap();
+        let rejection = admitter.admit(&pod("second", vec![], vec![])).unwrap_err();
+        assert_eq!(rejection.reason, "OutOfpods");
+    }
+
+    #[test]
+    fn test_node_ready() {
+        let admitter = admitter(10);
+        assert!(admitter.node_ready());
+        admitter.set_node_ready(false);
+        assert!(!admitter.node_ready());
+        admitter.set_node_ready(true);
+        assert!(admitter.node_ready());
+    }
+}
@@ -12,6 +12,7 @@ use crate::device_plugin_api::v1beta1::{
     ContainerAllocateResponse, RegisterRequest, API_VERSION,
 };
 use crate::grpc_sock;
+use crate::metrics::{self, DEVICE_PLUGIN_ALLOCATIONS};
 use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
@@ -304,11 +305,11 @@ impl DeviceManager {
             let allocate_request = AllocateRequest {
                 container_requests: container_requests.clone(),
             };
-            let container_responses = plugin_connection
-                .allocate(allocate_request)
-                .await?
-                .into_inner()
-                .container_responses;
+            let allocate_response = plugin_connection.allocate(allocate_request).await;
+            DEVICE_PLUGIN_ALLOCATIONS
+                .with_label_values(&[&resource_name, metrics::result_label(&allocate_response)])
+                .inc();
+            let container_responses = allocate_response?.into_inner().container_responses;
 
             // By doing one allocate call per Pod, an assumption is being made that the container
             // requests array (sent to a DP) and container responses array returned are the same
@@ -1,7 +1,9 @@
 //! `resources` contains utilities and managers for container resources.
 
+pub(crate) mod admission;
 pub(crate) mod device_plugin_manager;
 pub(crate) mod quantity;
 
+pub use admission::{PodAdmitter, Rejection};
 pub use device_plugin_manager::manager::DeviceManager;
 pub mod util;
@@ -1,7 +1,8 @@
 //! The pod is backing off after repeated failures and retries.
 
 use super::registered::Registered;
-use super::{BackoffSequence, GenericPodState, GenericProvider};
+use super::{record_event, BackoffSequence, GenericPodState, GenericProvider};
+use crate::event::EventType;
 use crate::pod::state::prelude::*;
 
 /// The pod is backing off after repeated failures and retries.
@@ -27,10 +28,18 @@ impl<P: GenericProvider> Default for CrashLoopBackoff<P> {
 impl<P: GenericProvider> State<P::PodState> for CrashLoopBackoff<P> {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<P::ProviderState>,
+        provider_state: SharedState<P::ProviderState>,
         pod_state: &mut P::PodState,
-        _pod: Manifest<Pod>,
+        pod: Manifest<Pod>,
     ) -> Transition<P::PodState> {
+        record_event(
+            &provider_state,
+            &pod.latest(),
+            EventType::Warning,
+            "BackOff",
+            "Back-off restarting failed pod",
+        )
+        .await;
         pod_state.backoff(BackoffSequence::CrashLoop).await;
         let next = Registered::<P>::default();
         Transition::next(self, next)
@@ -2,7 +2,8 @@
 
 use super::crash_loop_backoff::CrashLoopBackoff;
 use super::registered::Registered;
-use super::{GenericPodState, GenericProvider, ThresholdTrigger};
+use super::{record_event, GenericPodState, GenericProvider, ThresholdTrigger};
+use crate::event::EventType;
 use crate::pod::state::prelude::*;
 
 /// The Pod failed to run.
@@ -32,10 +33,18 @@ impl<P: GenericProvider> Error<P> {
 impl<P: GenericProvider> State<P::PodState> for Error<P> {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<P::ProviderState>,
+        provider_state: SharedState<P::ProviderState>,
         pod_state: &mut P::PodState,
-        _pod: Manifest<Pod>,
+        pod: Manifest<Pod>,
     ) -> Transition<P::PodState> {
+        record_event(
+            &provider_state,
+            &pod.latest(),
+            EventType::Warning,
+            "Failed",
+            &self.message,
+        )
+        .await;
         match pod_state.record_error().await {
             ThresholdTrigger::Triggered => {
                 let next = CrashLoopBackoff::<P>::default();
@@ -2,7 +2,10 @@
 
 use super::image_pull_backoff::ImagePullBackoff;
 use super::volume_mount::VolumeMount;
-use super::{BackoffSequence, GenericPodState, GenericProvider, GenericProviderState};
+use super::{
+    record_event, BackoffSequence, GenericPodState, GenericProvider, GenericProviderState,
+};
+use crate::event::EventType;
 use crate::pod::state::prelude::*;
 
 use tracing::{error, instrument};
@@ -53,9 +56,26 @@ impl<P: GenericProvider> State<P::PodState> for ImagePull<P> {
             Ok(m) => m,
             Err(e) => {
                 error!(error = %e);
+                let message = format!("Failed to pull image: {:#}", e);
+                record_event(
+                    &provider_state,
+                    &pod,
+                    EventType::Warning,
+                    "Failed",
+                    &message,
+                )
+                .await;
                 return Transition::next(self, ImagePullBackoff::<P>::default());
             }
         };
+        record_event(
+            &provider_state,
+            &pod,
+            EventType::Normal,
+            "Pulled",
+            "Successfully pulled images",
+        )
+        .await;
         pod_state.set_modules(modules).await;
         pod_state.reset_backoff(BackoffSequence::ImagePull).await;
         Transition::next(self, VolumeMount::<P>::default())
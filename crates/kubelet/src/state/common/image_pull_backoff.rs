@@ -1,7 +1,8 @@
 //! Kubelet encountered an error when pulling container image.
 
 use super::image_pull::ImagePull;
-use super::{BackoffSequence, GenericPodState, GenericProvider};
+use super::{record_event, BackoffSequence, GenericPodState, GenericProvider};
+use crate::event::EventType;
 use crate::pod::state::prelude::*;
 
 /// Kubelet encountered an error when pulling container image.
@@ -27,10 +28,18 @@ impl<P: GenericProvider> Default for ImagePullBackoff<P> {
 impl<P: GenericProvider> State<P::PodState> for ImagePullBackoff<P> {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<P::ProviderState>,
+        provider_state: SharedState<P::ProviderState>,
         pod_state: &mut P::PodState,
-        _pod: Manifest<Pod>,
+        pod: Manifest<Pod>,
     ) -> Transition<P::PodState> {
+        record_event(
+            &provider_state,
+            &pod.latest(),
+            EventType::Normal,
+            "BackOff",
+            "Back-off pulling image",
+        )
+        .await;
         pod_state.backoff(BackoffSequence::ImagePull).await;
         Transition::next(self, ImagePull::<P>::default())
     }
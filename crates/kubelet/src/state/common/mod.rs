@@ -3,10 +3,14 @@
 //! states in many providers; instead, the provider need only implement the
 //! GenericProviderState and GenericPodState traits for its state types.
 
+use crate::event::EventType;
 use crate::pod::state::prelude::PodStatus;
 use crate::pod::Pod;
-use crate::provider::{DevicePluginSupport, PluginSupport, VolumeSupport};
-use krator::{ObjectState, State};
+use crate::provider::{
+    AdmissionSupport, DevicePluginSupport, EventSupport, PluginSupport, VolumeSupport,
+};
+use futures::future::BoxFuture;
+use krator::{ObjectState, SharedState, State};
 use std::collections::HashMap;
 
 pub mod crash_loop_backoff;
@@ -14,6 +18,7 @@ pub mod error;
 pub mod image_pull;
 pub mod image_pull_backoff;
 pub mod registered;
+pub mod rejected;
 pub mod resources;
 pub mod terminated;
 pub mod volume_mount;
@@ -43,9 +48,13 @@ pub trait GenericProviderState: 'static + Send + Sync {
     fn client(&self) -> kube::Client;
     /// Gets the `Store` used by the provider.
     fn store(&self) -> std::sync::Arc<dyn crate::store::Store + Sync + Send>;
-    /// Stops the specified pod. This typically involves tearing down a
-    /// runtime or other execution environment.
-    async fn stop(&self, pod: &crate::pod::Pod) -> anyhow::Result<()>;
+    /// Returns a future that stops the specified pod. This typically involves
+    /// tearing down a runtime or other execution environment.
+    ///
+    /// Stopping a pod can take as long as its grace period, so the future must
+    /// not borrow the provider state. That way callers do not hold the lock on
+    /// the provider state while waiting for it.
+    fn stop(&self, pod: &crate::pod::Pod) -> BoxFuture<'static, anyhow::Result<()>>;
 }
 
 /// Exposes pod state in a way that can be consumed by
@@ -75,7 +84,12 @@ pub trait GenericPodState: ObjectState<Manifest = Pod, Status = PodStatus> {
 /// module.
 pub trait GenericProvider: 'static + Send + Sync {
     /// The state of the provider itself.
-    type ProviderState: GenericProviderState + VolumeSupport + PluginSupport + DevicePluginSupport;
+    type ProviderState: GenericProviderState
+        + VolumeSupport
+        + PluginSupport
+        + DevicePluginSupport
+        + AdmissionSupport
+        + EventSupport;
     /// The state that is passed between Pod state handlers.
     type PodState: GenericPodState + ObjectState<SharedState = Self::ProviderState>;
     /// The state to which pods should transition after they have completed
@@ -108,3 +122,19 @@ pub trait GenericProvider: 'static + Send + Sync {
         Ok(())
     }
 }
+
+/// Records an event about the pod, if the provider records events.
+pub(crate) async fn record_event<S: EventSupport>(
+    provider_state: &SharedState<S>,
+    pod: &Pod,
+    event_type: EventType,
+    reason: &str,
+    message: &str,
+) {
+    let recorder = provider_state.read().await.event_recorder();
+    if let Some(recorder) = recorder {
+        recorder
+            .pod_event(pod, None, event_type, reason, message)
+            .await;
+    }
+}
@@ -1,12 +1,17 @@
 //! The Kubelet is aware of the Pod.
 
 use crate::pod::state::prelude::*;
-use tracing::{debug, error, info, instrument};
+use crate::provider::AdmissionSupport;
+use tracing::{debug, error, info, instrument, warn};
 
 use super::error::Error;
+use super::rejected::Rejected;
 use super::resources::Resources;
 use super::GenericProvider;
 
+/// How often to check whether a node that is not ready became ready again.
+const NODE_READY_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
 /// The Kubelet is aware of the Pod.
 pub struct Registered<P: GenericProvider> {
     phantom: std::marker::PhantomData<P>,
@@ -30,12 +35,12 @@ impl<P: GenericProvider> Default for Registered<P> {
 impl<P: GenericProvider> State<P::PodState> for Registered<P> {
     #[instrument(
         level = "info",
-        skip(self, _provider_state, _pod_state, pod),
+        skip(self, provider_state, _pod_state, pod),
         fields(pod_name)
     )]
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<P::ProviderState>,
+        provider_state: SharedState<P::ProviderState>,
         _pod_state: &mut P::PodState,
         pod: Manifest<Pod>,
     ) -> Transition<P::PodState> {
@@ -52,6 +57,23 @@ impl<P: GenericProvider> State<P::PodState> for Registered<P> {
                 return Transition::next(self, next);
             }
         }
+        let pod_admitter = provider_state.read().await.pod_admitter();
+        if let Some(pod_admitter) = pod_admitter {
+            // Losing contact with the API server is usually transient, so pods
+            // wait for the node to become ready again rather than being
+            // rejected
+            if !pod_admitter.node_ready() {
+                info!("Node is not ready, waiting before admitting pod");
+                while !pod_admitter.node_ready() {
+                    tokio::time::sleep(NODE_READY_RETRY_INTERVAL).await;
+                }
+            }
+            if let Err(rejection) = pod_admitter.admit(&pod) {
+                warn!(reason = %rejection.reason, message = %rejection.message, "Pod rejected");
+                let next = Rejected::<P>::new(rejection.reason, rejection.message);
+                return Transition::next(self, next);
+            }
+        }
         info!("Pod registered");
         let next = Resources::<P>::default();
         Transition::next(self, next)
@@ -63,4 +85,5 @@ impl<P: GenericProvider> State<P::PodState> for Registered<P> {
 }
 
 impl<P: GenericProvider> TransitionTo<Error<P>> for Registered<P> {}
+impl<P: GenericProvider> TransitionTo<Rejected<P>> for Registered<P> {}
 impl<P: GenericProvider> TransitionTo<Resources<P>> for Registered<P> {}
@@ -0,0 +1,49 @@
+//! The Pod was rejected by the node.
+
+use super::GenericProvider;
+use crate::pod::state::prelude::*;
+
+/// The Pod was rejected by the node and will not be run.
+pub struct Rejected<P: GenericProvider> {
+    phantom: std::marker::PhantomData<P>,
+    reason: String,
+    message: String,
+}
+
+impl<P: GenericProvider> std::fmt::Debug for Rejected<P> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = format!("Rejected: {}", self.message);
+        text.fmt(formatter)
+    }
+}
+
+impl<P: GenericProvider> Rejected<P> {
+    /// Creates an instance of the Rejected state.
+    pub fn new(reason: String, message: String) -> Self {
+        Self {
+            phantom: std::marker::PhantomData,
+            reason,
+            message,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: GenericProvider> State<P::PodState> for Rejected<P> {
+    async fn next(
+        self: Box<Self>,
+        _provider_state: SharedState<P::ProviderState>,
+        _pod_state: &mut P::PodState,
+        _pod: Manifest<Pod>,
+    ) -> Transition<P::PodState> {
+        Transition::Complete(Ok(()))
+    }
+
+    async fn status(&self, _pod_state: &mut P::PodState, _pod: &Pod) -> anyhow::Result<PodStatus> {
+        Ok(StatusBuilder::new()
+            .phase(Phase::Failed)
+            .reason(&self.reason)
+            .message(&self.message)
+            .build())
+    }
+}
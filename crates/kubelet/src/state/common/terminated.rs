@@ -32,12 +32,11 @@ impl<P: GenericProvider> State<P::PodState> for Terminated<P> {
     ) -> Transition<P::PodState> {
         let pod = pod.latest();
 
-        let state_reader = provider_state.read().await;
         // TODO: In original code, pod key was stored in state rather than
         // re-derived.  Is this important e.g. could pod mutate in ways
         // that invalidate the key assigned on startup?
-        let stop_result = state_reader.stop(&pod).await;
-        Transition::Complete(stop_result)
+        let stop = provider_state.read().await.stop(&pod);
+        Transition::Complete(stop.await)
     }
 
     async fn status(&self, _pod_state: &mut P::PodState, _pod: &Pod) -> anyhow::Result<PodStatus> {
@@ -2,11 +2,12 @@
 
 use tracing::{error, info, instrument};
 
-use super::{GenericPodState, GenericProvider, GenericProviderState};
+use super::{record_event, GenericPodState, GenericProvider, GenericProviderState};
+use crate::event::EventType;
 use crate::pod::state::prelude::*;
 use crate::provider::{PluginSupport, VolumeSupport};
 use crate::state::common::error::Error;
-use crate::volume::VolumeRef;
+use crate::volume::{pod_dir_name, VolumeRef};
 
 /// Kubelet is pulling container images.
 pub struct VolumeMount<P: GenericProvider> {
@@ -65,7 +66,16 @@ impl<P: GenericProvider> State<P::PodState> for VolumeMount<P> {
             Ok(v) => v,
             Err(e) => {
                 error!(error = %e);
-                let next = Error::<P>::new(e.to_string());
+                let message = e.to_string();
+                record_event(
+                    &provider_state,
+                    &pod,
+                    EventType::Warning,
+                    "FailedMount",
+                    &message,
+                )
+                .await;
+                let next = Error::<P>::new(message);
                 return Transition::next(self, next);
             }
         };
@@ -85,7 +95,16 @@ impl<P: GenericProvider> State<P::PodState> for VolumeMount<P> {
             .collect::<anyhow::Result<()>>()
         {
             error!(error = %e);
-            let next = Error::<P>::new(e.to_string());
+            let message = e.to_string();
+            record_event(
+                &provider_state,
+                &pod,
+                EventType::Warning,
+                "FailedMount",
+                &message,
+            )
+            .await;
+            let next = Error::<P>::new(message);
             return Transition::next(self, next);
         }
         pod_state.set_volumes(volumes).await;
@@ -98,7 +117,3 @@ impl<P: GenericProvider> State<P::PodState> for VolumeMount<P> {
 }
 
 impl<P: GenericProvider> TransitionTo<Error<P>> for VolumeMount<P> {}
-
-fn pod_dir_name(pod: &Pod) -> String {
-    format!("{}-{}", pod.name(), pod.namespace())
-}
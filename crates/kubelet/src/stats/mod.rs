@@ -0,0 +1,462 @@
+//! `stats` contains the types of the kubelet summary API (`/stats/summary`),
+//! which is scraped by metrics-server, and the machinery to collect them.
+//!
+//! Node level statistics are collected by the kubelet itself. Pod and
+//! container statistics are supplied by the provider through
+//! [`crate::provider::Provider::stats`].
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use tracing::warn;
+
+pub(crate) mod system;
+
+/// The summary of node and pod statistics, as served on `/stats/summary`.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    /// Statistics of the node itself
+    pub node: NodeStats,
+    /// Statistics of all pods running on the node
+    pub pods: Vec<PodStats>,
+}
+
+impl Summary {
+    /// Drops everything but CPU and memory statistics, which is all that
+    /// metrics-server asks for.
+    pub fn retain_cpu_and_memory(&mut self) {
+        self.node.fs = None;
+        for pod in self.pods.iter_mut() {
+            pod.volume = None;
+            pod.ephemeral_storage = None;
+            for container in pod.containers.iter_mut() {
+                container.rootfs = None;
+                container.logs = None;
+            }
+        }
+    }
+}
+
+/// Statistics of a node.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStats {
+    /// The name of the node
+    pub node_name: String,
+    /// When the node was started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<DateTime<Utc>>,
+    /// CPU usage of the node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuStats>,
+    /// Memory usage of the node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryStats>,
+    /// Usage of the filesystem holding the kubelet data directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fs: Option<FsStats>,
+}
+
+impl NodeStats {
+    /// Collects the statistics of the node the kubelet is running on. This
+    /// blocks while reading from `/proc` and the filesystem.
+    pub(crate) fn collect(node_name: &str, data_dir: &Path) -> Self {
+        let time = Utc::now();
+        let (cpu, start_time) = match system::cpu_usage() {
+            Ok(usage) => (
+                Some(CpuStats {
+                    time,
+                    usage_nano_cores: None,
+                    usage_core_nano_seconds: Some(usage.usage_core_nano_seconds),
+                }),
+                usage.boot_time.map(|t| Utc.timestamp(t, 0)),
+            ),
+            Err(e) => {
+                warn!(error = %e, "Unable to read node CPU usage");
+                (None, None)
+            }
+        };
+        let memory = match system::meminfo() {
+            Ok(meminfo) => {
+                let total = meminfo.get("MemTotal").copied().unwrap_or_default();
+                let free = meminfo.get("MemFree").copied().unwrap_or_default();
+                let available = meminfo.get("MemAvailable").copied().unwrap_or(free);
+                Some(MemoryStats {
+                    time,
+                    available_bytes: Some(available),
+                    usage_bytes: Some(total.saturating_sub(free)),
+                    working_set_bytes: Some(total.saturating_sub(available)),
+                    rss_bytes: None,
+                })
+            }
+            Err(e) => {
+                warn!(error = %e, "Unable to read node memory usage");
+                None
+            }
+        };
+        let fs = match FsStats::filesystem(data_dir) {
+            Ok(fs) => Some(fs),
+            Err(e) => {
+                warn!(error = %e, "Unable to read node filesystem usage");
+                None
+            }
+        };
+        NodeStats {
+            node_name: node_name.to_owned(),
+            start_time,
+            cpu,
+            memory,
+            fs,
+        }
+    }
+}
+
+/// Statistics of a pod.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodStats {
+    /// Identifies the pod
+    pub pod_ref: PodReference,
+    /// When the pod was started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<DateTime<Utc>>,
+    /// Statistics of the containers of the pod
+    pub containers: Vec<ContainerStats>,
+    /// CPU usage of all containers of the pod
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuStats>,
+    /// Memory usage of all containers of the pod
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryStats>,
+    /// Usage of the volumes of the pod
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<Vec<VolumeStats>>,
+    /// Local disk used by the pod, including logs and volumes
+    #[serde(rename = "ephemeral-storage", skip_serializing_if = "Option::is_none")]
+    pub ephemeral_storage: Option<FsStats>,
+}
+
+impl PodStats {
+    /// Creates the statistics of a pod out of the statistics of its
+    /// containers and volumes, summing up their usage.
+    pub fn new(
+        pod: &crate::pod::Pod,
+        containers: Vec<ContainerStats>,
+        volumes: Vec<VolumeStats>,
+    ) -> Self {
+        let time = Utc::now();
+        let cpu = sum(containers.iter().filter_map(|c| c.cpu.as_ref()), |c| {
+            c.usage_core_nano_seconds
+        })
+        .map(|usage| CpuStats {
+            time,
+            usage_nano_cores: None,
+            usage_core_nano_seconds: Some(usage),
+        });
+        let memory = sum(containers.iter().filter_map(|c| c.memory.as_ref()), |m| {
+            m.working_set_bytes
+        })
+        .map(|usage| MemoryStats {
+            time,
+            available_bytes: None,
+            usage_bytes: Some(usage),
+            working_set_bytes: Some(usage),
+            rss_bytes: Some(usage),
+        });
+        let disk = containers
+            .iter()
+            .filter_map(|c| c.logs.as_ref())
+            .chain(volumes.iter().map(|v| &v.fs));
+        let ephemeral_storage = sum(disk, |fs| fs.used_bytes).map(|used| FsStats {
+            time,
+            used_bytes: Some(used),
+            ..Default::default()
+        });
+        PodStats {
+            pod_ref: PodReference {
+                name: pod.name().to_owned(),
+                namespace: pod.namespace().to_owned(),
+                uid: pod.pod_uid().to_owned(),
+            },
+            start_time: pod
+                .as_kube_pod()
+                .status
+                .as_ref()
+                .and_then(|s| s.start_time.as_ref())
+                .map(|t| t.0),
+            containers,
+            cpu,
+            memory,
+            volume: Some(volumes),
+            ephemeral_storage,
+        }
+    }
+}
+
+fn sum<'a, T: 'a>(
+    items: impl Iterator<Item = &'a T>,
+    value: impl Fn(&T) -> Option<u64>,
+) -> Option<u64> {
+    items
+        .filter_map(value)
+        .fold(None, |total, v| Some(total.unwrap_or_default() + v))
+}
+
+/// Identifies a pod.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PodReference {
+    /// The name of the pod
+    pub name: String,
+    /// The namespace of the pod
+    pub namespace: String,
+    /// The uid of the pod
+    pub uid: String,
+}
+
+/// Statistics of a container.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerStats {
+    /// The name of the container
+    pub name: String,
+    /// When the container was started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<DateTime<Utc>>,
+    /// CPU usage of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuStats>,
+    /// Memory usage of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryStats>,
+    /// Usage of the root filesystem of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<FsStats>,
+    /// Usage of the logs of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<FsStats>,
+}
+
+impl ContainerStats {
+    /// Creates the statistics of a container from the usage reported by its handle.
+    pub fn new(name: &str, usage: &ContainerUsage) -> Self {
+        let time = Utc::now();
+        ContainerStats {
+            name: name.to_owned(),
+            start_time: usage.start_time,
+            cpu: Some(CpuStats {
+                time,
+                usage_nano_cores: None,
+                usage_core_nano_seconds: Some(usage.cpu_time.as_nanos() as u64),
+            }),
+            memory: Some(MemoryStats {
+                time,
+                available_bytes: None,
+                usage_bytes: Some(usage.memory_bytes),
+                working_set_bytes: Some(usage.memory_bytes),
+                rss_bytes: Some(usage.memory_bytes),
+            }),
+            rootfs: None,
+            logs: usage.log_bytes.map(|used| FsStats {
+                time,
+                used_bytes: Some(used),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// CPU usage.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuStats {
+    /// When the usage was measured
+    pub time: DateTime<Utc>,
+    /// The average number of nanoseconds of CPU time used per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_nano_cores: Option<u64>,
+    /// The total CPU time used, in nanoseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_core_nano_seconds: Option<u64>,
+}
+
+/// Memory usage.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryStats {
+    /// When the usage was measured
+    pub time: DateTime<Utc>,
+    /// The memory that is still available, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+    /// The total memory in use, including caches, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_bytes: Option<u64>,
+    /// The memory that cannot be reclaimed, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_set_bytes: Option<u64>,
+    /// The anonymous and swap cache memory, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<u64>,
+}
+
+/// Usage of a filesystem or of a part of it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FsStats {
+    /// When the usage was measured
+    pub time: DateTime<Utc>,
+    /// The space available on the filesystem, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+    /// The total size of the filesystem, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity_bytes: Option<u64>,
+    /// The space in use, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used_bytes: Option<u64>,
+    /// The number of free inodes of the filesystem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inodes_free: Option<u64>,
+    /// The total number of inodes of the filesystem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inodes: Option<u64>,
+    /// The number of inodes in use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inodes_used: Option<u64>,
+}
+
+impl Default for FsStats {
+    fn default() -> Self {
+        FsStats {
+            time: Utc::now(),
+            available_bytes: None,
+            capacity_bytes: None,
+            used_bytes: None,
+            inodes_free: None,
+            inodes: None,
+            inodes_used: None,
+        }
+    }
+}
+
+impl FsStats {
+    /// Returns the usage of the whole filesystem the path is on. This blocks
+    /// while reading from the filesystem.
+    pub fn filesystem(path: &Path) -> anyhow::Result<Self> {
+        let usage = system::filesystem_usage(path)?;
+        Ok(FsStats {
+            time: Utc::now(),
+            available_bytes: Some(usage.available),
+            capacity_bytes: Some(usage.capacity),
+            used_bytes: Some(usage.capacity - usage.free),
+            inodes_free: Some(usage.inodes_free),
+            inodes: Some(usage.inodes),
+            inodes_used: Some(usage.inodes - usage.inodes_free),
+        })
+    }
+
+    /// Returns the usage of everything under the given directory, along with
+    /// the capacity of the filesystem it is on. This blocks while walking the
+    /// directory tree.
+    pub fn directory(path: &Path) -> anyhow::Result<Self> {
+        let (used_bytes, inodes_used) = system::disk_usage(path)?;
+        let mut stats = FsStats::filesystem(path)?;
+        stats.used_bytes = Some(used_bytes);
+        stats.inodes_used = Some(inodes_used);
+        Ok(stats)
+    }
+}
+
+/// Usage of a pod volume.
+#[derive(Clone, Debug, Serialize)]
+pub struct VolumeStats {
+    /// The name of the volume
+    pub name: String,
+    /// Usage of the volume
+    #[serde(flatten)]
+    pub fs: FsStats,
+}
+
+/// Resource usage of a running container, as reported by a
+/// [`crate::handle::StatsHandler`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContainerUsage {
+    /// When the container was started
+    pub start_time: Option<DateTime<Utc>>,
+    /// The CPU time the container has used
+    pub cpu_time: Duration,
+    /// The memory the container is using, in bytes
+    pub memory_bytes: u64,
+    /// The disk space used by the logs of the container, in bytes
+    pub log_bytes: Option<u64>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::Pod as KubePod;
+    use kube::api::ObjectMeta;
+
+    #[test]
+    fn test_pod_stats() {
+        let pod = crate::pod::Pod::from(KubePod {
+            metadata: ObjectMeta {
+                name: Some("pod".to_owned()),
+                namespace: Some("default".to_owned()),
+                uid: Some("1234".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let usage = ContainerUsage {
+            start_time: None,
+            cpu_time: Duration::from_millis(1500),
+            memory_bytes: 1024,
+            log_bytes: Some(100),
+        };
+        let containers = vec![
+            ContainerStats::new("first", &usage),
+            ContainerStats::new("second", &usage),
+        ];
+        let volumes = vec![VolumeStats {
+            name: "data".to_owned(),
+            fs: FsStats {
+                used_bytes: Some(50),
+                ..Default::default()
+            },
+        }];
+        let mut summary = Summary {
+            node: NodeStats::default(),
+            pods: vec![PodStats::new(&pod, containers, volumes)],
+        };
+
+        let json = serde_json::to_value(&summary).unwrap();
+        let pod = &json["pods"][0];
+        assert_eq!(pod["podRef"]["uid"], "1234");
+        assert_eq!(pod["cpu"]["usageCoreNanoSeconds"], 3_000_000_000u64);
+        assert_eq!(pod["memory"]["workingSetBytes"], 2048);
+        assert_eq!(pod["ephemeral-storage"]["usedBytes"], 250);
+        assert_eq!(pod["volume"][0]["name"], "data");
+        assert_eq!(pod["volume"][0]["usedBytes"], 50);
+        assert_eq!(pod["containers"][1]["logs"]["usedBytes"], 100);
+
+        summary.retain_cpu_and_memory();
+        let json = serde_json::to_value(&summary).unwrap();
+        assert!(json["pods"][0].get("volume").is_none());
+        assert!(json["pods"][0]["containers"][0].get("logs").is_none());
+    }
+
+    #[test]
+    fn test_node_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = NodeStats::collect("node", dir.path());
+        assert_eq!(stats.node_name, "node");
+        if cfg!(target_os = "linux") {
+            assert!(stats.cpu.is_some());
+            assert!(stats.memory.is_some());
+            assert!(stats.fs.unwrap().capacity_bytes.unwrap() > 0);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Readers for the resource usage of the machine, backed by `/proc` and `statvfs`.
+use std::collections::HashMap;
+use std::path::Path;
+
+const MEMINFO_PATH: &str = "/proc/meminfo";
+const STAT_PATH: &str = "/proc/stat";
+const LOADAVG_PATH: &str = "/proc/loadavg";
+const PID_MAX_PATH: &str = "/proc/sys/kernel/pid_max";
+
+/// Usage of a filesystem, in bytes and inodes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct FilesystemUsage {
+    pub capacity: u64,
+    /// Space available to unprivileged users
+    pub available: u64,
+    /// Space that is free, including space reserved for privileged users
+    pub free: u64,
+    pub inodes: u64,
+    pub inodes_free: u64,
+}
+
+/// Reads `/proc/meminfo`, with all sizes converted to bytes.
+pub(crate) fn meminfo() -> anyhow::Result<HashMap<String, u64>> {
+    Ok(parse_meminfo(&std::fs::read_to_string(MEMINFO_PATH)?))
+}
+
+fn parse_meminfo(meminfo: &str) -> HashMap<String, u64> {
+    meminfo
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let mut value = value.split_whitespace();
+            let amount: u64 = value.next()?.parse().ok()?;
+            let multiplier = match value.next() {
+                Some("kB") => 1024,
+                _ => 1,
+            };
+            Some((key.to_owned(), amount * multiplier))
+        })
+        .collect()
+}
+
+/// CPU time used by all cores of the machine.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct CpuUsage {
+    /// Time spent on anything but idling, in nanoseconds
+    pub usage_core_nano_seconds: u64,
+    /// When the machine was booted, as seconds since the epoch
+    pub boot_time: Option<i64>,
+}
+
+/// Reads the CPU time used by the machine from `/proc/stat`.
+#[cfg(target_family = "unix")]
+pub(crate) fn cpu_usage() -> anyhow::Result<CpuUsage> {
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    parse_stat(
+        &std::fs::read_to_string(STAT_PATH)?,
+        ticks_per_second as u64,
+    )
+}
+
+#[cfg(not(target_family = "unix"))]
+pub(crate) fn cpu_usage() -> anyhow::Result<CpuUsage> {
+    anyhow::bail!("CPU usage is not supported on this platform")
+}
+
+fn parse_stat(stat: &str, ticks_per_second: u64) -> anyhow::Result<CpuUsage> {
+    let mut usage = CpuUsage::default();
+    let cpu = stat
+        .lines()
+        .find(|l| l.starts_with("cpu "))
+        .ok_or_else(|| anyhow::anyhow!("no cpu entry in {}", STAT_PATH))?;
+    // The fields are user, nice, system, idle, iowait, irq, softirq and
+    // steal, followed by guest times that are already included in user
+    let ticks = cpu
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|t| t.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let busy: u64 = ticks
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 3 && *i != 4)
+        .map(|(_, t)| t)
+        .sum();
+    usage.usage_core_nano_seconds = busy * (1_000_000_000 / ticks_per_second);
+    usage.boot_time = stat
+        .lines()
+        .find_map(|l| l.strip_prefix("btime "))
+        .and_then(|t| t.trim().parse().ok());
+    Ok(usage)
+}
+
+/// Returns the usage of the filesystem the given path is on. If the path does
+/// not exist yet, its closest existing parent is used instead.
+#[cfg(target_family = "unix")]
+pub(crate) fn filesystem_usage(path: &Path) -> anyhow::Result<FilesystemUsage> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = path
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| anyhow::anyhow!("no existing parent directory"))?;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // The types of the statvfs fields differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    let fragment_size = stat.f_frsize as u64;
+    #[allow(clippy::unnecessary_cast)]
+    Ok(FilesystemUsage {
+        capacity: stat.f_blocks as u64 * fragment_size,
+        available: stat.f_bavail as u64 * fragment_size,
+        free: stat.f_bfree as u64 * fragment_size,
+        inodes: stat.f_files as u64,
+        inodes_free: stat.f_ffree as u64,
+    })
+}
+
+#[cfg(not(target_family = "unix"))]
+pub(crate) fn filesystem_usage(_path: &Path) -> anyhow::Result<FilesystemUsage> {
+    anyhow::bail!("filesystem usage is not supported on this platform")
+}
+
+/// Usage of process IDs on the machine.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct PidUsage {
+    /// The highest number of process IDs that can be in use at a time
+    pub max: u64,
+    /// The number of processes and threads that currently exist
+    pub running: u64,
+}
+
+/// Reads the usage of process IDs from `/proc/sys/kernel/pid_max` and
+/// `/proc/loadavg`.
+pub(crate) fn pid_usage() -> anyhow::Result<PidUsage> {
+    let max = std::fs::read_to_string(PID_MAX_PATH)?.trim().parse()?;
+    let running = parse_loadavg(&std::fs::read_to_string(LOADAVG_PATH)?)?;
+    Ok(PidUsage { max, running })
+}
+
+/// Returns the total number of scheduling entities from the fourth field of
+/// `/proc/loadavg`, which looks like `running/total`.
+fn parse_loadavg(loadavg: &str) -> anyhow::Result<u64> {
+    loadavg
+        .split_whitespace()
+        .nth(3)
+        .and_then(|entities| entities.split_once('/'))
+        .ok_or_else(|| anyhow::anyhow!("no process count in {}", LOADAVG_PATH))?
+        .1
+        .parse()
+        .map_err(anyhow::Error::from)
+}
+
+/// Returns the number of bytes and inodes used by everything under the given
+/// path. Symlinks are not followed. This blocks while walking the directory tree.
+pub(crate) fn disk_usage(path: &Path) -> std::io::Result<(u64, u64)> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut bytes = metadata.len();
+    let mut inodes = 1;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let (b, i) = disk_usage(&entry?.path())?;
+            bytes += b;
+            inodes += i;
+        }
+    }
+    Ok((bytes, inodes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = parse_meminfo(
+            "MemTotal:        4032800 kB\nMemFree:          123456 kB\nHugePages_Total:       0\n",
+        );
+        assert_eq!(meminfo.get("MemTotal"), Some(&(4032800 * 1024)));
+        assert_eq!(meminfo.get("MemFree"), Some(&(123456 * 1024)));
+        assert_eq!(meminfo.get("HugePages_Total"), Some(&0));
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "cpu  100 20 30 4000 50 6 7 8 0 0\ncpu0 100 20 30 4000 50 6 7 8 0 0\nbtime 1600000000\n";
+        let usage = parse_stat(stat, 100).unwrap();
+        assert_eq!(usage.usage_core_nano_seconds, 171 * 10_000_000);
+        assert_eq!(usage.boot_time, Some(1600000000));
+        assert!(parse_stat("intr 1 2 3\n", 100).is_err());
+    }
+
+    #[test]
+    fn test_parse_loadavg() {
+        assert_eq!(
+            parse_loadavg("0.52 0.58 0.59 2/1011 12345\n").unwrap(),
+            1011
+        );
+        assert!(parse_loadavg("0.52 0.58\n").is_err());
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("nested/file"), vec![0u8; 1000]).unwrap();
+        let (bytes, inodes) = disk_usage(dir.path()).unwrap();
+        assert!(bytes >= 1000);
+        assert_eq!(inodes, 3);
+    }
+}
@@ -1,5 +1,6 @@
 //! `composite` implements building complex stores from simpler ones.
 
+use crate::config::Config;
 use crate::store::PullPolicy;
 use crate::store::Store;
 use async_trait::async_trait;
@@ -73,6 +74,11 @@ impl Store for CompositeStore {
             self.base.get(image_ref, pull_policy, auth).await
         }
     }
+
+    async fn reconfigure(&self, config: &Config) {
+        self.interceptor.reconfigure(config).await;
+        self.base.reconfigure(config).await;
+    }
 }
 
 #[cfg(test)]
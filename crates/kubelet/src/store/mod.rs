@@ -14,7 +14,9 @@ use async_trait::async_trait;
 use oci_distribution::Reference;
 use tracing::{debug, instrument};
 
+use crate::config::Config;
 use crate::container::PullPolicy;
+use crate::metrics;
 use crate::pod::Pod;
 use crate::store::oci::Client;
 
@@ -100,6 +102,12 @@ pub trait Store: Sync {
             .into_iter()
             .collect()
     }
+
+    /// Applies a reloaded kubelet configuration, e.g. to pick up changes to
+    /// the insecure registries.
+    ///
+    /// The default implementation ignores the configuration.
+    async fn reconfigure(&self, _config: &Config) {}
 }
 
 /// A `Store` implementation which obtains module data from remote registries
@@ -113,7 +121,19 @@ impl<S: Storer, C: Client> LocalStore<S, C> {
     #[instrument(level = "info", skip(self, auth))]
     async fn pull(&self, image_ref: &Reference, auth: &RegistryAuth) -> anyhow::Result<()> {
         debug!("Pulling image ref from registry");
-        let image_data = self.client.lock().await.pull(image_ref, auth).await?;
+        let image_data = metrics::timed(
+            &metrics::IMAGE_PULL_DURATION,
+            &[],
+            self.client.lock().await.pull(image_ref, auth),
+        )
+        .await?;
+        metrics::IMAGE_PULL_BYTES.inc_by(
+            image_data
+                .layers
+                .iter()
+                .map(|layer| layer.data.len() as u64)
+                .sum(),
+        );
         self.storer
             .write()
             .await
@@ -131,12 +151,8 @@ impl<S: Storer + Sync + Send, C: Client + Sync + Send> Store for LocalStore<S, C
         pull_policy: PullPolicy,
         auth: &RegistryAuth,
     ) -> anyhow::Result<Vec<u8>> {
-        match pull_policy {
-            PullPolicy::IfNotPresent => {
-                if !self.storer.read().await.is_present(image_ref).await {
-                    self.pull(image_ref, auth).await?
-                }
-            }
+        let present = match pull_policy {
+            PullPolicy::IfNotPresent => self.storer.read().await.is_present(image_ref).await,
             PullPolicy::Always => {
                 let digest = self
                     .client
@@ -144,21 +160,27 @@ impl<S: Storer + Sync + Send, C: Client + Sync + Send> Store for LocalStore<S, C
                     .await
                     .fetch_digest(image_ref, auth)
                     .await?;
-                let already_got_with_digest = self
-                    .storer
+                self.storer
                     .read()
                     .await
                     .is_present_with_digest(image_ref, digest)
-                    .await;
-                if !already_got_with_digest {
-                    self.pull(image_ref, auth).await?
-                }
+                    .await
             }
-            PullPolicy::Never => (),
+            PullPolicy::Never => return self.storer.read().await.get_local(image_ref).await,
         };
+        metrics::IMAGE_CACHE_LOOKUPS
+            .with_label_values(&[if present { "hit" } else { "miss" }])
+            .inc();
+        if !present {
+            self.pull(image_ref, auth).await?
+        }
 
         self.storer.read().await.get_local(image_ref).await
     }
+
+    async fn reconfigure(&self, config: &Config) {
+        self.client.lock().await.reconfigure(config);
+    }
 }
 
 /// A backing store for the `LocalStore` implementation of `Store`. The Storer
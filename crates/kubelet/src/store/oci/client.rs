@@ -6,6 +6,8 @@ use oci_distribution::secrets::RegistryAuth;
 
 use oci_distribution::Reference;
 
+use crate::config::Config;
+
 /// An image client capable of fetching images from a storage location
 #[async_trait]
 pub trait Client {
@@ -54,6 +56,12 @@ pub trait Client {
             .digest
             .ok_or_else(|| anyhow::anyhow!("image {} does not have a digest", image_ref))
     }
+
+    /// Applies a reloaded kubelet configuration, e.g. to pick up changes to
+    /// the insecure registries.
+    ///
+    /// The default implementation ignores the configuration.
+    fn reconfigure(&mut self, _config: &Config) {}
 }
 
 #[async_trait]
@@ -70,4 +78,8 @@ impl Client for oci_distribution::Client {
     ) -> anyhow::Result<String> {
         self.fetch_manifest_digest(image, auth).await
     }
+
+    fn reconfigure(&mut self, config: &Config) {
+        *self = oci_distribution::Client::from_source(config);
+    }
 }
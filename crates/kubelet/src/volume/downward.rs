@@ -3,10 +3,12 @@ use std::path::Path;
 
 use k8s_openapi::{
     api::core::v1::{
-        DownwardAPIVolumeFile, ObjectFieldSelector, ResourceFieldSelector, Volume as KubeVolume,
+        DownwardAPIVolumeFile, Node, ObjectFieldSelector, ResourceFieldSelector,
+        Volume as KubeVolume,
     },
     apimachinery::pkg::api::resource::Quantity as KubeQuantity,
 };
+use kube::api::Api;
 use tracing::warn;
 
 use crate::container::Container;
@@ -19,12 +21,13 @@ pub struct DownwardApiVolume {
     pod: Pod,
     items: Vec<DownwardAPIVolumeFile>,
     mounted_path: Option<PathBuf>,
+    client: kube::Client,
 }
 
 impl DownwardApiVolume {
     /// Creates a new Downward API volume from a Kubernetes volume object. Passing a non-Downward
     /// API volume type will result in an error
-    pub fn new(vol: &KubeVolume, pod: Pod) -> anyhow::Result<Self> {
+    pub fn new(vol: &KubeVolume, pod: Pod, client: kube::Client) -> anyhow::Result<Self> {
         let da_source = vol.downward_api.as_ref().ok_or_else(|| {
             anyhow::anyhow!(
                 "Called a Downward API volume constructor with a non-Downward API volume"
@@ -35,6 +38,7 @@ impl DownwardApiVolume {
             pod,
             items: da_source.items.clone().unwrap_or_default(),
             mounted_path: None,
+            client,
         })
     }
 
@@ -80,13 +84,26 @@ impl DownwardApiVolume {
         let field_refs = futures::future::join_all(field_refs);
 
         let containers = self.pod.containers();
+        let node_allocatable = if self
+            .items
+            .iter()
+            .filter_map(|d| d.resource_field_ref.as_ref())
+            .any(|f| is_unset_limit(f, &containers))
+        {
+            node_allocatable(&self.client, &self.pod).await?
+        } else {
+            BTreeMap::new()
+        };
         let resource_refs = self
             .items
             .iter()
             .filter_map(|d| {
-                d.resource_field_ref
-                    .as_ref()
-                    .map(|f| (path.join(&d.path), data_from_resource_ref(f, &containers)))
+                d.resource_field_ref.as_ref().map(|f| {
+                    (
+                        path.join(&d.path),
+                        data_from_resource_ref(f, &containers, &node_allocatable),
+                    )
+                })
             })
             .map(|(p, res)| async move {
                 let data = res?;
@@ -211,9 +228,51 @@ fn btree_to_data(data: &BTreeMap<String, String>) -> Vec<u8> {
         .into_bytes()
 }
 
-fn data_from_resource_ref(
+/// Whether the resource ref is for a limit that the referenced container does not set, which
+/// defaults to the node allocatable value
+pub(crate) fn is_unset_limit(
+    resource_ref: &ResourceFieldSelector,
+    containers: &[Container],
+) -> bool {
+    let limit = match resource_ref.resource.strip_prefix("limits.") {
+        Some(limit) => limit,
+        None => return false,
+    };
+    let container_name = resource_ref.container_name.as_deref().unwrap_or_default();
+    !containers.iter().any(|c| {
+        c.name() == container_name
+            && c.resources()
+                .and_then(|r| r.limits.as_ref())
+                .map_or(false, |limits| limits.contains_key(limit))
+    })
+}
+
+/// Fetches the allocatable resources of the node the pod is scheduled to.
+pub(crate) async fn node_allocatable(
+    client: &kube::Client,
+    pod: &Pod,
+) -> anyhow::Result<BTreeMap<String, KubeQuantity>> {
+    let node_name = pod
+        .node_name()
+        .ok_or_else(|| anyhow::anyhow!("pod is not scheduled to a node"))?;
+    let node = Api::<Node>::all(client.clone())
+        .get(node_name)
+        .await
+        .map_err(|e| {
+            anyhow::Error::new(e).context(format!("unable to fetch node \"{}\"", node_name))
+        })?;
+    Ok(node
+        .status
+        .and_then(|status| status.allocatable)
+        .unwrap_or_default())
+}
+
+/// Generates the data for the given resource ref. Limits that are not set default to the given
+/// node allocatable value, or to zero if it is not given either
+pub(crate) fn data_from_resource_ref(
     resource_ref: &ResourceFieldSelector,
     containers: &[Container],
+    node_allocatable: &BTreeMap<String, KubeQuantity>,
 ) -> anyhow::Result<Vec<u8>> {
     let (kind, path) = resource_ref.resource.split_once('.').ok_or_else(|| {
         anyhow::anyhow!(
@@ -268,15 +327,15 @@ fn data_from_resource_ref(
             ),
             resource_ref.divisor.as_ref(),
         ),
-        // TODO(thomastaylor312): According to the docs, if a limit is not specified, we should
-        // default to the node allocatable value for CPU and memory. We have no easy way to access
-        // that here, so for now we are just defaulting
+        // According to the docs, if a limit is not specified, we default to the node allocatable
+        // value for CPU and memory
         ("limits", "cpu") => calculate_value(
             QuantityType::Cpu(
                 resources
                     .limits
                     .as_ref()
                     .and_then(|requests| requests.get("cpu"))
+                    .or_else(|| node_allocatable.get("cpu"))
                     .unwrap_or(&empty_quantity),
             ),
             resource_ref.divisor.as_ref(),
@@ -287,6 +346,7 @@ fn data_from_resource_ref(
                     .limits
                     .as_ref()
                     .and_then(|requests| requests.get("memory"))
+                    .or_else(|| node_allocatable.get("memory"))
                     .unwrap_or(&empty_quantity),
             ),
             resource_ref.divisor.as_ref(),
@@ -321,6 +381,11 @@ fn calculate_value(
 mod test {
     use super::*;
 
+    fn client() -> kube::Client {
+        let config = kube::Config::new("http://127.0.0.1:1".parse().unwrap());
+        kube::Client::try_from(config).unwrap()
+    }
+
     #[tokio::test]
     async fn test_valid_mount() {
         let pod_namespace = "test";
@@ -462,7 +527,7 @@ mod test {
         });
         let fake_pod: Pod = serde_json::from_value(fake_pod).unwrap();
         let vol = fake_pod.volumes().unwrap()[0].clone();
-        let mut downward = DownwardApiVolume::new(&vol, fake_pod)
+        let mut downward = DownwardApiVolume::new(&vol, fake_pod, client())
             .expect("Should be able to create a new DownwardApiVolume");
         // Setup a tempdir where we can mount things at
         let tempdir = tempfile::tempdir().expect("Unable to create tempdir");
@@ -617,7 +682,7 @@ mycustomannotation="{}""#,
 
         let fake_pod: Pod = serde_json::from_value(fake_pod).unwrap();
         let mut vol = fake_pod.volumes().unwrap()[0].clone();
-        let mut downward = DownwardApiVolume::new(&vol, fake_pod.clone())
+        let mut downward = DownwardApiVolume::new(&vol, fake_pod.clone(), client())
             .expect("Should be able to create a new DownwardApiVolume");
         // Setup a tempdir where we can mount things at
         let tempdir = tempfile::tempdir().expect("Unable to create tempdir");
@@ -641,7 +706,7 @@ mycustomannotation="{}""#,
             .unwrap()
             .resource = "requests.memory".to_string();
 
-        let mut downward = DownwardApiVolume::new(&vol, fake_pod.clone())
+        let mut downward = DownwardApiVolume::new(&vol, fake_pod.clone(), client())
             .expect("Should be able to create a new DownwardApiVolume");
         downward
             .mount(tempdir.path())
@@ -667,7 +732,7 @@ mycustomannotation="{}""#,
             });
         }
 
-        let mut downward = DownwardApiVolume::new(&vol, fake_pod.clone())
+        let mut downward = DownwardApiVolume::new(&vol, fake_pod.clone(), client())
             .expect("Should be able to create a new DownwardApiVolume");
         downward
             .mount(tempdir.path())
@@ -688,7 +753,7 @@ mycustomannotation="{}""#,
             .unwrap()
             .field_path = "spec.status".to_string();
 
-        let mut downward = DownwardApiVolume::new(&vol, fake_pod)
+        let mut downward = DownwardApiVolume::new(&vol, fake_pod, client())
             .expect("Should be able to create a new DownwardApiVolume");
         downward
             .mount(tempdir.path())
@@ -696,6 +761,47 @@ mycustomannotation="{}""#,
             .expect_err("A valid path outside of metadata should fail");
     }
 
+    #[test]
+    fn test_limits_default_to_node_allocatable() {
+        let container: k8s_openapi::api::core::v1::Container =
+            serde_json::from_value(serde_json::json!({
+                "name": "foo",
+                "resources": {
+                    "limits": {
+                        "memory": "64Mi"
+                    }
+                }
+            }))
+            .unwrap();
+        let containers = vec![Container::new(&container)];
+        let mut allocatable = BTreeMap::new();
+        allocatable.insert("cpu".to_string(), KubeQuantity("2".to_string()));
+        allocatable.insert("memory".to_string(), KubeQuantity("1Gi".to_string()));
+        let resource_ref = |resource: &str| ResourceFieldSelector {
+            container_name: Some("foo".to_string()),
+            resource: resource.to_string(),
+            ..Default::default()
+        };
+
+        let data =
+            data_from_resource_ref(&resource_ref("limits.cpu"), &containers, &allocatable).unwrap();
+        assert_eq!(
+            data, b"2000m",
+            "Unset limit should default to node allocatable"
+        );
+        let data =
+            data_from_resource_ref(&resource_ref("limits.memory"), &containers, &allocatable)
+                .unwrap();
+        assert_eq!(
+            data, b"64Mi",
+            "Set limit should not default to node allocatable"
+        );
+
+        assert!(is_unset_limit(&resource_ref("limits.cpu"), &containers));
+        assert!(!is_unset_limit(&resource_ref("limits.memory"), &containers));
+        assert!(!is_unset_limit(&resource_ref("requests.cpu"), &containers));
+    }
+
     async fn assert_content(path: PathBuf, expected: &str, message: &str) {
         let content = tokio::fs::read_to_string(path)
             .await
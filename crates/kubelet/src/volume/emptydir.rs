@@ -0,0 +1,208 @@
+use k8s_openapi::api::core::v1::Volume as KubeVolume;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity as KubeQuantity;
+use tracing::{info, warn};
+
+use crate::resources::quantity::{Quantity, QuantityType};
+
+use super::*;
+
+/// The medium of emptyDir volumes that are backed by memory instead of disk.
+const MEMORY_MEDIUM: &str = "Memory";
+
+/// A type that can manage an emptyDir volume with mounting and unmounting support
+pub struct EmptyDirVolume {
+    vol_name: String,
+    memory_medium: bool,
+    size_limit: Option<u64>,
+    mounted_path: Option<PathBuf>,
+    /// Whether a tmpfs is mounted at the mounted path
+    tmpfs: bool,
+}
+
+impl EmptyDirVolume {
+    /// Creates a new emptyDir volume from a Kubernetes volume object. Passing a non-emptyDir volume
+    /// type will result in an error
+    pub fn new(vol: &KubeVolume) -> anyhow::Result<Self> {
+        let source = vol.empty_dir.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("Called an emptyDir volume constructor with a non-emptyDir volume")
+        })?;
+        let memory_medium = match source.medium.as_deref() {
+            None | Some("") => false,
+            Some(MEMORY_MEDIUM) => true,
+            Some(medium) => anyhow::bail!("Unsupported emptyDir medium {}", medium),
+        };
+        let size_limit = source.size_limit.as_ref().map(size_limit).transpose()?;
+        Ok(EmptyDirVolume {
+            vol_name: vol.name.clone(),
+            memory_medium,
+            size_limit,
+            mounted_path: None,
+            tmpfs: false,
+        })
+    }
+
+    /// Returns the path where the volume is mounted on the host. Will return `None` if the volume
+    /// hasn't been mounted yet
+    pub fn get_path(&self) -> Option<&Path> {
+        self.mounted_path.as_deref()
+    }
+
+    /// Returns the maximum size of the volume in bytes, if it is limited
+    pub fn size_limit(&self) -> Option<u64> {
+        self.size_limit
+    }
+
+    /// Mounts the emptyDir volume in the given directory. The actual path will be
+    /// $BASE_PATH/$VOLUME_NAME
+    ///
+    /// Volumes with the `Memory` medium are backed by a tmpfs of the size of the volume's size
+    /// limit. If a tmpfs cannot be mounted, e.g. because the kubelet lacks the privileges to do
+    /// so, a directory on disk is used instead.
+    pub async fn mount(&mut self, base_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = base_path.as_ref().join(&self.vol_name);
+        tokio::fs::create_dir_all(&path).await?;
+        if self.memory_medium {
+            match mount_tmpfs(path.clone(), self.size_limit).await {
+                Ok(()) => {
+                    info!(volume_name = %self.vol_name, "Mounted tmpfs for emptyDir volume");
+                    self.tmpfs = true;
+                }
+                Err(e) => warn!(
+                    error = %e,
+                    volume_name = %self.vol_name,
+                    "Unable to mount tmpfs for emptyDir volume, storing it on disk instead"
+                ),
+            }
+        }
+        self.mounted_path = Some(path);
+        Ok(())
+    }
+
+    /// Unmounts the directory, which removes all files. Calling `unmount` on a directory that
+    /// hasn't been mounted will log a warning, but otherwise not error
+    pub async fn unmount(&mut self) -> anyhow::Result<()> {
+        match self.mounted_path.take() {
+            Some(p) => {
+                if std::mem::take(&mut self.tmpfs) {
+                    unmount_tmpfs(p.clone()).await?;
+                }
+                //although remove_dir_all crate could default to std::fs::remove_dir_all for unix family, we still prefer std::fs implemetation for unix
+                #[cfg(target_family = "windows")]
+                tokio::task::spawn_blocking(|| remove_dir_all::remove_dir_all(p)).await??;
+
+                #[cfg(target_family = "unix")]
+                tokio::fs::remove_dir_all(p).await?;
+            }
+            None => {
+                warn!("Attempted to unmount emptyDir directory that wasn't mounted, this generally shouldn't happen");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts the size limit of an emptyDir volume to bytes.
+pub(crate) fn size_limit(quantity: &KubeQuantity) -> anyhow::Result<u64> {
+    match Quantity::from_kube_quantity(QuantityType::Memory(quantity))? {
+        Quantity::Memory(bytes) => Ok(bytes as u64),
+        _ => anyhow::bail!("Invalid emptyDir size limit {}", quantity.0),
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn mount_tmpfs(path: PathBuf, size_limit: Option<u64>) -> anyhow::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    tokio::task::spawn_blocking(move || {
+        let target = CString::new(path.as_os_str().as_bytes())?;
+        let fs_type = CString::new("tmpfs")?;
+        let options = CString::new(
+            size_limit
+                .map(|size| format!("size={}", size))
+                .unwrap_or_default(),
+        )?;
+        let result = unsafe {
+            libc::mount(
+                fs_type.as_ptr(),
+                target.as_ptr(),
+                fs_type.as_ptr(),
+                0,
+                options.as_ptr() as *const libc::c_void,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn mount_tmpfs(_path: PathBuf, _size_limit: Option<u64>) -> anyhow::Result<()> {
+    anyhow::bail!("tmpfs is only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+async fn unmount_tmpfs(path: PathBuf) -> anyhow::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    tokio::task::spawn_blocking(move || {
+        let target = CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::umount(target.as_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn unmount_tmpfs(_path: PathBuf) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn volume(empty_dir: serde_json::Value) -> KubeVolume {
+        serde_json::from_value(serde_json::json!({
+            "name": "scratch",
+            "emptyDir": empty_dir,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mount_and_unmount() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vol = EmptyDirVolume::new(&volume(serde_json::json!({
+            "sizeLimit": "1Mi",
+        })))
+        .unwrap();
+        assert_eq!(vol.size_limit(), Some(1024 * 1024));
+
+        vol.mount(dir.path()).await.unwrap();
+        let path = vol.get_path().unwrap().to_owned();
+        assert_eq!(path, dir.path().join("scratch"));
+        tokio::fs::write(path.join("data"), b"scratch data")
+            .await
+            .unwrap();
+
+        vol.unmount().await.unwrap();
+        assert!(vol.get_path().is_none());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_medium() {
+        assert!(EmptyDirVolume::new(&volume(serde_json::json!({}))).is_ok());
+        assert!(EmptyDirVolume::new(&volume(serde_json::json!({ "medium": "Memory" }))).is_ok());
+        assert!(
+            EmptyDirVolume::new(&volume(serde_json::json!({ "medium": "HugePages" }))).is_err()
+        );
+    }
+}
@@ -14,7 +14,8 @@ use crate::plugin_watcher::PluginRegistry;
 use crate::pod::Pod;
 
 mod configmap;
-mod downward;
+pub(crate) mod downward;
+pub(crate) mod emptydir;
 mod hostpath;
 mod persistentvolumeclaim;
 mod projected;
@@ -22,6 +23,7 @@ mod secret;
 
 pub use configmap::ConfigMapVolume;
 pub use downward::DownwardApiVolume;
+pub use emptydir::EmptyDirVolume;
 pub use hostpath::HostPathVolume;
 pub use persistentvolumeclaim::PvcVolume;
 pub use projected::ProjectedVolume;
@@ -48,6 +50,8 @@ pub enum VolumeRef {
     /// Projected volume, a new volume type used for all projected data types (ConfigMap, Secret,
     /// and Downward API)
     Projected(ProjectedVolume),
+    /// emptyDir volume, scratch space that lives as long as the pod
+    EmptyDir(EmptyDirVolume),
 }
 
 impl VolumeRef {
@@ -80,6 +84,7 @@ impl VolumeRef {
             VolumeRef::HostPath(host) => host.get_path(),
             VolumeRef::DownwardApi(d) => d.get_path(),
             VolumeRef::Projected(p) => p.get_path(),
+            VolumeRef::EmptyDir(e) => e.get_path(),
         }
     }
 
@@ -95,6 +100,7 @@ impl VolumeRef {
             // We need to clone the path here so we are sure that it is owned since this mount call
             // results in recursion
             VolumeRef::Projected(p) => p.mount(path.as_ref().to_owned()).await,
+            VolumeRef::EmptyDir(e) => e.mount(path).await,
         }
     }
 
@@ -109,10 +115,17 @@ impl VolumeRef {
             VolumeRef::HostPath(_) => Ok(()),
             VolumeRef::DownwardApi(d) => d.unmount().await,
             VolumeRef::Projected(p) => p.unmount().await,
+            VolumeRef::EmptyDir(e) => e.unmount().await,
         }
     }
 }
 
+/// Returns the name of the directory, under the provider's volume path, that
+/// holds the volumes of the given pod.
+pub fn pod_dir_name(pod: &Pod) -> String {
+    format!("{}-{}", pod.name(), pod.namespace())
+}
+
 fn mount_setting_for(key: &str, items_to_mount: &Option<Vec<KeyToPath>>) -> ItemMount {
     match items_to_mount {
         None => ItemMount::MountAt(key.to_string()),
@@ -167,6 +180,7 @@ async fn to_volume_ref(
         Ok(VolumeRef::DownwardApi(DownwardApiVolume::new(
             vol,
             pod.to_owned(),
+            client.clone(),
         )?))
     } else if vol.projected.is_some() {
         Ok(VolumeRef::Projected(ProjectedVolume::new(
@@ -174,9 +188,11 @@ async fn to_volume_ref(
             pod.to_owned(),
             client.clone(),
         )?))
+    } else if vol.empty_dir.is_some() {
+        Ok(VolumeRef::EmptyDir(EmptyDirVolume::new(vol)?))
     } else {
         Err(anyhow::anyhow!(
-            "Unsupported volume type. Currently supported types: ConfigMap, Secret, PersistentVolumeClaim, HostPath, and DownwardAPI"
+            "Unsupported volume type. Currently supported types: ConfigMap, Secret, PersistentVolumeClaim, HostPath, DownwardAPI, Projected, and EmptyDir"
         ))
     }
 }
@@ -26,6 +26,7 @@ use thiserror::Error;
 use tracing::log::{info, warn};
 
 use crate::grpc_sock;
+use crate::metrics::{self, CSI_OPERATION_DURATION};
 use crate::plugin_watcher::PluginRegistry;
 
 use super::*;
@@ -373,9 +374,12 @@ async fn supports_stage_unstage(
     csi_client: &mut NodeClient<tonic::transport::Channel>,
 ) -> anyhow::Result<bool> {
     let mut stage_unstage_volume = false;
-    let response = csi_client
-        .node_get_capabilities(NodeGetCapabilitiesRequest {})
-        .await?;
+    let response = metrics::timed(
+        &CSI_OPERATION_DURATION,
+        &["NodeGetCapabilities"],
+        csi_client.node_get_capabilities(NodeGetCapabilitiesRequest {}),
+    )
+    .await?;
     for capability in &response.get_ref().capabilities {
         if let Some(typ) = &capability.r#type {
             let _typ_stage_unstage_volume = rpc::Type::StageUnstageVolume as i32;
@@ -402,23 +406,27 @@ async fn stage_volume(
     // NOTE: The volume attachments API is referenced in Kubelet, but the information it provides
     // seems to be handled by info on a PVC and calls to the CSI plugin. So we have NO IDEA if this
     // is even doable or useful
-    csi_client
-        .node_stage_volume(NodeStageVolumeRequest {
-            volume_id: csi.volume_handle.clone(),
-            staging_target_path: staging_path.to_string_lossy().to_string(),
-            volume_capability: Some(VolumeCapability {
-                // TODO: determine the correct access mode and mount flags from the volume
-                // https://github.com/kubernetes/kubernetes/blob/734889ed822d1a60c6dd61ccd8f1ed0e8ab31ea5/pkg/volume/csi/csi_attacher.go#L325-L333
-                access_mode: Some(CSIAccessMode {
-                    mode: CSIMode::SingleNodeWriter as i32,
-                }),
-                access_type: Some(access_type),
+    let req = NodeStageVolumeRequest {
+        volume_id: csi.volume_handle.clone(),
+        staging_target_path: staging_path.to_string_lossy().to_string(),
+        volume_capability: Some(VolumeCapability {
+            // TODO: determine the correct access mode and mount flags from the volume
+            // https://github.com/kubernetes/kubernetes/blob/734889ed822d1a60c6dd61ccd8f1ed0e8ab31ea5/pkg/volume/csi/csi_attacher.go#L325-L333
+            access_mode: Some(CSIAccessMode {
+                mode: CSIMode::SingleNodeWriter as i32,
             }),
-            secrets,
-            publish_context: Default::default(),
-            volume_context: Default::default(),
-        })
-        .await?;
+            access_type: Some(access_type),
+        }),
+        secrets,
+        publish_context: Default::default(),
+        volume_context: Default::default(),
+    };
+    metrics::timed(
+        &CSI_OPERATION_DURATION,
+        &["NodeStageVolume"],
+        csi_client.node_stage_volume(req),
+    )
+    .await?;
     Ok(())
 }
 
@@ -451,7 +459,12 @@ async fn publish_volume(
     if stage_unstage_volume {
         req.staging_target_path = staging_path.to_string_lossy().to_string();
     }
-    csi_client.node_publish_volume(req).await?;
+    metrics::timed(
+        &CSI_OPERATION_DURATION,
+        &["NodePublishVolume"],
+        csi_client.node_publish_volume(req),
+    )
+    .await?;
     Ok(())
 }
 
@@ -464,7 +477,12 @@ async fn unpublish_volume(
         volume_id: csi.volume_handle.clone(),
         target_path: path.to_string_lossy().to_string(),
     };
-    csi_client.node_unpublish_volume(req).await?;
+    metrics::timed(
+        &CSI_OPERATION_DURATION,
+        &["NodeUnpublishVolume"],
+        csi_client.node_unpublish_volume(req),
+    )
+    .await?;
     Ok(())
 }
 
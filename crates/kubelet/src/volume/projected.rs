@@ -232,7 +232,7 @@ fn to_volume_ref(
             ..Default::default()
         };
         Ok(Either::Left(VolumeRef::DownwardApi(
-            DownwardApiVolume::new(&vol, pod.to_owned())?,
+            DownwardApiVolume::new(&vol, pod.to_owned(), client)?,
         )))
     } else if let Some(sa) = proj.service_account_token.as_ref() {
         Ok(Either::Right(ServiceAccountSource{
@@ -0,0 +1,559 @@
+//! Implements the Kubernetes remote command streaming protocol over websockets
+//! and SPDY.
+//!
+//! Every websocket message is prefixed with a single byte identifying the
+//! channel it belongs to: stdin (0), stdout (1), stderr (2), error (3) and
+//! resize (4). Over SPDY, the client instead opens a separate stream for each
+//! channel. Once the command has completed, its result is written to the
+//! error channel and the connection is closed.
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use hyper::upgrade::Upgraded;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Status, StatusCause, StatusDetails};
+use tokio::io::{AsyncRead, WriteHalf};
+use tokio::sync::mpsc;
+use tracing::{debug, error, instrument, warn};
+use warp::ws::{Message, WebSocket};
+
+use super::spdy::{Frame, FrameReader, FrameWriter, Headers, PendingUpgrade, REFUSED_STREAM};
+use crate::exec::{Options, Output, Session, StreamType};
+use crate::provider::Provider;
+
+const STDIN_CHANNEL: u8 = 0;
+const STDOUT_CHANNEL: u8 = 1;
+const STDERR_CHANNEL: u8 = 2;
+const ERROR_CHANNEL: u8 = 3;
+const RESIZE_CHANNEL: u8 = 4;
+
+// TODO: ~magic~ number
+const CHANNEL_BUFFER_SIZE: usize = 16;
+
+/// How long to wait for a SPDY client to open the streams it asked for.
+const STREAM_CREATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The streaming protocols understood by the exec endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// `v4.channel.k8s.io`, which reports the command result as a JSON encoded `Status`.
+    V4Channel,
+    /// `channel.k8s.io`, which reports only failures as plain text.
+    Channel,
+}
+
+impl Protocol {
+    /// Picks the preferred protocol out of those offered by the client in the
+    /// `Sec-WebSocket-Protocol` (or, over SPDY, `X-Stream-Protocol-Version`)
+    /// header. Clients that offer no protocol at all are treated as speaking
+    /// `channel.k8s.io`.
+    pub(crate) fn negotiate(offered: Option<&str>) -> Option<Self> {
+        let offered: Vec<&str> = match offered {
+            Some(o) => o.split(',').map(str::trim).collect(),
+            None => return Some(Protocol::Channel),
+        };
+        if offered.contains(&"v4.channel.k8s.io") {
+            Some(Protocol::V4Channel)
+        } else if offered.contains(&"channel.k8s.io") {
+            Some(Protocol::Channel)
+        } else {
+            None
+        }
+    }
+
+    /// The name of the protocol as sent in the negotiation header.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Protocol::V4Channel => "v4.channel.k8s.io",
+            Protocol::Channel => "channel.k8s.io",
+        }
+    }
+
+    /// Encodes the result of a command for the error channel, if anything needs to be sent.
+    fn result_message(&self, result: &anyhow::Result<i32>) -> Option<Vec<u8>> {
+        match self {
+            Protocol::V4Channel => {
+                let status = match result {
+                    Ok(0) => Status {
+                        status: Some("Success".to_owned()),
+                        ..Default::default()
+                    },
+                    Ok(code) => Status {
+                        status: Some("Failure".to_owned()),
+                        message: Some(format!(
+                            "command terminated with non-zero exit code: {}",
+                            code
+                        )),
+                        reason: Some("NonZeroExitCode".to_owned()),
+                        details: Some(StatusDetails {
+                            causes: Some(vec![StatusCause {
+                                reason: Some("ExitCode".to_owned()),
+                                message: Some(code.to_string()),
+                                ..Default::default()
+                            }]),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                    Err(e) => Status {
+                        status: Some("Failure".to_owned()),
+                        message: Some(format!("{:#}", e)),
+                        ..Default::default()
+                    },
+                };
+                // Serializing a Status cannot fail
+                serde_json::to_vec(&status).ok()
+            }
+            Protocol::Channel => match result {
+                Ok(0) => None,
+                Ok(code) => Some(
+                    format!("command terminated with non-zero exit code: {}", code).into_bytes(),
+                ),
+                Err(e) => Some(format!("{:#}", e).into_bytes()),
+            },
+        }
+    }
+}
+
+fn frame(channel: u8, data: &[u8]) -> Message {
+    let mut buf = Vec::with_capacity(data.len() + 1);
+    buf.push(channel);
+    buf.extend_from_slice(data);
+    Message::binary(buf)
+}
+
+/// Streams an exec session between the given websocket and the provider
+/// until the command has completed.
+#[instrument(level = "info", skip(provider, opts, socket))]
+pub(crate) async fn stream<T: Provider>(
+    provider: Arc<T>,
+    namespace: String,
+    pod: String,
+    container: String,
+    opts: Options,
+    protocol: Protocol,
+    socket: WebSocket,
+) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let (output_tx, mut output_rx) = mpsc::channel::<Output>(CHANNEL_BUFFER_SIZE);
+    let (mut stdin_tx, stdin_rx) = if opts.stdin {
+        let (tx, rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+
+    let session = Session::new(opts, stdin_rx, output_tx);
+    let mut exec = provider.exec(namespace, pod, container, session);
+    let mut client_connected = true;
+
+    let result = loop {
+        tokio::select! {
+            result = &mut exec => break result,
+            Some(output) = output_rx.recv() => {
+                if client_connected {
+                    if let Err(e) = ws_tx.send(output_frame(output)).await {
+                        warn!(error = %e, "Unable to send exec output, client likely disconnected");
+                        client_connected = false;
+                    }
+                }
+            }
+            msg = ws_rx.next(), if client_connected => match msg {
+                Some(Ok(msg)) if msg.is_binary() => {
+                    match msg.as_bytes().split_first() {
+                        Some((&STDIN_CHANNEL, data)) => {
+                            if let Some(tx) = stdin_tx.as_ref() {
+                                if tx.send(data.to_vec()).await.is_err() {
+                                    debug!("Command is no longer reading stdin");
+                                    stdin_tx = None;
+                                }
+                            }
+                        }
+                        Some((&RESIZE_CHANNEL, _)) => {
+                            debug!("Ignoring terminal resize request");
+                        }
+                        _ => debug!("Ignoring message on unknown channel"),
+                    }
+                }
+                Some(Ok(msg)) if msg.is_close() => {
+                    debug!("Client closed exec connection");
+                    client_connected = false;
+                    stdin_tx = None;
+                }
+                Some(Ok(_)) => (),
+                Some(Err(e)) => {
+                    warn!(error = %e, "Error reading from exec connection");
+                    client_connected = false;
+                    stdin_tx = None;
+                }
+                None => {
+                    client_connected = false;
+                    stdin_tx = None;
+                }
+            },
+        }
+    };
+
+    if let Err(e) = &result {
+        error!(error = %e, "Error running exec command");
+    }
+
+    // Flush any output written before the command completed. The session (and
+    // with it the sending side of the channel) has been dropped at this point.
+    while let Some(output) = output_rx.recv().await {
+        if client_connected && ws_tx.send(output_frame(output)).await.is_err() {
+            client_connected = false;
+        }
+    }
+
+    if !client_connected {
+        return;
+    }
+    if let Some(message) = protocol.result_message(&result) {
+        if let Err(e) = ws_tx.send(frame(ERROR_CHANNEL, &message)).await {
+            warn!(error = %e, "Unable to send exec result");
+            return;
+        }
+    }
+    if let Err(e) = ws_tx.close().await {
+        debug!(error = %e, "Error closing exec connection");
+    }
+}
+
+fn output_frame(output: Output) -> Message {
+    let channel = match output.stream {
+        StreamType::Stdout => STDOUT_CHANNEL,
+        StreamType::Stderr => STDERR_CHANNEL,
+    };
+    frame(channel, &output.data)
+}
+
+/// Streams an exec session over a connection upgraded to SPDY until the
+/// command has completed. The command is only started once the client has
+/// opened a stream for each of the channels it asked for.
+#[instrument(level = "info", skip(provider, opts, upgrade))]
+pub(crate) async fn stream_spdy<T: Provider>(
+    provider: Arc<T>,
+    namespace: String,
+    pod: String,
+    container: String,
+    opts: Options,
+    protocol: Protocol,
+    upgrade: PendingUpgrade,
+) {
+    let io = match upgrade.upgraded().await {
+        Ok(io) => io,
+        Err(e) => {
+            error!(error = %e, "Unable to upgrade exec connection to SPDY");
+            return;
+        }
+    };
+    let (read, write) = tokio::io::split(io);
+    // Frames are read on their own task, as reading a frame cannot be
+    // cancelled halfway through
+    let (frame_tx, mut frame_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+    let reader = tokio::spawn(read_frames(FrameReader::new(read), frame_tx));
+
+    let (output_tx, mut output_rx) = mpsc::channel::<Output>(CHANNEL_BUFFER_SIZE);
+    let (stdin_tx, stdin_rx) = if opts.stdin {
+        let (tx, rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+    let mut conn = SpdyConnection {
+        writer: FrameWriter::new(write),
+        streams: SpdyStreams::default(),
+        stdin_tx,
+        last_stream_id: 0,
+        connected: true,
+    };
+
+    let created = tokio::time::timeout(STREAM_CREATION_TIMEOUT, async {
+        while !conn.streams.created(&opts) {
+            match frame_rx.recv().await {
+                Some(frame) => conn.handle(frame).await?,
+                None => anyhow::bail!("connection closed before all streams were created"),
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap_or_else(|_| {
+        Err(anyhow::anyhow!(
+            "timed out waiting for client to create streams"
+        ))
+    });
+    if let Err(e) = created {
+        warn!(error = %e, "Unable to set up exec streams");
+        conn.close(protocol.result_message(&Err(e))).await;
+        reader.abort();
+        return;
+    }
+
+    let session = Session::new(opts, stdin_rx, output_tx);
+    let mut exec = provider.exec(namespace, pod, container, session);
+
+    let result = loop {
+        tokio::select! {
+            result = &mut exec => break result,
+            Some(output) = output_rx.recv() => conn.send_output(output).await,
+            frame = frame_rx.recv(), if conn.connected => match frame {
+                Some(frame) => {
+                    if let Err(e) = conn.handle(frame).await {
+                        warn!(error = %e, "Error handling exec connection frame");
+                        conn.disconnect();
+                    }
+                }
+                None => conn.disconnect(),
+            },
+        }
+    };
+
+    if let Err(e) = &result {
+        error!(error = %e, "Error running exec command");
+    }
+
+    // Flush any output written before the command completed. The session (and
+    // with it the sending side of the channel) has been dropped at this point.
+    while let Some(output) = output_rx.recv().await {
+        conn.send_output(output).await;
+    }
+    conn.close(protocol.result_message(&result)).await;
+    reader.abort();
+}
+
+async fn read_frames<R: AsyncRead + Unpin>(
+    mut reader: FrameReader<R>,
+    frames: mpsc::Sender<Frame>,
+) {
+    loop {
+        match reader.read_frame().await {
+            Ok(Some(frame)) => {
+                if frames.send(frame).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                warn!(error = %e, "Error reading from exec connection");
+                return;
+            }
+        }
+    }
+}
+
+/// The IDs of the streams opened by a SPDY client, one per channel.
+#[derive(Debug, Default)]
+struct SpdyStreams {
+    error: Option<u32>,
+    stdin: Option<u32>,
+    stdout: Option<u32>,
+    stderr: Option<u32>,
+    resize: Option<u32>,
+}
+
+impl SpdyStreams {
+    /// Whether the client has opened all streams needed for the given options.
+    /// No stderr stream is opened when a TTY was requested.
+    fn created(&self, opts: &Options) -> bool {
+        self.error.is_some()
+            && (!opts.stdin || self.stdin.is_some())
+            && (!opts.stdout || self.stdout.is_some())
+            && (!opts.stderr || opts.tty || self.stderr.is_some())
+    }
+
+    fn all(&self) -> impl Iterator<Item = u32> {
+        [
+            self.error,
+            self.stdin,
+            self.stdout,
+            self.stderr,
+            self.resize,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+struct SpdyConnection {
+    writer: FrameWriter<WriteHalf<Upgraded>>,
+    streams: SpdyStreams,
+    stdin_tx: Option<mpsc::Sender<Vec<u8>>>,
+    last_stream_id: u32,
+    connected: bool,
+}
+
+impl SpdyConnection {
+    async fn handle(&mut self, frame: Frame) -> anyhow::Result<()> {
+        match frame {
+            Frame::SynStream {
+                stream_id,
+                fin,
+                headers,
+            } => {
+                self.last_stream_id = stream_id;
+                let stream = match headers.get("streamtype").map(String::as_str) {
+                    Some("error") => &mut self.streams.error,
+                    Some("stdin") => &mut self.streams.stdin,
+                    Some("stdout") => &mut self.streams.stdout,
+                    Some("stderr") => &mut self.streams.stderr,
+                    Some("resize") => &mut self.streams.resize,
+                    stream_type => {
+                        warn!(?stream_type, "Refusing exec stream of unknown type");
+                        let reset = Frame::RstStream {
+                            stream_id,
+                            status: REFUSED_STREAM,
+                        };
+                        return self.writer.write_frame(&reset).await;
+                    }
+                };
+                *stream = Some(stream_id);
+                if fin && self.streams.stdin == Some(stream_id) {
+                    self.stdin_tx = None;
+                }
+                let reply = Frame::SynReply {
+                    stream_id,
+                    fin: false,
+                    headers: Headers::new(),
+                };
+                self.writer.write_frame(&reply).await?;
+            }
+            Frame::Data {
+                stream_id,
+                fin,
+                data,
+            } if self.streams.stdin == Some(stream_id) => {
+                if let Some(tx) = self.stdin_tx.as_ref() {
+                    if !data.is_empty() && tx.send(data).await.is_err() {
+                        debug!("Command is no longer reading stdin");
+                        self.stdin_tx = None;
+                    }
+                }
+                if fin {
+                    self.stdin_tx = None;
+                }
+            }
+            Frame::Data { stream_id, .. } if self.streams.resize == Some(stream_id) => {
+                debug!("Ignoring terminal resize request");
+            }
+            Frame::RstStream { stream_id, .. } => {
+                let streams = &mut self.streams;
+                for stream in [
+                    &mut streams.error,
+                    &mut streams.stdin,
+                    &mut streams.stdout,
+                    &mut streams.stderr,
+                    &mut streams.resize,
+                ] {
+                    if *stream == Some(stream_id) {
+                        *stream = None;
+                    }
+                }
+                if self.streams.stdin.is_none() {
+                    self.stdin_tx = None;
+                }
+            }
+            Frame::Ping { id } => self.writer.write_frame(&Frame::Ping { id }).await?,
+            Frame::GoAway { .. } => {
+                debug!("Client closed exec connection");
+                self.disconnect();
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.stdin_tx = None;
+    }
+
+    async fn send_output(&mut self, output: Output) {
+        let stream = match output.stream {
+            StreamType::Stdout => self.streams.stdout,
+            StreamType::Stderr => self.streams.stderr,
+        };
+        if let (true, Some(stream_id)) = (self.connected, stream) {
+            let frame = Frame::Data {
+                stream_id,
+                fin: false,
+                data: output.data,
+            };
+            if let Err(e) = self.writer.write_frame(&frame).await {
+                warn!(error = %e, "Unable to send exec output, client likely disconnected");
+                self.disconnect();
+            }
+        }
+    }
+
+    /// Writes the result of the command to the error stream and closes the
+    /// connection.
+    async fn close(mut self, result: Option<Vec<u8>>) {
+        if !self.connected {
+            return;
+        }
+        if let Err(e) = self.finish(result).await {
+            debug!(error = %e, "Error closing exec connection");
+        }
+    }
+
+    async fn finish(&mut self, result: Option<Vec<u8>>) -> anyhow::Result<()> {
+        if let (Some(stream_id), Some(data)) = (self.streams.error, result) {
+            let frame = Frame::Data {
+                stream_id,
+                fin: false,
+                data,
+            };
+            self.writer.write_frame(&frame).await?;
+        }
+        for stream_id in self.streams.all() {
+            let frame = Frame::Data {
+                stream_id,
+                fin: true,
+                data: Vec::new(),
+            };
+            self.writer.write_frame(&frame).await?;
+        }
+        let goaway = Frame::GoAway {
+            last_stream_id: self.last_stream_id,
+            status: 0,
+        };
+        self.writer.write_frame(&goaway).await?;
+        self.writer.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_protocol() {
+        assert_eq!(Protocol::negotiate(None), Some(Protocol::Channel));
+        assert_eq!(
+            Protocol::negotiate(Some("v4.channel.k8s.io, channel.k8s.io")),
+            Some(Protocol::V4Channel)
+        );
+        assert_eq!(
+            Protocol::negotiate(Some("base64.channel.k8s.io,channel.k8s.io")),
+            Some(Protocol::Channel)
+        );
+        assert_eq!(Protocol::negotiate(Some("v5.channel.k8s.io")), None);
+    }
+
+    #[test]
+    fn test_result_message() {
+        assert!(Protocol::Channel.result_message(&Ok(0)).is_none());
+
+        let success: serde_json::Value =
+            serde_json::from_slice(&Protocol::V4Channel.result_message(&Ok(0)).unwrap()).unwrap();
+        assert_eq!(success["status"], "Success");
+
+        let failure: serde_json::Value =
+            serde_json::from_slice(&Protocol::V4Channel.result_message(&Ok(2)).unwrap()).unwrap();
+        assert_eq!(failure["status"], "Failure");
+        assert_eq!(failure["reason"], "NonZeroExitCode");
+        assert_eq!(failure["details"]["causes"][0]["message"], "2");
+    }
+}
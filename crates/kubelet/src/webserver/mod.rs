@@ -2,26 +2,54 @@
 //!
 //! Logs and exec calls are the main things that a server should handle.
 
-use crate::config::ServerConfig;
+use crate::config::Config;
+use crate::exec::Options as ExecOptions;
 use crate::log::{Options, Sender};
-use crate::provider::{NotImplementedError, Provider};
+use crate::metrics;
+use crate::provider::{NotImplementedError, Provider, ProviderError};
+use crate::stats::{NodeStats, Summary};
+use anyhow::Context;
+use http::header::{HeaderMap, HeaderValue, CONNECTION, UPGRADE};
 use http::status::StatusCode;
 use http::Response;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
 use hyper::Body;
+use serde::Deserialize;
 use std::convert::Infallible;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
 use tracing::{debug, error, instrument};
-use warp::Filter;
+use warp::ws::Ws;
+use warp::{Filter, Reply};
+
+mod exec;
+mod spdy;
 
 const PING: &str = "this is the Krustlet HTTP server";
 
+/// The header used to negotiate the streaming protocol of SPDY exec requests.
+const STREAM_PROTOCOL_VERSION: &str = "x-stream-protocol-version";
+
+/// How long to wait before accepting connections again after a failure.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
 /// Start the Krustlet HTTP(S) server
 ///
 /// This is a primitive implementation of an HTTP provider for the internal API.
-pub(crate) async fn start<T: Provider>(
-    provider: Arc<T>,
-    config: &ServerConfig,
-) -> anyhow::Result<()> {
+pub(crate) async fn start<T: Provider>(provider: Arc<T>, config: &Config) -> anyhow::Result<()> {
     let health = warp::get().and(warp::path("healthz")).map(|| PING);
     let ping = warp::get().and(warp::path::end()).map(|| PING);
 
@@ -35,22 +63,124 @@ pub(crate) async fn start<T: Provider>(
         });
 
     let exec_provider = provider.clone();
-    let exec = warp::post()
-        .and(warp::path!("exec" / String / String / String))
-        .and_then(move |namespace, pod, container| {
-            let provider = exec_provider.clone();
-            post_exec(provider, namespace, pod, container)
+    let exec = warp::path!("exec" / String / String / String)
+        .and(warp::query::raw())
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(warp::ws())
+        .map(
+            move |namespace, pod, container, query: String, protocols: Option<String>, ws| {
+                let provider = exec_provider.clone();
+                exec_websocket(provider, namespace, pod, container, query, protocols, ws)
+            },
+        );
+
+    let spdy_exec_provider = provider.clone();
+    let spdy_exec = warp::path!("exec" / String / String / String)
+        .and(warp::query::raw())
+        .and(warp::header::headers_cloned())
+        .and(spdy::upgrade())
+        .map(
+            move |namespace, pod, container, query: String, headers: HeaderMap, upgrade| {
+                let provider = spdy_exec_provider.clone();
+                exec_spdy(provider, namespace, pod, container, query, headers, upgrade)
+            },
+        );
+
+    let stats_provider = provider.clone();
+    let node_name = config.node_name.clone();
+    let data_dir = config.data_dir.clone();
+    let stats = warp::get()
+        .and(warp::path!("stats" / "summary"))
+        .and(warp::query::<SummaryOptions>())
+        .and_then(move |opts| {
+            let provider = stats_provider.clone();
+            get_stats_summary(provider, node_name.clone(), data_dir.clone(), opts)
         });
 
-    let routes = ping.or(health).or(logs).or(exec);
+    let metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and_then(get_metrics);
+
+    let routes = ping
+        .or(health)
+        .or(logs)
+        .or(exec)
+        .or(spdy_exec)
+        .or(stats)
+        .or(metrics);
 
-    warp::serve(routes)
-        .tls()
-        .cert_path(&config.cert_file)
-        .key_path(&config.private_key_file)
-        .run((config.addr, config.port))
-        .await;
-    Ok(())
+    let server_config = &config.server_config;
+    let tls = tls_acceptor(&server_config.cert_file, &server_config.private_key_file)?;
+    let listener = TcpListener::bind((server_config.addr, server_config.port)).await?;
+    let service = warp::service(routes);
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Accepting usually fails because the process ran out of file
+                // descriptors, so back off instead of spinning
+                error!(error = %e, "Error accepting connection");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let tls = tls.clone();
+        let mut service = service.clone();
+        // Warp only hands out the upgrade of websocket requests, so requests
+        // switching to SPDY get theirs before being routed. The response
+        // future is boxed as the compiler cannot prove the filter futures to
+        // be `Send` inside of the spawned task otherwise.
+        let service = service_fn(move |mut req| {
+            spdy::register_upgrade(&mut req);
+            let response: ResponseFuture = Box::pin(service.call(req));
+            response
+        });
+        tokio::spawn(async move {
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!(error = %e, %remote_addr, "TLS handshake failed");
+                    return;
+                }
+            };
+            if let Err(e) = Http::new()
+                .http1_only(true)
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                debug!(error = %e, %remote_addr, "Error serving connection");
+            }
+        });
+    }
+}
+
+/// Load the TLS certificate and (PKCS8 or RSA) private key served by the kubelet.
+fn tls_acceptor(cert_file: &Path, key_file: &Path) -> anyhow::Result<TlsAcceptor> {
+    let cert_reader = File::open(cert_file)
+        .with_context(|| format!("Unable to open TLS certificate {}", cert_file.display()))?;
+    let certs = pemfile::certs(&mut BufReader::new(cert_reader))
+        .map_err(|()| anyhow::anyhow!("Invalid TLS certificate {}", cert_file.display()))?;
+
+    let key_data = std::fs::read(key_file)
+        .with_context(|| format!("Unable to read TLS private key {}", key_file.display()))?;
+    let invalid_key = |()| anyhow::anyhow!("Invalid TLS private key {}", key_file.display());
+    let mut keys = pemfile::pkcs8_private_keys(&mut key_data.as_slice()).map_err(invalid_key)?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut key_data.as_slice()).map_err(invalid_key)?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", key_file.display()))?;
+
+    let mut tls = ServerConfig::new(NoClientAuth::new());
+    tls.set_single_cert(certs, key)?;
+    // Exec and attach upgrade the connection to SPDY or a websocket, which
+    // only works over HTTP/1.1, so HTTP/2 is never negotiated
+    tls.set_protocols(&[b"http/1.1".to_vec()]);
+    Ok(TlsAcceptor::from(Arc::new(tls)))
 }
 
 /// Get the logs from the running container.
@@ -77,6 +207,10 @@ async fn get_container_logs<T: Provider>(
                     StatusCode::NOT_IMPLEMENTED,
                     "Logs not implemented in provider.".to_owned(),
                 ))
+            } else if let Some(ProviderError::PreviousContainerNotFound { .. }) =
+                e.downcast_ref::<ProviderError>()
+            {
+                Ok(return_with_code(StatusCode::BAD_REQUEST, e.to_string()))
             } else {
                 Ok(return_with_code(
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -87,19 +221,167 @@ async fn get_container_logs<T: Provider>(
     }
 }
 
-/// Run a pod exec command and get the output
+/// Run a pod exec command, streaming its input and output over a websocket
+///
+/// Implements the kubelet path /exec/{namespace}/{pod}/{container}
+#[instrument(level = "info", skip(provider, query, ws))]
+fn exec_websocket<T: Provider>(
+    provider: Arc<T>,
+    namespace: String,
+    pod: String,
+    container: String,
+    query: String,
+    protocols: Option<String>,
+    ws: Ws,
+) -> Response<Body> {
+    debug!("Got container exec request");
+    let opts = match ExecOptions::from_query(&query) {
+        Ok(opts) => opts,
+        Err(e) => return return_with_code(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+    };
+    let protocol = match exec::Protocol::negotiate(protocols.as_deref()) {
+        Some(p) => p,
+        None => {
+            return return_with_code(
+                StatusCode::BAD_REQUEST,
+                format!("Unsupported exec protocols requested: {:?}", protocols),
+            )
+        }
+    };
+
+    let reply = ws.on_upgrade(move |socket| {
+        exec::stream(provider, namespace, pod, container, opts, protocol, socket)
+    });
+    if protocols.is_some() {
+        warp::reply::with_header(reply, "sec-websocket-protocol", protocol.name()).into_response()
+    } else {
+        reply.into_response()
+    }
+}
+
+/// Run a pod exec command, streaming its input and output over a connection
+/// upgraded to SPDY. This is how the API server proxies `kubectl exec`.
 ///
 /// Implements the kubelet path /exec/{namespace}/{pod}/{container}
-async fn post_exec<T: Provider>(
-    _provider: Arc<T>,
-    _namespace: String,
-    _pod: String,
-    _container: String,
+#[instrument(level = "info", skip(provider, query, headers, upgrade))]
+fn exec_spdy<T: Provider>(
+    provider: Arc<T>,
+    namespace: String,
+    pod: String,
+    container: String,
+    query: String,
+    headers: HeaderMap,
+    upgrade: spdy::PendingUpgrade,
+) -> Response<Body> {
+    debug!("Got container exec request");
+    let opts = match ExecOptions::from_query(&query) {
+        Ok(opts) => opts,
+        Err(e) => return return_with_code(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+    };
+    // Clients send one header per protocol version they support
+    let offered: Vec<&str> = headers
+        .get_all(STREAM_PROTOCOL_VERSION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    let offered = if offered.is_empty() {
+        None
+    } else {
+        Some(offered.join(","))
+    };
+    let protocol = match exec::Protocol::negotiate(offered.as_deref()) {
+        Some(p) => p,
+        None => {
+            return return_with_code(
+                StatusCode::BAD_REQUEST,
+                format!("Unsupported exec protocols requested: {:?}", offered),
+            )
+        }
+    };
+
+    tokio::spawn(exec::stream_spdy(
+        provider, namespace, pod, container, opts, protocol, upgrade,
+    ));
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    let response_headers = response.headers_mut();
+    response_headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+    response_headers.insert(UPGRADE, HeaderValue::from_static(spdy::PROTOCOL));
+    if offered.is_some() {
+        response_headers.insert(
+            STREAM_PROTOCOL_VERSION,
+            HeaderValue::from_static(protocol.name()),
+        );
+    }
+    response
+}
+
+/// Query options of the summary API.
+#[derive(Debug, Default, Deserialize)]
+struct SummaryOptions {
+    /// Only report CPU and memory usage, as requested by metrics-server
+    #[serde(default)]
+    only_cpu_and_memory: bool,
+}
+
+/// Get the resource usage of the node and its pods.
+///
+/// Implements the kubelet path /stats/summary
+#[instrument(level = "debug", skip(provider))]
+async fn get_stats_summary<T: Provider>(
+    provider: Arc<T>,
+    node_name: String,
+    data_dir: PathBuf,
+    opts: SummaryOptions,
 ) -> Result<Response<Body>, Infallible> {
-    Ok(return_with_code(
-        StatusCode::NOT_IMPLEMENTED,
-        "Exec not implemented.".to_string(),
-    ))
+    let node = match tokio::task::spawn_blocking(move || NodeStats::collect(&node_name, &data_dir))
+        .await
+    {
+        Ok(node) => node,
+        Err(e) => {
+            error!(error = %e, "Error collecting node stats");
+            return Ok(return_with_code(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Server error: {}", e),
+            ));
+        }
+    };
+    let pods = match provider.stats().await {
+        Ok(pods) => pods,
+        Err(e) if e.is::<NotImplementedError>() => Vec::new(),
+        Err(e) => {
+            error!(error = %e, "Error fetching pod stats");
+            return Ok(return_with_code(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Server error: {}", e),
+            ));
+        }
+    };
+
+    let mut summary = Summary { node, pods };
+    if opts.only_cpu_and_memory {
+        summary.retain_cpu_and_memory();
+    }
+    Ok(warp::reply::json(&summary).into_response())
+}
+
+/// Get the metrics of the kubelet in the Prometheus text format.
+///
+/// Implements the kubelet path /metrics
+async fn get_metrics() -> Result<Response<Body>, Infallible> {
+    match metrics::encode() {
+        Ok((content_type, body)) => {
+            Ok(warp::reply::with_header(body, "content-type", content_type).into_response())
+        }
+        Err(e) => {
+            error!(error = %e, "Error encoding metrics");
+            Ok(return_with_code(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Server error: {}", e),
+            ))
+        }
+    }
 }
 
 fn return_with_code(code: StatusCode, body: String) -> Response<Body> {
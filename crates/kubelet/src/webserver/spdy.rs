@@ -0,0 +1,580 @@
+//! Implements the parts of SPDY/3.1 used by the Kubernetes streaming protocols.
+//!
+//! The API server proxies `kubectl exec` to the kubelet as an HTTP/1.1
+//! connection upgraded to SPDY/3.1. The client opens one stream per channel,
+//! identified by its `streamtype` header, and the server replies to each of
+//! them. Flow control windows are not enforced and `WINDOW_UPDATE` frames are
+//! ignored, as the Kubernetes clients do not rely on them.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper::{Body, Request};
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use miniz_oxide::inflate::core::{
+    decompress, inflate_flags, DecompressorOxide, TINFL_LZ_DICT_SIZE,
+};
+use miniz_oxide::inflate::TINFLStatus;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use warp::{Filter, Rejection};
+
+/// The value of the `Upgrade` header used to switch a connection to SPDY.
+pub(crate) const PROTOCOL: &str = "SPDY/3.1";
+
+/// The status sent with a `RST_STREAM` frame when refusing a stream.
+pub(crate) const REFUSED_STREAM: u32 = 3;
+
+const VERSION: u16 = 3;
+const CONTROL_BIT: u8 = 0x80;
+const FLAG_FIN: u8 = 0x01;
+const STREAM_ID_MASK: u32 = 0x7fff_ffff;
+const MAX_LENGTH: usize = 0x00ff_ffff;
+
+const SYN_STREAM: u16 = 1;
+const SYN_REPLY: u16 = 2;
+const RST_STREAM: u16 = 3;
+const PING: u16 = 6;
+const GOAWAY: u16 = 7;
+const HEADERS: u16 = 8;
+
+const ZLIB_DEFLATE: u8 = 8;
+const ZLIB_FDICT: u8 = 0x20;
+const COMPRESSION_LEVEL: i32 = 6;
+
+/// The dictionary that the zlib stream of header blocks is primed with, as
+/// defined in section 2.6.10.1 of the SPDY/3 specification.
+const HEADER_DICTIONARY: &[u8] = b"\
+    \x00\x00\x00\x07options\x00\x00\x00\x04head\x00\x00\x00\x04post\x00\x00\x00\x03put\x00\
+    \x00\x00\x06delete\x00\x00\x00\x05trace\x00\x00\x00\x06accept\x00\x00\x00\x0eaccept-char\
+    set\x00\x00\x00\x0faccept-encoding\x00\x00\x00\x0faccept-language\x00\x00\x00\x0daccept-\
+    ranges\x00\x00\x00\x03age\x00\x00\x00\x05allow\x00\x00\x00\x0dauthorization\x00\x00\x00\
+    \x0dcache-control\x00\x00\x00\x0aconnection\x00\x00\x00\x0ccontent-base\x00\x00\x00\x10c\
+    ontent-encoding\x00\x00\x00\x10content-language\x00\x00\x00\x0econtent-length\x00\x00\
+    \x00\x10content-location\x00\x00\x00\x0bcontent-md5\x00\x00\x00\x0dcontent-range\x00\x00\
+    \x00\x0ccontent-type\x00\x00\x00\x04date\x00\x00\x00\x04etag\x00\x00\x00\x06expect\x00\
+    \x00\x00\x07expires\x00\x00\x00\x04from\x00\x00\x00\x04host\x00\x00\x00\x08if-match\x00\
+    \x00\x00\x11if-modified-since\x00\x00\x00\x0dif-none-match\x00\x00\x00\x08if-range\x00\
+    \x00\x00\x13if-unmodified-since\x00\x00\x00\x0dlast-modified\x00\x00\x00\x08location\x00\
+    \x00\x00\x0cmax-forwards\x00\x00\x00\x06pragma\x00\x00\x00\x12proxy-authenticate\x00\x00\
+    \x00\x13proxy-authorization\x00\x00\x00\x05range\x00\x00\x00\x07referer\x00\x00\x00\x0br\
+    etry-after\x00\x00\x00\x06server\x00\x00\x00\x02te\x00\x00\x00\x07trailer\x00\x00\x00\
+    \x11transfer-encoding\x00\x00\x00\x07upgrade\x00\x00\x00\x0auser-agent\x00\x00\x00\x04va\
+    ry\x00\x00\x00\x03via\x00\x00\x00\x07warning\x00\x00\x00\x10www-authenticate\x00\x00\x00\
+    \x06method\x00\x00\x00\x03get\x00\x00\x00\x06status\x00\x00\x00\x06200 OK\x00\x00\x00\
+    \x07version\x00\x00\x00\x08HTTP/1.1\x00\x00\x00\x03url\x00\x00\x00\x06public\x00\x00\x00\
+    \x0aset-cookie\x00\x00\x00\x0akeep-alive\x00\x00\x00\x06origin10010120120220520630030230\
+    3304305306307402405406407408409410411412413414415416417502504505203 Non-Authoritative In\
+    formation204 No Content301 Moved Permanently400 Bad Request401 Unauthorized403 Forbidden\
+    404 Not Found500 Internal Server Error501 Not Implemented503 Service UnavailableJan Feb \
+    Mar Apr May Jun Jul Aug Sept Oct Nov Dec 00:00:00 Mon, Tue, Wed, Thu, Fri, Sat, Sun, GMT\
+    chunked,text/html,image/png,image/jpg,image/gif,application/xml,application/xhtml+xml,te\
+    xt/plain,text/javascript,publicprivatemax-age=gzip,deflate,sdchcharset=utf-8charset=iso-\
+    8859-1,utf-,*,enq=0.";
+
+/// The headers of a `SYN_STREAM` or `SYN_REPLY` frame. SPDY requires header
+/// names to be lowercase.
+pub(crate) type Headers = BTreeMap<String, String>;
+
+/// A SPDY frame. Only the frames and fields used by the streaming protocols
+/// are represented.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Frame {
+    SynStream {
+        stream_id: u32,
+        fin: bool,
+        headers: Headers,
+    },
+    SynReply {
+        stream_id: u32,
+        fin: bool,
+        headers: Headers,
+    },
+    RstStream {
+        stream_id: u32,
+        status: u32,
+    },
+    Ping {
+        id: u32,
+    },
+    GoAway {
+        last_stream_id: u32,
+        status: u32,
+    },
+    Data {
+        stream_id: u32,
+        fin: bool,
+        data: Vec<u8>,
+    },
+    /// Any other frame (e.g. `SETTINGS` or `WINDOW_UPDATE`), which needs no
+    /// handling.
+    Other,
+}
+
+/// The pending upgrade of a request asking to switch to SPDY.
+///
+/// Warp only hands out the upgrade of websocket requests, so the upgrade is
+/// moved into this (cloneable) wrapper by [`register_upgrade`] before the
+/// request is routed and extracted again by the [`upgrade`] filter.
+#[derive(Clone)]
+pub(crate) struct PendingUpgrade(Arc<Mutex<Option<OnUpgrade>>>);
+
+impl PendingUpgrade {
+    /// Waits for the connection to be upgraded, which happens once the
+    /// `101 Switching Protocols` response has been sent.
+    pub(crate) async fn upgraded(self) -> anyhow::Result<Upgraded> {
+        let on_upgrade = self
+            .0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("upgrade lock poisoned"))?
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("connection has already been upgraded"))?;
+        Ok(on_upgrade.await?)
+    }
+}
+
+/// Makes the upgrade of a request asking to switch to SPDY available to the
+/// [`upgrade`] filter. Other requests are left untouched.
+pub(crate) fn register_upgrade(req: &mut Request<Body>) {
+    let is_spdy = req
+        .headers()
+        .get(http::header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case(PROTOCOL))
+        .unwrap_or(false);
+    if !is_spdy {
+        return;
+    }
+    if let Some(on_upgrade) = req.extensions_mut().remove::<OnUpgrade>() {
+        req.extensions_mut()
+            .insert(PendingUpgrade(Arc::new(Mutex::new(Some(on_upgrade)))));
+    }
+}
+
+/// Matches requests asking to upgrade the connection to SPDY.
+pub(crate) fn upgrade() -> impl Filter<Extract = (PendingUpgrade,), Error = Rejection> + Clone {
+    warp::header::exact_ignore_case("upgrade", PROTOCOL).and(warp::ext::get::<PendingUpgrade>())
+}
+
+/// Reads frames from a SPDY connection.
+pub(crate) struct FrameReader<R> {
+    io: R,
+    decompressor: Decompressor,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub(crate) fn new(io: R) -> Self {
+        FrameReader {
+            io,
+            decompressor: Decompressor::new(),
+        }
+    }
+
+    /// Reads the next frame, returning `None` once the connection has been closed.
+    pub(crate) async fn read_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut head = [0u8; 8];
+        match self.io.read_exact(&mut head).await {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let length = u32::from_be_bytes([0, head[5], head[6], head[7]]) as usize;
+        let mut payload = vec![0; length];
+        self.io.read_exact(&mut payload).await?;
+        self.decode(head, payload).map(Some)
+    }
+
+    fn decode(&mut self, head: [u8; 8], payload: Vec<u8>) -> anyhow::Result<Frame> {
+        let fin = head[4] & FLAG_FIN != 0;
+        if head[0] & CONTROL_BIT == 0 {
+            return Ok(Frame::Data {
+                stream_id: read_u32(&head, 0)?,
+                fin,
+                data: payload,
+            });
+        }
+
+        let version = u16::from_be_bytes([head[0] & !CONTROL_BIT, head[1]]);
+        if version != VERSION {
+            anyhow::bail!("unsupported SPDY version {}", version);
+        }
+        let frame = match u16::from_be_bytes([head[2], head[3]]) {
+            SYN_STREAM => Frame::SynStream {
+                stream_id: read_u32(&payload, 0)? & STREAM_ID_MASK,
+                fin,
+                headers: self.headers(payload.get(10..))?,
+            },
+            SYN_REPLY => Frame::SynReply {
+                stream_id: read_u32(&payload, 0)? & STREAM_ID_MASK,
+                fin,
+                headers: self.headers(payload.get(4..))?,
+            },
+            RST_STREAM => Frame::RstStream {
+                stream_id: read_u32(&payload, 0)? & STREAM_ID_MASK,
+                status: read_u32(&payload, 4)?,
+            },
+            PING => Frame::Ping {
+                id: read_u32(&payload, 0)?,
+            },
+            GOAWAY => Frame::GoAway {
+                last_stream_id: read_u32(&payload, 0)? & STREAM_ID_MASK,
+                status: read_u32(&payload, 4)?,
+            },
+            HEADERS => {
+                // The header block still has to be inflated to keep the
+                // compression context in sync with the client
+                self.headers(payload.get(4..))?;
+                Frame::Other
+            }
+            _ => Frame::Other,
+        };
+        Ok(frame)
+    }
+
+    fn headers(&mut self, block: Option<&[u8]>) -> anyhow::Result<Headers> {
+        let block = block.ok_or_else(|| anyhow::anyhow!("frame is too short"))?;
+        decode_headers(&self.decompressor.decompress(block)?)
+    }
+}
+
+/// Writes frames to a SPDY connection.
+pub(crate) struct FrameWriter<W> {
+    io: W,
+    compressor: Compressor,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    pub(crate) fn new(io: W) -> Self {
+        FrameWriter {
+            io,
+            compressor: Compressor::new(),
+        }
+    }
+
+    /// Writes a frame to the connection. Data longer than the maximum frame
+    /// length is split across several frames.
+    pub(crate) async fn write_frame(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        match frame {
+            Frame::Data {
+                stream_id,
+                fin,
+                data,
+            } if data.len() > MAX_LENGTH => {
+                let mut chunks = data.chunks(MAX_LENGTH).peekable();
+                while let Some(chunk) = chunks.next() {
+                    let flags = if *fin && chunks.peek().is_none() {
+                        FLAG_FIN
+                    } else {
+                        0
+                    };
+                    let buf = frame_bytes(stream_id.to_be_bytes(), flags, chunk)?;
+                    self.io.write_all(&buf).await?;
+                }
+            }
+            _ => {
+                let buf = self.encode(frame)?;
+                self.io.write_all(&buf).await?;
+            }
+        }
+        self.io.flush().await?;
+        Ok(())
+    }
+
+    /// Shuts down the writing side of the connection.
+    pub(crate) async fn shutdown(&mut self) -> anyhow::Result<()> {
+        Ok(self.io.shutdown().await?)
+    }
+
+    fn encode(&mut self, frame: &Frame) -> anyhow::Result<Vec<u8>> {
+        let flag = |fin: bool| if fin { FLAG_FIN } else { 0 };
+        match frame {
+            Frame::SynReply {
+                stream_id,
+                fin,
+                headers,
+            } => {
+                let mut payload = stream_id.to_be_bytes().to_vec();
+                payload.extend(self.compressor.compress(&encode_headers(headers))?);
+                control_frame(SYN_REPLY, flag(*fin), &payload)
+            }
+            Frame::RstStream { stream_id, status } => control_frame(
+                RST_STREAM,
+                0,
+                &[stream_id.to_be_bytes(), status.to_be_bytes()].concat(),
+            ),
+            Frame::Ping { id } => control_frame(PING, 0, &id.to_be_bytes()),
+            Frame::GoAway {
+                last_stream_id,
+                status,
+            } => control_frame(
+                GOAWAY,
+                0,
+                &[last_stream_id.to_be_bytes(), status.to_be_bytes()].concat(),
+            ),
+            Frame::Data {
+                stream_id,
+                fin,
+                data,
+            } => frame_bytes(stream_id.to_be_bytes(), flag(*fin), data),
+            Frame::SynStream { .. } | Frame::Other => {
+                anyhow::bail!("sending {:?} frames is not supported", frame)
+            }
+        }
+    }
+}
+
+fn control_frame(kind: u16, flags: u8, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let [version_high, version_low] = VERSION.to_be_bytes();
+    let [kind_high, kind_low] = kind.to_be_bytes();
+    frame_bytes(
+        [version_high | CONTROL_BIT, version_low, kind_high, kind_low],
+        flags,
+        payload,
+    )
+}
+
+fn frame_bytes(prefix: [u8; 4], flags: u8, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if payload.len() > MAX_LENGTH {
+        anyhow::bail!("frame payload of {} bytes is too long", payload.len());
+    }
+    let length = (payload.len() as u32).to_be_bytes();
+    let mut buf = Vec::with_capacity(payload.len() + 8);
+    buf.extend_from_slice(&prefix);
+    buf.push(flags);
+    buf.extend_from_slice(&length[1..]);
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> anyhow::Result<u32> {
+    match buf.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(anyhow::anyhow!("frame is too short")),
+    }
+}
+
+fn read_string(buf: &[u8], offset: &mut usize) -> anyhow::Result<String> {
+    let length = read_u32(buf, *offset)? as usize;
+    *offset += 4;
+    let data = buf
+        .get(*offset..)
+        .and_then(|b| b.get(..length))
+        .ok_or_else(|| anyhow::anyhow!("header block is too short"))?;
+    *offset += length;
+    Ok(String::from_utf8_lossy(data).into_owned())
+}
+
+fn decode_headers(block: &[u8]) -> anyhow::Result<Headers> {
+    let count = read_u32(block, 0)?;
+    let mut offset = 4;
+    let mut headers = Headers::new();
+    for _ in 0..count {
+        let name = read_string(block, &mut offset)?;
+        let value = read_string(block, &mut offset)?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+fn encode_headers(headers: &Headers) -> Vec<u8> {
+    let mut block = (headers.len() as u32).to_be_bytes().to_vec();
+    for (name, value) in headers {
+        for s in [name, value] {
+            block.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            block.extend_from_slice(s.as_bytes());
+        }
+    }
+    block
+}
+
+/// Inflates the header blocks received on a connection, which form a single
+/// zlib stream primed with [`HEADER_DICTIONARY`].
+struct Decompressor {
+    state: Box<DecompressorOxide>,
+    /// The sliding window of the stream, which starts out with the dictionary.
+    window: Vec<u8>,
+    position: usize,
+    header_read: bool,
+}
+
+impl Decompressor {
+    fn new() -> Self {
+        let mut window = vec![0; TINFL_LZ_DICT_SIZE];
+        window[..HEADER_DICTIONARY.len()].copy_from_slice(HEADER_DICTIONARY);
+        Decompressor {
+            state: Box::new(DecompressorOxide::new()),
+            window,
+            position: HEADER_DICTIONARY.len(),
+            header_read: false,
+        }
+    }
+
+    fn decompress(&mut self, mut input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if !self.header_read {
+            input = self.read_zlib_header(input)?;
+            self.header_read = true;
+        }
+
+        let mut output = Vec::new();
+        loop {
+            let (status, read, written) = decompress(
+                &mut self.state,
+                input,
+                &mut self.window,
+                self.position,
+                inflate_flags::TINFL_FLAG_HAS_MORE_INPUT,
+            );
+            output.extend_from_slice(&self.window[self.position..self.position + written]);
+            self.position = (self.position + written) & (TINFL_LZ_DICT_SIZE - 1);
+            input = &input[read..];
+            match status {
+                TINFLStatus::HasMoreOutput => continue,
+                TINFLStatus::NeedsMoreInput if !input.is_empty() => continue,
+                TINFLStatus::NeedsMoreInput | TINFLStatus::Done => return Ok(output),
+                _ => anyhow::bail!("invalid header block: {:?}", status),
+            }
+        }
+    }
+
+    /// Validates the zlib header at the start of the stream, returning the
+    /// input following it.
+    fn read_zlib_header<'a>(&self, input: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+        let (cmf, flg) = match input {
+            [cmf, flg, ..] => (*cmf, *flg),
+            _ => anyhow::bail!("header block is too short"),
+        };
+        if cmf & 0x0f != ZLIB_DEFLATE || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+            anyhow::bail!("invalid zlib header in header block");
+        }
+        if flg & ZLIB_FDICT == 0 {
+            return Ok(&input[2..]);
+        }
+        let dictionary_id = read_u32(input, 2)?;
+        if dictionary_id != miniz_oxide::mz_adler32_oxide(1, HEADER_DICTIONARY) {
+            anyhow::bail!("header block uses an unknown dictionary");
+        }
+        Ok(&input[6..])
+    }
+}
+
+/// Deflates the header blocks sent on a connection. The blocks do not
+/// reference [`HEADER_DICTIONARY`], which is valid for any zlib decoder.
+struct Compressor {
+    state: Box<CompressorOxide>,
+}
+
+impl Compressor {
+    fn new() -> Self {
+        // Positive window bits add the zlib header to the stream
+        let flags = create_comp_flags_from_zip_params(COMPRESSION_LEVEL, 15, 0);
+        Compressor {
+            state: Box::new(CompressorOxide::new(flags)),
+        }
+    }
+
+    fn compress(&mut self, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let (status, _) = compress_to_output(&mut self.state, input, TDEFLFlush::Sync, |data| {
+            output.extend_from_slice(data);
+            true
+        });
+        match status {
+            TDEFLStatus::Okay => Ok(output),
+            _ => Err(anyhow::anyhow!(
+                "unable to compress header block: {:?}",
+                status
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Two SYN_STREAM frames as sent by the Go SPDY implementation, with header
+    // blocks that reference the dictionary and the previous block
+    const SYN_STREAMS: &[u8] = b"\
+        \x80\x03\x00\x01\x00\x00\x00\x28\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x78\xf9\xe3\xc6\
+        \xa7\xc2\x02\xa6\x23\x46\x70\x3a\x2b\x29\x4a\x4d\xcc\x85\x16\x25\xac\xa9\xa0\x80\x06\x00\
+        \x00\x00\xff\xff\x80\x03\x00\x01\x01\x00\x00\x17\x00\x00\x00\x03\x00\x00\x00\x00\x00\x00\
+        \xc2\x2e\x55\x5c\x02\xcc\x2e\x00\x00\x00\x00\xff\xff";
+
+    fn stream_type(stream_type: &str) -> Headers {
+        vec![("streamtype".to_owned(), stream_type.to_owned())]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_header_dictionary() {
+        assert_eq!(HEADER_DICTIONARY.len(), 1423);
+        assert_eq!(
+            miniz_oxide::mz_adler32_oxide(1, HEADER_DICTIONARY),
+            0xe3c6_a7c2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_syn_streams() {
+        let mut reader = FrameReader::new(SYN_STREAMS);
+        assert_eq!(
+            reader.read_frame().await.unwrap(),
+            Some(Frame::SynStream {
+                stream_id: 1,
+                fin: false,
+                headers: stream_type("error"),
+            })
+        );
+        assert_eq!(
+            reader.read_frame().await.unwrap(),
+            Some(Frame::SynStream {
+                stream_id: 3,
+                fin: true,
+                headers: stream_type("stdin"),
+            })
+        );
+        assert_eq!(reader.read_frame().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let frames = vec![
+            Frame::SynReply {
+                stream_id: 1,
+                fin: false,
+                headers: Headers::new(),
+            },
+            Frame::SynReply {
+                stream_id: 3,
+                fin: false,
+                headers: stream_type("stdout"),
+            },
+            Frame::Data {
+                stream_id: 3,
+                fin: true,
+                data: b"hello".to_vec(),
+            },
+            Frame::Ping { id: 7 },
+            Frame::RstStream {
+                stream_id: 5,
+                status: REFUSED_STREAM,
+            },
+            Frame::GoAway {
+                last_stream_id: 5,
+                status: 0,
+            },
+        ];
+
+        let mut writer = FrameWriter::new(Vec::new());
+        for frame in &frames {
+            writer.write_frame(frame).await.unwrap();
+        }
+        let mut reader = FrameReader::new(writer.io.as_slice());
+        for frame in frames {
+            assert_eq!(reader.read_frame().await.unwrap(), Some(frame));
+        }
+        assert_eq!(reader.read_frame().await.unwrap(), None);
+    }
+}
@@ -35,37 +35,44 @@
 
 #![deny(missing_docs)]
 
+mod module_cache;
 mod wasi_runtime;
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use futures::future::BoxFuture;
+use kubelet::event::Recorder;
+use kubelet::log::{LogHandleFactory, LogManager};
 use kubelet::node::Builder;
 use kubelet::plugin_watcher::PluginRegistry;
 use kubelet::pod::state::prelude::SharedState;
 use kubelet::pod::{Handle, Pod, PodKey};
 use kubelet::provider::{
-    DevicePluginSupport, PluginSupport, Provider, ProviderError, VolumeSupport,
+    AdmissionSupport, DevicePluginSupport, EventSupport, PluginSupport, Provider, ProviderError,
+    VolumeSupport,
 };
-use kubelet::resources::DeviceManager;
+use kubelet::resources::{DeviceManager, PodAdmitter};
 use kubelet::state::common::registered::Registered;
 use kubelet::state::common::terminated::Terminated;
 use kubelet::state::common::{GenericProvider, GenericProviderState};
+use kubelet::stats::{FsStats, PodStats, VolumeStats};
 use kubelet::store::Store;
-use kubelet::volume::VolumeRef;
+use kubelet::volume::{pod_dir_name, VolumeRef};
 use tokio::sync::RwLock;
-use wasi_runtime::Runtime;
+use tracing::warn;
+use wasi_runtime::{Engines, PoolLimits, Runtime};
 
 mod states;
-use kubelet::node;
 use states::pod::PodState;
 
 const TARGET_WASM32_WASI: &str = "wasm32-wasi";
-const LOG_DIR_NAME: &str = "wasi-logs";
 const VOLUME_DIR: &str = "volumes";
+const MODULE_CACHE_DIR: &str = ".oci/compiled";
 
 /// WasiProvider provides a Kubelet runtime implementation that executes WASM
 /// binaries conforming to the WASI spec.
@@ -74,18 +81,22 @@ pub struct WasiProvider {
     shared: ProviderState,
 }
 
-type PodHandleMap = Arc<RwLock<HashMap<PodKey, Arc<Handle<Runtime, wasi_runtime::HandleFactory>>>>>;
+type PodHandleMap = Arc<RwLock<HashMap<PodKey, Arc<Handle<Runtime, LogHandleFactory>>>>>;
 
 /// Provider-level state shared between all pods
 #[derive(Clone)]
 pub struct ProviderState {
     handles: PodHandleMap,
     store: Arc<dyn Store + Sync + Send>,
-    log_path: PathBuf,
+    log_manager: LogManager,
     client: kube::Client,
     volume_path: PathBuf,
     plugin_registry: Arc<PluginRegistry>,
     device_plugin_manager: Arc<DeviceManager>,
+    node_ip: IpAddr,
+    engines: Engines,
+    pod_admitter: Arc<PodAdmitter>,
+    recorder: Recorder,
 }
 
 #[async_trait]
@@ -96,14 +107,20 @@ impl GenericProviderState for ProviderState {
     fn store(&self) -> std::sync::Arc<(dyn Store + Send + Sync + 'static)> {
         self.store.clone()
     }
-    async fn stop(&self, pod: &Pod) -> anyhow::Result<()> {
+    fn stop(&self, pod: &Pod) -> BoxFuture<'static, anyhow::Result<()>> {
         let key = PodKey::from(pod);
-        let mut handle_writer = self.handles.write().await;
-        if let Some(handle) = handle_writer.get_mut(&key) {
-            handle.stop().await
-        } else {
-            Ok(())
-        }
+        let handles = self.handles.clone();
+        let grace_period = pod.termination_grace_period();
+        let host = self.node_ip.to_string();
+        Box::pin(async move {
+            // Do not hold the lock while the pod shuts down, which can take as
+            // long as its grace period
+            let handle = handles.read().await.get(&key).cloned();
+            match handle {
+                Some(handle) => handle.terminate(grace_period, &host).await,
+                None => Ok(()),
+            }
+        })
     }
 }
 
@@ -125,6 +142,18 @@ impl DevicePluginSupport for ProviderState {
     }
 }
 
+impl AdmissionSupport for ProviderState {
+    fn pod_admitter(&self) -> Option<Arc<PodAdmitter>> {
+        Some(self.pod_admitter.clone())
+    }
+}
+
+impl EventSupport for ProviderState {
+    fn event_recorder(&self) -> Option<Recorder> {
+        Some(self.recorder.clone())
+    }
+}
+
 impl WasiProvider {
     /// Create a new wasi provider from a module store and a kubelet config
     pub async fn new(
@@ -134,20 +163,28 @@ impl WasiProvider {
         plugin_registry: Arc<PluginRegistry>,
         device_plugin_manager: Arc<DeviceManager>,
     ) -> anyhow::Result<Self> {
-        let log_path = config.data_dir.join(LOG_DIR_NAME);
         let volume_path = config.data_dir.join(VOLUME_DIR);
-        tokio::fs::create_dir_all(&log_path).await?;
         tokio::fs::create_dir_all(&volume_path).await?;
+        let pod_admitter = Arc::new(PodAdmitter::new(config));
+        let engines = Engines::new(
+            &config.wasm,
+            PoolLimits::new(config.max_pods, pod_admitter.allocatable_memory()),
+            &config.data_dir.join(MODULE_CACHE_DIR),
+        )?;
         let client = kube::Client::try_from(kubeconfig)?;
         Ok(Self {
             shared: ProviderState {
                 handles: Default::default(),
                 store,
-                log_path,
+                log_manager: LogManager::from_config(config),
                 volume_path,
-                client,
                 plugin_registry,
                 device_plugin_manager,
+                node_ip: config.node_ip,
+                engines,
+                pod_admitter,
+                recorder: Recorder::new(client.clone(), &config.node_name),
+                client,
             },
         })
     }
@@ -183,6 +220,11 @@ impl Provider for WasiProvider {
         Ok(PodState::new(pod))
     }
 
+    async fn reconfigure(&self, config: &kubelet::config::Config) -> anyhow::Result<()> {
+        self.shared.store.reconfigure(config).await;
+        Ok(())
+    }
+
     async fn logs(
         &self,
         namespace: String,
@@ -199,11 +241,61 @@ impl Provider for WasiProvider {
         handle.output(&container_name, sender).await
     }
 
-    // Evict all pods upon shutdown
-    async fn shutdown(&self, node_name: &str) -> anyhow::Result<()> {
-        node::drain(&self.shared.client, node_name).await?;
-        Ok(())
+    async fn exec(
+        &self,
+        namespace: String,
+        pod_name: String,
+        container_name: String,
+        session: kubelet::exec::Session,
+    ) -> anyhow::Result<i32> {
+        let handle = {
+            let handles = self.shared.handles.read().await;
+            handles
+                .get(&PodKey::new(&namespace, &pod_name))
+                .cloned()
+                .ok_or(ProviderError::PodNotFound { pod_name })?
+        };
+        handle.exec(&container_name, session).await
     }
+
+    async fn stats(&self) -> anyhow::Result<Vec<PodStats>> {
+        let handles: Vec<_> = self.shared.handles.read().await.values().cloned().collect();
+        let mut stats = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let pod_volume_path = self.shared.volume_path.join(pod_dir_name(handle.pod()));
+            let volumes =
+                tokio::task::spawn_blocking(move || volume_stats(&pod_volume_path)).await?;
+            stats.push(handle.stats(volumes).await);
+        }
+        Ok(stats)
+    }
+}
+
+/// Returns the disk usage of every volume mounted under the given pod volume
+/// directory. This blocks while walking the directories.
+fn volume_stats(pod_volume_path: &Path) -> Vec<VolumeStats> {
+    let entries = match std::fs::read_dir(pod_volume_path) {
+        Ok(entries) => entries,
+        // Pods without volumes have no directory
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            warn!(error = %e, path = %pod_volume_path.display(), "Unable to list pod volumes");
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| match FsStats::directory(&entry.path()) {
+            Ok(fs) => Some(VolumeStats {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                fs,
+            }),
+            Err(e) => {
+                warn!(error = %e, path = %entry.path().display(), "Unable to read volume usage");
+                None
+            }
+        })
+        .collect()
 }
 
 impl GenericProvider for WasiProvider {
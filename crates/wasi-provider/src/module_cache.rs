@@ -0,0 +1,240 @@
+//! An on-disk cache of compiled wasm modules.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use kubelet::metrics::MODULE_COMPILE_DURATION;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use wasmtime::{Engine, Module};
+
+/// The file extension wasmtime uses for precompiled modules
+const COMPILED_MODULE_EXTENSION: &str = "cwasm";
+/// The version of wasmtime modules are compiled by. Other versions cannot load
+/// them, so every version gets a directory of its own. Keep this in sync with
+/// the wasmtime dependency.
+const WASMTIME_VERSION: &str = "0.30";
+/// How much disk space compiled modules may take up across all engine
+/// configurations before the least recently used ones are removed
+const MAX_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Caches compiled modules on disk so that a module is compiled once per node
+/// instead of on every container start.
+///
+/// Entries are keyed by the sha256 digest of the module, which is also the
+/// digest of the image layer it was pulled from. Compiled modules can only be
+/// loaded by an engine configured the same way as the one that compiled them,
+/// so every engine configuration gets a directory of its own.
+///
+/// Whenever a module is added, the least recently used modules of all
+/// configurations are removed until the cache fits in its maximum size.
+#[derive(Clone, Debug)]
+pub struct ModuleCache {
+    root: PathBuf,
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl ModuleCache {
+    /// Creates a cache under `root` for engines with the given settings, as
+    /// described by `engine_key`.
+    pub(crate) fn new(root: &Path, engine_key: &str) -> Self {
+        let engine_key =
+            hex_digest(format!("wasmtime {} {}", WASMTIME_VERSION, engine_key).as_bytes());
+        ModuleCache {
+            root: root.to_owned(),
+            dir: root.join(&engine_key[..16]),
+            max_size: MAX_CACHE_SIZE,
+        }
+    }
+
+    fn path(&self, module_data: &[u8]) -> PathBuf {
+        self.dir.join(format!(
+            "{}.{}",
+            hex_digest(module_data),
+            COMPILED_MODULE_EXTENSION
+        ))
+    }
+
+    /// Loads the compiled version of a module, compiling and caching it if it
+    /// is not in the cache yet. This blocks the current thread while reading
+    /// from disk or compiling.
+    pub(crate) fn load(&self, engine: &Engine, module_data: &[u8]) -> anyhow::Result<Module> {
+        let path = self.path(module_data);
+        if path.exists() {
+            // The cache directory only ever contains output of
+            // `Module::serialize`. Files written by other versions of wasmtime
+            // are rejected, in which case we recompile below.
+            match unsafe { Module::deserialize_file(engine, &path) } {
+                Ok(module) => {
+                    debug!(path = %path.display(), "Loaded compiled module from cache");
+                    // The modification time tells which modules were used
+                    // least recently
+                    if let Err(e) = filetime::set_file_mtime(&path, FileTime::now()) {
+                        debug!(error = %e, path = %path.display(), "Unable to mark cached module as used");
+                    }
+                    return Ok(module);
+                }
+                Err(e) => {
+                    warn!(error = %e, path = %path.display(), "Unable to load cached module, recompiling")
+                }
+            }
+        }
+
+        let compile_timer = MODULE_COMPILE_DURATION.start_timer();
+        let module = Module::new(engine, module_data)?;
+        compile_timer.observe_duration();
+        match self.store(&path, &module) {
+            Ok(()) => debug!(path = %path.display(), "Stored compiled module in cache"),
+            Err(e) => warn!(error = %e, path = %path.display(), "Unable to cache compiled module"),
+        }
+        if let Err(e) = self.evict(&path) {
+            warn!(error = %e, "Unable to remove modules from cache");
+        }
+        Ok(module)
+    }
+
+    /// Removes the least recently used modules of all engine configurations
+    /// until the cache fits in its maximum size. The module at `keep`, which
+    /// was just added, is never removed.
+    fn evict(&self, keep: &Path) -> anyhow::Result<()> {
+        let mut size = std::fs::metadata(keep).map(|m| m.len()).unwrap_or(0);
+        let mut entries = Vec::new();
+        for dir in std::fs::read_dir(&self.root)? {
+            let dir = dir?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path == keep || path.extension() != Some(COMPILED_MODULE_EXTENSION.as_ref()) {
+                    continue;
+                }
+                // Other containers may remove entries at the same time
+                if let Ok(metadata) = std::fs::metadata(&path) {
+                    size += metadata.len();
+                    entries.push((
+                        FileTime::from_last_modification_time(&metadata),
+                        metadata.len(),
+                        path,
+                    ));
+                }
+            }
+        }
+
+        entries.sort();
+        for (_, len, path) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => debug!(path = %path.display(), "Removed compiled module from cache"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    warn!(error = %e, path = %path.display(), "Unable to remove compiled module from cache");
+                    continue;
+                }
+            }
+            size -= len;
+        }
+        Ok(())
+    }
+
+    fn store(&self, path: &Path, module: &Module) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first so that other containers loading the
+        // same module never see a partially written file
+        let mut file = tempfile::NamedTempFile::new_in(&self.dir)?;
+        file.write_all(&module.serialize()?)?;
+        file.persist(path)?;
+        Ok(())
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_caches_compiled_module() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::default();
+        let cache = ModuleCache::new(dir.path(), "test");
+        let module_data = wat::parse_str(r#"(module (func (export "_start")))"#).unwrap();
+
+        let module = cache.load(&engine, &module_data).unwrap();
+        assert!(module.get_export("_start").is_some());
+        let path = cache.path(&module_data);
+        assert!(path.exists());
+
+        // Loading again uses the cached file
+        let cached = cache.load(&engine, &module_data).unwrap();
+        assert!(cached.get_export("_start").is_some());
+
+        // Corrupted entries are replaced. Cached modules are mapped from their
+        // file, so they need to be dropped before it is overwritten in place.
+        drop((module, cached));
+        std::fs::write(&path, b"not a module").unwrap();
+        cache.load(&engine, &module_data).unwrap();
+        assert_ne!(std::fs::read(&path).unwrap(), b"not a module");
+    }
+
+    #[test]
+    fn test_engine_configs_are_separated() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = ModuleCache::new(dir.path(), "first");
+        let second = ModuleCache::new(dir.path(), "second");
+        assert_ne!(first.dir, second.dir);
+        assert_eq!(first.dir, ModuleCache::new(dir.path(), "first").dir);
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::default();
+        let other = ModuleCache::new(dir.path(), "other");
+        let mut cache = ModuleCache::new(dir.path(), "test");
+        let modules: Vec<Vec<u8>> = (0..3)
+            .map(|i| wat::parse_str(format!(r#"(module (func (export "f{}")))"#, i)).unwrap())
+            .collect();
+        let paths: Vec<PathBuf> = vec![
+            other.path(&modules[0]),
+            cache.path(&modules[1]),
+            cache.path(&modules[2]),
+        ];
+
+        other.load(&engine, &modules[0]).unwrap();
+        cache.load(&engine, &modules[1]).unwrap();
+        filetime::set_file_mtime(&paths[0], FileTime::from_unix_time(1, 0)).unwrap();
+        filetime::set_file_mtime(&paths[1], FileTime::from_unix_time(2, 0)).unwrap();
+
+        // Loading a cached module marks it as used
+        cache.load(&engine, &modules[1]).unwrap();
+        let used = FileTime::from_last_modification_time(&std::fs::metadata(&paths[1]).unwrap());
+        assert!(used > FileTime::from_unix_time(2, 0));
+
+        // Only the module of the other configuration has to go to make room
+        let size = |path: &PathBuf| std::fs::metadata(path).unwrap().len();
+        let new_size = Module::new(&engine, &modules[2])
+            .unwrap()
+            .serialize()
+            .unwrap()
+            .len();
+        cache.max_size = size(&paths[1]) + new_size as u64;
+        cache.load(&engine, &modules[2]).unwrap();
+        assert!(!paths[0].exists());
+        assert!(paths[1].exists());
+        assert!(paths[2].exists());
+
+        // The module that was just added is kept, even if it does not fit
+        cache.max_size = 0;
+        cache.load(&engine, &modules[0]).unwrap();
+        assert!(cache.path(&modules[0]).exists());
+        assert!(!paths[1].exists());
+        assert!(!paths[2].exists());
+    }
+}
@@ -1,9 +1,11 @@
 use crate::ModuleRunContext;
 use crate::ProviderState;
 use krator::{ObjectState, SharedState};
+use kubelet::backoff::ExponentialBackoffStrategy;
 use kubelet::container::{Container, ContainerKey, Status};
 use kubelet::pod::Pod;
 
+pub(crate) mod config_error;
 pub(crate) mod running;
 pub(crate) mod terminated;
 pub(crate) mod waiting;
@@ -12,6 +14,10 @@ pub(crate) struct ContainerState {
     pod: Pod,
     container_key: ContainerKey,
     run_context: SharedState<ModuleRunContext>,
+    /// How often the container was restarted before this run
+    restart_count: u32,
+    /// How long to wait before retrying to create the container config
+    config_backoff_strategy: ExponentialBackoffStrategy,
 }
 
 impl ContainerState {
@@ -19,11 +25,14 @@ impl ContainerState {
         pod: Pod,
         container_key: ContainerKey,
         run_context: SharedState<ModuleRunContext>,
+        restart_count: u32,
     ) -> Self {
         ContainerState {
             pod,
             container_key,
             run_context,
+            restart_count,
+            config_backoff_strategy: ExponentialBackoffStrategy::default(),
         }
     }
 }
@@ -0,0 +1,74 @@
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use kube::api::Api;
+use tracing::{info, instrument};
+
+use kubelet::backoff::BackoffStrategy;
+use kubelet::container::state::prelude::*;
+use kubelet::state::common::GenericProviderState;
+
+use crate::ProviderState;
+
+use super::terminated::Terminated;
+use super::waiting::Waiting;
+use super::ContainerState;
+
+/// The container config, e.g. its environment, could not be created. The
+/// container is started once the config can be created, retrying with an
+/// exponential backoff.
+#[derive(Debug, TransitionTo)]
+#[transition_to(Waiting, Terminated)]
+pub struct ConfigError {
+    message: String,
+}
+
+impl ConfigError {
+    pub fn new(message: String) -> Self {
+        ConfigError { message }
+    }
+}
+
+#[async_trait::async_trait]
+impl State<ContainerState> for ConfigError {
+    #[instrument(
+        level = "info",
+        skip(self, shared, state, _container),
+        fields(pod_name = state.pod.name())
+    )]
+    async fn next(
+        self: Box<Self>,
+        shared: SharedState<ProviderState>,
+        state: &mut ContainerState,
+        _container: Manifest<Container>,
+    ) -> Transition<ContainerState> {
+        state.config_backoff_strategy.wait().await;
+
+        // Stop retrying once the pod is being deleted, as the container was
+        // never started and will not be stopped with the pod
+        let client = shared.read().await.client();
+        let deleted = match Api::<KubePod>::namespaced(client, state.pod.namespace())
+            .get(state.pod.name())
+            .await
+        {
+            Ok(pod) => pod.metadata.deletion_timestamp.is_some(),
+            Err(kube::Error::Api(e)) if e.code == 404 => true,
+            Err(_) => false,
+        };
+        if deleted {
+            info!("Pod is being deleted, not starting container");
+            let message = self.message.clone();
+            return Transition::next(self, Terminated::new(message, true));
+        }
+        Transition::next(self, Waiting)
+    }
+
+    async fn status(
+        &self,
+        _state: &mut ContainerState,
+        _container: &Container,
+    ) -> anyhow::Result<Status> {
+        Ok(Status::waiting_with_reason(
+            "CreateContainerConfigError",
+            &self.message,
+        ))
+    }
+}
@@ -1,40 +1,193 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use super::terminated::Terminated;
 use super::ContainerState;
+use crate::wasi_runtime::ExecContext;
 use crate::ProviderState;
 use kubelet::container::state::prelude::*;
+use kubelet::container::{patch_container_ready, patch_container_started};
+use kubelet::event::EventType;
+use kubelet::lifecycle::{Hook, HookType};
+use kubelet::pod::PodKey;
+use kubelet::probe::{ProbeEvent, Prober};
+use kubelet::state::common::GenericProviderState;
 use tokio::sync::mpsc::Receiver;
-use tracing::{debug, instrument, warn};
+use tracing::{debug, error, instrument, warn};
 
 /// The container is starting.
 #[derive(Debug, TransitionTo)]
 #[transition_to(Terminated)]
 pub struct Running {
     rx: Receiver<Status>,
+    exec_context: ExecContext,
 }
 
 impl Running {
-    pub fn new(rx: Receiver<Status>) -> Self {
-        Running { rx }
+    pub fn new(rx: Receiver<Status>, exec_context: ExecContext) -> Self {
+        Running { rx, exec_context }
+    }
+
+    /// Runs the postStart hook of the container, if it has one, for at most
+    /// `timeout`. Exec hooks call the function exported by the module under
+    /// the name given as the command, in a new instance sharing the
+    /// environment and volumes of the running one.
+    ///
+    /// The hook is cancelled if the container terminates while it runs, e.g.
+    /// because the pod was deleted, in which case the state to transition to
+    /// is returned.
+    async fn run_post_start_hook(
+        &mut self,
+        container: &Container,
+        host: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<Terminated>> {
+        let hook = match Hook::for_container(HookType::PostStart, container, host)? {
+            Some(hook) => hook,
+            None => return Ok(None),
+        };
+        debug!(?timeout, "Running postStart hook");
+        let run = hook.run(timeout, Some(&self.exec_context));
+        tokio::pin!(run);
+        loop {
+            tokio::select! {
+                result = &mut run => return result.map(|_| None),
+                status = self.rx.recv() => match status {
+                    Some(Status::Terminated {
+                        failed,
+                        message,
+                        reason,
+                        exit_code,
+                        signal,
+                        started_at,
+                        ..
+                    }) => {
+                        debug!("Container terminated, cancelling postStart hook");
+                        return Ok(Some(
+                            Terminated::new(message, failed)
+                                .with_reason(reason)
+                                .with_exit(exit_code, signal, started_at),
+                        ));
+                    }
+                    Some(status) => debug!(?status, "Got status update from WASI Runtime"),
+                    None => anyhow::bail!("WASI Runtime channel hung up"),
+                },
+            }
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl State<ContainerState> for Running {
-    #[instrument(level = "info", skip(self, _shared_state, _state, _container))]
+    #[instrument(level = "info", skip(self, shared_state, state, container))]
     async fn next(
         mut self: Box<Self>,
-        _shared_state: SharedState<ProviderState>,
-        _state: &mut ContainerState,
-        _container: Manifest<Container>,
+        shared_state: SharedState<ProviderState>,
+        state: &mut ContainerState,
+        container: Manifest<Container>,
     ) -> Transition<ContainerState> {
+        let (client, node_ip, recorder) = {
+            let provider_state = shared_state.read().await;
+            (
+                provider_state.client(),
+                provider_state.node_ip,
+                provider_state.recorder.clone(),
+            )
+        };
+        let container = container.latest();
+
+        // Like the upstream kubelet, probes only start once the postStart
+        // hook completed. The hook may not hold up the container for longer
+        // than the container would be given to shut down.
+        let timeout = state.pod.termination_grace_period();
+        match self
+            .run_post_start_hook(&container, &node_ip.to_string(), timeout)
+            .await
+        {
+            Ok(None) => (),
+            Ok(Some(terminated)) => return Transition::next(self, terminated),
+            Err(e) => {
+                warn!(error = %e, "PostStart hook failed, stopping container");
+                let message = format!("{:#}", e);
+                recorder
+                    .pod_event(
+                        &state.pod,
+                        Some(&state.container_key),
+                        EventType::Warning,
+                        "FailedPostStartHook",
+                        &message,
+                    )
+                    .await;
+                stop_container(&shared_state, state).await;
+                return Transition::next(self, Terminated::new(message, true));
+            }
+        }
+
+        let mut prober = match Prober::start(
+            &container,
+            &node_ip.to_string(),
+            Some(Arc::new(self.exec_context.clone())),
+        ) {
+            Ok(prober) => prober,
+            Err(e) => {
+                error!(error = %e, "Unable to start container probes");
+                stop_container(&shared_state, state).await;
+                return Transition::next(
+                    self,
+                    Terminated::new(format!("Invalid container probe: {:#}", e), true),
+                );
+            }
+        };
+        let api = kube::Api::namespaced(client, state.pod.namespace());
+
         debug!("Awaiting container status updates");
-        while let Some(status) = self.rx.recv().await {
-            debug!(?status, "Got status update from WASI Runtime");
-            if let Status::Terminated {
-                failed, message, ..
-            } = status
-            {
-                return Transition::next(self, Terminated::new(message, failed));
+        // Set when a probe determined that the container must be restarted
+        let mut unhealthy: Option<String> = None;
+        loop {
+            tokio::select! {
+                status = self.rx.recv() => match status {
+                    Some(Status::Terminated {
+                        failed,
+                        message,
+                        reason,
+                        exit_code,
+                        signal,
+                        started_at,
+                        ..
+                    }) => {
+                        let terminated = match unhealthy {
+                            Some(reason) => Terminated::new(reason, true),
+                            None => Terminated::new(message, failed).with_reason(reason),
+                        };
+                        return Transition::next(
+                            self,
+                            terminated.with_exit(exit_code, signal, started_at),
+                        );
+                    }
+                    Some(status) => debug!(?status, "Got status update from WASI Runtime"),
+                    None => break,
+                },
+                Some(event) = prober.next_event(), if unhealthy.is_none() => {
+                    debug!(?event, "Got probe event");
+                    let result = match event {
+                        ProbeEvent::Ready(ready) => {
+                            patch_container_ready(&api, &state.pod, &state.container_key, ready).await
+                        }
+                        ProbeEvent::Started => {
+                            patch_container_started(&api, &state.pod, &state.container_key, true)
+                                .await
+                        }
+                        ProbeEvent::Unhealthy(probe_type, reason) => {
+                            warn!(%probe_type, %reason, "Container is unhealthy, stopping it");
+                            unhealthy = Some(format!("{} probe failed: {}", probe_type, reason));
+                            stop_container(&shared_state, state).await;
+                            Ok(())
+                        }
+                    };
+                    if let Err(e) = result {
+                        error!(error = %e, "Unable to patch container status with probe result");
+                    }
+                }
             }
         }
         warn!("WASI Runtime channel hung up");
@@ -52,3 +205,17 @@ impl State<ContainerState> for Running {
         Ok(Status::running())
     }
 }
+
+/// Signals the running module of the container to stop.
+async fn stop_container(shared_state: &SharedState<ProviderState>, state: &ContainerState) {
+    let handle = {
+        let provider_state = shared_state.read().await;
+        let handles = provider_state.handles.read().await;
+        handles.get(&PodKey::from(&state.pod)).cloned()
+    };
+    if let Some(handle) = handle {
+        if let Err(e) = handle.stop_container(&state.container_key.name()).await {
+            error!(error = %e, "Unable to stop container");
+        }
+    }
+}
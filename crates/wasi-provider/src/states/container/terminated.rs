@@ -11,11 +11,42 @@ use super::ContainerState;
 pub struct Terminated {
     message: String,
     failed: bool,
+    reason: Option<String>,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    started_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl Terminated {
     pub fn new(message: String, failed: bool) -> Self {
-        Terminated { message, failed }
+        Terminated {
+            message,
+            failed,
+            reason: None,
+            exit_code: None,
+            signal: None,
+            started_at: None,
+        }
+    }
+
+    /// Sets the reason reported in the terminated container status.
+    pub fn with_reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason;
+        self
+    }
+
+    /// Sets how the module exited, as reported in the terminated container
+    /// status.
+    pub fn with_exit(
+        mut self,
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+        started_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        self.exit_code = exit_code;
+        self.signal = signal;
+        self.started_at = started_at;
+        self
     }
 }
 
@@ -48,6 +79,14 @@ impl State<ContainerState> for Terminated {
         _state: &mut ContainerState,
         _container: &Container,
     ) -> anyhow::Result<Status> {
-        Ok(Status::terminated(&self.message, self.failed))
+        Ok(Status::Terminated {
+            timestamp: chrono::Utc::now(),
+            message: self.message.clone(),
+            failed: self.failed,
+            reason: self.reason.clone(),
+            exit_code: self.exit_code,
+            signal: self.signal,
+            started_at: self.started_at,
+        })
     }
 }
@@ -3,16 +3,20 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use tokio::sync::mpsc;
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
 
+use kubelet::backoff::BackoffStrategy;
 use kubelet::container::state::prelude::*;
+use kubelet::event::EventType;
 use kubelet::pod::{Handle as PodHandle, PodKey};
+use kubelet::provider::Provider;
 use kubelet::state::common::GenericProviderState;
 use kubelet::volume::VolumeRef;
 
-use crate::wasi_runtime::{WasiHttpConfig, WasiRuntime};
-use crate::ProviderState;
+use crate::wasi_runtime::{ResourceLimits, WasiHttpConfig, WasiRuntime};
+use crate::{ProviderState, WasiProvider};
 
+use super::config_error::ConfigError;
 use super::running::Running;
 use super::terminated::Terminated;
 use super::ContainerState;
@@ -21,6 +25,16 @@ pub const MAX_CONNCURRENT_REQUESTS_ANNOTATION_KEY: &str =
     "alpha.wasi.krustlet.dev/max-concurrent-requests";
 pub const ALLOWED_DOMAINS_ANNOTATION_KEY: &str = "alpha.wasi.krustlet.dev/allowed-domains";
 
+fn resource_limits(container: &Container) -> anyhow::Result<ResourceLimits> {
+    let memory = container
+        .memory_limit()?
+        .map(|bytes| usize::try_from(bytes).unwrap_or(usize::MAX));
+    Ok(ResourceLimits {
+        memory,
+        cpu: container.cpu_limit()?,
+    })
+}
+
 fn volume_path_map(
     container: &Container,
     volumes: &HashMap<String, VolumeRef>,
@@ -84,7 +98,7 @@ fn volume_path_map(
 
 /// The container is starting.
 #[derive(Default, Debug, TransitionTo)]
-#[transition_to(Running, Terminated)]
+#[transition_to(Running, Terminated, ConfigError)]
 pub struct Waiting;
 
 #[async_trait::async_trait]
@@ -106,14 +120,19 @@ impl State<ContainerState> for Waiting {
 
         info!("Starting container for pod");
 
-        let (client, log_path) = {
+        let (client, log_manager, engines, recorder) = {
             let provider_state = shared.read().await;
-            (provider_state.client(), provider_state.log_path.clone())
+            (
+                provider_state.client(),
+                provider_state.log_manager.clone(),
+                provider_state.engines.clone(),
+                provider_state.recorder.clone(),
+            )
         };
 
         let (module_data, container_volumes, container_envs) = {
-            let mut run_context = state.run_context.write().await;
-            let module_data = match run_context.modules.remove(container.name()) {
+            let run_context = state.run_context.read().await;
+            let module_data = match run_context.modules.get(container.name()).cloned() {
                 Some(data) => data,
                 None => {
                     return Transition::next(
@@ -151,12 +170,33 @@ impl State<ContainerState> for Waiting {
                 container_volumes,
                 run_context
                     .env_vars
-                    .remove(container.name())
+                    .get(container.name())
+                    .cloned()
                     .unwrap_or_default(),
             )
         };
 
-        let mut env = kubelet::provider::env_vars(&container, &state.pod, &client).await;
+        let mut env =
+            match WasiProvider::env_vars(&container, &state.pod, &client, Some(&recorder)).await {
+                Ok(env) => {
+                    state.config_backoff_strategy.reset();
+                    env
+                }
+                Err(e) => {
+                    warn!(error = %e, "Unable to create container config");
+                    let message = format!("{:#}", e);
+                    recorder
+                        .pod_event(
+                            &state.pod,
+                            Some(&state.container_key),
+                            EventType::Warning,
+                            "Failed",
+                            &format!("Error: {}", message),
+                        )
+                        .await;
+                    return Transition::next(self, ConfigError::new(message));
+                }
+            };
         env.extend(container_envs);
         let args: Vec<String> = container.args().map(|t| t.to_owned()).unwrap_or_default();
 
@@ -215,26 +255,52 @@ impl State<ContainerState> for Waiting {
             }
         }
 
-        // TODO: decide how/what it means to propagate annotations (from run_context) into WASM modules.
-        let runtime = match WasiRuntime::new(
-            name,
-            module_data,
-            env,
-            args,
-            container_volumes,
-            log_path,
-            tx,
-            wasi_http_config,
-        )
-        .await
+        let limits = match resource_limits(&container) {
+            Ok(limits) => limits,
+            Err(e) => {
+                return Transition::next(
+                    self,
+                    Terminated::new(
+                        format!(
+                            "Pod {} container {} has invalid resource limits: {:?}",
+                            state.pod.name(),
+                            container.name(),
+                            e
+                        ),
+                        true,
+                    ),
+                )
+            }
+        };
+        let (engine, module_cache) = match engines.get(&limits).await {
+            Ok(engine) => engine,
+            Err(e) => {
+                return Transition::next(
+                    self,
+                    Terminated::new(
+                        format!(
+                            "Pod {} container {} failed to create engine: {:?}",
+                            state.pod.name(),
+                            container.name(),
+                            e
+                        ),
+                        true,
+                    ),
+                )
+            }
+        };
+
+        let log = match log_manager
+            .open(&state.pod, container.name(), state.restart_count)
+            .await
         {
-            Ok(runtime) => runtime,
+            Ok(log) => log,
             Err(e) => {
                 return Transition::next(
                     self,
                     Terminated::new(
                         format!(
-                            "Pod {} container {} failed to construct runtime: {:?}",
+                            "Pod {} container {} failed to open log: {:?}",
                             state.pod.name(),
                             container.name(),
                             e
@@ -244,6 +310,22 @@ impl State<ContainerState> for Waiting {
                 )
             }
         };
+
+        // TODO: decide how/what it means to propagate annotations (from run_context) into WASM modules.
+        let runtime = WasiRuntime::new(
+            name,
+            module_data,
+            env,
+            args,
+            container_volumes,
+            log,
+            tx,
+            wasi_http_config,
+            limits,
+            engine,
+            module_cache,
+        );
+        let exec_context = runtime.exec_context();
         debug!("Starting container on thread");
         let container_handle = match runtime.start().await {
             Ok(handle) => handle,
@@ -274,7 +356,7 @@ impl State<ContainerState> for Waiting {
                 .insert_container_handle(state.container_key.clone(), container_handle)
                 .await;
         }
-        Transition::next(self, Running::new(rx))
+        Transition::next(self, Running::new(rx, exec_context))
     }
 
     async fn status(
@@ -5,6 +5,7 @@ use async_trait::async_trait;
 use krator::{ObjectState, SharedState};
 use kubelet::backoff::BackoffStrategy;
 use kubelet::backoff::ExponentialBackoffStrategy;
+use kubelet::container::ContainerKey;
 use kubelet::pod::Pod;
 use kubelet::pod::PodKey;
 use kubelet::pod::Status;
@@ -23,10 +24,15 @@ pub(crate) mod starting;
 /// State that is shared between pod state handlers.
 pub struct PodState {
     key: PodKey,
+    /// The pod as it was first seen, to find the files kept for it
+    pod: Pod,
     run_context: SharedState<ModuleRunContext>,
     errors: usize,
     image_pull_backoff_strategy: ExponentialBackoffStrategy,
     pub(crate) crash_loop_backoff_strategy: ExponentialBackoffStrategy,
+    /// The back-off of each container restarted after exiting, so that a
+    /// crashing container does not delay the restarts of its siblings.
+    pub(crate) container_backoff_strategies: HashMap<ContainerKey, ExponentialBackoffStrategy>,
 }
 
 #[async_trait]
@@ -49,6 +55,10 @@ impl ObjectState for PodState {
             let mut handles = provider_state.handles.write().await;
             handles.remove(&self.key);
         }
+        // The logs of a deleted pod can no longer be requested
+        if let Err(e) = provider_state.log_manager.remove_pod(&self.pod).await {
+            error!(error = %e, "Unable to remove pod logs");
+        }
     }
 }
 
@@ -62,10 +72,12 @@ impl PodState {
         let key = PodKey::from(pod);
         PodState {
             key,
+            pod: pod.clone(),
             run_context: Arc::new(RwLock::new(run_context)),
             errors: 0,
             image_pull_backoff_strategy: ExponentialBackoffStrategy::default(),
             crash_loop_backoff_strategy: ExponentialBackoffStrategy::default(),
+            container_backoff_strategies: HashMap::new(),
         }
     }
 }
@@ -58,6 +58,7 @@ impl State<PodState> for Initializing {
                 pod.clone(),
                 container_key.clone(),
                 Arc::clone(&pod_state.run_context),
+                0,
             );
 
             match run_to_completion(
@@ -1,22 +1,36 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use tokio::sync::mpsc::Receiver;
+use tracing::{error, info, warn};
 
+use kubelet::backoff::BackoffStrategy;
+use kubelet::container::{
+    patch_container_restart_count, patch_container_status, ContainerKey, Status as ContainerStatus,
+};
 use kubelet::pod::state::prelude::*;
 use kubelet::state::common::error::Error;
 use kubelet::state::common::GenericProviderState;
 
 use super::completed::Completed;
+use super::starting::{start_container, ContainerResult};
 use crate::fail_fatal;
 use crate::{PodState, ProviderState};
 
+/// Containers that run for at least this long before exiting have their
+/// restart backoff reset.
+const BACKOFF_RESET_THRESHOLD: Duration = Duration::from_secs(600);
+
 /// The Kubelet is running the Pod.
 #[derive(Debug, TransitionTo)]
 #[transition_to(Completed, Error<crate::WasiProvider>)]
 pub struct Running {
-    rx: Receiver<anyhow::Result<()>>,
+    rx: Receiver<ContainerResult>,
 }
 
 impl Running {
-    pub fn new(rx: Receiver<anyhow::Result<()>>) -> Self {
+    pub fn new(rx: Receiver<ContainerResult>) -> Self {
         Running { rx }
     }
 }
@@ -26,31 +40,95 @@ impl State<PodState> for Running {
     async fn next(
         mut self: Box<Self>,
         provider_state: SharedState<ProviderState>,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         pod: Manifest<Pod>,
     ) -> Transition<PodState> {
+        let pod_rx = pod.clone();
         let pod = pod.latest();
 
-        let mut completed = 0;
+        let restart_policy = pod.restart_policy();
         let total_containers = pod.containers().len();
+        let mut completed = HashSet::new();
+        // Restart counts carry on from the container statuses, which outlive
+        // this state
+        let mut restart_counts: HashMap<ContainerKey, i32> = HashMap::new();
+        let mut started_at: HashMap<ContainerKey, Instant> = HashMap::new();
+        let running_since = Instant::now();
+
+        // Restarted containers report back on a sender that lives as long as
+        // this state, so that pending restarts are abandoned once it exits.
+        let (restart_tx, mut restart_rx) = tokio::sync::mpsc::channel(total_containers.max(1));
+
+        loop {
+            let (key, result) = tokio::select! {
+                Some(r) = self.rx.recv() => r,
+                Some(r) = restart_rx.recv() => r,
+                else => break,
+            };
 
-        while let Some(result) = self.rx.recv().await {
-            match result {
-                Ok(()) => {
-                    completed += 1;
-                    if completed == total_containers {
-                        return Transition::next(self, Completed);
+            if !restart_policy.should_restart(result.is_err()) {
+                match result {
+                    Ok(()) => {
+                        completed.insert(key);
+                        if completed.len() == total_containers {
+                            return Transition::next(self, Completed);
+                        }
+                        continue;
                     }
-                }
-                Err(e) => {
-                    // Stop remaining containers;
-                    {
-                        let provider = provider_state.write().await;
-                        provider.stop(&pod).await.ok();
+                    Err(e) => {
+                        // Stop remaining containers without holding the lock on
+                        // the provider state while they shut down
+                        let stop = provider_state.read().await.stop(&pod);
+                        stop.await.ok();
+                        fail_fatal!(e);
                     }
-                    fail_fatal!(e);
                 }
             }
+
+            if let Err(e) = &result {
+                warn!(container = %key, error = %e, "Container failed");
+            }
+
+            let backoff_strategy = pod_state
+                .container_backoff_strategies
+                .entry(key.clone())
+                .or_default();
+            let ran_for = started_at.get(&key).unwrap_or(&running_since).elapsed();
+            if ran_for >= BACKOFF_RESET_THRESHOLD {
+                backoff_strategy.reset();
+            }
+            let delay = backoff_strategy.next_duration();
+
+            let restart_count = restart_counts
+                .entry(key.clone())
+                .or_insert_with(|| pod_rx.latest().container_restart_count(&key));
+            *restart_count += 1;
+            info!(
+                container = %key,
+                ?restart_policy,
+                restart_count = *restart_count,
+                ?delay,
+                "Restarting container"
+            );
+            report_restart(
+                &provider_state,
+                &pod_rx.latest(),
+                &key,
+                *restart_count,
+                delay,
+            )
+            .await;
+
+            started_at.insert(key.clone(), Instant::now() + delay);
+            start_container(
+                Arc::clone(&provider_state),
+                Arc::clone(&pod_state.run_context),
+                pod_rx.clone(),
+                key,
+                *restart_count as u32,
+                delay,
+                restart_tx.clone(),
+            );
         }
         Transition::next(
             self,
@@ -65,3 +143,29 @@ impl State<PodState> for Running {
         Ok(make_status(Phase::Running, "Running"))
     }
 }
+
+/// Updates the container status to reflect that it is backing off before
+/// being restarted.
+async fn report_restart(
+    provider_state: &SharedState<ProviderState>,
+    pod: &Pod,
+    key: &ContainerKey,
+    restart_count: i32,
+    delay: Duration,
+) {
+    let client = {
+        let provider_state = provider_state.read().await;
+        provider_state.client()
+    };
+    let api = kube::Api::namespaced(client, pod.namespace());
+    if let Err(e) = patch_container_restart_count(&api, pod, key, restart_count).await {
+        error!(container = %key, error = %e, "Unable to patch container restart count");
+    }
+    let status = ContainerStatus::waiting_with_reason(
+        "CrashLoopBackOff",
+        &format!("back-off {}s restarting failed container", delay.as_secs()),
+    );
+    if let Err(e) = patch_container_status(&api, pod, key, &status).await {
+        error!(container = %key, error = %e, "Unable to patch container status");
+    }
+}
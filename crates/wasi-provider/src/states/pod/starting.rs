@@ -1,6 +1,8 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use tracing::{info, instrument};
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, instrument};
 
 use kubelet::container::state::run_to_completion;
 use kubelet::container::ContainerKey;
@@ -9,10 +11,13 @@ use kubelet::state::common::GenericProviderState;
 
 use crate::states::container::waiting::Waiting;
 use crate::states::container::ContainerState;
-use crate::{PodState, ProviderState};
+use crate::{ModuleRunContext, PodState, ProviderState};
 
 use super::running::Running;
 
+/// The result of running a single container to completion.
+pub(crate) type ContainerResult = (ContainerKey, anyhow::Result<()>);
+
 #[derive(Default, Debug, TransitionTo)]
 #[transition_to(Running)]
 /// The Kubelet is starting the Pod containers
@@ -40,33 +45,15 @@ impl State<PodState> for Starting {
         let containers = pod.containers();
         let (tx, rx) = tokio::sync::mpsc::channel(containers.len());
         for container in containers {
-            let initial_state = Waiting;
-            let container_key = ContainerKey::App(container.name().to_string());
-            let container_state = ContainerState::new(
-                pod.clone(),
-                container_key.clone(),
+            start_container(
+                Arc::clone(&provider_state),
                 Arc::clone(&pod_state.run_context),
+                pod_rx.clone(),
+                ContainerKey::App(container.name().to_string()),
+                0,
+                Duration::from_secs(0),
+                tx.clone(),
             );
-            let task_provider = Arc::clone(&provider_state);
-            let task_tx = tx.clone();
-            let task_pod = pod_rx.clone();
-            tokio::task::spawn(async move {
-                let client = {
-                    let provider_state = task_provider.read().await;
-                    provider_state.client()
-                };
-
-                let result = run_to_completion(
-                    &client,
-                    initial_state,
-                    task_provider,
-                    container_state,
-                    task_pod,
-                    container_key,
-                )
-                .await;
-                task_tx.send(result).await
-            });
         }
         info!("All containers started for pod");
         Transition::next(self, Running::new(rx))
@@ -76,3 +63,49 @@ impl State<PodState> for Starting {
         Ok(make_status(Phase::Pending, "Starting"))
     }
 }
+
+/// Spawns a task that waits for the given delay and then runs the state
+/// machine of a container to completion, sending the result on `tx`. The
+/// restart count tells apart the logs of the runs of a container.
+///
+/// If the receiving end of `tx` has hung up by the time the delay has elapsed
+/// (i.e. the pod is no longer running), the container is not started.
+pub(crate) fn start_container(
+    provider_state: SharedState<ProviderState>,
+    run_context: SharedState<ModuleRunContext>,
+    pod: Manifest<Pod>,
+    container_key: ContainerKey,
+    restart_count: u32,
+    delay: Duration,
+    tx: Sender<ContainerResult>,
+) {
+    tokio::task::spawn(async move {
+        tokio::time::sleep(delay).await;
+        if tx.is_closed() {
+            debug!(container = %container_key, "Pod is no longer running, not starting container");
+            return;
+        }
+
+        let client = {
+            let provider_state = provider_state.read().await;
+            provider_state.client()
+        };
+        let container_state = ContainerState::new(
+            pod.latest(),
+            container_key.clone(),
+            run_context,
+            restart_count,
+        );
+
+        let result = run_to_completion(
+            &client,
+            Waiting,
+            provider_state,
+            container_state,
+            pod,
+            container_key.clone(),
+        )
+        .await;
+        tx.send((container_key, result)).await.ok();
+    });
+}
@@ -1,39 +1,521 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, instrument, trace, warn};
 
 use cap_std::ambient_authority;
-use tempfile::NamedTempFile;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::OnceCell;
 use tokio::task::JoinHandle;
 use wasi_cap_std_sync::WasiCtxBuilder;
-use wasmtime::{InterruptHandle, Linker};
+use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasi_common::{WasiCtx, WasiFile};
+use wasmtime::{
+    Caller, Engine, InstanceAllocationStrategy, InstanceLimits, InterruptHandle, Linker,
+    ModuleLimits, OptLevel, PoolingAllocationStrategy, ResourceLimiter, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
 
+use kubelet::config::{WasmConfig, WasmOptLevel};
 use kubelet::container::Handle as ContainerHandle;
 use kubelet::container::Status;
-use kubelet::handle::StopHandler;
+use kubelet::exec::{ExecFuture, Session};
+use kubelet::handle::{ExecHandler, StatsHandler, StopHandler};
+use kubelet::log::{ContainerLog, LogHandleFactory, Stream};
+use kubelet::stats::ContainerUsage;
+
+use crate::module_cache::ModuleCache;
 
 use wasi_experimental_http_wasmtime::HttpCtx as WasiHttpCtx;
 
+/// Roughly how much fuel a module consumes per second of CPU time. Wasmtime
+/// charges about one unit of fuel per executed instruction.
+const FUEL_PER_CPU_SECOND: u64 = 1_000_000_000;
+/// The period over which CPU limits are enforced. This matches the default
+/// CFS period used for Linux containers.
+const CPU_PERIOD: Duration = Duration::from_millis(100);
+/// The host function a module can import to find out whether it was asked to
+/// shut down. It takes no arguments and returns `1` once the pod is being
+/// terminated and `0` before that, so modules are expected to poll it.
+const SHUTDOWN_IMPORT_MODULE: &str = "krustlet";
+const SHUTDOWN_IMPORT: &str = "shutdown_requested";
+// Module limits of the pooling allocator, which needs to know the size of
+// every instance up front
+const MAX_TABLES: usize = 1;
+const MAX_MEMORIES: usize = 1;
+const MAX_TABLE_ELEMENTS: u32 = 100_000;
+/// The size of a page of linear memory
+const WASM_PAGE_SIZE: u64 = 65536;
+/// The largest linear memory a module can have, 4GiB
+const MAX_MEMORY_PAGES: u64 = 65536;
+
 pub struct Runtime {
     handle: JoinHandle<anyhow::Result<()>>,
-    interrupt_handle: InterruptHandle,
+    /// How the module exited, once `handle` completed. A completed join
+    /// handle must not be awaited again, e.g. when a pod is terminated twice.
+    exit: Option<Result<(), String>>,
+    /// Not set if the engine does not support interrupts
+    interrupt_handle: Option<InterruptHandle>,
+    /// Used to run exec commands against the module
+    exec_context: ExecContext,
+    /// Set to ask the module to shut down. Not set if the module does not
+    /// import the function to find out about it, so it could never notice.
+    shutdown_requested: Option<Arc<AtomicBool>>,
+    /// The resources used by the running module
+    usage: Arc<Usage>,
+    /// When the module was started
+    start_time: chrono::DateTime<chrono::Utc>,
+    /// The log that output from the module is written to
+    output: ContainerLog,
 }
 
 #[async_trait::async_trait]
 impl StopHandler for Runtime {
     async fn stop(&mut self) -> anyhow::Result<()> {
-        self.interrupt_handle.interrupt();
+        match self.interrupt_handle.as_ref() {
+            Some(handle) => handle.interrupt(),
+            None => warn!("Interrupts are disabled, module will keep running until it exits"),
+        }
         Ok(())
     }
 
+    async fn shutdown(&mut self) -> anyhow::Result<bool> {
+        match self.shutdown_requested.as_ref() {
+            Some(requested) => {
+                requested.store(true, Ordering::Relaxed);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     async fn wait(&mut self) -> anyhow::Result<()> {
-        (&mut self.handle).await??;
-        Ok(())
+        if self.exit.is_none() {
+            let exit = match (&mut self.handle).await {
+                Ok(result) => result.map_err(|e| format!("{:#}", e)),
+                Err(e) => Err(e.to_string()),
+            };
+            self.exit = Some(exit);
+        }
+        match self.exit.clone() {
+            Some(Err(e)) => Err(anyhow::Error::msg(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ExecHandler for Runtime {
+    fn exec(&self, session: Session) -> anyhow::Result<ExecFuture> {
+        self.exec_context.exec(session)
+    }
+}
+
+impl StatsHandler for Runtime {
+    fn usage(&self) -> ContainerUsage {
+        ContainerUsage {
+            start_time: Some(self.start_time),
+            cpu_time: self.usage.cpu_time(),
+            memory_bytes: self.usage.memory_bytes.load(Ordering::Relaxed),
+            log_bytes: Some(self.output.size()),
+        }
+    }
+}
+
+/// The resources used by a running module, updated by the thread running it.
+#[derive(Debug, Default)]
+struct Usage {
+    /// The size of the module's linear memory in bytes
+    memory_bytes: AtomicU64,
+    /// The CPU time spent running the module in nanoseconds
+    cpu_time: AtomicU64,
+    /// The CPU clock of the thread running the module and its reading when
+    /// the module started, while it runs
+    #[cfg(target_os = "linux")]
+    cpu_clock: std::sync::Mutex<Option<(libc::clockid_t, Duration)>>,
+}
+
+impl Usage {
+    /// The CPU time spent running the module so far. While the module runs,
+    /// this is read from the CPU clock of its thread, as modules without a CPU
+    /// limit never yield to record it.
+    fn cpu_time(&self) -> Duration {
+        #[cfg(target_os = "linux")]
+        if let Some((clock, start)) = *self.cpu_clock.lock().unwrap() {
+            return clock_time(clock).saturating_sub(start);
+        }
+        Duration::from_nanos(self.cpu_time.load(Ordering::Relaxed))
+    }
+
+    /// Reads the CPU time from the clock of the current thread until
+    /// `untrack_thread` is called. The thread must keep running the module
+    /// until then, as threads are reused once they are done.
+    fn track_thread(&self) {
+        #[cfg(target_os = "linux")]
+        {
+            let mut clock: libc::clockid_t = 0;
+            if unsafe { libc::pthread_getcpuclockid(libc::pthread_self(), &mut clock) } == 0 {
+                *self.cpu_clock.lock().unwrap() = Some((clock, thread_cpu_time()));
+            }
+        }
+    }
+
+    /// Stops reading the CPU time from the clock of the thread running the
+    /// module, falling back to the time it last recorded.
+    fn untrack_thread(&self) {
+        #[cfg(target_os = "linux")]
+        self.cpu_clock.lock().unwrap().take();
+    }
+}
+
+/// Returns the current time of the given clock.
+#[cfg(target_family = "unix")]
+fn clock_time(clock: libc::clockid_t) -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Returns the CPU time used by the current thread so far.
+#[cfg(target_family = "unix")]
+fn thread_cpu_time() -> Duration {
+    clock_time(libc::CLOCK_THREAD_CPUTIME_ID)
+}
+
+/// Returns the time since the current thread first asked for it. Without a
+/// per thread CPU clock this is the best approximation available.
+#[cfg(not(target_family = "unix"))]
+fn thread_cpu_time() -> Duration {
+    thread_local!(static STARTED: Instant = Instant::now());
+    STARTED.with(|started| started.elapsed())
+}
+
+/// Everything needed to run exec commands against new instances of a
+/// container's module, with the same environment and preopened directories as
+/// the running container.
+#[derive(Clone)]
+pub struct ExecContext {
+    /// The name of the process, used to identify exec sessions
+    name: String,
+    /// Data used to instantiate the module
+    data: Arc<Data>,
+    /// Configuration for the WASI http
+    http_config: WasiHttpConfig,
+}
+
+impl std::fmt::Debug for ExecContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecContext")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl ExecHandler for ExecContext {
+    fn exec(&self, session: Session) -> anyhow::Result<ExecFuture> {
+        let context = self.clone();
+        Ok(Box::pin(async move {
+            let (interrupt_tx, interrupt_rx) = tokio::sync::oneshot::channel();
+            let task = tokio::task::spawn_blocking(move || context.run(session, interrupt_tx));
+            // Interrupt the command if this future is dropped before it
+            // completes, e.g. because a probe timed out or the client went away
+            let _guard = interrupt_rx.await.ok().flatten().map(InterruptOnDrop);
+            task.await?
+        }))
     }
 }
 
+impl ExecContext {
+    /// Runs the command requested by an exec session. If the first element of
+    /// the command names a function exported by the module, that function is
+    /// called. Otherwise the module is run in command mode with the command as
+    /// its arguments.
+    ///
+    /// The interrupt handle of the instance, if the engine supports interrupts,
+    /// is sent on `interrupt_tx` before it starts running. This blocks the
+    /// current thread until the command completes.
+    #[instrument(level = "info", skip(self, session, interrupt_tx), fields(name = %self.name))]
+    fn run(
+        &self,
+        session: Session,
+        interrupt_tx: tokio::sync::oneshot::Sender<Option<InterruptHandle>>,
+    ) -> anyhow::Result<i32> {
+        let args = session.command().to_vec();
+        let (stdin, stdout, stderr) = session.into_io();
+
+        let module = self.data.module()?;
+        let func_name = match module.get_export(&args[0]) {
+            Some(wasmtime::ExternType::Func(_)) => args[0].as_str(),
+            _ => "_start",
+        };
+        debug!(func_name, ?args, "running exec command");
+
+        let ctx = self.data.wasi_ctx(
+            &args,
+            Box::new(ReadPipe::new(stdin)),
+            Box::new(WritePipe::new(stdout)),
+            Box::new(WritePipe::new(stderr)),
+        )?;
+        let mut store = self.data.store(ctx)?;
+        interrupt_tx.send(store.interrupt_handle().ok()).ok();
+        let mut linker = Linker::new(&self.data.engine);
+        link_imports(&mut linker, self.http_config.clone())?;
+        let limits = &self.data.limits;
+        let instance =
+            block_on_throttled(linker.instantiate_async(&mut store, &module), limits, None)?;
+        let func = instance.get_func(&mut store, func_name).ok_or_else(|| {
+            anyhow::anyhow!("{} is not a function exported by the module", func_name)
+        })?;
+
+        match block_on_throttled(func.call_async(&mut store, &[]), limits, None) {
+            Ok(_) => Ok(0),
+            Err(e) => match e
+                .downcast_ref::<wasmtime::Trap>()
+                .and_then(|t| t.i32_exit_status())
+            {
+                Some(code) => Ok(code),
+                None => Err(e.context(format!("unable to run {}", func_name))),
+            },
+        }
+    }
+}
+
+/// Interrupts a wasmtime instance when dropped.
+struct InterruptOnDrop(InterruptHandle);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        self.0.interrupt();
+    }
+}
+
+/// Runs a wasmtime future to completion on the current thread.
+///
+/// Stores created by [`Data::store`] yield every time they have used up their
+/// fuel for the current period. If the module has a CPU limit, the thread
+/// sleeps until the next period starts before the module continues.
+///
+/// If `usage` is given, the CPU time spent polling the future is recorded in
+/// it every time the module yields.
+fn block_on_throttled<F: Future>(
+    future: F,
+    limits: &ResourceLimits,
+    usage: Option<&Usage>,
+) -> F::Output {
+    futures::pin_mut!(future);
+    let waker = futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut period_start = Instant::now();
+    let cpu_start = thread_cpu_time();
+    loop {
+        let poll = future.as_mut().poll(&mut cx);
+        if let Some(usage) = usage {
+            let cpu_time = thread_cpu_time().saturating_sub(cpu_start);
+            usage
+                .cpu_time
+                .store(cpu_time.as_nanos() as u64, Ordering::Relaxed);
+        }
+        match poll {
+            Poll::Ready(output) => return output,
+            Poll::Pending => {
+                if limits.cpu.is_some() {
+                    if let Some(remaining) = CPU_PERIOD.checked_sub(period_start.elapsed()) {
+                        std::thread::sleep(remaining);
+                    }
+                }
+                period_start = Instant::now();
+            }
+        }
+    }
+}
+
+/// The size of the instance pool of engines using the pooling allocator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PoolLimits {
+    /// The number of instances that can exist at the same time
+    pub(crate) instances: u32,
+    /// The number of pages of linear memory reserved for every instance
+    pub(crate) memory_pages: u64,
+}
+
+impl PoolLimits {
+    /// Makes room for an instance per pod, each of which may use as much
+    /// memory as the node can allocate to pods. Without a known amount of
+    /// allocatable memory, instances may use the most a module can address.
+    pub(crate) fn new(max_pods: u16, allocatable_memory: Option<u128>) -> Self {
+        let memory_pages = allocatable_memory
+            .map(|bytes| (bytes / WASM_PAGE_SIZE as u128).min(MAX_MEMORY_PAGES as u128) as u64)
+            .unwrap_or(MAX_MEMORY_PAGES)
+            .max(1);
+        PoolLimits {
+            instances: u32::from(max_pods).max(1),
+            memory_pages,
+        }
+    }
+}
+
+/// Creates an engine used to compile and run modules. Only engines that
+/// consume fuel can enforce CPU limits, at the cost of counting the
+/// instructions every module executes. Async support is always enabled, as
+/// modules are run as futures, but they only ever yield when they run out of
+/// fuel.
+///
+/// With the pooling allocator, the pool reserves address space for the linear
+/// memory of every instance in `pool` up front.
+pub(crate) fn new_engine(
+    wasm_config: &WasmConfig,
+    pool: PoolLimits,
+    fuel: bool,
+) -> anyhow::Result<Engine> {
+    let mut config = wasmtime::Config::new();
+    config
+        .interruptable(wasm_config.interruptable)
+        .cranelift_opt_level(match wasm_config.opt_level {
+            WasmOptLevel::None => OptLevel::None,
+            WasmOptLevel::Speed => OptLevel::Speed,
+            WasmOptLevel::SpeedAndSize => OptLevel::SpeedAndSize,
+        })
+        .parallel_compilation(wasm_config.parallel_compilation)
+        .consume_fuel(fuel)
+        .async_support(true);
+    if wasm_config.pooling_allocator {
+        // Only reserve as much memory as an instance can use. Without the
+        // default guard region, smaller pools need bounds checks on every
+        // memory access, but that is better than running out of address space.
+        config
+            .static_memory_maximum_size(pool.memory_pages * WASM_PAGE_SIZE)
+            .static_memory_guard_size(0);
+        // The default module limits are too small for most real world modules,
+        // so allow anything that fits within the per store limits
+        config.allocation_strategy(InstanceAllocationStrategy::Pooling {
+            strategy: PoolingAllocationStrategy::NextAvailable,
+            module_limits: ModuleLimits {
+                types: 10_000,
+                functions: 100_000,
+                globals: 1_000,
+                tables: MAX_TABLES as u32,
+                memories: MAX_MEMORIES as u32,
+                table_elements: MAX_TABLE_ELEMENTS,
+                memory_pages: pool.memory_pages,
+                ..Default::default()
+            },
+            instance_limits: InstanceLimits {
+                count: pool.instances,
+            },
+        });
+    }
+    Engine::new(&config)
+}
+
+/// Describes the settings of an engine that the code it compiles depends on,
+/// so that modules compiled with other settings are cached separately.
+fn engine_key(wasm_config: &WasmConfig, pool: PoolLimits, fuel: bool) -> String {
+    let opt_level = match wasm_config.opt_level {
+        WasmOptLevel::None => "none",
+        WasmOptLevel::Speed => "speed",
+        WasmOptLevel::SpeedAndSize => "speed_and_size",
+    };
+    // The size of pooled memories decides which bounds checks are needed
+    let memory_pages = if wasm_config.pooling_allocator {
+        pool.memory_pages
+    } else {
+        0
+    };
+    format!(
+        "interruptable={} opt_level={} fuel={} pooled_memory_pages={}",
+        wasm_config.interruptable, opt_level, fuel, memory_pages
+    )
+}
+
+/// The engines modules are compiled and run with, each with the cache of the
+/// modules it compiled. Only containers with a CPU limit run on the engine
+/// that consumes fuel. It is created once the first of them starts, as every
+/// engine using the pooling allocator reserves a pool of its own.
+#[derive(Clone)]
+pub(crate) struct Engines {
+    wasm_config: WasmConfig,
+    pool: PoolLimits,
+    cache_root: PathBuf,
+    unmetered: (Engine, ModuleCache),
+    metered: Arc<OnceCell<(Engine, ModuleCache)>>,
+}
+
+impl Engines {
+    /// Creates the engine for containers without a CPU limit, caching the
+    /// modules compiled by the engines under `cache_root`.
+    pub(crate) fn new(
+        wasm_config: &WasmConfig,
+        pool: PoolLimits,
+        cache_root: &Path,
+    ) -> anyhow::Result<Self> {
+        Ok(Engines {
+            wasm_config: wasm_config.clone(),
+            pool,
+            cache_root: cache_root.to_owned(),
+            unmetered: Self::create(wasm_config, pool, cache_root, false)?,
+            metered: Arc::new(OnceCell::new()),
+        })
+    }
+
+    fn create(
+        wasm_config: &WasmConfig,
+        pool: PoolLimits,
+        cache_root: &Path,
+        fuel: bool,
+    ) -> anyhow::Result<(Engine, ModuleCache)> {
+        let engine = new_engine(wasm_config, pool, fuel)?;
+        let module_cache = ModuleCache::new(cache_root, &engine_key(wasm_config, pool, fuel));
+        Ok((engine, module_cache))
+    }
+
+    /// Returns the engine to run a container with the given limits on, along
+    /// with the cache of modules compiled by it.
+    pub(crate) async fn get(
+        &self,
+        limits: &ResourceLimits,
+    ) -> anyhow::Result<(Engine, ModuleCache)> {
+        if limits.cpu.is_none() {
+            return Ok(self.unmetered.clone());
+        }
+        let metered = self
+            .metered
+            .get_or_try_init(|| async {
+                Self::create(&self.wasm_config, self.pool, &self.cache_root, true)
+            })
+            .await?;
+        Ok(metered.clone())
+    }
+}
+
+/// Adds the WASI and WASI HTTP imports to the given linker.
+fn link_imports(linker: &mut Linker<StoreData>, http_config: WasiHttpConfig) -> anyhow::Result<()> {
+    wasmtime_wasi::add_to_linker(linker, |data| &mut data.wasi)?;
+    linker.func_wrap(
+        SHUTDOWN_IMPORT_MODULE,
+        SHUTDOWN_IMPORT,
+        |caller: Caller<'_, StoreData>| -> i32 {
+            caller.data().shutdown_requested.load(Ordering::Relaxed) as i32
+        },
+    )?;
+
+    // Link WASI HTTP
+    let WasiHttpConfig {
+        allowed_domains,
+        max_concurrent_requests,
+    } = http_config;
+    let wasi_http = WasiHttpCtx::new(allowed_domains, max_concurrent_requests)?;
+    wasi_http.add_to_linker(linker)?;
+    Ok(())
+}
+
 /// WasiRuntime provides a WASI compatible runtime. A runtime should be used for
 /// each "instance" of a process and can be passed to a thread pool for running
 pub struct WasiRuntime {
@@ -41,8 +523,8 @@ pub struct WasiRuntime {
     name: String,
     /// Data needed for the runtime
     data: Arc<Data>,
-    /// The tempfile that output from the wasmtime process writes to
-    output: Arc<NamedTempFile>,
+    /// The log that output from the wasmtime process is written to
+    output: ContainerLog,
     /// A channel to send status updates on the runtime
     status_sender: Sender<Status>,
     /// Configuration for the WASI http
@@ -56,6 +538,77 @@ pub struct WasiHttpConfig {
     pub max_concurrent_requests: Option<u32>,
 }
 
+/// Resource limits applied to every instance of a module.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceLimits {
+    /// The maximum size of linear memory in bytes
+    pub memory: Option<usize>,
+    /// The number of CPU cores the module may use, e.g. 0.5 for half a core
+    pub cpu: Option<f64>,
+}
+
+impl ResourceLimits {
+    /// The amount of fuel a module may consume in a single period.
+    fn fuel_per_period(&self) -> u64 {
+        let cores = self.cpu.unwrap_or(1.0);
+        (FUEL_PER_CPU_SECOND as f64 * cores * CPU_PERIOD.as_secs_f64()).max(1.0) as u64
+    }
+}
+
+/// A resource limiter that records whether the module was denied memory, so
+/// that a failed run can be reported as an out of memory kill.
+struct Limiter {
+    limits: StoreLimits,
+    /// Whether the most recent request for memory was denied. This is cleared
+    /// by a later request that succeeds, as the module recovered from it.
+    out_of_memory: bool,
+    /// Where to record the size of linear memory, if anywhere
+    usage: Option<Arc<Usage>>,
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        let allowed = self.limits.memory_growing(current, desired, maximum);
+        self.out_of_memory = !allowed;
+        if let (true, Some(usage)) = (allowed, self.usage.as_ref()) {
+            usage.memory_bytes.store(desired as u64, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
+/// The data held by the store of each module instance.
+struct StoreData {
+    wasi: WasiCtx,
+    limiter: Limiter,
+    /// Whether the instance was asked to shut down
+    shutdown_requested: Arc<AtomicBool>,
+}
+
+impl StoreData {
+    /// Whether the module was denied the memory it last asked for. A module
+    /// that fails after this is considered to have run out of memory.
+    fn out_of_memory(&self) -> bool {
+        self.limiter.out_of_memory
+    }
+}
+
 struct Data {
     /// binary module data to be run as a wasm module
     module_data: Vec<u8>,
@@ -67,17 +620,90 @@ struct Data {
     /// (e.g. /tmp/foo/myfile -> /app/config). If the optional value is not given,
     /// the same path will be allowed in the runtime
     dirs: HashMap<PathBuf, Option<PathBuf>>,
+    /// limits on the resources the module may use
+    limits: ResourceLimits,
+    /// the engine used to run the module, which consumes fuel if the module
+    /// has a CPU limit
+    engine: Engine,
+    /// the cache used to avoid compiling the module on every start
+    module_cache: ModuleCache,
 }
 
-/// Holds our tempfile handle.
-pub struct HandleFactory {
-    temp: Arc<NamedTempFile>,
-}
+impl Data {
+    /// Loads the compiled module from the cache, compiling it if needed. This
+    /// blocks the current thread.
+    fn module(&self) -> anyhow::Result<wasmtime::Module> {
+        self.module_cache.load(&self.engine, &self.module_data)
+    }
 
-impl kubelet::log::HandleFactory<tokio::fs::File> for HandleFactory {
-    /// Creates `tokio::fs::File` on demand for log reading.
-    fn new_handle(&self) -> tokio::fs::File {
-        tokio::fs::File::from_std(self.temp.reopen().unwrap())
+    /// Creates a store for a new instance of this module, with its resource
+    /// limits applied. Without limits, the store allows as much as wasmtime
+    /// does by default.
+    fn store(&self, wasi: WasiCtx) -> anyhow::Result<Store<StoreData>> {
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(memory) = self.limits.memory {
+            limits = limits.memory_size(memory);
+        }
+        let data = StoreData {
+            wasi,
+            limiter: Limiter {
+                limits: limits.build(),
+                out_of_memory: false,
+                usage: None,
+            },
+            shutdown_requested: Arc::new(AtomicBool::new(false)),
+        };
+        let mut store = Store::new(&self.engine, data);
+        store.limiter(|data| &mut data.limiter);
+
+        // Hand out fuel one period at a time so that the module yields to
+        // `block_on_throttled` whenever it has used up its share
+        if self.limits.cpu.is_some() {
+            let fuel = self.limits.fuel_per_period();
+            store.add_fuel(fuel)?;
+            store.out_of_fuel_async_yield(u64::MAX, fuel);
+        }
+        Ok(store)
+    }
+
+    /// Creates a WASI context with the environment and preopened directories of
+    /// this module, using the given arguments and standard streams.
+    fn wasi_ctx(
+        &self,
+        args: &[String],
+        stdin: Box<dyn WasiFile>,
+        stdout: Box<dyn WasiFile>,
+        stderr: Box<dyn WasiFile>,
+    ) -> anyhow::Result<WasiCtx> {
+        let env: Vec<(String, String)> = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        // Create the WASI context builder and pass arguments, environment,
+        // and standard input, output and error.
+        let mut builder = WasiCtxBuilder::new()
+            .args(args)?
+            .envs(&env)?
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr);
+
+        // Add preopen dirs.
+        for (key, value) in self.dirs.iter() {
+            let guest_dir = value.as_ref().unwrap_or(key);
+            debug!(
+                hostpath = %key.display(),
+                guestpath = %guest_dir.display(),
+                "mounting hostpath in modules"
+            );
+            let preopen_dir = cap_std::fs::Dir::open_ambient_dir(key, ambient_authority())?;
+
+            builder = builder.preopened_dir(preopen_dir, guest_dir)?;
+        }
+
+        Ok(builder.build())
     }
 }
 
@@ -92,128 +718,111 @@ impl WasiRuntime {
     /// * `dirs` - a map of local file system paths to optional path names in the runtime
     ///     (e.g. /tmp/foo/myfile -> /app/config). If the optional value is not given,
     ///     the same path will be allowed in the runtime
-    /// * `log_dir` - location for storing logs
+    /// * `output` - the log that output from the module is written to
+    /// * `limits` - limits on the memory and CPU the module may use
+    /// * `engine` - the engine used to compile and run the module, which must
+    ///     consume fuel if `limits` include a CPU limit
+    /// * `module_cache` - cache of compiled modules for `engine`
     #[allow(clippy::too_many_arguments)]
-    pub async fn new<L: AsRef<Path> + Send + Sync + 'static>(
+    pub fn new(
         name: String,
         module_data: Vec<u8>,
         env: HashMap<String, String>,
         args: Vec<String>,
         dirs: HashMap<PathBuf, Option<PathBuf>>,
-        log_dir: L,
+        output: ContainerLog,
         status_sender: Sender<Status>,
         http_config: WasiHttpConfig,
-    ) -> anyhow::Result<Self> {
-        let temp = tokio::task::spawn_blocking(move || -> anyhow::Result<NamedTempFile> {
-            Ok(NamedTempFile::new_in(log_dir)?)
-        })
-        .await??;
-
-        // We need to use named temp file because we need multiple file handles
-        // and if we are running in the temp dir, we run the possibility of the
-        // temp file getting cleaned out from underneath us while running. If we
-        // think it necessary, we can make these permanent files with a cleanup
-        // loop that runs elsewhere. These will get deleted when the reference
-        // is dropped
-        Ok(WasiRuntime {
+        limits: ResourceLimits,
+        engine: Engine,
+        module_cache: ModuleCache,
+    ) -> Self {
+        WasiRuntime {
             name,
             data: Arc::new(Data {
                 module_data,
                 env,
                 args,
                 dirs,
+                limits,
+                engine,
+                module_cache,
             }),
-            output: Arc::new(temp),
+            output,
             status_sender,
             http_config,
-        })
+        }
     }
 
-    pub async fn start(&self) -> anyhow::Result<ContainerHandle<Runtime, HandleFactory>> {
-        let temp = self.output.clone();
-        // Because a reopen is blocking, run in a blocking task to get new
-        // handles to the tempfile
-        let output_write = tokio::task::spawn_blocking(move || -> anyhow::Result<std::fs::File> {
-            Ok(temp.reopen()?)
-        })
-        .await??;
-
-        let (interrupt_handle, handle) = self
-            .spawn_wasmtime(tokio::fs::File::from_std(output_write))
-            .await?;
+    /// Returns a context that can be used to run exec commands against this
+    /// runtime's module.
+    pub fn exec_context(&self) -> ExecContext {
+        ExecContext {
+            name: self.name.clone(),
+            data: self.data.clone(),
+            http_config: self.http_config.clone(),
+        }
+    }
 
-        let log_handle_factory = HandleFactory {
-            temp: self.output.clone(),
-        };
+    pub async fn start(&self) -> anyhow::Result<ContainerHandle<Runtime, LogHandleFactory>> {
+        let usage = Arc::new(Usage::default());
+        let start_time = chrono::Utc::now();
+        let (interrupt_handle, shutdown_requested, handle) =
+            self.spawn_wasmtime(usage.clone()).await?;
 
         Ok(ContainerHandle::new(
             Runtime {
                 handle,
+                exit: None,
                 interrupt_handle,
+                exec_context: self.exec_context(),
+                shutdown_requested,
+                usage,
+                start_time,
+                output: self.output.clone(),
             },
-            log_handle_factory,
+            self.output.handle_factory(),
         ))
     }
 
     // Spawns a running wasmtime instance with the given context and status
     // channel.
-    #[instrument(level = "info", skip(self, output_write), fields(name = %self.name))]
+    #[instrument(level = "info", skip(self, usage), fields(name = %self.name))]
     async fn spawn_wasmtime(
         &self,
-        output_write: tokio::fs::File,
-    ) -> anyhow::Result<(InterruptHandle, JoinHandle<anyhow::Result<()>>)> {
+        usage: Arc<Usage>,
+    ) -> anyhow::Result<(
+        Option<InterruptHandle>,
+        Option<Arc<AtomicBool>>,
+        JoinHandle<anyhow::Result<()>>,
+    )> {
         // Clone the module data Arc so it can be moved
         let data = self.data.clone();
         let status_sender = self.status_sender.clone();
 
         // Log this info here so it isn't on _every_ log line
         trace!(env = ?data.env, args = ?data.args, dirs = ?data.dirs, "Starting setup of wasmtime module");
-        let env: Vec<(String, String)> = data
-            .env
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
-        let stdout = wasi_cap_std_sync::file::File::from_cap_std(cap_std::fs::File::from_std(
-            output_write.try_clone().await?.into_std().await,
-            ambient_authority(),
-        ));
-        let stderr = wasi_cap_std_sync::file::File::from_cap_std(cap_std::fs::File::from_std(
-            output_write.try_clone().await?.into_std().await,
-            ambient_authority(),
-        ));
+        let stdout = WritePipe::new(self.output.writer(Stream::Stdout));
+        let stderr = WritePipe::new(self.output.writer(Stream::Stderr));
 
-        // Create the WASI context builder and pass arguments, environment,
-        // and standard output and error.
-        let mut builder = WasiCtxBuilder::new()
-            .args(&data.args)?
-            .envs(&env)?
-            .stdout(Box::new(stdout))
-            .stderr(Box::new(stderr));
+        let ctx = data.wasi_ctx(
+            &data.args,
+            Box::new(ReadPipe::new(std::io::empty())),
+            Box::new(stdout),
+            Box::new(stderr),
+        )?;
 
-        // Add preopen dirs.
-        for (key, value) in data.dirs.iter() {
-            let guest_dir = value.as_ref().unwrap_or(key);
-            debug!(
-                hostpath = %key.display(),
-                guestpath = %guest_dir.display(),
-                "mounting hostpath in modules"
-            );
-            let preopen_dir = cap_std::fs::Dir::open_ambient_dir(key, ambient_authority())?;
-
-            builder = builder.preopened_dir(preopen_dir, guest_dir)?;
-        }
-
-        let ctx = builder.build();
+        let mut store = data.store(ctx)?;
+        store.data_mut().limiter.usage = Some(usage.clone());
+        let interrupt = store.interrupt_handle().ok();
 
-        let mut config = wasmtime::Config::new();
-        config.interruptable(true);
-        let engine = wasmtime::Engine::new(&config)?;
-        let mut store = wasmtime::Store::new(&engine, ctx);
-        let interrupt = store.interrupt_handle()?;
+        let mut linker = Linker::new(&data.engine);
 
-        let mut linker = Linker::new(&engine);
-
-        let module = match wasmtime::Module::new(&engine, &data.module_data) {
+        let module = {
+            let data = data.clone();
+            tokio::task::spawn_blocking(move || data.module()).await?
+        };
+        let module = match module {
             // We can't map errors here or it moves the send channel, so we
             // do it in a match
             Ok(m) => m,
@@ -225,24 +834,38 @@ impl WasiRuntime {
                         failed: true,
                         message: message.into(),
                         timestamp: chrono::Utc::now(),
+                        reason: None,
+                        exit_code: None,
+                        signal: None,
+                        started_at: None,
                     })
                     .await?;
 
                 return Err(anyhow::anyhow!("{}: {}", message, e));
             }
         };
+        let shutdown_requested = module
+            .imports()
+            .any(|i| i.module() == SHUTDOWN_IMPORT_MODULE && i.name() == Some(SHUTDOWN_IMPORT))
+            .then(|| store.data().shutdown_requested.clone());
 
-        wasmtime_wasi::add_to_linker(&mut linker, |cx| cx)?;
+        link_imports(&mut linker, self.http_config.clone())?;
 
-        // Link WASI HTTP
-        let WasiHttpConfig {
-            allowed_domains,
-            max_concurrent_requests,
-        } = self.http_config.clone();
-        let wasi_http = WasiHttpCtx::new(allowed_domains, max_concurrent_requests)?;
-        wasi_http.add_to_linker(&mut linker)?;
-
-        let instance = match linker.instantiate(&mut store, &module) {
+        // Instantiation runs the start function of the module, if any, which
+        // may run for as long as it likes
+        let (mut store, instance) = {
+            let data = data.clone();
+            tokio::task::spawn_blocking(move || {
+                let instance = block_on_throttled(
+                    linker.instantiate_async(&mut store, &module),
+                    &data.limits,
+                    None,
+                );
+                (store, instance)
+            })
+            .await?
+        };
+        let instance = match instance {
             // We can't map errors here or it moves the send channel, so we
             // do it in a match
             Ok(i) => i,
@@ -254,6 +877,10 @@ impl WasiRuntime {
                         failed: true,
                         message: message.into(),
                         timestamp: chrono::Utc::now(),
+                        reason: store.data().out_of_memory().then(|| "OOMKilled".to_owned()),
+                        exit_code: None,
+                        signal: None,
+                        started_at: None,
                     })
                     .await?;
                 // Converting from anyhow
@@ -262,9 +889,10 @@ impl WasiRuntime {
         };
 
         info!("starting run of module");
+        let started_at = chrono::Utc::now();
         status_sender
             .send(Status::Running {
-                timestamp: chrono::Utc::now(),
+                timestamp: started_at,
             })
             .await?;
 
@@ -274,9 +902,6 @@ impl WasiRuntime {
             .get_export(&mut store, "_start")
             .ok_or_else(|| anyhow::anyhow!("_start import doesn't exist in wasm module"))?;
 
-        // NOTE(thomastaylor312): In the future (pun intended) we might be able to use something
-        // like `func.call(...).await`. We should check every once and a while when upgraing
-        // wasmtime
         let func = match export {
             wasmtime::Extern::Func(f) => f,
             _ => {
@@ -288,6 +913,10 @@ impl WasiRuntime {
                         failed: true,
                         message: message.into(),
                         timestamp: chrono::Utc::now(),
+                        reason: None,
+                        exit_code: None,
+                        signal: None,
+                        started_at: None,
                     })
                     .await?;
 
@@ -300,41 +929,145 @@ impl WasiRuntime {
             let span = tracing::info_span!("wasmtime_module_run", %name);
             let _enter = span.enter();
 
-            match func.call(&mut store, &[]) {
-                // We can't map errors here or it moves the send channel, so we
-                // do it in a match
-                Ok(_) => {}
-                Err(e) => {
-                    let message = "unable to run module";
-                    error!(error = %e, "{}", message);
-                    send(
-                        &status_sender,
-                        &name,
-                        Status::Terminated {
-                            failed: true,
-                            message: message.into(),
-                            timestamp: chrono::Utc::now(),
-                        },
-                    );
-
-                    return Err(anyhow::anyhow!("{}: {}", message, e));
+            usage.track_thread();
+            let result =
+                block_on_throttled(func.call_async(&mut store, &[]), &data.limits, Some(&usage))
+                    .map(|_| ());
+            usage.untrack_thread();
+            let exit = Exit::from_result(&result, store.data().out_of_memory());
+            let failed = exit.exit_code != 0;
+            match &result {
+                Err(e) if failed => {
+                    error!(error = %e, exit_code = exit.exit_code, "{}", exit.message)
                 }
-            };
-
-            info!("module run complete");
+                _ => info!(exit_code = exit.exit_code, "module run complete"),
+            }
             send(
                 &status_sender,
                 &name,
                 Status::Terminated {
-                    failed: false,
-                    message: "Module run completed".into(),
+                    failed,
+                    message: exit.message.clone(),
                     timestamp: chrono::Utc::now(),
+                    reason: Some(exit.reason),
+                    exit_code: Some(exit.exit_code),
+                    signal: exit.signal,
+                    started_at: Some(started_at),
                 },
             );
-            Ok(())
+
+            match result {
+                Err(e) if failed => Err(anyhow::anyhow!("{}: {}", exit.message, e)),
+                _ => Ok(()),
+            }
         });
         // Wait for the interrupt to be sent back to us
-        Ok((interrupt, handle))
+        Ok((interrupt, shutdown_requested, handle))
+    }
+}
+
+const SIGILL: i32 = 4;
+const SIGABRT: i32 = 6;
+const SIGFPE: i32 = 8;
+const SIGKILL: i32 = 9;
+const SIGSEGV: i32 = 11;
+
+/// How a module run ended, described like the exit of a process so that it
+/// can be reported in the terminated container status.
+#[derive(Debug, PartialEq)]
+struct Exit {
+    exit_code: i32,
+    /// The signal a process would have received for an equivalent failure
+    signal: Option<i32>,
+    reason: String,
+    message: String,
+}
+
+impl Exit {
+    /// Determines how a module run ended from its result. `out_of_memory` is
+    /// set if the module was denied the memory it last asked for.
+    ///
+    /// Modules that call `proc_exit` report the given exit code. Traps and
+    /// interrupts are reported as if the process was killed by a signal, with
+    /// an exit code of 128 plus the signal number. A module that fails after
+    /// being denied memory is reported as killed for running out of memory.
+    fn from_result(result: &anyhow::Result<()>, out_of_memory: bool) -> Self {
+        let error = match result {
+            Ok(()) => return Exit::exited(0),
+            Err(e) => e,
+        };
+        let trap = error.downcast_ref::<wasmtime::Trap>();
+        if let Some(code) = trap.and_then(|t| t.i32_exit_status()) {
+            return Exit::exited(code);
+        }
+        let code = trap.and_then(|t| t.trap_code());
+        if code == Some(wasmtime::TrapCode::Interrupt) {
+            return Exit::signaled(
+                SIGKILL,
+                "Error".into(),
+                "module was stopped before it exited".into(),
+            );
+        }
+        if out_of_memory {
+            return Exit::signaled(
+                SIGKILL,
+                "OOMKilled".into(),
+                "module ran out of memory".into(),
+            );
+        }
+        // Errors of the runtime or of host functions are not traps of the module
+        let code = match code {
+            Some(code) => code,
+            None => {
+                return Exit {
+                    exit_code: 1,
+                    signal: None,
+                    reason: "Error".into(),
+                    message: "unable to run module".into(),
+                }
+            }
+        };
+        use wasmtime::TrapCode::*;
+        let (signal, reason) = match code {
+            StackOverflow => (SIGSEGV, "StackOverflow"),
+            MemoryOutOfBounds => (SIGSEGV, "MemoryOutOfBounds"),
+            HeapMisaligned => (SIGSEGV, "HeapMisaligned"),
+            TableOutOfBounds => (SIGSEGV, "TableOutOfBounds"),
+            IndirectCallToNull => (SIGSEGV, "IndirectCallToNull"),
+            BadSignature => (SIGILL, "BadSignature"),
+            IntegerOverflow => (SIGFPE, "IntegerOverflow"),
+            IntegerDivisionByZero => (SIGFPE, "IntegerDivisionByZero"),
+            BadConversionToInteger => (SIGFPE, "BadConversionToInteger"),
+            UnreachableCodeReached => (SIGABRT, "UnreachableCodeReached"),
+            _ => (SIGABRT, "Trap"),
+        };
+        Exit::signaled(signal, reason.into(), format!("module trapped: {}", code))
+    }
+
+    fn exited(exit_code: i32) -> Self {
+        let (reason, message) = if exit_code == 0 {
+            ("Completed".into(), "Module run completed".into())
+        } else {
+            (
+                "Error".into(),
+                format!("module exited with status {}", exit_code),
+            )
+        };
+        Exit {
+            exit_code,
+            signal: None,
+            reason,
+            message,
+        }
+    }
+
+    fn signaled(signal: i32, reason: String, message: String) -> Self {
+        Exit {
+            exit_code: 128 + signal,
+            signal: Some(signal),
+            reason,
+            message,
+        }
     }
 }
 
@@ -345,3 +1078,508 @@ fn send(sender: &Sender<Status>, name: &str, status: Status) {
         Ok(_) => debug!("send completed"),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kubelet::exec::Options;
+    use kubelet::log::LogManager;
+
+    const TEST_POOL: PoolLimits = PoolLimits {
+        instances: 4,
+        memory_pages: 16,
+    };
+
+    const MODULE: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (memory (export "memory") 1)
+            (func (export "grow")
+                (if (i32.eq (memory.grow (i32.const 4)) (i32.const -1)) (then unreachable)))
+            (func (export "_start"))
+            (func (export "check") (call $proc_exit (i32.const 3)))
+            (func (export "spin") (local $i i32)
+                (loop $continue
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $continue (i32.lt_u (local.get $i) (i32.const 1000000))))))
+    "#;
+
+    const OOM_MODULE: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (memory 1)
+            (func (export "_start")
+                (if (i32.eq (memory.grow (i32.const 4)) (i32.const -1)) (then unreachable))))
+    "#;
+
+    fn exec(command: &[&str], limits: ResourceLimits) -> anyhow::Result<i32> {
+        exec_with_engine(command, limits, &WasmConfig::default())
+    }
+
+    fn exec_with_engine(
+        command: &[&str],
+        limits: ResourceLimits,
+        wasm_config: &WasmConfig,
+    ) -> anyhow::Result<i32> {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let context = ExecContext {
+            name: "test".to_owned(),
+            data: Arc::new(Data {
+                module_data: wat::parse_str(MODULE).unwrap(),
+                env: HashMap::new(),
+                args: Vec::new(),
+                dirs: HashMap::new(),
+                limits,
+                engine: new_engine(wasm_config, TEST_POOL, limits.cpu.is_some()).unwrap(),
+                module_cache: ModuleCache::new(cache_dir.path(), "test"),
+            }),
+            http_config: WasiHttpConfig::default(),
+        };
+        let (output_tx, _output_rx) = tokio::sync::mpsc::channel(1);
+        let (interrupt_tx, _interrupt_rx) = tokio::sync::oneshot::channel();
+        let opts = Options {
+            command: command.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        };
+        context.run(Session::new(opts, None, output_tx), interrupt_tx)
+    }
+
+    #[test]
+    fn test_exec_exported_function() {
+        assert_eq!(exec(&["check"], ResourceLimits::default()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_exec_command_mode() {
+        assert_eq!(
+            exec(&["/bin/app", "--flag"], ResourceLimits::default()).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_engine_config() {
+        let wasm_config = WasmConfig {
+            interruptable: false,
+            opt_level: WasmOptLevel::None,
+            pooling_allocator: true,
+            parallel_compilation: false,
+        };
+        assert_eq!(
+            exec_with_engine(&["check"], ResourceLimits::default(), &wasm_config).unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_pool_limits() {
+        assert_eq!(
+            PoolLimits::new(110, Some(256 * 1024 * 1024)),
+            PoolLimits {
+                instances: 110,
+                memory_pages: 4096
+            }
+        );
+        assert_eq!(PoolLimits::new(110, None).memory_pages, MAX_MEMORY_PAGES);
+        assert_eq!(
+            PoolLimits::new(0, Some(1024)),
+            PoolLimits {
+                instances: 1,
+                memory_pages: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engines_consume_fuel_only_with_cpu_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let engines = Engines::new(&WasmConfig::default(), TEST_POOL, dir.path()).unwrap();
+        let (engine, _) = engines.get(&ResourceLimits::default()).await.unwrap();
+        assert!(Store::new(&engine, ()).add_fuel(1).is_err());
+
+        let limits = ResourceLimits {
+            memory: None,
+            cpu: Some(0.5),
+        };
+        let (engine, _) = engines.get(&limits).await.unwrap();
+        assert!(Store::new(&engine, ()).add_fuel(1).is_ok());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        assert_eq!(exec(&["grow"], ResourceLimits::default()).unwrap(), 0);
+
+        let limits = ResourceLimits {
+            memory: Some(2 * 65536),
+            cpu: None,
+        };
+        assert!(exec(&["grow"], limits).is_err());
+    }
+
+    #[test]
+    fn test_cpu_limit() {
+        // The loop burns a few million units of fuel, so it needs multiple
+        // periods at this limit
+        let limits = ResourceLimits {
+            memory: None,
+            cpu: Some(0.01),
+        };
+        let start = Instant::now();
+        assert_eq!(exec(&["spin"], limits).unwrap(), 0);
+        assert!(start.elapsed() >= 2 * CPU_PERIOD);
+    }
+
+    async fn container_log(data_dir: &Path) -> ContainerLog {
+        let pod: k8s_openapi::api::core::v1::Pod = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "namespace": "default", "uid": "1234" }
+        }))
+        .unwrap();
+        LogManager::new(data_dir, 1024 * 1024, 2)
+            .open(&pod.into(), "test", 0)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status_tx, _status_rx) = tokio::sync::mpsc::channel(4);
+        let runtime = WasiRuntime::new(
+            "test".to_owned(),
+            wat::parse_str(OOM_MODULE).unwrap(),
+            HashMap::new(),
+            Vec::new(),
+            HashMap::new(),
+            container_log(dir.path()).await,
+            status_tx,
+            WasiHttpConfig::default(),
+            ResourceLimits::default(),
+            new_engine(&WasmConfig::default(), TEST_POOL, false).unwrap(),
+            ModuleCache::new(dir.path(), "test"),
+        );
+        let mut handle = runtime.start().await.unwrap();
+        handle.wait().await.unwrap();
+
+        let usage = handle.usage();
+        assert_eq!(usage.memory_bytes, 5 * 65536);
+        assert!(usage.start_time.is_some());
+        assert_eq!(usage.log_bytes, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_oom_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status_tx, mut status_rx) = tokio::sync::mpsc::channel(4);
+        let limits = ResourceLimits {
+            memory: Some(2 * 65536),
+            cpu: None,
+        };
+        let runtime = WasiRuntime::new(
+            "test".to_owned(),
+            wat::parse_str(OOM_MODULE).unwrap(),
+            HashMap::new(),
+            Vec::new(),
+            HashMap::new(),
+            container_log(dir.path()).await,
+            status_tx,
+            WasiHttpConfig::default(),
+            limits,
+            new_engine(&WasmConfig::default(), TEST_POOL, false).unwrap(),
+            ModuleCache::new(dir.path(), "test"),
+        );
+        let _handle = runtime.start().await.unwrap();
+
+        assert!(matches!(
+            status_rx.recv().await,
+            Some(Status::Running { .. })
+        ));
+        match status_rx.recv().await {
+            Some(Status::Terminated {
+                failed,
+                reason,
+                exit_code,
+                ..
+            }) => {
+                assert!(failed);
+                assert_eq!(reason.as_deref(), Some("OOMKilled"));
+                assert_eq!(exit_code, Some(137));
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+    }
+
+    /// Runs a module to completion and returns its terminated status.
+    async fn run_to_exit(module: &str) -> Status {
+        run_to_exit_with_limits(module, ResourceLimits::default()).await
+    }
+
+    async fn run_to_exit_with_limits(module: &str, limits: ResourceLimits) -> Status {
+        let dir = tempfile::tempdir().unwrap();
+        let (status_tx, mut status_rx) = tokio::sync::mpsc::channel(4);
+        let runtime = WasiRuntime::new(
+            "test".to_owned(),
+            wat::parse_str(module).unwrap(),
+            HashMap::new(),
+            Vec::new(),
+            HashMap::new(),
+            container_log(dir.path()).await,
+            status_tx,
+            WasiHttpConfig::default(),
+            limits,
+            new_engine(&WasmConfig::default(), TEST_POOL, false).unwrap(),
+            ModuleCache::new(dir.path(), "test"),
+        );
+        let _handle = runtime.start().await.unwrap();
+        assert!(matches!(
+            status_rx.recv().await,
+            Some(Status::Running { .. })
+        ));
+        status_rx.recv().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exit_after_denied_memory() {
+        let limits = ResourceLimits {
+            memory: Some(2 * 65536),
+            cpu: None,
+        };
+        // A module that is denied memory but then exits on its own reports its
+        // exit code
+        let exit = r#"
+            (module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (memory.grow (i32.const 4)))
+                    (call $proc_exit (i32.const 2))))
+        "#;
+        match run_to_exit_with_limits(exit, limits).await {
+            Status::Terminated {
+                reason, exit_code, ..
+            } => {
+                assert_eq!(reason.as_deref(), Some("Error"));
+                assert_eq!(exit_code, Some(2));
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_limits_by_default() {
+        // More table elements than the pooling allocator allows and a start
+        // function that runs during instantiation
+        let status = run_to_exit(
+            r#"
+            (module
+                (table 200000 funcref)
+                (func $init)
+                (start $init)
+                (func (export "_start")))
+            "#,
+        )
+        .await;
+        assert!(
+            matches!(
+                status,
+                Status::Terminated {
+                    exit_code: Some(0),
+                    ..
+                }
+            ),
+            "Expected successful exit, got {:?}",
+            status
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exit_status() {
+        let exit = |code: i32| {
+            format!(
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                    (memory (export "memory") 1)
+                    (func (export "_start") (call $proc_exit (i32.const {}))))
+                "#,
+                code
+            )
+        };
+        match run_to_exit(&exit(3)).await {
+            Status::Terminated {
+                failed,
+                reason,
+                exit_code,
+                signal,
+                started_at,
+                ..
+            } => {
+                assert!(failed);
+                assert_eq!(reason.as_deref(), Some("Error"));
+                assert_eq!(exit_code, Some(3));
+                assert_eq!(signal, None);
+                assert!(started_at.is_some());
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+        match run_to_exit(&exit(0)).await {
+            Status::Terminated {
+                failed,
+                reason,
+                exit_code,
+                ..
+            } => {
+                assert!(!failed);
+                assert_eq!(reason.as_deref(), Some("Completed"));
+                assert_eq!(exit_code, Some(0));
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trap_status() {
+        let module = r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "_start") unreachable))
+        "#;
+        match run_to_exit(module).await {
+            Status::Terminated {
+                failed,
+                reason,
+                exit_code,
+                signal,
+                ..
+            } => {
+                assert!(failed);
+                assert_eq!(reason.as_deref(), Some("UnreachableCodeReached"));
+                assert_eq!(exit_code, Some(134));
+                assert_eq!(signal, Some(6));
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status_tx, mut status_rx) = tokio::sync::mpsc::channel(4);
+        // Runs until it is asked to shut down
+        let module = r#"
+            (module
+                (import "krustlet" "shutdown_requested" (func $shutdown_requested (result i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (loop $wait (br_if $wait (i32.eqz (call $shutdown_requested))))))
+        "#;
+        let runtime = WasiRuntime::new(
+            "test".to_owned(),
+            wat::parse_str(module).unwrap(),
+            HashMap::new(),
+            Vec::new(),
+            HashMap::new(),
+            container_log(dir.path()).await,
+            status_tx,
+            WasiHttpConfig::default(),
+            ResourceLimits::default(),
+            new_engine(&WasmConfig::default(), TEST_POOL, false).unwrap(),
+            ModuleCache::new(dir.path(), "test"),
+        );
+        let mut handle = runtime.start().await.unwrap();
+        assert!(handle.shutdown().await.unwrap());
+        handle.wait().await.unwrap();
+        assert!(matches!(
+            status_rx.recv().await,
+            Some(Status::Running { .. })
+        ));
+        match status_rx.recv().await {
+            Some(Status::Terminated {
+                failed, exit_code, ..
+            }) => {
+                assert!(!failed);
+                assert_eq!(exit_code, Some(0));
+            }
+            status => panic!("Expected terminated status, got {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_terminate_twice() {
+        use k8s_openapi::api::core::v1::{Container as KubeContainer, Pod as KubePod, PodSpec};
+        use kubelet::container::ContainerKey;
+        use kubelet::pod::{Handle as PodHandle, Pod};
+
+        let dir = tempfile::tempdir().unwrap();
+        let (status_tx, _status_rx) = tokio::sync::mpsc::channel(4);
+        let module = r#"
+            (module
+                (import "krustlet" "shutdown_requested" (func $shutdown_requested (result i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (loop $wait (br_if $wait (i32.eqz (call $shutdown_requested))))))
+        "#;
+        let runtime = WasiRuntime::new(
+            "test".to_owned(),
+            wat::parse_str(module).unwrap(),
+            HashMap::new(),
+            Vec::new(),
+            HashMap::new(),
+            container_log(dir.path()).await,
+            status_tx,
+            WasiHttpConfig::default(),
+            ResourceLimits::default(),
+            new_engine(&WasmConfig::default(), TEST_POOL, false).unwrap(),
+            ModuleCache::new(dir.path(), "test"),
+        );
+        let pod = Pod::from(KubePod {
+            spec: Some(PodSpec {
+                containers: vec![KubeContainer {
+                    name: "test".to_owned(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mut handles = HashMap::new();
+        handles.insert(
+            ContainerKey::App("test".to_owned()),
+            runtime.start().await.unwrap(),
+        );
+        let pod_handle = PodHandle::new(handles, pod);
+
+        // The module exits once asked to, after which terminating the pod
+        // again must not wait for it a second time
+        let grace_period = Duration::from_secs(5);
+        pod_handle
+            .terminate(grace_period, "127.0.0.1")
+            .await
+            .unwrap();
+        pod_handle
+            .terminate(grace_period, "127.0.0.1")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_not_imported() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status_tx, _status_rx) = tokio::sync::mpsc::channel(4);
+        let runtime = WasiRuntime::new(
+            "test".to_owned(),
+            wat::parse_str(MODULE).unwrap(),
+            HashMap::new(),
+            Vec::new(),
+            HashMap::new(),
+            container_log(dir.path()).await,
+            status_tx,
+            WasiHttpConfig::default(),
+            ResourceLimits::default(),
+            new_engine(&WasmConfig::default(), TEST_POOL, false).unwrap(),
+            ModuleCache::new(dir.path(), "test"),
+        );
+        let mut handle = runtime.start().await.unwrap();
+        assert!(!handle.shutdown().await.unwrap());
+        handle.wait().await.unwrap();
+    }
+}
@@ -6,6 +6,7 @@ use kubelet::store::oci::FileStore;
 use kubelet::Kubelet;
 use std::convert::TryFrom;
 use std::sync::Arc;
+use tracing_subscriber::EnvFilter;
 use wasi_provider::WasiProvider;
 
 #[tokio::main(flavor = "multi_thread")]
@@ -14,11 +15,13 @@ async fn main() -> anyhow::Result<()> {
     // a new Kubelet, all you need to implement is a provider.
     let config = Config::new_from_file_and_flags(env!("CARGO_PKG_VERSION"), None);
 
-    // Initialize the logger
-    tracing_subscriber::fmt()
+    // Initialize the logger. The filter can be replaced when the config is reloaded.
+    let subscriber = tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+        .with_env_filter(log_filter(&config)?)
+        .with_filter_reloading();
+    let log_filter_handle = subscriber.reload_handle();
+    subscriber.init();
 
     let kubeconfig = kubelet::bootstrap(&config, &config.bootstrap_file, notify_bootstrap).await?;
 
@@ -38,10 +41,24 @@ async fn main() -> anyhow::Result<()> {
         device_plugin_manager,
     )
     .await?;
-    let kubelet = Kubelet::new(provider, kubeconfig, config).await?;
+    let kubelet = Kubelet::new(provider, kubeconfig, config)
+        .await?
+        .with_config_reload(move || {
+            let config = Config::try_new_from_file_and_flags(env!("CARGO_PKG_VERSION"), None)?;
+            log_filter_handle.reload(log_filter(&config)?)?;
+            Ok(config)
+        });
     kubelet.start().await
 }
 
+/// Uses the configured log level, falling back to `RUST_LOG`.
+fn log_filter(config: &Config) -> anyhow::Result<EnvFilter> {
+    match &config.log_level {
+        Some(level) => Ok(EnvFilter::try_new(level)?),
+        None => Ok(EnvFilter::from_default_env()),
+    }
+}
+
 fn make_store(config: &Config) -> Arc<dyn kubelet::store::Store + Send + Sync> {
     let client = oci_distribution::Client::from_source(config);
     let mut store_path = config.data_dir.join(".oci");